@@ -5,38 +5,88 @@
 
 use anyhow::Error;
 use common_macros::hash_map;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, path::Path};
 
-#[derive(Debug, Clone, Deserialize, PartialEq)]
+/// How a mongo collection is split by item, the ratings of an item with key `k`
+/// are kept on `{prefix}_{k % count}`. With a single shard the collection is
+/// just named `prefix`
+#[derive(Debug, Clone, Deserialize, PartialEq, Serialize)]
+pub struct Shards {
+    pub prefix: String,
+    pub count: usize,
+}
+
+impl Default for Shards {
+    fn default() -> Self {
+        Self {
+            prefix: "users_who_rated".into(),
+            count: 1,
+        }
+    }
+}
+
+impl Shards {
+    /// Name of the collection that holds the item with `key`
+    pub fn collection_name(&self, key: u64) -> String {
+        if self.count <= 1 {
+            self.prefix.clone()
+        } else {
+            format!("{}_{}", self.prefix, key % self.count as u64)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Serialize)]
 pub struct DatabaseEntry {
     pub psql_url: String,
     pub mongo_url: String,
     pub mongo_db: String,
     pub users_ratings_mongo: bool,
     pub users_who_rated_mongo: bool,
+    /// Query postgres when a `users_who_rated` query on mongo fails
+    #[serde(default)]
+    pub mongo_fallback: bool,
+    #[serde(default)]
+    pub users_who_rated_shards: Shards,
 }
 
-#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, PartialEq, Serialize)]
 pub struct MatrixConfig {
     pub chunk_size_threshold: f64,
     pub partial_users_chunk_size: usize,
     pub allow_chunk_optimization: bool,
+    /// Similarities below this value aren't stored in the similarity matrix
+    #[serde(default)]
+    pub min_similarity: Option<f64>,
 }
 
-#[derive(Debug, Clone, Deserialize, PartialEq)]
+/// Normalization applied to the user ratings on item based predictions
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Normalization {
+    #[default]
+    MinMax,
+    MeanCentered,
+    ZScore,
+    None,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Serialize)]
 pub struct EngineConfig {
     pub partial_users_chunk_size: usize,
+    #[serde(default)]
+    pub normalization: Normalization,
 }
 
-#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, PartialEq, Serialize)]
 pub struct SystemConfig {
     pub term_verbosity_level: usize,
     pub file_verbosity_level: usize,
     pub log_output: Option<String>,
 }
 
-#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, PartialEq, Serialize)]
 pub struct Config {
     pub system: SystemConfig,
     pub engine: EngineConfig,
@@ -54,16 +104,20 @@ impl Default for Config {
             },
             engine: EngineConfig {
                 partial_users_chunk_size: 10000,
+                normalization: Normalization::MinMax,
             },
             matrix: MatrixConfig {
                 chunk_size_threshold: 0.3,
                 partial_users_chunk_size: 10000,
                 allow_chunk_optimization: true,
+                min_similarity: None,
             },
             databases: hash_map! {
                 "simple-movie".into() => DatabaseEntry {
                     users_ratings_mongo: false,
                     users_who_rated_mongo: true,
+                    mongo_fallback: false,
+                    users_who_rated_shards: Shards::default(),
                     psql_url: "postgres://postgres:@localhost/simple-movie".into(),
                     mongo_url: "mongodb://localhost:27017".into(),
                     mongo_db: "simple-movie".into()
@@ -71,6 +125,8 @@ impl Default for Config {
                 "books".into() => DatabaseEntry {
                     users_ratings_mongo: false,
                     users_who_rated_mongo: true,
+                    mongo_fallback: false,
+                    users_who_rated_shards: Shards::default(),
                     psql_url: "postgres://postgres:@localhost/books".into(),
                     mongo_url: "mongodb://localhost:27017".into(),
                     mongo_db: "books".into()
@@ -78,6 +134,8 @@ impl Default for Config {
                 "shelves".into() => DatabaseEntry {
                     users_ratings_mongo: false,
                     users_who_rated_mongo: true,
+                    mongo_fallback: false,
+                    users_who_rated_shards: Shards::default(),
                     psql_url: "postgres://postgres:@localhost/shelves".into(),
                     mongo_url: "mongodb://localhost:27017".into(),
                     mongo_db: "shelves".into(),
@@ -85,6 +143,8 @@ impl Default for Config {
                 "movie-lens".into() => DatabaseEntry {
                     users_ratings_mongo: false,
                     users_who_rated_mongo: true,
+                    mongo_fallback: false,
+                    users_who_rated_shards: Shards::default(),
                     psql_url: "postgres://postgres:@localhost/movie-lens".into(),
                     mongo_url: "mongodb://localhost:27017".into(),
                     mongo_db: "movie-lens".into(),
@@ -92,6 +152,8 @@ impl Default for Config {
                 "movie-lens-small".into() => DatabaseEntry {
                     users_ratings_mongo: false,
                     users_who_rated_mongo: true,
+                    mongo_fallback: false,
+                    users_who_rated_shards: Shards::default(),
                     psql_url: "postgres://postgres:@localhost/movie-lens-small".into(),
                     mongo_url: "mongodb://localhost:27017".into(),
                     mongo_db: "movie-lens-small".into(),
@@ -107,6 +169,13 @@ impl Config {
         let parsed: Self = toml::from_str(&contents)?;
         Ok(parsed)
     }
+
+    /// Write the config as TOML to `path`, so it can be read back with `load`
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let contents = toml::to_string_pretty(self)?;
+        std::fs::write(path, contents)?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -125,16 +194,20 @@ mod tests {
             },
             engine: EngineConfig {
                 partial_users_chunk_size: 10000,
+                normalization: Normalization::MinMax,
             },
             matrix: MatrixConfig {
                 chunk_size_threshold: 0.3,
                 partial_users_chunk_size: 10000,
                 allow_chunk_optimization: true,
+                min_similarity: None,
             },
             databases: hash_map! {
                 "some-database".into() => DatabaseEntry {
                     users_ratings_mongo: false,
                     users_who_rated_mongo: true,
+                    mongo_fallback: false,
+                    users_who_rated_shards: Shards::default(),
                     psql_url: "postgres://postgres:@localhost/some-database".into(),
                     mongo_url: "mongodb://localhost:27017".into(),
                     mongo_db: "some-database".into(),
@@ -147,4 +220,67 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn sharded_collections() -> Result<(), Error> {
+        let single = Shards::default();
+        assert_eq!(single.collection_name(0), "users_who_rated");
+        assert_eq!(single.collection_name(7), "users_who_rated");
+
+        let config: Config = toml::from_str(
+            r#"
+            [system]
+            file_verbosity_level = 2
+            term_verbosity_level = 1
+
+            [matrix]
+            allow_chunk_optimization = true
+            chunk_size_threshold = 0.3
+            partial_users_chunk_size = 10000
+
+            [engine]
+            partial_users_chunk_size = 10000
+
+            [databases.shelves]
+            mongo_db = "shelves"
+            mongo_url = "mongodb://localhost:27017"
+            psql_url = "postgres://postgres:@localhost/shelves"
+            users_ratings_mongo = false
+            users_who_rated_mongo = true
+
+            [databases.shelves.users_who_rated_shards]
+            prefix = "shelves_who_rated"
+            count = 2
+            "#,
+        )?;
+
+        let shards = &config.databases["shelves"].users_who_rated_shards;
+        assert_eq!(shards.collection_name(4), "shelves_who_rated_0");
+        assert_eq!(shards.collection_name(7), "shelves_who_rated_1");
+
+        Ok(())
+    }
+
+    #[test]
+    fn save_and_load_round_trip() -> Result<(), Error> {
+        let path = std::env::temp_dir().join(format!("config-{}.toml", std::process::id()));
+
+        let loaded = Config::load("example.toml")?;
+        loaded.save(&path)?;
+        let reloaded = Config::load(&path);
+        std::fs::remove_file(&path)?;
+
+        assert_eq!(loaded, reloaded?);
+
+        let mut modified = Config::default();
+        modified.engine.normalization = Normalization::ZScore;
+        modified.system.log_output = None;
+        modified.save(&path)?;
+        let reloaded = Config::load(&path);
+        std::fs::remove_file(&path)?;
+
+        assert_eq!(modified, reloaded?);
+
+        Ok(())
+    }
 }
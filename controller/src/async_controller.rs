@@ -0,0 +1,112 @@
+// Copyright (c) 2020 White Leaf
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Adapter to use any (synchronous) controller from async code, every call is
+//! moved to tokio's blocking thread pool so the runtime is never blocked.
+//!
+//! Only the read methods needed to serve recommendations are async: searching
+//! users and items (`users_by`, `items_by`, `create_partial_*`), the ratings used
+//! by user based methods (`user_ratings`, `users_ratings`, `users_ratings_except`),
+//! the inverted ratings used by item based methods (`users_who_rated`) and the
+//! users means. Writes are rare and can go through `with_controller`.
+
+use crate::{eid, maped_ratings, means, ratings, Controller, Entity, Result, SearchBy};
+use std::sync::{Arc, Mutex};
+use tokio::task::spawn_blocking;
+
+pub struct AsyncController<C> {
+    inner: Arc<Mutex<C>>,
+}
+
+impl<C> Clone for AsyncController<C> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<C, U, I> AsyncController<C>
+where
+    C: Controller<User = U, Item = I> + Send + 'static,
+    U: Entity + Send + 'static,
+    I: Entity + Send + 'static,
+    eid!(U): Send + 'static,
+    eid!(I): Send + 'static,
+{
+    pub fn new(controller: C) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(controller)),
+        }
+    }
+
+    async fn run<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&C) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let inner = Arc::clone(&self.inner);
+        spawn_blocking(move || {
+            let controller = inner
+                .lock()
+                .map_err(|_| anyhow::anyhow!("Controller mutex was poisoned"))?;
+
+            f(&controller)
+        })
+        .await?
+    }
+
+    /// Run any other (synchronous) controller method on the blocking thread pool
+    pub async fn with_controller<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&C) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.run(f).await
+    }
+
+    pub async fn users_by(&self, by: SearchBy) -> Result<Vec<U>> {
+        self.run(move |controller| controller.users_by(&by)).await
+    }
+
+    pub async fn items_by(&self, by: SearchBy) -> Result<Vec<I>> {
+        self.run(move |controller| controller.items_by(&by)).await
+    }
+
+    pub async fn create_partial_users(&self, user_ids: Vec<eid!(U)>) -> Result<Vec<U>> {
+        self.run(move |controller| controller.create_partial_users(&user_ids))
+            .await
+    }
+
+    pub async fn create_partial_items(&self, item_ids: Vec<eid!(I)>) -> Result<Vec<I>> {
+        self.run(move |controller| controller.create_partial_items(&item_ids))
+            .await
+    }
+
+    pub async fn users_who_rated(&self, items: Vec<I>) -> Result<maped_ratings!(I => U)> {
+        self.run(move |controller| controller.users_who_rated(&items))
+            .await
+    }
+
+    pub async fn user_ratings(&self, user: U) -> Result<ratings!(I)> {
+        self.run(move |controller| controller.user_ratings(&user))
+            .await
+    }
+
+    pub async fn users_ratings(&self, users: Vec<U>) -> Result<maped_ratings!(U => I)> {
+        self.run(move |controller| controller.users_ratings(&users))
+            .await
+    }
+
+    pub async fn users_ratings_except(&self, user: U) -> Result<maped_ratings!(U => I)> {
+        self.run(move |controller| controller.users_ratings_except(&user))
+            .await
+    }
+
+    pub async fn users_means(&self, users: Vec<U>) -> Result<means!(U)> {
+        self.run(move |controller| controller.users_means(&users))
+            .await
+    }
+}
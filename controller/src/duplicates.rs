@@ -0,0 +1,165 @@
+// Copyright (c) 2020 White Leaf
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use crate::error::ErrorKind;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::str::FromStr;
+
+/// Score kept when the same (user, item) pair is rated more than once
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum DuplicatePolicy {
+    KeepFirst,
+    #[default]
+    KeepLast,
+    Average,
+}
+
+impl DuplicatePolicy {
+    // Averages keep the sum of the scores until they're resolved
+    fn resolve(self, (kept, count): (f64, usize)) -> f64 {
+        match self {
+            Self::Average => kept / count as f64,
+            _ => kept,
+        }
+    }
+}
+
+impl FromStr for DuplicatePolicy {
+    type Err = ErrorKind;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep_first" => Ok(Self::KeepFirst),
+            "keep_last" => Ok(Self::KeepLast),
+            "average" => Ok(Self::Average),
+            _ => Err(ErrorKind::ValueConvert(format!("duplicate policy {}", s))),
+        }
+    }
+}
+
+/// Ratings being loaded, keyed by user (or item), that resolves repeated keys with
+/// a `DuplicatePolicy` instead of silently overwriting them
+#[derive(Debug, Clone)]
+pub struct DedupRatings<K> {
+    policy: DuplicatePolicy,
+    scores: HashMap<K, (f64, usize)>,
+    conflicts: usize,
+}
+
+impl<K> DedupRatings<K>
+where
+    K: Hash + Eq,
+{
+    pub fn new(policy: DuplicatePolicy) -> Self {
+        Self {
+            policy,
+            scores: HashMap::new(),
+            conflicts: 0,
+        }
+    }
+
+    /// Add the `score` of `key`, when `key` was already added with a different
+    /// score the conflict is returned as `(kept so far, new)`
+    pub fn insert(&mut self, key: K, score: f64) -> Option<(f64, f64)> {
+        let policy = self.policy;
+        let entry = match self.scores.get_mut(&key) {
+            Some(entry) => entry,
+            None => {
+                self.scores.insert(key, (score, 1));
+                return None;
+            }
+        };
+
+        let previous = policy.resolve(*entry);
+        match policy {
+            DuplicatePolicy::KeepFirst => {}
+            DuplicatePolicy::KeepLast => entry.0 = score,
+            DuplicatePolicy::Average => entry.0 += score,
+        }
+        entry.1 += 1;
+
+        if (previous - score).abs() < f64::EPSILON {
+            None
+        } else {
+            self.conflicts += 1;
+            Some((previous, score))
+        }
+    }
+
+    /// Number of repeated keys with a different score seen so far
+    pub fn conflicts(&self) -> usize {
+        self.conflicts
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    /// Take the resolved scores, leaving it empty to be reused
+    pub fn drain(&mut self) -> HashMap<K, f64> {
+        let policy = self.policy;
+        self.scores
+            .drain()
+            .map(|(key, entry)| (key, policy.resolve(entry)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    fn load_fixture(policy: DuplicatePolicy) -> (HashMap<(i32, i32), f64>, usize) {
+        let fixture = include_str!("../fixtures/duplicate-ratings.csv");
+        let mut ratings = DedupRatings::new(policy);
+
+        for line in fixture.lines().skip(1) {
+            let fields: Vec<_> = line.split(',').collect();
+            let user_id: i32 = fields[0].parse().unwrap();
+            let item_id: i32 = fields[1].parse().unwrap();
+            let score: f64 = fields[2].parse().unwrap();
+
+            ratings.insert((user_id, item_id), score);
+        }
+
+        let conflicts = ratings.conflicts();
+        (ratings.drain(), conflicts)
+    }
+
+    #[test]
+    fn duplicate_policies() {
+        // User 1 rated item 10 twice (4 then 2), user 2 rated it twice with 3
+        let (first, conflicts) = load_fixture(DuplicatePolicy::KeepFirst);
+        assert_eq!(conflicts, 1);
+        assert_eq!(first.len(), 4);
+        assert_approx_eq!(first[&(1, 10)], 4.0);
+        assert_approx_eq!(first[&(2, 10)], 3.0);
+
+        let (last, _) = load_fixture(DuplicatePolicy::KeepLast);
+        assert_approx_eq!(last[&(1, 10)], 2.0);
+        assert_approx_eq!(last[&(2, 10)], 3.0);
+
+        let (average, _) = load_fixture(DuplicatePolicy::Average);
+        assert_approx_eq!(average[&(1, 10)], 3.0);
+        assert_approx_eq!(average[&(2, 10)], 3.0);
+        assert_approx_eq!(average[&(1, 11)], 1.0);
+    }
+
+    #[test]
+    fn parse_policy() {
+        assert_eq!(
+            "average".parse::<DuplicatePolicy>().unwrap(),
+            DuplicatePolicy::Average
+        );
+        assert!("newest".parse::<DuplicatePolicy>().is_err());
+    }
+}
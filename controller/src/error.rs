@@ -34,9 +34,18 @@ pub enum ErrorKind {
     #[error("Couldn't insert rating for user({0}) on item({1})")]
     InsertRatingFailed(String, String),
 
+    #[error("Score {0} is out of the range [{1}, {2}]")]
+    ScoreOutOfRange(f64, f64, f64),
+
     #[error("Couldn't remove rating for user({0}) on item({1})")]
     RemoveRatingFailed(String, String),
 
     #[error("Database config not found for {0}")]
     DbConfigError(String),
+
+    #[error("Bulk write on {0} failed for {1} document(s)")]
+    BulkWriteFailed(String, usize),
+
+    #[error("Couldn't undo the writes of a failed batch: {0}")]
+    RollbackFailed(String),
 }
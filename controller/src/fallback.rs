@@ -0,0 +1,51 @@
+// Copyright (c) 2020 White Leaf
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use crate::Result;
+
+/// Keep the result of a `primary` query unless it failed and falling back is
+/// `enabled`, in that case the error is logged and `fallback` is queried instead
+pub fn or_fallback<T, F>(primary: Result<T>, enabled: bool, fallback: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T>,
+{
+    match primary {
+        Err(e) if enabled => {
+            log::warn!("Primary query failed, using the fallback: {}", e);
+            fallback()
+        }
+
+        res => res,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+
+    fn unreachable_mongo() -> Result<Vec<i32>> {
+        Err(ErrorKind::BsonConvert.into())
+    }
+
+    #[test]
+    fn fallback_on_failure() {
+        let res = or_fallback(unreachable_mongo(), true, || Ok(vec![1, 2]));
+        assert_eq!(res.unwrap(), vec![1, 2]);
+
+        // Disabled fallback keeps the original error
+        let res = or_fallback(unreachable_mongo(), false, || Ok(vec![1, 2]));
+        assert!(matches!(
+            res.unwrap_err().downcast_ref::<ErrorKind>(),
+            Some(ErrorKind::BsonConvert)
+        ));
+
+        // The fallback isn't queried when the primary query succeeds
+        let res = or_fallback(Ok(vec![3]), true, || -> Result<Vec<i32>> {
+            panic!("fallback shouldn't be used")
+        });
+        assert_eq!(res.unwrap(), vec![3]);
+    }
+}
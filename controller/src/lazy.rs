@@ -12,6 +12,7 @@ where
 {
     pub(crate) curr_offset: usize,
     pub(crate) chunk_size: usize,
+    pub(crate) seed: Option<u64>,
     pub(crate) controller: &'a C,
 }
 
@@ -23,10 +24,16 @@ where
     type Item = Vec<U>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let users = self
-            .controller
-            .users_offset_limit(self.curr_offset, self.chunk_size)
-            .ok();
+        let users = match self.seed {
+            Some(seed) => {
+                self.controller
+                    .users_offset_limit_shuffled(self.curr_offset, self.chunk_size, seed)
+            }
+            None => self
+                .controller
+                .users_offset_limit(self.curr_offset, self.chunk_size),
+        }
+        .ok();
 
         self.curr_offset += self.chunk_size;
         match users {
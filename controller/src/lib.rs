@@ -3,10 +3,17 @@
 // This software is released under the MIT License.
 // https://opensource.org/licenses/MIT
 
+#[cfg(feature = "async")]
+pub mod async_controller;
+pub mod duplicates;
 pub mod entity;
 pub mod error;
+pub mod fallback;
 pub mod lazy;
+#[cfg(feature = "mongo")]
+pub mod mongo;
 pub mod searchby;
+pub mod snapshot;
 pub mod values;
 
 #[macro_export]
@@ -38,8 +45,12 @@ macro_rules! means {
 }
 
 use anyhow::Error;
-use std::collections::HashMap;
+use error::ErrorKind;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
 
+#[cfg(feature = "async")]
+pub use async_controller::AsyncController;
 pub use entity::{Entity, ToTable};
 pub use lazy::{LazyItemChunks, LazyUserChunks};
 pub use searchby::SearchBy;
@@ -50,6 +61,18 @@ pub type Means<K, Value = f64> = HashMap<K, Value>;
 pub type Ratings<I, Value = f64> = HashMap<I, Value>;
 pub type MapedRatings<K, I, Value = f64> = HashMap<K, Ratings<I, Value>>;
 
+/// Offset of a window of `n` out of `total` entities derived from `seed`, `n` must
+/// be smaller than `total`
+fn sample_offset(total: usize, n: usize, seed: u64) -> usize {
+    // SplitMix64 finalizer, enough to spread consecutive seeds
+    let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+
+    (z % (total - n + 1) as u64) as usize
+}
+
 pub trait Controller {
     type User: Entity;
     type Item: Entity;
@@ -59,6 +82,7 @@ pub trait Controller {
     fn users(&self) -> Result<Vec<Self::User>>;
 
     /// Get users that matched the search criteria by id, name or custom (if implemented)
+    /// ordered by id, except `SearchBy::Ids` that keeps the order of the requested ids
     fn users_by(&self, by: &SearchBy) -> Result<Vec<Self::User>>;
 
     /// Get a chunk of users specified by certain offset and limit
@@ -72,14 +96,87 @@ pub trait Controller {
         LazyUserChunks {
             curr_offset: 0,
             chunk_size,
+            seed: None,
             controller: self,
         }
     }
 
+    /// Get a chunk of users, in a pseudo-random order given by `seed`, the same
+    /// seed always gives the same order as long as the users don't change
+    fn users_offset_limit_shuffled(
+        &self,
+        _offset: usize,
+        _limit: usize,
+        _seed: u64,
+    ) -> Result<Vec<Self::User>> {
+        Err(ErrorKind::NotImplemented.into())
+    }
+
+    /// Like `users_by_chunks` but the users are shuffled with `seed`, see
+    /// `users_offset_limit_shuffled`
+    fn users_by_chunks_shuffled(
+        &self,
+        chunk_size: usize,
+        seed: u64,
+    ) -> LazyUserChunks<'_, Self, Self::User>
+    where
+        Self: Sized,
+    {
+        LazyUserChunks {
+            curr_offset: 0,
+            chunk_size,
+            seed: Some(seed),
+            controller: self,
+        }
+    }
+
+    /// Get a reproducible pseudo-random sample of (at most) `n` users, the same
+    /// seed always returns the same users as long as the users don't change.
+    ///
+    /// The default implementation takes `n` consecutive users starting from an
+    /// offset derived from the seed, so it's only as deterministic as the order
+    /// of `users_offset_limit`
+    fn sample_users(&self, n: usize, seed: u64) -> Result<Vec<Self::User>> {
+        let total = self.users_count()?;
+        if total <= n {
+            return self.users_offset_limit(0, n);
+        }
+
+        self.users_offset_limit(sample_offset(total, n, seed), n)
+    }
+
+    /// Get the ratings of a sample of `n` users, see `sample_users`
+    #[allow(clippy::type_complexity)]
+    fn sample_ratings(
+        &self,
+        n: usize,
+        seed: u64,
+    ) -> Result<maped_ratings!(Self::User => Self::Item)> {
+        let users = self.sample_users(n, seed)?;
+        self.users_ratings(&users)
+    }
+
     /// Get all items
     fn items(&self) -> Result<Vec<Self::Item>>;
 
+    /// Get the number of items
+    fn items_count(&self) -> Result<usize> {
+        Ok(self.items()?.len())
+    }
+
+    /// Get a reproducible pseudo-random sample of (at most) `n` items, see
+    /// `sample_users`
+    fn sample_items(&self, n: usize, seed: u64) -> Result<Vec<Self::Item>> {
+        let total = self.items_count()?;
+        if total <= n {
+            return self.items_offset_limit(0, n);
+        }
+
+        self.items_offset_limit(sample_offset(total, n, seed), n)
+    }
+
     /// Get items that matched the search criteria by id, name or custom (if implemented)
+    /// ordered by id, except `SearchBy::Ids` that keeps the order of the requested ids
     fn items_by(&self, by: &SearchBy) -> Result<Vec<Self::Item>>;
 
     /// Get a chunk of items specified by certain offset and limit
@@ -110,9 +207,86 @@ pub trait Controller {
         items: &[Self::Item],
     ) -> Result<maped_ratings!(Self::Item => Self::User)>;
 
+    /// Get the users who rated every one of the specified items, unlike
+    /// `users_who_rated` that has the users who rated any of them. Useful to build
+    /// dense sub-matrices, no items means no users
+    fn users_who_rated_all(&self, items: &[Self::Item]) -> Result<HashSet<eid!(Self::User)>>
+    where
+        eid!(Self::User): Hash + Eq + Clone,
+        eid!(Self::Item): Hash + Eq,
+    {
+        let items_users = self.users_who_rated(items)?;
+
+        let mut common: Option<HashSet<_>> = None;
+        for item in items {
+            let rated = items_users.get(&item.get_id());
+            common = Some(match common {
+                None => rated
+                    .map(|users| users.keys().cloned().collect())
+                    .unwrap_or_default(),
+                Some(common) => common
+                    .into_iter()
+                    .filter(|user_id| rated.is_some_and(|users| users.contains_key(user_id)))
+                    .collect(),
+            });
+        }
+
+        Ok(common.unwrap_or_default())
+    }
+
     /// Get the ratings for the specified user
     fn user_ratings(&self, user: &Self::User) -> Result<ratings!(Self::Item)>;
 
+    /// Get a page of the ratings for the specified user, ordered by item id
+    fn ratings_by_offset_limit(
+        &self,
+        user: &Self::User,
+        offset: usize,
+        limit: usize,
+    ) -> Result<ratings!(Self::Item)>
+    where
+        eid!(Self::Item): Hash + Eq + Ord,
+    {
+        let mut ratings: Vec<_> = self.user_ratings(user)?.into_iter().collect();
+        ratings.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        Ok(ratings.into_iter().skip(offset).take(limit).collect())
+    }
+
+    /// Get a page of the users who rated the specified item, ordered by user id.
+    /// Unlike `users_who_rated` a very popular item doesn't need to be loaded at once
+    fn users_who_rated_paged(
+        &self,
+        item: &Self::Item,
+        offset: usize,
+        limit: usize,
+    ) -> Result<ratings!(Self::User)>
+    where
+        eid!(Self::User): Hash + Eq + Ord,
+        eid!(Self::Item): Hash + Eq,
+    {
+        let mut ratings: Vec<_> = self
+            .users_who_rated(std::slice::from_ref(item))?
+            .remove(&item.get_id())
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        ratings.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        Ok(ratings.into_iter().skip(offset).take(limit).collect())
+    }
+
+    /// Get the ratings of the specified item along with when they were given, as unix
+    /// timestamps in seconds, i.e. maps User::Id => (score, timestamp). Controllers
+    /// that don't keep timestamps fail with `NotImplemented`
+    #[allow(clippy::type_complexity)]
+    fn ratings_with_time_by(
+        &self,
+        _item: &Self::Item,
+    ) -> Result<HashMap<eid!(Self::User), (f64, i64)>> {
+        Err(ErrorKind::NotImplemented.into())
+    }
+
     /// Get all normal MapedRatings, i.e. maps User::Id => Item::Id
     #[allow(clippy::type_complexity)]
     fn all_users_ratings(&self) -> Result<maped_ratings!(Self::User => Self::Item)>;
@@ -134,9 +308,60 @@ pub trait Controller {
     /// Get means for the specified users, returns a map of User::Id => f64
     fn users_means(&self, users: &[Self::User]) -> Result<means!(Self::User)>;
 
+    /// Recompute and store the means for the specified users from their ratings,
+    /// returns the number of updated means. Controllers without a means table compute
+    /// them on demand, so there is nothing to store
+    fn recompute_means(&self, users: &[Self::User]) -> Result<usize> {
+        Ok(self.users_means(users)?.len())
+    }
+
+    /// Get the mean of all the ratings, returns `None` if there isn't any rating
+    fn global_mean(&self) -> Result<Option<f64>> {
+        let mut sum = 0.0;
+        let mut count = 0;
+
+        for ratings in self.all_users_ratings()?.values() {
+            for score in ratings.values() {
+                sum += score;
+                count += 1;
+            }
+        }
+
+        if count == 0 {
+            Ok(None)
+        } else {
+            Ok(Some(sum / count as f64))
+        }
+    }
+
+    /// Get the number of users
+    fn users_count(&self) -> Result<usize> {
+        Ok(self.users()?.len())
+    }
+
+    /// Get the number of ratings
+    fn ratings_count(&self) -> Result<usize> {
+        Ok(self
+            .all_users_ratings()?
+            .values()
+            .map(|ratings| ratings.len())
+            .sum())
+    }
+
     /// The controller score range, ex. (0.0, 5.0) is (min_rating, max_rating)
     fn score_range(&self) -> (f64, f64);
 
+    /// Check that a score is inside of `score_range` before storing it
+    fn validate_score(&self, score: f64) -> Result<()> {
+        let (min, max) = self.score_range();
+
+        if (min..=max).contains(&score) {
+            Ok(())
+        } else {
+            Err(ErrorKind::ScoreOutOfRange(score, min, max).into())
+        }
+    }
+
     /// Return a list of fields required to insert a new user
     fn fields_for_users(&self) -> Vec<Field>;
 
@@ -157,6 +382,21 @@ pub trait Controller {
         score: f64,
     ) -> Result<Self::Rating>;
 
+    /// Create a batch of ratings, returns the number of inserted ratings
+    #[allow(clippy::type_complexity)]
+    fn insert_ratings(
+        &self,
+        ratings: &[(eid!(Self::User), eid!(Self::Item), f64)],
+    ) -> Result<usize> {
+        let mut inserted = 0;
+        for (user_id, item_id, score) in ratings {
+            self.insert_rating(user_id, item_id, *score)?;
+            inserted += 1;
+        }
+
+        Ok(inserted)
+    }
+
     /// Remove a rating in user for an item
     fn remove_rating(
         &self,
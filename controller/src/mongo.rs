@@ -0,0 +1,299 @@
+// Copyright (c) 2020 White Leaf
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Batch writes of ratings shared by the controllers that keep the scores of
+//! every item on a `users_who_rated` collection and, optionally, the scores of
+//! every user on a `users_ratings` collection.
+
+use crate::error::ErrorKind;
+use anyhow::Error;
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::sync::{Collection, Database};
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+// Maximum number of statements sent on a single `update` command
+const BULK_CHUNK_SIZE: usize = 10_000;
+
+/// Turn a document of `id => score` into `scores.id => score`, the layout used by
+/// the update operators on the mongo collections
+pub fn prefix_scores(scores: &Document) -> Document {
+    scores
+        .iter()
+        .map(|(id, score)| (format!("scores.{}", id), score.clone()))
+        .collect()
+}
+
+// Documents of a single collection written by a batch, `key` is the field with
+// the id of each document
+struct Target<'a> {
+    collection: String,
+    key: &'static str,
+    documents: Vec<(Bson, &'a Document)>,
+}
+
+impl Target<'_> {
+    // Update statements applying `operator` to the scores of every document,
+    // split in chunks of at most `BULK_CHUNK_SIZE`
+    fn statements(&self, operator: &str, upsert: bool) -> Vec<Vec<Bson>> {
+        let statements: Vec<Bson> = self
+            .documents
+            .iter()
+            .map(|(id, scores)| {
+                Bson::from(doc! {
+                    "q": { self.key: id.clone() },
+                    "u": { operator: prefix_scores(scores) },
+                    "upsert": upsert,
+                })
+            })
+            .collect();
+
+        statements
+            .chunks(BULK_CHUNK_SIZE)
+            .map(|chunk| chunk.to_vec())
+            .collect()
+    }
+}
+
+// Send a chunk of update statements on a single `update` command
+fn bulk_update(
+    db: &Database,
+    collection: &str,
+    statements: Vec<Bson>,
+    ordered: bool,
+) -> Result<(), Error> {
+    let command = doc! {
+        "update": collection,
+        "updates": statements,
+        "ordered": ordered,
+    };
+
+    let reply = db.run_command(command, None)?;
+    match reply.get_array("writeErrors") {
+        Ok(errors) if !errors.is_empty() => {
+            Err(ErrorKind::BulkWriteFailed(collection.to_string(), errors.len()).into())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Scores of a batch of ratings grouped by item and by user, so every mongo
+/// document is written only once
+pub struct RatingsBatch<'a, U, I> {
+    items_scores: HashMap<&'a I, Document>,
+    users_scores: HashMap<&'a U, Document>,
+}
+
+impl<'a, U, I> RatingsBatch<'a, U, I>
+where
+    U: Hash + Eq + Clone + ToString,
+    I: Hash + Eq + Clone + ToString,
+    Bson: From<U> + From<I>,
+{
+    pub fn new(ratings: &'a [(U, I, f64)]) -> Self {
+        let mut items_scores = HashMap::new();
+        let mut users_scores = HashMap::new();
+        for (user_id, item_id, score) in ratings {
+            items_scores
+                .entry(item_id)
+                .or_insert_with(Document::new)
+                .insert(user_id.to_string(), *score);
+
+            users_scores
+                .entry(user_id)
+                .or_insert_with(Document::new)
+                .insert(item_id.to_string(), *score);
+        }
+
+        Self {
+            items_scores,
+            users_scores,
+        }
+    }
+
+    /// Ids of the users in the batch, sorted and without duplicates
+    pub fn user_ids(&self) -> Vec<U>
+    where
+        U: Ord,
+    {
+        let mut user_ids: Vec<_> = self.users_scores.keys().map(|&id| id.clone()).collect();
+        user_ids.sort_unstable();
+        user_ids
+    }
+
+    /// Fail with `InsertRatingFailed` if any rating of the batch is already stored
+    /// on the `users_who_rated` collection of its item
+    pub fn check_unrated<F>(&self, collection_for: F) -> Result<(), Error>
+    where
+        F: Fn(&I) -> Collection,
+    {
+        for (&item_id, scores) in &self.items_scores {
+            let exists: Vec<_> = scores
+                .keys()
+                .map(|user_id| doc! { format!("scores.{}", user_id): { "$exists": true } })
+                .collect();
+
+            let query = doc! {
+                "item_id": item_id.clone(),
+                "$or": exists
+            };
+
+            if let Some(rating) = collection_for(item_id).find_one(query, None)? {
+                let stored = rating.get_document("scores")?;
+                let user_id = scores
+                    .keys()
+                    .find(|user_id| stored.contains_key(user_id))
+                    .cloned()
+                    .unwrap_or_default();
+
+                return Err(ErrorKind::InsertRatingFailed(user_id, item_id.to_string()).into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write the scores on the `users_who_rated` collection of every item and, if
+    /// given, on the `users_ratings` collection, then run `insert`.
+    ///
+    /// If any of these steps fails every score written on mongo is unset again,
+    /// a failure while undoing a write doesn't stop the rest of the rollback and
+    /// is reported along with the original error.
+    pub fn write<T, F, G>(
+        &self,
+        db: &Database,
+        collection_for: F,
+        users_ratings: Option<&str>,
+        insert: G,
+    ) -> Result<T, Error>
+    where
+        F: Fn(&I) -> Collection,
+        G: FnOnce() -> Result<T, Error>,
+    {
+        let targets = self.targets(collection_for, users_ratings);
+
+        // Number of targets that may hold some of the scores, including the one
+        // that failed partway through
+        let mut touched = 0;
+        let mut result = Ok(());
+        'targets: for target in &targets {
+            touched += 1;
+            for statements in target.statements("$set", true) {
+                result = bulk_update(db, &target.collection, statements, true);
+                if result.is_err() {
+                    break 'targets;
+                }
+            }
+        }
+
+        let error = match result.and_then(|_| insert()) {
+            Ok(value) => return Ok(value),
+            Err(e) => e,
+        };
+
+        let mut undo_errors = Vec::new();
+        for target in &targets[..touched] {
+            for statements in target.statements("$unset", false) {
+                if let Err(e) = bulk_update(db, &target.collection, statements, false) {
+                    undo_errors.push(e.to_string());
+                }
+            }
+        }
+
+        if undo_errors.is_empty() {
+            Err(error)
+        } else {
+            Err(error.context(ErrorKind::RollbackFailed(undo_errors.join("; "))))
+        }
+    }
+
+    // Collections written by the batch, the items are grouped by the collection
+    // returned by `collection_for` since they may be spread between shards
+    fn targets<F>(&self, collection_for: F, users_ratings: Option<&str>) -> Vec<Target<'_>>
+    where
+        F: Fn(&I) -> Collection,
+    {
+        let mut items_documents = BTreeMap::new();
+        for (&item_id, scores) in &self.items_scores {
+            items_documents
+                .entry(collection_for(item_id).name().to_string())
+                .or_insert_with(Vec::new)
+                .push((Bson::from(item_id.clone()), scores));
+        }
+
+        let mut targets: Vec<_> = items_documents
+            .into_iter()
+            .map(|(collection, documents)| Target {
+                collection,
+                key: "item_id",
+                documents,
+            })
+            .collect();
+
+        if let Some(collection) = users_ratings {
+            targets.push(Target {
+                collection: collection.to_string(),
+                key: "user_id",
+                documents: self
+                    .users_scores
+                    .iter()
+                    .map(|(&user_id, scores)| (Bson::from(user_id.clone()), scores))
+                    .collect(),
+            });
+        }
+
+        targets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefix_scores_of_document() {
+        let scores = doc! { "1": 3.0, "abc": 4.5 };
+        assert_eq!(
+            prefix_scores(&scores),
+            doc! { "scores.1": 3.0, "scores.abc": 4.5 }
+        );
+    }
+
+    #[test]
+    fn batch_groups_by_item_and_user() {
+        let ratings = [(1, 10, 2.0), (2, 10, 3.0), (1, 20, 4.0)];
+        let batch = RatingsBatch::new(&ratings);
+
+        assert_eq!(batch.items_scores[&10], doc! { "1": 2.0, "2": 3.0 });
+        assert_eq!(batch.items_scores[&20], doc! { "1": 4.0 });
+        assert_eq!(batch.users_scores[&1], doc! { "10": 2.0, "20": 4.0 });
+        assert_eq!(batch.users_scores[&2], doc! { "10": 3.0 });
+        assert_eq!(batch.user_ids(), vec![1, 2]);
+    }
+
+    #[test]
+    fn statements_are_chunked() {
+        let scores = doc! { "1": 2.0 };
+        let target = Target {
+            collection: "users_who_rated".to_string(),
+            key: "item_id",
+            documents: (0..BULK_CHUNK_SIZE as i32 + 1)
+                .map(|id| (Bson::from(id), &scores))
+                .collect(),
+        };
+
+        let chunks = target.statements("$unset", false);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].len(), BULK_CHUNK_SIZE);
+        assert_eq!(
+            chunks[1][0],
+            Bson::from(doc! {
+                "q": { "item_id": BULK_CHUNK_SIZE as i32 },
+                "u": { "$unset": { "scores.1": 2.0 } },
+                "upsert": false,
+            })
+        );
+    }
+}
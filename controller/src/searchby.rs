@@ -3,12 +3,20 @@
 // This software is released under the MIT License.
 // https://opensource.org/licenses/MIT
 
+use crate::Entity;
+use std::collections::HashMap;
 use std::fmt::{self, Display};
+use std::hash::Hash;
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum SearchBy {
     Id(String),
+    /// Many ids at once, results follow the order of the ids and the ids that
+    /// don't match anything are skipped
+    Ids(Vec<String>),
     Name(String),
+    /// Case insensitive partial match of the name
+    NameLike(String),
     Custom(String, String),
 }
 
@@ -17,10 +25,18 @@ impl SearchBy {
         Self::Id(id.into())
     }
 
+    pub fn ids(ids: &[&str]) -> Self {
+        Self::Ids(ids.iter().map(|id| id.to_string()).collect())
+    }
+
     pub fn name(name: &str) -> Self {
         Self::Name(name.into())
     }
 
+    pub fn name_like(name: &str) -> Self {
+        Self::NameLike(name.into())
+    }
+
     pub fn custom(key: &str, val: &str) -> Self {
         Self::Custom(key.into(), val.into())
     }
@@ -30,8 +46,36 @@ impl Display for SearchBy {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             SearchBy::Id(id) => write!(f, "id({})", id),
+            SearchBy::Ids(ids) => write!(f, "ids({})", ids.join(", ")),
             SearchBy::Name(name) => write!(f, "name({})", name),
+            SearchBy::NameLike(name) => write!(f, "like({})", name),
             SearchBy::Custom(key, val) => write!(f, "{}({})", key, val),
         }
     }
 }
+
+/// Order the entities found by a `SearchBy::Ids` as their ids were requested,
+/// repeated ids only keep their first position
+pub fn sort_by_ids<E, K>(entities: Vec<E>, ids: &[K]) -> Vec<E>
+where
+    E: Entity<Id = K>,
+    K: Hash + Eq,
+{
+    let mut by_id: HashMap<_, _> = entities
+        .into_iter()
+        .map(|entity| (entity.get_id(), entity))
+        .collect();
+
+    ids.iter().filter_map(|id| by_id.remove(id)).collect()
+}
+
+/// Build the `ILIKE` pattern of a `SearchBy::NameLike`, the wildcards in the name
+/// are escaped so they match literally
+pub fn like_pattern(name: &str) -> String {
+    let escaped = name
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+
+    format!("%{}%", escaped)
+}
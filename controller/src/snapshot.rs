@@ -0,0 +1,14 @@
+// Copyright (c) 2020 White Leaf
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use serde::{Deserialize, Serialize};
+
+/// A line of a ratings snapshot, snapshots are stored as JSON Lines with all
+/// the ratings of a user per line so they can be written and read by streaming
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserRatingsRecord<U, I> {
+    pub user_id: U,
+    pub ratings: Vec<(I, f64)>,
+}
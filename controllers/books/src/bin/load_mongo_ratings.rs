@@ -6,12 +6,21 @@
 use anyhow::Error;
 use books::BooksController;
 use config::Config;
+use controller::duplicates::{DedupRatings, DuplicatePolicy};
 use controller::Controller;
 use indicatif::ProgressIterator;
 use mongodb::bson::{doc, to_bson, Bson, Document};
 use mongodb::sync::Client;
 use std::collections::{HashMap, HashSet};
 
+fn to_scores(ratings: &mut DedupRatings<String>) -> HashMap<String, Bson> {
+    ratings
+        .drain()
+        .into_iter()
+        .map(|(id, score)| (id, Bson::Double(score)))
+        .collect()
+}
+
 fn main() -> Result<(), Error> {
     let vars: HashMap<String, String> = dotenv::vars().collect();
     let mut config = Config::default();
@@ -21,6 +30,14 @@ fn main() -> Result<(), Error> {
     db.mongo_url = vars["MONGO_URL"].clone();
     db.mongo_db = vars["MONGO_DB"].clone();
 
+    // Repeated (user, book) pairs are resolved with `DUPLICATE_POLICY`, one of
+    // `keep_first`, `keep_last` (the default) or `average`
+    let policy: DuplicatePolicy = vars
+        .get("DUPLICATE_POLICY")
+        .map(|policy| policy.parse())
+        .transpose()?
+        .unwrap_or_default();
+
     let client = Client::with_uri_str(&db.mongo_url)?;
     let users_who_rated = client.database(&db.mongo_db).collection("users_who_rated");
     let users_ratings = client.database(&db.mongo_db).collection("users_ratings");
@@ -43,6 +60,7 @@ fn main() -> Result<(), Error> {
     let records: Vec<_> = csv.records().collect();
 
     let mut docs = HashMap::new();
+    let mut conflicts = 0;
     for record in records.into_iter().progress() {
         if let Ok(record) = record {
             let user_id: i32 = record[0].parse()?;
@@ -53,16 +71,29 @@ fn main() -> Result<(), Error> {
                 continue;
             }
 
-            docs.entry(book_id.to_string())
-                .or_insert_with(HashMap::new)
-                .insert(user_id.to_string(), Bson::Double(score));
+            let conflict = docs
+                .entry(book_id.to_string())
+                .or_insert_with(|| DedupRatings::new(policy))
+                .insert(user_id.to_string(), score);
+
+            if let Some((kept, new)) = conflict {
+                conflicts += 1;
+                println!(
+                    "User {} rated book {} with {} and {}, using {:?}",
+                    user_id, book_id, kept, new, policy
+                );
+            }
         }
     }
 
+    if conflicts > 0 {
+        println!("Found {} conflicting duplicate ratings", conflicts);
+    }
+
     let docs: Vec<Document> = docs
         .into_iter()
-        .map(|(k, v)| -> Result<_, Error> {
-            let data = to_bson(&v)?;
+        .map(|(k, mut v)| -> Result<_, Error> {
+            let data = to_bson(&to_scores(&mut v))?;
             Ok(doc! { "item_id": k, "scores": data  })
         })
         .collect::<Result<_, Error>>()?;
@@ -92,16 +123,17 @@ fn main() -> Result<(), Error> {
                 continue;
             }
 
+            // Conflicts were already reported while loading users_who_rated
             docs.entry(user_id)
-                .or_insert_with(HashMap::new)
-                .insert(book_id.to_string(), Bson::Double(score));
+                .or_insert_with(|| DedupRatings::new(policy))
+                .insert(book_id.to_string(), score);
         }
     }
 
     let docs: Vec<Document> = docs
         .into_iter()
-        .map(|(k, v)| -> Result<_, Error> {
-            let data = to_bson(&v)?;
+        .map(|(k, mut v)| -> Result<_, Error> {
+            let data = to_bson(&to_scores(&mut v))?;
             Ok(doc! { "user_id": k, "scores": data  })
         })
         .collect::<Result<_, Error>>()?;
@@ -0,0 +1,910 @@
+// Copyright (c) 2020 White Leaf
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+pub mod models;
+
+use crate::models::{items::Item, ratings::Rating, users::User};
+use anyhow::Error;
+use controller::searchby::sort_by_ids;
+use controller::snapshot::UserRatingsRecord;
+use controller::{
+    eid, error::ErrorKind, maped_ratings, means, ratings, Controller, Field, SearchBy, Type, Value,
+};
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// A controller that keeps everything in memory, useful for tests and small
+/// experiments where setting up the databases isn't worth it.
+pub struct InMemoryController {
+    score_range: (f64, f64),
+    next_rating_id: Cell<i32>,
+    users: RefCell<BTreeMap<i32, User>>,
+    items: RefCell<BTreeMap<i32, Item>>,
+    ratings: RefCell<BTreeMap<(i32, i32), Rating>>,
+}
+
+impl Default for InMemoryController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InMemoryController {
+    pub fn new() -> Self {
+        Self::with_score_range(1., 5.)
+    }
+
+    pub fn with_score_range(min: f64, max: f64) -> Self {
+        Self {
+            score_range: (min, max),
+            next_rating_id: Cell::new(1),
+            users: Default::default(),
+            items: Default::default(),
+            ratings: Default::default(),
+        }
+    }
+
+    /// Build a controller from a list of (user_id, item_id, score), users and
+    /// items are created as needed
+    pub fn from_ratings(ratings: &[(i32, i32, f64)]) -> Result<Self, Error> {
+        Self::from_ratings_with_score_range(ratings, 1., 5.)
+    }
+
+    /// Same as `from_ratings` but the scores must be between `min` and `max`
+    pub fn from_ratings_with_score_range(
+        ratings: &[(i32, i32, f64)],
+        min: f64,
+        max: f64,
+    ) -> Result<Self, Error> {
+        let controller = Self::with_score_range(min, max);
+        for (user_id, item_id, _) in ratings {
+            controller.add_user(*user_id);
+            controller.add_item(*item_id);
+        }
+
+        controller.insert_ratings(ratings)?;
+        Ok(controller)
+    }
+
+    /// Load the ratings of a JSON Lines snapshot, like the ones written by
+    /// `Engine::export_ratings`, users and items are created as needed.
+    /// Returns the number of inserted ratings
+    pub fn load_snapshot<P: AsRef<Path>>(&self, path: P) -> Result<usize, Error> {
+        let reader = BufReader::new(File::open(path)?);
+
+        let mut inserted = 0;
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let record: UserRatingsRecord<i32, i32> = serde_json::from_str(&line)?;
+            let user_id = record.user_id;
+            self.add_user(user_id);
+
+            let ratings: Vec<_> = record
+                .ratings
+                .into_iter()
+                .map(|(item_id, score)| {
+                    self.add_item(item_id);
+                    (user_id, item_id, score)
+                })
+                .collect();
+
+            inserted += self.insert_ratings(&ratings)?;
+        }
+
+        Ok(inserted)
+    }
+
+    /// Add a user with the given id if it doesn't exist yet
+    pub fn add_user(&self, id: i32) {
+        self.users.borrow_mut().entry(id).or_insert_with(|| User {
+            id,
+            name: format!("user-{}", id),
+        });
+    }
+
+    /// Add an item with the given id if it doesn't exist yet
+    pub fn add_item(&self, id: i32) {
+        self.items.borrow_mut().entry(id).or_insert_with(|| Item {
+            id,
+            name: format!("item-{}", id),
+        });
+    }
+
+    /// Change when the rating of `user_id` for `item_id` was given, e.g. to replay a
+    /// dataset with its original timestamps
+    pub fn set_rating_timestamp(
+        &self,
+        user_id: i32,
+        item_id: i32,
+        timestamp: i64,
+    ) -> Result<(), Error> {
+        match self.ratings.borrow_mut().get_mut(&(user_id, item_id)) {
+            Some(rating) => {
+                rating.timestamp = timestamp;
+                Ok(())
+            }
+
+            None => {
+                Err(ErrorKind::UpdateRatingFailed(user_id.to_string(), item_id.to_string()).into())
+            }
+        }
+    }
+
+    fn check_user(&self, user_id: i32) -> Result<(), Error> {
+        if self.users.borrow().contains_key(&user_id) {
+            Ok(())
+        } else {
+            Err(ErrorKind::NotFoundById(user_id.to_string()).into())
+        }
+    }
+
+    fn check_item(&self, item_id: i32) -> Result<(), Error> {
+        if self.items.borrow().contains_key(&item_id) {
+            Ok(())
+        } else {
+            Err(ErrorKind::NotFoundById(item_id.to_string()).into())
+        }
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn maped_ratings_filtered<F>(&self, filter: F) -> maped_ratings!(User => Item)
+    where
+        F: Fn(i32) -> bool,
+    {
+        let mut maped_ratings = HashMap::new();
+        for rating in self.ratings.borrow().values() {
+            if !filter(rating.user_id) {
+                continue;
+            }
+
+            maped_ratings
+                .entry(rating.user_id)
+                .or_insert_with(HashMap::new)
+                .insert(rating.item_id, rating.score);
+        }
+
+        maped_ratings
+    }
+}
+
+impl Controller for InMemoryController {
+    type User = User;
+    type Item = Item;
+    type Rating = Rating;
+
+    fn users(&self) -> Result<Vec<Self::User>, Error> {
+        Ok(self.users.borrow().values().cloned().collect())
+    }
+
+    fn users_by(&self, by: &SearchBy) -> Result<Vec<Self::User>, Error> {
+        match by {
+            SearchBy::Id(id) => {
+                let id: i32 = id.parse()?;
+
+                match self.users.borrow().get(&id) {
+                    Some(user) => Ok(vec![user.clone()]),
+                    None => Err(ErrorKind::NotFoundById(id.to_string()).into()),
+                }
+            }
+
+            SearchBy::Ids(ids) => {
+                let parsed_ids = ids
+                    .iter()
+                    .map(|id| id.parse())
+                    .collect::<Result<Vec<i32>, _>>()?;
+
+                let users: Vec<_> = {
+                    let users = self.users.borrow();
+                    parsed_ids
+                        .iter()
+                        .filter_map(|id| users.get(id).cloned())
+                        .collect()
+                };
+
+                if users.is_empty() {
+                    Err(ErrorKind::NotFoundById(ids.join(", ")).into())
+                } else {
+                    Ok(sort_by_ids(users, &parsed_ids))
+                }
+            }
+
+            SearchBy::Name(name) => {
+                let users: Vec<_> = self
+                    .users
+                    .borrow()
+                    .values()
+                    .filter(|user| &user.name == name)
+                    .cloned()
+                    .collect();
+
+                if users.is_empty() {
+                    Err(ErrorKind::NotFoundByName(name.clone()).into())
+                } else {
+                    Ok(users)
+                }
+            }
+
+            SearchBy::NameLike(name) => {
+                let pattern = name.to_lowercase();
+                let users: Vec<_> = self
+                    .users
+                    .borrow()
+                    .values()
+                    .filter(|user| user.name.to_lowercase().contains(&pattern))
+                    .cloned()
+                    .collect();
+
+                if users.is_empty() {
+                    Err(ErrorKind::NotFoundByName(name.clone()).into())
+                } else {
+                    Ok(users)
+                }
+            }
+
+            SearchBy::Custom(k, v) => Err(ErrorKind::NotFoundByCustom(k.clone(), v.clone()).into()),
+        }
+    }
+
+    fn users_offset_limit(&self, offset: usize, limit: usize) -> Result<Vec<Self::User>, Error> {
+        Ok(self
+            .users
+            .borrow()
+            .values()
+            .skip(offset)
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    fn users_offset_limit_shuffled(
+        &self,
+        offset: usize,
+        limit: usize,
+        seed: u64,
+    ) -> Result<Vec<Self::User>, Error> {
+        // SplitMix64 finalizer over the id mixed with the seed, ties are broken by id
+        let key = |id: i32| {
+            let mut z = (id as u64 ^ seed.rotate_left(32)).wrapping_add(0x9e37_79b9_7f4a_7c15);
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            (z ^ (z >> 31), id)
+        };
+
+        let mut users: Vec<_> = self.users.borrow().values().cloned().collect();
+        users.sort_by_key(|user| key(user.id));
+
+        Ok(users.into_iter().skip(offset).take(limit).collect())
+    }
+
+    fn items(&self) -> Result<Vec<Self::Item>, Error> {
+        Ok(self.items.borrow().values().cloned().collect())
+    }
+
+    fn items_by(&self, by: &SearchBy) -> Result<Vec<Self::Item>, Error> {
+        match by {
+            SearchBy::Id(id) => {
+                let id: i32 = id.parse()?;
+
+                match self.items.borrow().get(&id) {
+                    Some(item) => Ok(vec![item.clone()]),
+                    None => Err(ErrorKind::NotFoundById(id.to_string()).into()),
+                }
+            }
+
+            SearchBy::Ids(ids) => {
+                let parsed_ids = ids
+                    .iter()
+                    .map(|id| id.parse())
+                    .collect::<Result<Vec<i32>, _>>()?;
+
+                let items: Vec<_> = {
+                    let items = self.items.borrow();
+                    parsed_ids
+                        .iter()
+                        .filter_map(|id| items.get(id).cloned())
+                        .collect()
+                };
+
+                if items.is_empty() {
+                    Err(ErrorKind::NotFoundById(ids.join(", ")).into())
+                } else {
+                    Ok(sort_by_ids(items, &parsed_ids))
+                }
+            }
+
+            SearchBy::Name(name) => {
+                let items: Vec<_> = self
+                    .items
+                    .borrow()
+                    .values()
+                    .filter(|item| &item.name == name)
+                    .cloned()
+                    .collect();
+
+                if items.is_empty() {
+                    Err(ErrorKind::NotFoundByName(name.clone()).into())
+                } else {
+                    Ok(items)
+                }
+            }
+
+            SearchBy::NameLike(name) => {
+                let pattern = name.to_lowercase();
+                let items: Vec<_> = self
+                    .items
+                    .borrow()
+                    .values()
+                    .filter(|item| item.name.to_lowercase().contains(&pattern))
+                    .cloned()
+                    .collect();
+
+                if items.is_empty() {
+                    Err(ErrorKind::NotFoundByName(name.clone()).into())
+                } else {
+                    Ok(items)
+                }
+            }
+
+            SearchBy::Custom(k, v) => Err(ErrorKind::NotFoundByCustom(k.clone(), v.clone()).into()),
+        }
+    }
+
+    fn items_offset_limit(&self, offset: usize, limit: usize) -> Result<Vec<Self::Item>, Error> {
+        Ok(self
+            .items
+            .borrow()
+            .values()
+            .skip(offset)
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    fn create_partial_users(
+        &self,
+        user_ids: &[eid!(Self::User)],
+    ) -> Result<Vec<Self::User>, Error> {
+        user_ids
+            .iter()
+            .map(|id| -> Result<User, Error> {
+                Ok(User {
+                    id: *id,
+                    ..Default::default()
+                })
+            })
+            .collect()
+    }
+
+    fn create_partial_items(
+        &self,
+        item_ids: &[eid!(Self::Item)],
+    ) -> Result<Vec<Self::Item>, Error> {
+        item_ids
+            .iter()
+            .map(|id| -> Result<Item, Error> {
+                Ok(Item {
+                    id: *id,
+                    ..Default::default()
+                })
+            })
+            .collect()
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn users_who_rated(
+        &self,
+        items: &[Self::Item],
+    ) -> Result<maped_ratings!(Self::Item => Self::User), Error> {
+        let mut items_users = HashMap::new();
+        for item in items {
+            for rating in self.ratings.borrow().values() {
+                if rating.item_id != item.id {
+                    continue;
+                }
+
+                items_users
+                    .entry(rating.item_id)
+                    .or_insert_with(HashMap::new)
+                    .insert(rating.user_id, rating.score);
+            }
+        }
+
+        Ok(items_users)
+    }
+
+    fn user_ratings(&self, user: &Self::User) -> Result<ratings!(Self::Item), Error> {
+        Ok(self
+            .ratings
+            .borrow()
+            .values()
+            .filter(|rating| rating.user_id == user.id)
+            .map(|rating| (rating.item_id, rating.score))
+            .collect())
+    }
+
+    fn ratings_with_time_by(&self, item: &Self::Item) -> Result<HashMap<i32, (f64, i64)>, Error> {
+        Ok(self
+            .ratings
+            .borrow()
+            .values()
+            .filter(|rating| rating.item_id == item.id)
+            .map(|rating| (rating.user_id, (rating.score, rating.timestamp)))
+            .collect())
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn all_users_ratings(&self) -> Result<maped_ratings!(Self::User => Self::Item), Error> {
+        Ok(self.maped_ratings_filtered(|_| true))
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn users_ratings(
+        &self,
+        users: &[Self::User],
+    ) -> Result<maped_ratings!(Self::User => Self::Item), Error> {
+        Ok(self.maped_ratings_filtered(|id| users.iter().any(|user| user.id == id)))
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn users_ratings_except(
+        &self,
+        user: &Self::User,
+    ) -> Result<maped_ratings!(Self::User => Self::Item), Error> {
+        Ok(self.maped_ratings_filtered(|id| id != user.id))
+    }
+
+    fn users_means(&self, users: &[Self::User]) -> Result<means!(Self::User), Error> {
+        let maped_ratings = self.users_ratings(users)?;
+
+        let means_by_user = maped_ratings
+            .into_iter()
+            .map(|(user_id, ratings)| {
+                let total: f64 = ratings.values().sum();
+                (user_id, total / ratings.len() as f64)
+            })
+            .collect();
+
+        Ok(means_by_user)
+    }
+
+    fn score_range(&self) -> (f64, f64) {
+        self.score_range
+    }
+
+    fn fields_for_users(&self) -> Vec<Field<'_>> {
+        vec![Field::Required("name", Type::String)]
+    }
+
+    fn fields_for_items(&self) -> Vec<Field<'_>> {
+        vec![Field::Required("name", Type::String)]
+    }
+
+    fn insert_user(&self, proto: HashMap<&str, Value>) -> Result<User, Error> {
+        let mut users = self.users.borrow_mut();
+        let id = users.keys().next_back().map_or(1, |id| id + 1);
+        let user = User {
+            id,
+            name: proto["name"].as_string()?.to_owned(),
+        };
+
+        users.insert(id, user.clone());
+        Ok(user)
+    }
+
+    fn insert_item(&self, proto: HashMap<&str, Value>) -> Result<Item, Error> {
+        let mut items = self.items.borrow_mut();
+        let id = items.keys().next_back().map_or(1, |id| id + 1);
+        let item = Item {
+            id,
+            name: proto["name"].as_string()?.to_owned(),
+        };
+
+        items.insert(id, item.clone());
+        Ok(item)
+    }
+
+    fn insert_rating(
+        &self,
+        user_id: &eid!(Self::User),
+        item_id: &eid!(Self::Item),
+        score: f64,
+    ) -> Result<Self::Rating, Error> {
+        self.validate_score(score)?;
+
+        self.check_user(*user_id)?;
+        self.check_item(*item_id)?;
+
+        let mut ratings = self.ratings.borrow_mut();
+        if ratings.contains_key(&(*user_id, *item_id)) {
+            return Err(
+                ErrorKind::InsertRatingFailed(user_id.to_string(), item_id.to_string()).into(),
+            );
+        }
+
+        let rating = Rating {
+            id: self.next_rating_id.get(),
+            user_id: *user_id,
+            item_id: *item_id,
+            score,
+            timestamp: unix_now(),
+        };
+
+        self.next_rating_id.set(rating.id + 1);
+        ratings.insert((*user_id, *item_id), rating.clone());
+
+        Ok(rating)
+    }
+
+    fn remove_rating(
+        &self,
+        user_id: &eid!(Self::User),
+        item_id: &eid!(Self::Item),
+    ) -> Result<Self::Rating, Error> {
+        self.ratings
+            .borrow_mut()
+            .remove(&(*user_id, *item_id))
+            .ok_or_else(|| {
+                ErrorKind::RemoveRatingFailed(user_id.to_string(), item_id.to_string()).into()
+            })
+    }
+
+    fn update_rating(
+        &self,
+        user_id: &eid!(Self::User),
+        item_id: &eid!(Self::Item),
+        score: f64,
+    ) -> Result<Self::Rating, Error> {
+        self.validate_score(score)?;
+
+        match self.ratings.borrow_mut().get_mut(&(*user_id, *item_id)) {
+            Some(rating) => {
+                rating.score = score;
+                Ok(rating.clone())
+            }
+
+            None => {
+                Err(ErrorKind::UpdateRatingFailed(user_id.to_string(), item_id.to_string()).into())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Error;
+    use controller::Entity;
+    use std::collections::HashSet;
+
+    #[test]
+    fn query_user_by_id() -> Result<(), Error> {
+        let controller = InMemoryController::from_ratings(&[(1, 1, 5.), (2, 1, 3.)])?;
+
+        let users = controller.users_by(&SearchBy::id("2"))?;
+        assert_eq!(users[0].get_id(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn query_users_by_ids() -> Result<(), Error> {
+        let controller = InMemoryController::from_ratings(&[(1, 1, 5.), (2, 1, 3.), (3, 1, 4.)])?;
+
+        // Results follow the order of the ids, repeated ids are only returned once
+        let users = controller.users_by(&SearchBy::ids(&["3", "1", "3"]))?;
+        let ids: Vec<_> = users.iter().map(|user| user.get_id()).collect();
+        assert_eq!(ids, vec![3, 1]);
+
+        // Missing ids are skipped, it's only an error when nothing matches
+        let users = controller.users_by(&SearchBy::ids(&["2", "9"]))?;
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].get_id(), 2);
+
+        assert!(controller.users_by(&SearchBy::ids(&["8", "9"])).is_err());
+        assert!(controller.items_by(&SearchBy::ids(&["1", "x"])).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn query_items_by_name_like() -> Result<(), Error> {
+        let controller = InMemoryController::from_ratings(&[(1, 1, 5.), (1, 2, 3.), (1, 12, 4.)])?;
+
+        let mut ids: Vec<_> = controller
+            .items_by(&SearchBy::name_like("ITEM-1"))?
+            .iter()
+            .map(|item| item.get_id())
+            .collect();
+        ids.sort_unstable();
+        assert_eq!(ids, vec![1, 12]);
+
+        assert!(controller.items_by(&SearchBy::name_like("user")).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn search_results_ordered_by_id() -> Result<(), Error> {
+        let controller = InMemoryController::new();
+        for id in &[30, 3, 12, 1] {
+            controller.add_user(*id);
+            controller.add_item(*id);
+        }
+
+        // The first match is always the one with the lowest id
+        let ids: Vec<_> = controller
+            .users_by(&SearchBy::name_like("user-"))?
+            .iter()
+            .map(|user| user.get_id())
+            .collect();
+        assert_eq!(ids, vec![1, 3, 12, 30]);
+
+        let ids: Vec<_> = controller
+            .items_by(&SearchBy::name_like("item-3"))?
+            .iter()
+            .map(|item| item.get_id())
+            .collect();
+        assert_eq!(ids, vec![3, 30]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn paged_user_ratings() -> Result<(), Error> {
+        let ratings: Vec<_> = (1..=5).map(|id| (1, id, id as f64)).collect();
+        let controller = InMemoryController::from_ratings(&ratings)?;
+        let user = controller.users_by(&SearchBy::id("1"))?.remove(0);
+
+        let mut pages = HashMap::new();
+        for offset in (0..6).step_by(2) {
+            let page = controller.ratings_by_offset_limit(&user, offset, 2)?;
+            assert!(page.len() <= 2);
+            pages.extend(page);
+        }
+
+        assert_eq!(pages, controller.user_ratings(&user)?);
+        assert!(controller.ratings_by_offset_limit(&user, 5, 2)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn paged_users_who_rated() -> Result<(), Error> {
+        let ratings: Vec<_> = (1..=7).map(|id| (id, 1, (id % 5 + 1) as f64)).collect();
+        let controller = InMemoryController::from_ratings(&ratings)?;
+        let item = controller.items_by(&SearchBy::id("1"))?.remove(0);
+
+        let mut pages = HashMap::new();
+        for offset in (0..8).step_by(3) {
+            let page = controller.users_who_rated_paged(&item, offset, 3)?;
+            assert!(page.len() <= 3);
+            pages.extend(page);
+        }
+
+        let mut all = controller.users_who_rated(std::slice::from_ref(&item))?;
+        assert_eq!(pages, all.remove(&1).unwrap());
+        assert!(controller.users_who_rated_paged(&item, 7, 3)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn users_who_rated_all_items() -> Result<(), Error> {
+        let controller = InMemoryController::from_ratings(&[
+            (1, 1, 5.),
+            (1, 2, 3.),
+            (2, 1, 4.),
+            (3, 2, 2.),
+            (3, 1, 1.),
+            (3, 3, 4.),
+        ])?;
+        let items = controller.items_by(&SearchBy::ids(&["1", "2"]))?;
+
+        // Any of the items
+        let mut any: Vec<_> = controller
+            .users_who_rated(&items)?
+            .values()
+            .flat_map(|users| users.keys().copied())
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        any.sort_unstable();
+        assert_eq!(any, vec![1, 2, 3]);
+
+        // All of them
+        let mut all: Vec<_> = controller
+            .users_who_rated_all(&items)?
+            .into_iter()
+            .collect();
+        all.sort_unstable();
+        assert_eq!(all, vec![1, 3]);
+
+        let items = controller.items_by(&SearchBy::ids(&["1", "2", "3"]))?;
+        assert_eq!(controller.users_who_rated_all(&items)?.len(), 1);
+        assert!(controller.users_who_rated_all(&[])?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn ratings_with_timestamps() -> Result<(), Error> {
+        let controller = InMemoryController::from_ratings(&[(1, 1, 5.), (2, 1, 3.), (2, 2, 4.)])?;
+        controller.set_rating_timestamp(1, 1, 1_000)?;
+        assert!(controller.set_rating_timestamp(1, 2, 1_000).is_err());
+
+        let item = controller.items_by(&SearchBy::id("1"))?.remove(0);
+        let ratings = controller.ratings_with_time_by(&item)?;
+
+        assert_eq!(ratings.len(), 2);
+        assert_eq!(ratings[&1], (5., 1_000));
+        assert_eq!(ratings[&2].0, 3.);
+        assert!(ratings[&2].1 > 1_000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn insert_rating_out_of_range() -> Result<(), Error> {
+        let controller = InMemoryController::from_ratings(&[(1, 1, 5.)])?;
+        controller.add_item(2);
+
+        let error = controller.insert_rating(&1, &2, 6.).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ErrorKind>(),
+            Some(ErrorKind::ScoreOutOfRange(..))
+        ));
+
+        assert!(controller.update_rating(&1, &1, 0.5).is_err());
+        assert_eq!(controller.all_users_ratings()?[&1][&1], 5.);
+
+        Ok(())
+    }
+
+    #[test]
+    fn insert_ratings_by_batch() -> Result<(), Error> {
+        let controller = InMemoryController::new();
+        controller.add_user(1);
+        controller.add_user(2);
+        controller.add_item(1);
+        controller.add_item(2);
+
+        let inserted = controller.insert_ratings(&[(1, 1, 5.), (1, 2, 3.), (2, 1, 4.)])?;
+        assert_eq!(inserted, 3);
+
+        let user = controller.users_by(&SearchBy::id("1"))?.remove(0);
+        assert_eq!(controller.user_ratings(&user)?.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn insert_ratings_stops_on_duplicate() -> Result<(), Error> {
+        let controller = InMemoryController::from_ratings(&[(1, 1, 5.)])?;
+        controller.add_item(2);
+
+        assert!(controller
+            .insert_ratings(&[(1, 2, 3.), (1, 1, 2.)])
+            .is_err());
+        assert_eq!(controller.all_users_ratings()?[&1].len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn mean_updates_after_new_rating() -> Result<(), Error> {
+        let controller = InMemoryController::from_ratings(&[(1, 1, 5.), (1, 2, 3.)])?;
+        controller.add_item(3);
+
+        let users = controller.users_by(&SearchBy::id("1"))?;
+        assert_eq!(controller.users_means(&users)?[&1], 4.);
+
+        controller.insert_rating(&1, &3, 1.)?;
+        assert_eq!(controller.recompute_means(&users)?, 1);
+        assert_eq!(controller.users_means(&users)?[&1], 3.);
+
+        Ok(())
+    }
+
+    #[test]
+    fn shuffled_user_chunks_are_reproducible() -> Result<(), Error> {
+        let ratings: Vec<_> = (1..=50).map(|id| (id, 1, 3.)).collect();
+        let controller = InMemoryController::from_ratings(&ratings)?;
+
+        let chunks = |seed| -> Vec<Vec<_>> {
+            controller
+                .users_by_chunks_shuffled(8, seed)
+                .map(|users| users.iter().map(|user| user.get_id()).collect())
+                .collect()
+        };
+
+        assert_eq!(chunks(42), chunks(42));
+        assert_ne!(chunks(42), chunks(7));
+
+        // Every user shows up exactly once
+        let mut ids: Vec<_> = chunks(42).into_iter().flatten().collect();
+        assert_eq!(chunks(42).len(), 7);
+        ids.sort();
+        assert_eq!(ids, (1..=50).collect::<Vec<_>>());
+
+        // The natural order is left untouched
+        let natural: Vec<_> = controller
+            .users_by_chunks(8)
+            .flatten()
+            .map(|user| user.get_id())
+            .collect();
+        assert_eq!(natural, (1..=50).collect::<Vec<_>>());
+
+        Ok(())
+    }
+
+    #[test]
+    fn sample_users_is_reproducible() -> Result<(), Error> {
+        let ratings: Vec<_> = (1..=100).map(|id| (id, 1, 3.)).collect();
+        let controller = InMemoryController::from_ratings(&ratings)?;
+
+        let ids = |seed| -> Result<Vec<_>, Error> {
+            Ok(controller
+                .sample_users(10, seed)?
+                .iter()
+                .map(|user| user.get_id())
+                .collect())
+        };
+
+        assert_eq!(ids(42)?.len(), 10);
+        assert_eq!(ids(42)?, ids(42)?);
+        assert_ne!(ids(42)?, ids(7)?);
+
+        let sample = controller.sample_ratings(10, 42)?;
+        assert_eq!(sample.len(), 10);
+        assert!(ids(42)?.iter().all(|id| sample.contains_key(id)));
+
+        // Asking for more users than available returns all of them
+        assert_eq!(controller.sample_users(1000, 42)?.len(), 100);
+
+        Ok(())
+    }
+
+    #[test]
+    fn async_adapter_reads() -> Result<(), Error> {
+        use controller::AsyncController;
+
+        let controller = InMemoryController::from_ratings(&[(1, 1, 5.), (2, 1, 3.), (2, 2, 4.)])?;
+        let controller = AsyncController::new(controller);
+
+        let mut runtime = tokio::runtime::Builder::new().basic_scheduler().build()?;
+
+        runtime.block_on(async {
+            let user = controller.users_by(SearchBy::id("2")).await?.remove(0);
+            let ratings = controller.user_ratings(user).await?;
+            assert_eq!(ratings.len(), 2);
+
+            let items = controller.items_by(SearchBy::id("1")).await?;
+            let users_who_rated = controller.users_who_rated(items).await?;
+            assert_eq!(users_who_rated[&1].len(), 2);
+
+            let users = controller.with_controller(|c| c.users()).await?;
+            assert_eq!(users.len(), 2);
+
+            Ok(())
+        })
+    }
+}
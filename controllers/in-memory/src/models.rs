@@ -0,0 +1,8 @@
+// Copyright (c) 2020 White Leaf
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+pub mod items;
+pub mod ratings;
+pub mod users;
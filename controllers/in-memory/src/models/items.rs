@@ -0,0 +1,28 @@
+// Copyright (c) 2020 White Leaf
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use common_macros::hash_map;
+use controller::Entity;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Default)]
+pub struct Item {
+    pub id: i32,
+    pub name: String,
+}
+
+impl Entity for Item {
+    type Id = i32;
+
+    fn get_id(&self) -> Self::Id {
+        self.id
+    }
+
+    fn get_data(&self) -> HashMap<String, String> {
+        hash_map! {
+            "name".into() => self.name.clone(),
+        }
+    }
+}
@@ -0,0 +1,35 @@
+// Copyright (c) 2020 White Leaf
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use common_macros::hash_map;
+use controller::Entity;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone)]
+pub struct Rating {
+    pub id: i32,
+    pub user_id: i32,
+    pub item_id: i32,
+    pub score: f64,
+    /// When the rating was given, as a unix timestamp in seconds
+    pub timestamp: i64,
+}
+
+impl Entity for Rating {
+    type Id = i32;
+
+    fn get_id(&self) -> Self::Id {
+        self.id
+    }
+
+    fn get_data(&self) -> HashMap<String, String> {
+        hash_map! {
+            "user_id".into() => self.user_id.to_string(),
+            "item_id".into() => self.item_id.to_string(),
+            "score".into() => self.score.to_string(),
+            "timestamp".into() => self.timestamp.to_string(),
+        }
+    }
+}
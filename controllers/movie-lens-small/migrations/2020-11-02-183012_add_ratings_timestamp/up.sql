@@ -0,0 +1,5 @@
+-- Your SQL goes here
+
+-- Ratings inserted without a timestamp are given at insertion time
+ALTER TABLE ratings
+    ADD COLUMN timestamp BIGINT NOT NULL DEFAULT extract(epoch FROM now())::BIGINT
\ No newline at end of file
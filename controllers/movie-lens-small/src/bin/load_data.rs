@@ -69,6 +69,7 @@ fn insert_ratings(conn: &PgConnection, config: &Config) -> Result<(), Error> {
             let user_id: i32 = record[0].parse()?;
             let movie_id: i32 = record[1].parse()?;
             let score: f64 = record[2].parse()?;
+            let timestamp: i64 = record[3].parse()?;
 
             match controller.items_by(&SearchBy::id(&movie_id.to_string())) {
                 Ok(movies) if movies.is_empty() => continue,
@@ -80,6 +81,7 @@ fn insert_ratings(conn: &PgConnection, config: &Config) -> Result<(), Error> {
                 score,
                 user_id,
                 movie_id,
+                timestamp: Some(timestamp),
             });
         }
     }
@@ -19,6 +19,8 @@ pub struct Rating {
     pub user_id: i32,
     pub movie_id: i32,
     pub score: f64,
+    /// When the rating was given, as a unix timestamp in seconds
+    pub timestamp: i64,
 }
 
 impl Entity for Rating {
@@ -33,6 +35,7 @@ impl Entity for Rating {
             "user_id".into() => self.user_id.to_string(),
             "movie_id".into() => self.movie_id.to_string(),
             "score".into() => self.score.to_string(),
+            "timestamp".into() => self.timestamp.to_string(),
         }
     }
 }
@@ -44,4 +47,13 @@ pub struct NewRating {
     pub user_id: i32,
     pub movie_id: i32,
     pub score: f64,
+    /// `None` uses the insertion time
+    pub timestamp: Option<i64>,
+}
+
+// To read the users of raw queries over the ratings
+#[derive(Debug, Clone, QueryableByName)]
+pub struct RatingUser {
+    #[sql_type = "diesel::sql_types::Integer"]
+    pub user_id: i32,
 }
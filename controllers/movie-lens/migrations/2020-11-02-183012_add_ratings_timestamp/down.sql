@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE ratings DROP COLUMN timestamp;
\ No newline at end of file
@@ -77,11 +77,13 @@ fn insert_ratings(conn: &PgConnection) -> Result<(), Error> {
             let user_id: i32 = record[0].parse()?;
             let movie_id: i32 = record[1].parse()?;
             let score: f64 = record[2].parse()?;
+            let timestamp: i64 = record[3].parse()?;
 
             ratings.push(NewRating {
                 score,
                 user_id,
                 movie_id,
+                timestamp: Some(timestamp),
             });
         }
 
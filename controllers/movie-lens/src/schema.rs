@@ -20,6 +20,7 @@ table! {
         user_id -> Int4,
         movie_id -> Int4,
         score -> Float8,
+        timestamp -> Int8,
     }
 }
 
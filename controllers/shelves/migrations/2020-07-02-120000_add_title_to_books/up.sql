@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE books ADD COLUMN title TEXT;
\ No newline at end of file
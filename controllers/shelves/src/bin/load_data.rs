@@ -58,7 +58,14 @@ fn insert_books(conn: &PgConnection) -> Result<(), Error> {
                 e
             })?;
 
-            books.push(NewBook { id });
+            // Titles are optional, the map may only have the ids
+            let title = record
+                .get(2)
+                .map(str::trim)
+                .filter(|title| !title.is_empty())
+                .map(String::from);
+
+            books.push(NewBook { id, title });
         }
     }
 
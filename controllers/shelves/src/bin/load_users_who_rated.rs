@@ -4,10 +4,12 @@
 // https://opensource.org/licenses/MIT
 
 use anyhow::Error;
+use config::Shards;
+use controller::duplicates::{DedupRatings, DuplicatePolicy};
 use indicatif::ProgressIterator;
 use mongodb::bson::{doc, to_bson, Bson};
 use mongodb::sync::Client;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::BufReader;
 
@@ -19,7 +21,41 @@ fn main() -> Result<(), Error> {
 
     let client = Client::with_uri_str(mongo_url)?;
     let db = client.database(mongo_db);
-    let collection = db.collection("users_who_rated");
+
+    // Items are split over `USERS_WHO_RATED_SHARDS` collections, it must match the
+    // `users_who_rated_shards` of the database config
+    let shards = Shards {
+        count: vars
+            .get("USERS_WHO_RATED_SHARDS")
+            .map(|count| count.parse())
+            .transpose()?
+            .unwrap_or(1),
+        ..Default::default()
+    };
+    let collection_for = |item_id: i32| {
+        let key = u64::from(item_id.unsigned_abs());
+        db.collection(&shards.collection_name(key))
+    };
+
+    // Repeated (user, item) pairs are resolved with `DUPLICATE_POLICY`, one of
+    // `keep_first`, `keep_last` (the default) or `average`
+    let policy: DuplicatePolicy = vars
+        .get("DUPLICATE_POLICY")
+        .map(|policy| policy.parse())
+        .transpose()?
+        .unwrap_or_default();
+
+    let insert_item = |item_id: i32, ratings: &mut DedupRatings<i32>| -> Result<(), Error> {
+        let scores: HashMap<_, _> = ratings
+            .drain()
+            .into_iter()
+            .map(|(user_id, score)| (user_id.to_string(), Bson::Double(score)))
+            .collect();
+
+        let data = to_bson(&scores)?;
+        collection_for(item_id).insert_one(doc! { "item_id": item_id, "scores": data}, None)?;
+        Ok(())
+    };
 
     let file = File::open("data/ratings.csv")?;
     let reader = BufReader::new(file);
@@ -29,7 +65,9 @@ fn main() -> Result<(), Error> {
         .from_reader(reader);
 
     let mut current_item = None;
-    let mut current_ratings = HashMap::new();
+    let mut current_ratings = DedupRatings::new(policy);
+    let mut loaded_items = HashSet::new();
+    let mut conflicts = 0;
 
     for record in csv.records().progress() {
         if let Ok(record) = record {
@@ -43,27 +81,44 @@ fn main() -> Result<(), Error> {
 
             if let Some(current_item) = &mut current_item {
                 if *current_item != book_id {
-                    let data = to_bson(&current_ratings)?;
-                    collection
-                        .insert_one(doc! { "item_id": *current_item, "scores": data}, None)?;
+                    conflicts += current_ratings.conflicts();
+                    insert_item(*current_item, &mut current_ratings)?;
+                    loaded_items.insert(*current_item);
 
                     *current_item = book_id;
-                    current_ratings.clear();
+                    current_ratings = DedupRatings::new(policy);
                 }
             } else {
                 current_item = Some(book_id);
             }
 
-            current_ratings.insert(user_id.to_string(), Bson::Double(score));
+            // The ratings of an item are written at once, they must be contiguous
+            if loaded_items.contains(&book_id) {
+                anyhow::bail!(
+                    "ratings of book {} aren't contiguous, sort the CSV by book",
+                    book_id
+                );
+            }
+
+            if let Some((kept, new)) = current_ratings.insert(user_id, score) {
+                println!(
+                    "User {} rated book {} with {} and {}, using {:?}",
+                    user_id, book_id, kept, new, policy
+                );
+            }
         }
     }
 
     if let Some(current_item) = current_item {
         if !current_ratings.is_empty() {
-            let data = to_bson(&current_ratings)?;
-            collection.insert_one(doc! { "item_id": current_item, "scores": data}, None)?;
+            conflicts += current_ratings.conflicts();
+            insert_item(current_item, &mut current_ratings)?;
         }
     }
 
+    if conflicts > 0 {
+        println!("Found {} conflicting duplicate ratings", conflicts);
+    }
+
     Ok(())
 }
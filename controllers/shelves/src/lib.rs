@@ -10,25 +10,30 @@ pub mod models;
 pub mod schema;
 
 use crate::models::{
-    books::Book,
-    ratings::Rating,
-    users::{Mean, User},
+    books::{Book, NewUnseenBook},
+    ratings::{Rating, RatingUser},
+    users::{Mean, NewMean, User},
 };
-use crate::schema::{books, ratings, users};
+use crate::schema::{books, means, ratings, users};
 use anyhow::Error;
-use config::Config;
-use controller::{eid, error::ErrorKind, maped_ratings, means, ratings, Controller, SearchBy};
+use config::{Config, Shards};
+use controller::mongo::RatingsBatch;
+use controller::searchby::{like_pattern, sort_by_ids};
+use controller::{
+    eid, error::ErrorKind, maped_ratings, means, ratings, Controller, Field, SearchBy, Type,
+};
+use diesel::pg::upsert::excluded;
 use diesel::pg::PgConnection;
 use diesel::{delete, insert_into, prelude::*, update};
 use models::ratings::NewRating;
 use mongodb::bson::doc;
 use mongodb::{
     options::{FindOptions, UpdateOptions},
-    sync::{Client, Database},
+    sync::{Client, Collection, Database},
 };
 
 use num_traits::Zero;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 pub fn establish_connection(url: &str) -> Result<PgConnection, Error> {
     Ok(PgConnection::establish(&url)?)
@@ -36,6 +41,7 @@ pub fn establish_connection(url: &str) -> Result<PgConnection, Error> {
 
 pub struct ShelvesController {
     users_who_rated_mongo: bool,
+    users_who_rated_shards: Shards,
     pg_conn: PgConnection,
     mongo_db: Database,
 }
@@ -54,6 +60,7 @@ impl ShelvesController {
             .ok_or_else(|| ErrorKind::DbConfigError(name.into()))?;
 
         let users_who_rated_mongo = db.users_who_rated_mongo;
+        let users_who_rated_shards = db.users_who_rated_shards.clone();
         let psql_url = &db.psql_url;
         let mongo_url = &db.mongo_url;
         let mongo_db = &db.mongo_db;
@@ -64,10 +71,18 @@ impl ShelvesController {
 
         Ok(Self {
             users_who_rated_mongo,
+            users_who_rated_shards,
             pg_conn,
             mongo_db,
         })
     }
+
+    /// Collection of `users_who_rated` that holds the ratings of `item_id`
+    fn collection_for(&self, item_id: &i32) -> Collection {
+        let key = u64::from(item_id.unsigned_abs());
+        let name = self.users_who_rated_shards.collection_name(key);
+        self.mongo_db.collection(&name)
+    }
 }
 
 impl Controller for ShelvesController {
@@ -84,7 +99,10 @@ impl Controller for ShelvesController {
         match by {
             SearchBy::Id(id) => {
                 let id: i32 = id.parse()?;
-                let users = users::table.filter(users::id.eq(id)).load(&self.pg_conn)?;
+                let users = users::table
+                    .filter(users::id.eq(id))
+                    .order(users::id)
+                    .load(&self.pg_conn)?;
 
                 if users.is_empty() {
                     Err(ErrorKind::NotFoundById(id.to_string()).into())
@@ -93,7 +111,25 @@ impl Controller for ShelvesController {
                 }
             }
 
-            SearchBy::Name(name) => Err(ErrorKind::NotFoundByName(name.clone()).into()),
+            SearchBy::Ids(ids) => {
+                let parsed_ids = ids
+                    .iter()
+                    .map(|id| id.parse())
+                    .collect::<Result<Vec<i32>, _>>()?;
+                let users = users::table
+                    .filter(users::id.eq_any(&parsed_ids))
+                    .load(&self.pg_conn)?;
+
+                if users.is_empty() {
+                    Err(ErrorKind::NotFoundById(ids.join(", ")).into())
+                } else {
+                    Ok(sort_by_ids(users, &parsed_ids))
+                }
+            }
+
+            SearchBy::Name(name) | SearchBy::NameLike(name) => {
+                Err(ErrorKind::NotFoundByName(name.clone()).into())
+            }
             SearchBy::Custom(k, v) => Err(ErrorKind::NotFoundByCustom(k.clone(), v.clone()).into()),
         }
     }
@@ -107,6 +143,37 @@ impl Controller for ShelvesController {
         Ok(users)
     }
 
+    fn users_offset_limit_shuffled(
+        &self,
+        offset: usize,
+        limit: usize,
+        seed: u64,
+    ) -> Result<Vec<Self::User>, Error> {
+        // Same seeded hash as `sample_users`, ties are broken by id
+        let order = format!("md5(id::text || '{}')", seed);
+        let users = users::table
+            .order((
+                diesel::dsl::sql::<diesel::sql_types::Text>(&order),
+                users::id,
+            ))
+            .offset(offset as i64)
+            .limit(limit as i64)
+            .load::<User>(&self.pg_conn)?;
+
+        Ok(users)
+    }
+
+    fn sample_users(&self, n: usize, seed: u64) -> Result<Vec<Self::User>, Error> {
+        // Hashing the ids with the seed gives a stable pseudo-random order
+        let order = format!("md5(id::text || '{}')", seed);
+        let users = users::table
+            .order(diesel::dsl::sql::<diesel::sql_types::Text>(&order))
+            .limit(n as i64)
+            .load::<User>(&self.pg_conn)?;
+
+        Ok(users)
+    }
+
     fn items(&self) -> Result<Vec<Self::Item>, Error> {
         let items = books::table.load::<Book>(&self.pg_conn)?;
         Ok(items)
@@ -116,7 +183,10 @@ impl Controller for ShelvesController {
         match by {
             SearchBy::Id(id) => {
                 let id: i32 = id.parse()?;
-                let books = books::table.filter(books::id.eq(id)).load(&self.pg_conn)?;
+                let books = books::table
+                    .filter(books::id.eq(id))
+                    .order(books::id)
+                    .load(&self.pg_conn)?;
 
                 if books.is_empty() {
                     Err(ErrorKind::NotFoundById(id.to_string()).into())
@@ -125,7 +195,48 @@ impl Controller for ShelvesController {
                 }
             }
 
-            SearchBy::Name(name) => Err(ErrorKind::NotFoundByName(name.clone()).into()),
+            SearchBy::Ids(ids) => {
+                let parsed_ids = ids
+                    .iter()
+                    .map(|id| id.parse())
+                    .collect::<Result<Vec<i32>, _>>()?;
+                let books = books::table
+                    .filter(books::id.eq_any(&parsed_ids))
+                    .load(&self.pg_conn)?;
+
+                if books.is_empty() {
+                    Err(ErrorKind::NotFoundById(ids.join(", ")).into())
+                } else {
+                    Ok(sort_by_ids(books, &parsed_ids))
+                }
+            }
+
+            SearchBy::Name(name) => {
+                let books = books::table
+                    .filter(books::title.eq(name))
+                    .order(books::id)
+                    .load(&self.pg_conn)?;
+
+                if books.is_empty() {
+                    Err(ErrorKind::NotFoundByName(name.clone()).into())
+                } else {
+                    Ok(books)
+                }
+            }
+
+            SearchBy::NameLike(name) => {
+                let books = books::table
+                    .filter(books::title.ilike(like_pattern(name)))
+                    .order(books::id)
+                    .load(&self.pg_conn)?;
+
+                if books.is_empty() {
+                    Err(ErrorKind::NotFoundByName(name.clone()).into())
+                } else {
+                    Ok(books)
+                }
+            }
+
             SearchBy::Custom(k, v) => Err(ErrorKind::NotFoundByCustom(k.clone(), v.clone()).into()),
         }
     }
@@ -155,7 +266,12 @@ impl Controller for ShelvesController {
     ) -> Result<Vec<Self::Item>, Error> {
         item_ids
             .iter()
-            .map(|id| -> Result<Book, Error> { Ok(Book { id: *id }) })
+            .map(|id| -> Result<Book, Error> {
+                Ok(Book {
+                    id: *id,
+                    title: None,
+                })
+            })
             .collect()
     }
 
@@ -177,29 +293,37 @@ impl Controller for ShelvesController {
 
             Ok(items_users)
         } else {
-            let collection = self.mongo_db.collection("users_who_rated");
-            let ids: Vec<_> = items.iter().map(|m| m.id).collect();
-            let options = FindOptions::builder().show_record_id(false).build();
-
-            let cursor = collection.find(
-                doc! {
-                    "item_id": { "$in": ids }
-                },
-                options,
-            )?;
+            // Items may be spread over several shards, each one is queried once
+            let mut shards: HashMap<_, Vec<_>> = HashMap::new();
+            for item in items {
+                let name = self.collection_for(&item.id).name().to_string();
+                shards.entry(name).or_default().push(item.id);
+            }
 
             let mut items_users = HashMap::new();
-            for doc in cursor {
-                let doc = doc?;
-                let item_id = doc.get_i32("item_id")?;
-
-                for (user_id, score) in doc.get_document("scores")? {
-                    let user_id: i32 = user_id.parse()?;
-                    let score = score.as_f64().ok_or_else(|| ErrorKind::BsonConvert)?;
-                    items_users
-                        .entry(item_id)
-                        .or_insert_with(HashMap::new)
-                        .insert(user_id, score);
+            for (name, ids) in shards {
+                let collection = self.mongo_db.collection(&name);
+                let options = FindOptions::builder().show_record_id(false).build();
+
+                let cursor = collection.find(
+                    doc! {
+                        "item_id": { "$in": ids }
+                    },
+                    options,
+                )?;
+
+                for doc in cursor {
+                    let doc = doc?;
+                    let item_id = doc.get_i32("item_id")?;
+
+                    for (user_id, score) in doc.get_document("scores")? {
+                        let user_id: i32 = user_id.parse()?;
+                        let score = score.as_f64().ok_or_else(|| ErrorKind::BsonConvert)?;
+                        items_users
+                            .entry(item_id)
+                            .or_insert_with(HashMap::new)
+                            .insert(user_id, score);
+                    }
                 }
             }
 
@@ -217,6 +341,66 @@ impl Controller for ShelvesController {
         Ok(ratings)
     }
 
+    fn ratings_by_offset_limit(
+        &self,
+        user: &Self::User,
+        offset: usize,
+        limit: usize,
+    ) -> Result<ratings!(Self::Item), Error> {
+        let ratings = Rating::belonging_to(user)
+            .order(ratings::book_id)
+            .offset(offset as i64)
+            .limit(limit as i64)
+            .load::<Rating>(&self.pg_conn)?
+            .into_iter()
+            .map(|rating| (rating.book_id, rating.score))
+            .collect();
+
+        Ok(ratings)
+    }
+
+    fn users_who_rated_all(&self, items: &[Self::Item]) -> Result<HashSet<i32>, Error> {
+        let mut ids: Vec<i32> = items.iter().map(|item| item.id).collect();
+        ids.sort_unstable();
+        ids.dedup();
+
+        if ids.is_empty() {
+            return Ok(HashSet::new());
+        }
+
+        let n_items = ids.len() as i64;
+        let users = diesel::sql_query(
+            "SELECT user_id FROM ratings WHERE book_id = ANY($1) \
+             GROUP BY user_id HAVING count(DISTINCT book_id) = $2",
+        )
+        .bind::<diesel::sql_types::Array<diesel::sql_types::Integer>, _>(ids)
+        .bind::<diesel::sql_types::BigInt, _>(n_items)
+        .load::<RatingUser>(&self.pg_conn)?
+        .into_iter()
+        .map(|row| row.user_id)
+        .collect();
+
+        Ok(users)
+    }
+
+    fn users_who_rated_paged(
+        &self,
+        item: &Self::Item,
+        offset: usize,
+        limit: usize,
+    ) -> Result<ratings!(Self::User), Error> {
+        let ratings = Rating::belonging_to(item)
+            .order(ratings::user_id)
+            .offset(offset as i64)
+            .limit(limit as i64)
+            .load::<Rating>(&self.pg_conn)?
+            .into_iter()
+            .map(|rating| (rating.user_id, rating.score))
+            .collect();
+
+        Ok(ratings)
+    }
+
     #[allow(clippy::type_complexity)]
     fn all_users_ratings(&self) -> Result<maped_ratings!(Self::User => Self::Item), Error> {
         let ratings = ratings::table.load::<Rating>(&self.pg_conn)?;
@@ -281,6 +465,53 @@ impl Controller for ShelvesController {
         Ok(means_by_user)
     }
 
+    fn recompute_means(&self, users: &[Self::User]) -> Result<usize, Error> {
+        let new_means: Vec<_> = self
+            .users_ratings(users)?
+            .into_iter()
+            .filter(|(_, ratings)| !ratings.is_empty())
+            .map(|(user_id, ratings)| NewMean {
+                user_id,
+                val: ratings.values().sum::<f64>() / ratings.len() as f64,
+                score_number: ratings.len() as i32,
+            })
+            .collect();
+
+        if new_means.is_empty() {
+            return Ok(0);
+        }
+
+        let updated = insert_into(means::table)
+            .values(&new_means)
+            .on_conflict(means::user_id)
+            .do_update()
+            .set((
+                means::val.eq(excluded(means::val)),
+                means::score_number.eq(excluded(means::score_number)),
+            ))
+            .execute(&self.pg_conn)?;
+
+        Ok(updated)
+    }
+
+    fn global_mean(&self) -> Result<Option<f64>, Error> {
+        let mean = ratings::table
+            .select(diesel::dsl::avg(ratings::score))
+            .first::<Option<f64>>(&self.pg_conn)?;
+
+        Ok(mean)
+    }
+
+    fn users_count(&self) -> Result<usize, Error> {
+        let count: i64 = users::table.count().get_result(&self.pg_conn)?;
+        Ok(count as usize)
+    }
+
+    fn ratings_count(&self) -> Result<usize, Error> {
+        let count: i64 = ratings::table.count().get_result(&self.pg_conn)?;
+        Ok(count as usize)
+    }
+
     fn score_range(&self) -> (f64, f64) {
         (0., 5.)
     }
@@ -289,8 +520,8 @@ impl Controller for ShelvesController {
         vec![]
     }
 
-    fn fields_for_items(&self) -> Vec<controller::Field> {
-        vec![]
+    fn fields_for_items(&self) -> Vec<Field> {
+        vec![Field::Optional("title", Type::String)]
     }
 
     fn insert_user<'a>(
@@ -304,10 +535,14 @@ impl Controller for ShelvesController {
 
     fn insert_item<'a>(
         &self,
-        _: HashMap<&'a str, controller::Value>,
+        proto: HashMap<&'a str, controller::Value>,
     ) -> controller::Result<Self::Item> {
+        let book = NewUnseenBook {
+            title: proto.get("title").map(|v| v.as_string()).transpose()?,
+        };
+
         Ok(insert_into(books::table)
-            .default_values()
+            .values(&book)
             .get_result(&self.pg_conn)?)
     }
 
@@ -317,7 +552,9 @@ impl Controller for ShelvesController {
         item_id: &eid!(Self::Item),
         score: f64,
     ) -> Result<Self::Rating, Error> {
-        let users_who_rated = self.mongo_db.collection("users_who_rated");
+        self.validate_score(score)?;
+
+        let users_who_rated = self.collection_for(item_id);
 
         let query = doc! {
             "item_id": item_id,
@@ -353,7 +590,10 @@ impl Controller for ShelvesController {
             .get_result(&self.pg_conn);
 
         match psql_result {
-            Ok(rating) => Ok(rating),
+            Ok(rating) => {
+                self.recompute_means(&self.create_partial_users(&[*user_id])?)?;
+                Ok(rating)
+            }
             Err(e) => {
                 let delete_doc = doc! {
                     "$unset": doc!{
@@ -367,12 +607,60 @@ impl Controller for ShelvesController {
         }
     }
 
+    fn insert_ratings(
+        &self,
+        ratings: &[(eid!(Self::User), eid!(Self::Item), f64)],
+    ) -> Result<usize, Error> {
+        for (_, _, score) in ratings {
+            self.validate_score(*score)?;
+        }
+
+        if ratings.is_empty() {
+            return Ok(0);
+        }
+
+        let batch = RatingsBatch::new(ratings);
+        batch.check_unrated(|item_id| self.collection_for(item_id))?;
+
+        let new_ratings: Vec<_> = ratings
+            .iter()
+            .map(|(user_id, item_id, score)| NewRating {
+                user_id: *user_id,
+                book_id: *item_id,
+                score: *score,
+            })
+            .collect();
+
+        let insert = || {
+            self.pg_conn.transaction::<_, Error, _>(|| {
+                let mut inserted = 0;
+                for chunk in new_ratings.chunks(10_000) {
+                    inserted += insert_into(ratings::table)
+                        .values(chunk)
+                        .execute(&self.pg_conn)?;
+                }
+
+                Ok(inserted)
+            })
+        };
+
+        let inserted = batch.write(
+            &self.mongo_db,
+            |item_id| self.collection_for(item_id),
+            None,
+            insert,
+        )?;
+
+        self.recompute_means(&self.create_partial_users(&batch.user_ids())?)?;
+        Ok(inserted)
+    }
+
     fn remove_rating(
         &self,
         user_id: &eid!(Self::User),
         item_id: &eid!(Self::Item),
     ) -> Result<Self::Rating, Error> {
-        let users_who_rated = self.mongo_db.collection("users_who_rated");
+        let users_who_rated = self.collection_for(item_id);
 
         let delete_doc = doc! {
             "$unset": doc!{
@@ -421,7 +709,9 @@ impl Controller for ShelvesController {
         item_id: &eid!(Self::Item),
         score: f64,
     ) -> Result<Self::Rating, Error> {
-        let users_who_rated = self.mongo_db.collection("users_who_rated");
+        self.validate_score(score)?;
+
+        let users_who_rated = self.collection_for(item_id);
 
         let update_doc = doc! {
             "$set": doc!{
@@ -449,7 +739,10 @@ impl Controller for ShelvesController {
             .get_result::<Rating>(&self.pg_conn);
 
         match psql_res {
-            Ok(rating) => Ok(rating),
+            Ok(rating) => {
+                self.recompute_means(&self.create_partial_users(&[*user_id])?)?;
+                Ok(rating)
+            }
             Err(e) => {
                 let update_doc = doc! {
                     "$set": doc! {
@@ -5,11 +5,13 @@
 
 use crate::schema::books;
 use controller::Entity;
+use std::collections::HashMap;
 
 // To query data from the database
 #[derive(Debug, Clone, Identifiable, Queryable, Default)]
 pub struct Book {
     pub id: i32,
+    pub title: Option<String>,
 }
 
 // To insert a new movie into the database
@@ -17,6 +19,13 @@ pub struct Book {
 #[table_name = "books"]
 pub struct NewBook {
     pub id: i32,
+    pub title: Option<String>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[table_name = "books"]
+pub struct NewUnseenBook<'a> {
+    pub title: Option<&'a str>,
 }
 
 impl Entity for Book {
@@ -25,4 +34,11 @@ impl Entity for Book {
     fn get_id(&self) -> Self::Id {
         self.id
     }
+
+    fn get_data(&self) -> HashMap<String, String> {
+        self.title
+            .iter()
+            .map(|title| ("title".into(), title.clone()))
+            .collect()
+    }
 }
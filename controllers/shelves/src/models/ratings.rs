@@ -45,3 +45,10 @@ pub struct NewRating {
     pub book_id: i32,
     pub score: f64,
 }
+
+// To read the users of raw queries over the ratings
+#[derive(Debug, Clone, QueryableByName)]
+pub struct RatingUser {
+    #[sql_type = "diesel::sql_types::Integer"]
+    pub user_id: i32,
+}
@@ -1,6 +1,7 @@
 table! {
     books (id) {
         id -> Int4,
+        title -> Nullable<Text>,
     }
 }
 
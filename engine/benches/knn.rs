@@ -0,0 +1,71 @@
+// Copyright (c) 2020 White Leaf
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use config::Config;
+use controller::{Controller, SearchBy};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use engine::{distances::users::Method, Engine};
+use in_memory::InMemoryController;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+// Users rate a random subset of the items, those in the first half of the users
+// prefer the first half of the items and the rest the other half
+fn generate_controller(users: i32, items: i32) -> InMemoryController {
+    let mut rng = StdRng::seed_from_u64(42);
+
+    let mut ratings = Vec::new();
+    for user_id in 1..=users {
+        for item_id in 1..=items {
+            if rng.gen_bool(0.7) {
+                continue;
+            }
+
+            let likes = (item_id <= items / 2) == (user_id <= users / 2);
+            let score: f64 = if likes {
+                rng.gen_range(3.5, 5.0)
+            } else {
+                rng.gen_range(1.0, 2.5)
+            };
+
+            ratings.push((user_id, item_id, score));
+        }
+    }
+
+    InMemoryController::from_ratings(&ratings).unwrap()
+}
+
+fn user_knn_2000(c: &mut Criterion) {
+    let controller = generate_controller(2000, 200);
+    let config = Config::default();
+    let engine = Engine::with_controller(&controller, &config);
+    let user = controller.users_by(&SearchBy::id("1")).unwrap().remove(0);
+
+    c.bench_function("exact user knn 2000", |bench| {
+        bench.iter(|| {
+            engine.user_knn(
+                black_box(10),
+                user.clone(),
+                Method::CosineSimilarity,
+                None,
+                false,
+            )
+        })
+    });
+
+    // The index is built once, only the queries are measured
+    c.bench_function("approx user knn 2000", |bench| {
+        bench.iter(|| {
+            engine.user_knn_approx(black_box(10), user.clone(), Method::CosineSimilarity, 32, 4)
+        })
+    });
+}
+
+criterion_group! {
+    name = knn_2000;
+    config = Criterion::default().sample_size(20);
+    targets = user_knn_2000
+}
+
+criterion_main!(knn_2000);
@@ -0,0 +1,154 @@
+// Copyright (c) 2020 White Leaf
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use config::Config;
+use controller::{Controller, SearchBy};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use engine::{
+    cancellation::CancellationToken,
+    distances::{items::Method as ItemMethod, users::Method as UserMethod},
+    Engine,
+};
+use in_memory::InMemoryController;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+// (users, items) of the generated datasets
+const SIZES: &[(i32, i32)] = &[(250, 50), (1000, 100), (2000, 200)];
+
+// Users rate a random subset of the items, those in the first half of the users
+// prefer the first half of the items and the rest the other half. The seed is
+// fixed so every run benchmarks the same ratings
+fn generate_controller(users: i32, items: i32) -> InMemoryController {
+    let mut rng = StdRng::seed_from_u64(42);
+
+    let mut ratings = Vec::new();
+    for user_id in 1..=users {
+        for item_id in 1..=items {
+            if rng.gen_bool(0.7) {
+                continue;
+            }
+
+            let likes = (item_id <= items / 2) == (user_id <= users / 2);
+            let score: f64 = if likes {
+                rng.gen_range(3.5, 5.0)
+            } else {
+                rng.gen_range(1.0, 2.5)
+            };
+
+            ratings.push((user_id, item_id, score));
+        }
+    }
+
+    InMemoryController::from_ratings(&ratings).unwrap()
+}
+
+// First item that the user with id 1 didn't rate, the one to be predicted
+fn unrated_item(controller: &InMemoryController) -> String {
+    let user = controller.users_by(&SearchBy::id("1")).unwrap().remove(0);
+    let ratings = controller.user_ratings(&user).unwrap();
+
+    (1..)
+        .find(|id| !ratings.contains_key(id))
+        .unwrap()
+        .to_string()
+}
+
+fn user_knn(c: &mut Criterion) {
+    let config = Config::default();
+    let mut group = c.benchmark_group("user knn");
+
+    for &(users, items) in SIZES {
+        let controller = generate_controller(users, items);
+        let engine = Engine::with_controller(&controller, &config);
+        let user = controller.users_by(&SearchBy::id("1")).unwrap().remove(0);
+
+        group.bench_function(BenchmarkId::from_parameter(users), |bench| {
+            bench.iter(|| {
+                engine.user_knn(
+                    black_box(10),
+                    user.clone(),
+                    UserMethod::PearsonCorrelation,
+                    Some(100),
+                    false,
+                )
+            })
+        });
+    }
+
+    group.finish();
+}
+
+fn user_based_predict(c: &mut Criterion) {
+    let config = Config::default();
+    let mut group = c.benchmark_group("user based predict");
+
+    for &(users, items) in SIZES {
+        let controller = generate_controller(users, items);
+        let engine = Engine::with_controller(&controller, &config);
+        let user = controller.users_by(&SearchBy::id("1")).unwrap().remove(0);
+        let item_id = unrated_item(&controller);
+        let item = controller
+            .items_by(&SearchBy::id(&item_id))
+            .unwrap()
+            .remove(0);
+
+        group.bench_function(BenchmarkId::from_parameter(users), |bench| {
+            bench.iter(|| {
+                engine.user_based_predict(
+                    black_box(10),
+                    user.clone(),
+                    item.clone(),
+                    UserMethod::PearsonCorrelation,
+                    Some(100),
+                    1,
+                    None,
+                )
+            })
+        });
+    }
+
+    group.finish();
+}
+
+fn item_based_predict(c: &mut Criterion) {
+    let config = Config::default();
+    let mut group = c.benchmark_group("item based predict");
+
+    for &(users, items) in SIZES {
+        let controller = generate_controller(users, items);
+        let engine = Engine::with_controller(&controller, &config);
+        let user = controller.users_by(&SearchBy::id("1")).unwrap().remove(0);
+        let item_id = unrated_item(&controller);
+        let item = controller
+            .items_by(&SearchBy::id(&item_id))
+            .unwrap()
+            .remove(0);
+        let cancel = CancellationToken::new();
+
+        group.bench_function(BenchmarkId::from_parameter(users), |bench| {
+            bench.iter(|| {
+                engine.item_based_predict(
+                    user.clone(),
+                    item.clone(),
+                    ItemMethod::AdjCosine,
+                    black_box(50),
+                    false,
+                    None,
+                    &cancel,
+                )
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group! {
+    name = predict;
+    config = Criterion::default().sample_size(20);
+    targets = user_knn, user_based_predict, item_based_predict
+}
+
+criterion_main!(predict);
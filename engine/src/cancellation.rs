@@ -0,0 +1,63 @@
+// Copyright (c) 2020 White Leaf
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use crate::error::ErrorKind;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// Shared flag used to stop long running operations between chunks,
+/// clones of a token observe the same flag
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Asks every operation holding this token to stop
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Clears the flag so the token can be reused by the next operation
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+
+    /// Fails with `ErrorKind::Cancelled` if the token was cancelled
+    pub fn check(&self) -> Result<(), ErrorKind> {
+        if self.is_cancelled() {
+            Err(ErrorKind::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clones_share_the_flag() {
+        let token = CancellationToken::new();
+        let other = token.clone();
+        assert!(token.check().is_ok());
+
+        other.cancel();
+        assert!(token.is_cancelled());
+        assert!(matches!(token.check(), Err(ErrorKind::Cancelled)));
+
+        token.reset();
+        assert!(!other.is_cancelled());
+    }
+}
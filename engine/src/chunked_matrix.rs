@@ -4,31 +4,97 @@
 // https://opensource.org/licenses/MIT
 
 use crate::{
-    distances::items::{slope_one, AdjCosine},
+    cancellation::CancellationToken,
+    distances::{
+        items::{slope_one, AdjCosine},
+        users::{distance, Method as UserMethod},
+    },
     error::ErrorKind,
 };
 use anyhow::Error;
 use config::Config;
-use controller::{eid, maped_ratings, Controller, Entity, LazyItemChunks};
+use controller::{
+    eid, maped_ratings, Controller, Entity, LazyItemChunks, LazyUserChunks, MapedRatings,
+};
+use num_traits::float::Float;
 use std::{
     cell::RefCell,
     collections::{HashMap, HashSet},
+    fmt::Display,
+    fs::File,
     hash::Hash,
+    io::{BufWriter, Write},
+    ops::{AddAssign, Mul, MulAssign, Sub},
+    path::Path,
     rc::Rc,
 };
 
-pub trait ChunkedMatrix<'a, C, I>
+/// Cast the scores of `maped_ratings` into the value type stored by a matrix
+fn cast_maped_ratings<K, I, Value>(
+    maped_ratings: MapedRatings<K, I>,
+) -> Result<MapedRatings<K, I, Value>, ErrorKind>
 where
-    C: Controller<Item = I>,
-    I: Entity,
+    K: Hash + Eq,
+    I: Hash + Eq,
+    Value: Float,
+{
+    maped_ratings
+        .into_iter()
+        .map(|(id, ratings)| {
+            let ratings = ratings
+                .into_iter()
+                .map(|(id, score)| Value::from(score).map(|score| (id, score)))
+                .collect::<Option<_>>()
+                .ok_or(ErrorKind::ConvertType)?;
+
+            Ok((id, ratings))
+        })
+        .collect()
+}
+
+/// A matrix of entities (items or users) computed by chunks, `Value` is the type of
+/// the stored values so huge chunks can be kept in `f32` to halve their memory
+pub trait ChunkedMatrix<'a, C, E, Value = f64>
+where
+    C: Controller,
+    E: Entity,
 {
     fn approximate_chunk_size(&self) -> usize;
     fn optimize_chunks_size(&mut self);
-    fn calculate_chunk(&mut self, i: usize, j: usize) -> Result<(), Error>;
-    fn get_value(&self, id_a: &eid!(I), id_b: &eid!(I)) -> Option<f64>;
+    fn calculate_chunk(&mut self, i: usize, j: usize) -> Result<(), Error> {
+        self.calculate_chunk_with_progress(i, j, |_, _| {})
+    }
+
+    /// Same as `calculate_chunk` but calls `progress` with the number of processed
+    /// entities and the total of entities every time a row of the chunk is completed
+    fn calculate_chunk_with_progress<F>(
+        &mut self,
+        i: usize,
+        j: usize,
+        progress: F,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(usize, usize),
+    {
+        self.calculate_chunk_cancellable(i, j, progress, &CancellationToken::new())
+    }
+
+    /// Same as `calculate_chunk_with_progress` but `cancel` is checked before every
+    /// row of the chunk, the chunk is left uncomputed with `ErrorKind::Cancelled`
+    fn calculate_chunk_cancellable<F>(
+        &mut self,
+        i: usize,
+        j: usize,
+        progress: F,
+        cancel: &CancellationToken,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(usize, usize);
+
+    fn get_value(&self, id_a: &eid!(E), id_b: &eid!(E)) -> Option<Value>;
 }
 
-pub struct SimilarityMatrix<'a, C, U, I>
+pub struct SimilarityMatrix<'a, C, U, I, Value = f64>
 where
     C: Controller<User = U, Item = I>,
     U: Entity,
@@ -41,20 +107,21 @@ where
     ver_chunk_size: usize,
     hor_chunk_size: usize,
 
-    adj_cosine: Rc<RefCell<AdjCosine<eid!(U), f64>>>,
+    adj_cosine: Rc<RefCell<AdjCosine<eid!(U), Value>>>,
 
     ver_iter: LazyItemChunks<'a, C, I>,
     hor_iter: LazyItemChunks<'a, C, I>,
 
-    matrix_chunk: HashMap<eid!(I), HashMap<eid!(I), f64>>,
+    matrix_chunk: HashMap<eid!(I), HashMap<eid!(I), Value>>,
 }
 
-impl<'a, C, U, I> SimilarityMatrix<'a, C, U, I>
+impl<'a, C, U, I, Value> SimilarityMatrix<'a, C, U, I, Value>
 where
     C: Controller<User = U, Item = I>,
     U: Entity,
     I: Entity,
     eid!(U): Hash + Eq + Default,
+    Value: Default,
 {
     pub fn new(controller: &'a C, config: &'a Config, m: usize, n: usize) -> Self {
         Self {
@@ -72,7 +139,7 @@ where
     pub fn with_cache(
         controller: &'a C,
         config: &'a Config,
-        adj_cosine: Rc<RefCell<AdjCosine<eid!(U), f64>>>,
+        adj_cosine: Rc<RefCell<AdjCosine<eid!(U), Value>>>,
         m: usize,
         n: usize,
     ) -> Self {
@@ -89,13 +156,55 @@ where
     }
 }
 
-impl<'a, C, U, I> ChunkedMatrix<'a, C, I> for SimilarityMatrix<'a, C, U, I>
+impl<'a, C, U, I, Value> SimilarityMatrix<'a, C, U, I, Value>
+where
+    C: Controller<User = U, Item = I>,
+    U: Entity,
+    I: Entity,
+    eid!(U): Hash + Eq,
+    eid!(I): Eq,
+    Value: Copy,
+{
+    /// Every pair computed in the current chunk as `(item_a, item_b, similarity)`,
+    /// pairs of an item with itself are skipped. A pair is stored in only one of
+    /// its orders, `get_value` looks up both
+    pub fn entries(&self) -> impl Iterator<Item = (&eid!(I), &eid!(I), Value)> + '_ {
+        self.matrix_chunk.iter().flat_map(|(item_a, row)| {
+            row.iter()
+                .filter(move |(item_b, _)| *item_b != item_a)
+                .map(move |(item_b, value)| (item_a, item_b, *value))
+        })
+    }
+
+    /// Write the `entries` of the current chunk to `path` as CSV with an
+    /// `item_a,item_b,similarity` header. Returns the number of written pairs
+    pub fn export_chunk<P: AsRef<Path>>(&self, path: P) -> Result<usize, Error>
+    where
+        eid!(I): Display,
+        Value: Display,
+    {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "item_a,item_b,similarity")?;
+
+        let mut exported = 0;
+        for (item_a, item_b, similarity) in self.entries() {
+            writeln!(writer, "{},{},{}", item_a, item_b, similarity)?;
+            exported += 1;
+        }
+
+        writer.flush()?;
+        Ok(exported)
+    }
+}
+
+impl<'a, C, U, I, Value> ChunkedMatrix<'a, C, I, Value> for SimilarityMatrix<'a, C, U, I, Value>
 where
     C: Controller<User = U, Item = I>,
     U: Entity,
     I: Entity,
     eid!(U): Hash + Eq + Clone + Default,
     eid!(I): Hash + Eq + Clone,
+    Value: Float + AddAssign,
 {
     fn approximate_chunk_size(&self) -> usize {
         todo!("Implement for each controller a 'counter' method for ratings")
@@ -119,7 +228,16 @@ where
         }
     }
 
-    fn calculate_chunk(&mut self, i: usize, j: usize) -> Result<(), Error> {
+    fn calculate_chunk_cancellable<F>(
+        &mut self,
+        i: usize,
+        j: usize,
+        mut progress: F,
+        cancel: &CancellationToken,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(usize, usize),
+    {
         let ver_items = self
             .ver_iter
             .nth(i)
@@ -144,6 +262,9 @@ where
             .filter(|(_, ratings)| !ratings.is_empty())
             .collect();
 
+        let ver_items_users: MapedRatings<_, _, Value> = cast_maped_ratings(ver_items_users)?;
+        let hor_items_users: MapedRatings<_, _, Value> = cast_maped_ratings(hor_items_users)?;
+
         let all_users_iter = ver_items_users.values().chain(hor_items_users.values());
         let mut all_users = HashSet::new();
 
@@ -165,22 +286,51 @@ where
 
         let partial_users_chunk_size = self.config.matrix.partial_users_chunk_size;
         for partial_users_chunk in all_partial_users.chunks(partial_users_chunk_size) {
-            let mean_chunk = self.controller.users_means(partial_users_chunk)?;
+            let mean_chunk = self
+                .controller
+                .users_means(partial_users_chunk)?
+                .into_iter()
+                .map(|(id, mean)| Value::from(mean).map(|mean| (id, mean)))
+                .collect::<Option<HashMap<_, _>>>()
+                .ok_or(ErrorKind::ConvertType)?;
+
             self.adj_cosine.borrow_mut().push_means(&mean_chunk);
         }
 
+        let min_similarity = match self.config.matrix.min_similarity {
+            Some(min) => Some(Value::from(min).ok_or(ErrorKind::ConvertType)?),
+            None => None,
+        };
+
+        let total = ver_items_users.len();
+        progress(0, total);
+
         let mut matrix = HashMap::new();
-        for (item_a, item_a_ratings) in ver_items_users.into_iter() {
+        for (processed, (item_a, item_a_ratings)) in ver_items_users.into_iter().enumerate() {
+            cancel.check()?;
             for (item_b, item_b_ratings) in hor_items_users.iter() {
-                if matrix.contains_key(item_b) {
+                // Skip only when the reversed pair was already computed, items may
+                // appear in both chunks and the pair must not be dropped otherwise
+                let is_computed = matrix
+                    .get(item_b)
+                    .and_then(|row: &HashMap<_, _>| row.get(&item_a))
+                    .is_some();
+
+                if item_b == &item_a || is_computed {
                     continue;
                 }
 
-                if let Ok(similarity) = self
+                let similarity = self
                     .adj_cosine
                     .borrow_mut()
-                    .calculate(&item_a_ratings, item_b_ratings)
-                {
+                    .calculate(&item_a_ratings, item_b_ratings);
+
+                // Pairs that aren't similar enough are left out to keep the chunk sparse
+                if let Ok(similarity) = similarity {
+                    if min_similarity.is_some_and(|min| similarity < min) {
+                        continue;
+                    }
+
                     matrix
                         .entry(item_a.clone())
                         .or_insert_with(HashMap::new)
@@ -191,7 +341,9 @@ where
             matrix
                 .entry(item_a.clone())
                 .or_insert_with(HashMap::new)
-                .insert(item_a, 1.0);
+                .insert(item_a, Value::one());
+
+            progress(processed + 1, total);
         }
 
         self.matrix_chunk = matrix;
@@ -199,7 +351,7 @@ where
         Ok(())
     }
 
-    fn get_value(&self, id_a: &eid!(I), id_b: &eid!(I)) -> Option<f64> {
+    fn get_value(&self, id_a: &eid!(I), id_b: &eid!(I)) -> Option<Value> {
         if let Some(row_a) = self.matrix_chunk.get(id_a) {
             let maybe_val = row_a.get(id_b);
             if let Some(val) = maybe_val {
@@ -218,7 +370,10 @@ where
     }
 }
 
-pub struct DeviationMatrix<'a, C, I>
+// The value is a tuple of (deviation, cardinality)
+type DeviationChunk<ItemId, Value> = HashMap<ItemId, HashMap<ItemId, (Value, usize)>>;
+
+pub struct DeviationMatrix<'a, C, I, Value = f64>
 where
     C: Controller<Item = I>,
     I: Entity,
@@ -232,10 +387,10 @@ where
     ver_iter: LazyItemChunks<'a, C, I>,
     hor_iter: LazyItemChunks<'a, C, I>,
 
-    matrix_chunk: HashMap<eid!(I), HashMap<eid!(I), f64>>,
+    matrix_chunk: DeviationChunk<eid!(I), Value>,
 }
 
-impl<'a, C, I> DeviationMatrix<'a, C, I>
+impl<'a, C, I, Value> DeviationMatrix<'a, C, I, Value>
 where
     C: Controller<Item = I>,
     I: Entity,
@@ -251,15 +406,38 @@ where
             matrix_chunk: Default::default(),
         }
     }
+
+    /// Deviation of `id_a` from `id_b` along with the number of users that rated
+    /// both items, `None` if the pair isn't in the current chunk
+    pub fn get_deviation_and_card(&self, id_a: &eid!(I), id_b: &eid!(I)) -> Option<(Value, usize)>
+    where
+        eid!(I): Hash + Eq,
+        Value: Float,
+    {
+        if let Some(row_a) = self.matrix_chunk.get(id_a) {
+            if let Some(val) = row_a.get(id_b) {
+                return Some(*val);
+            }
+        }
+
+        if let Some(row_b) = self.matrix_chunk.get(id_b) {
+            if let Some((dev, card)) = row_b.get(id_a) {
+                return Some((-*dev, *card));
+            }
+        }
+
+        None
+    }
 }
 
-impl<'a, C, U, I> ChunkedMatrix<'a, C, I> for DeviationMatrix<'a, C, I>
+impl<'a, C, U, I, Value> ChunkedMatrix<'a, C, I, Value> for DeviationMatrix<'a, C, I, Value>
 where
     C: Controller<User = U, Item = I>,
     U: Entity,
     I: Entity,
     eid!(U): Hash + Eq,
     eid!(I): Hash + Eq + Clone,
+    Value: Float + AddAssign,
 {
     fn approximate_chunk_size(&self) -> usize {
         todo!("Implement for each controller a 'counter' method for ratings")
@@ -283,7 +461,16 @@ where
         }
     }
 
-    fn calculate_chunk(&mut self, i: usize, j: usize) -> Result<(), Error> {
+    fn calculate_chunk_cancellable<F>(
+        &mut self,
+        i: usize,
+        j: usize,
+        mut progress: F,
+        cancel: &CancellationToken,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(usize, usize),
+    {
         let ver_items = self
             .ver_iter
             .nth(i)
@@ -308,25 +495,51 @@ where
             .filter(|(_, ratings)| !ratings.is_empty())
             .collect();
 
+        let ver_items_users: MapedRatings<_, _, Value> = cast_maped_ratings(ver_items_users)?;
+        let hor_items_users: MapedRatings<_, _, Value> = cast_maped_ratings(hor_items_users)?;
+
+        let total = ver_items_users.len();
+        progress(0, total);
+
         let mut matrix = HashMap::new();
-        for (item_a, item_a_ratings) in ver_items_users.into_iter() {
+        for (processed, (item_a, item_a_ratings)) in ver_items_users.into_iter().enumerate() {
+            cancel.check()?;
             for (item_b, item_b_ratings) in hor_items_users.iter() {
-                if matrix.contains_key(item_b) {
+                // Skip only when the reversed pair was already computed, items may
+                // appear in both chunks and the pair must not be dropped otherwise
+                let is_computed = matrix
+                    .get(item_b)
+                    .and_then(|row: &HashMap<_, _>| row.get(&item_a))
+                    .is_some();
+
+                if item_b == &item_a || is_computed {
                     continue;
                 }
 
-                if let Ok((dev, _)) = slope_one(&item_a_ratings, item_b_ratings) {
+                if let Ok(dev_and_card) = slope_one(&item_a_ratings, item_b_ratings) {
                     matrix
                         .entry(item_a.clone())
                         .or_insert_with(HashMap::new)
-                        .insert(item_b.clone(), dev);
+                        .insert(item_b.clone(), dev_and_card);
                 }
             }
 
             matrix
                 .entry(item_a.clone())
                 .or_insert_with(HashMap::new)
-                .insert(item_a, 0.0);
+                .insert(item_a, (Value::zero(), item_a_ratings.len()));
+
+            progress(processed + 1, total);
+        }
+
+        // The deviation of an item with itself is always zero, no matter in
+        // which of the chunks it was found
+        for (item_b, item_b_ratings) in hor_items_users {
+            matrix
+                .entry(item_b.clone())
+                .or_insert_with(HashMap::new)
+                .entry(item_b)
+                .or_insert((Value::zero(), item_b_ratings.len()));
         }
 
         self.matrix_chunk = matrix;
@@ -334,21 +547,490 @@ where
         Ok(())
     }
 
-    fn get_value(&self, id_a: &eid!(I), id_b: &eid!(I)) -> Option<f64> {
-        if let Some(row_a) = self.matrix_chunk.get(id_a) {
-            let maybe_val = row_a.get(id_b);
-            if let Some(val) = maybe_val {
-                return Some(*val);
+    fn get_value(&self, id_a: &eid!(I), id_b: &eid!(I)) -> Option<Value> {
+        self.get_deviation_and_card(id_a, id_b).map(|(dev, _)| dev)
+    }
+}
+
+/// A matrix of users computed by chunks, every pair of users in a chunk is compared
+/// with a `UserMethod`, useful to precompute user neighborhoods
+pub struct UserSimilarityMatrix<'a, C, U, I, Value = f64>
+where
+    C: Controller<User = U, Item = I>,
+    U: Entity,
+    I: Entity,
+{
+    config: &'a Config,
+    controller: &'a C,
+    method: UserMethod,
+
+    ver_chunk_size: usize,
+    hor_chunk_size: usize,
+
+    ver_iter: LazyUserChunks<'a, C, U>,
+    hor_iter: LazyUserChunks<'a, C, U>,
+
+    matrix_chunk: HashMap<eid!(U), HashMap<eid!(U), Value>>,
+}
+
+impl<'a, C, U, I, Value> UserSimilarityMatrix<'a, C, U, I, Value>
+where
+    C: Controller<User = U, Item = I>,
+    U: Entity,
+    I: Entity,
+{
+    pub fn new(
+        controller: &'a C,
+        config: &'a Config,
+        method: UserMethod,
+        m: usize,
+        n: usize,
+    ) -> Self {
+        Self {
+            config,
+            controller,
+            method,
+            ver_chunk_size: m,
+            hor_chunk_size: n,
+            ver_iter: controller.users_by_chunks(m),
+            hor_iter: controller.users_by_chunks(n),
+            matrix_chunk: Default::default(),
+        }
+    }
+
+    pub fn method(&self) -> UserMethod {
+        self.method
+    }
+
+    // Average number of ratings per user, `None` if the controller can't count
+    // its users or ratings
+    fn ratings_per_user(&self) -> Option<usize> {
+        let users = self.controller.users_count().ok()?;
+        let ratings = self.controller.ratings_count().ok()?;
+
+        Some((ratings as f64 / users.max(1) as f64).ceil() as usize)
+    }
+}
+
+impl<'a, C, U, I, Value> ChunkedMatrix<'a, C, U, Value> for UserSimilarityMatrix<'a, C, U, I, Value>
+where
+    C: Controller<User = U, Item = I>,
+    U: Entity,
+    I: Entity,
+    eid!(U): Hash + Eq + Clone,
+    eid!(I): Hash + Eq,
+    Value: Float + AddAssign + Sub + Mul + MulAssign,
+{
+    /// Ratings loaded for both chunks plus the values computed between them, the
+    /// ratings of every user are approximated by the average of the controller
+    fn approximate_chunk_size(&self) -> usize {
+        let ratings_per_user = self.ratings_per_user().unwrap_or(1);
+        let users = self.ver_chunk_size + self.hor_chunk_size;
+
+        users * ratings_per_user + self.ver_chunk_size * self.hor_chunk_size
+    }
+
+    fn optimize_chunks_size(&mut self) {
+        // Keep the configured sizes if the ratings per user can't be estimated
+        if !self.config.matrix.allow_chunk_optimization || self.ratings_per_user().is_none() {
+            return;
+        }
+
+        let threshold = self.config.matrix.chunk_size_threshold;
+        let original_size = self.approximate_chunk_size();
+        let target_size = (original_size as f64 * threshold) as usize;
+
+        while self.approximate_chunk_size() > target_size
+            && self.ver_chunk_size > 1
+            && self.hor_chunk_size > 1
+        {
+            self.ver_chunk_size /= 2;
+            self.hor_chunk_size /= 2;
+
+            self.ver_iter = self.controller.users_by_chunks(self.ver_chunk_size);
+            self.hor_iter = self.controller.users_by_chunks(self.hor_chunk_size);
+        }
+    }
+
+    fn calculate_chunk_cancellable<F>(
+        &mut self,
+        i: usize,
+        j: usize,
+        mut progress: F,
+        cancel: &CancellationToken,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(usize, usize),
+    {
+        let ver_users = self.ver_iter.nth(i).ok_or(ErrorKind::IndexOutOfBound)?;
+
+        let hor_users = self.hor_iter.nth(j).ok_or(ErrorKind::IndexOutOfBound)?;
+
+        let ver_users_ratings: maped_ratings!(U => I) =
+            self.controller.users_ratings(&ver_users)?;
+        let hor_users_ratings: maped_ratings!(U => I) =
+            self.controller.users_ratings(&hor_users)?;
+
+        let ver_users_ratings: MapedRatings<_, _, Value> = cast_maped_ratings(ver_users_ratings)?;
+        let hor_users_ratings: MapedRatings<_, _, Value> = cast_maped_ratings(hor_users_ratings)?;
+
+        let total = ver_users_ratings.len();
+        progress(0, total);
+
+        let mut matrix = HashMap::new();
+        for (processed, (user_a, user_a_ratings)) in ver_users_ratings.into_iter().enumerate() {
+            cancel.check()?;
+            for (user_b, user_b_ratings) in hor_users_ratings.iter() {
+                // Users may appear in both chunks, the value is symmetric so the
+                // reversed pair is enough
+                let is_computed = matrix
+                    .get(user_b)
+                    .and_then(|row: &HashMap<_, _>| row.get(&user_a))
+                    .is_some();
+
+                if is_computed {
+                    continue;
+                }
+
+                if let Ok(value) = distance(&user_a_ratings, user_b_ratings, self.method) {
+                    matrix
+                        .entry(user_a.clone())
+                        .or_insert_with(HashMap::new)
+                        .insert(user_b.clone(), value);
+                }
             }
+
+            progress(processed + 1, total);
         }
 
-        if let Some(row_b) = self.matrix_chunk.get(id_b) {
-            let maybe_val = row_b.get(id_a);
-            if let Some(val) = maybe_val {
-                return Some(-val);
+        self.matrix_chunk = matrix;
+
+        Ok(())
+    }
+
+    fn get_value(&self, id_a: &eid!(U), id_b: &eid!(U)) -> Option<Value> {
+        if let Some(val) = self.matrix_chunk.get(id_a).and_then(|row| row.get(id_b)) {
+            return Some(*val);
+        }
+
+        self.matrix_chunk
+            .get(id_b)
+            .and_then(|row| row.get(id_a))
+            .copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+    use in_memory::InMemoryController;
+
+    fn controller() -> InMemoryController {
+        InMemoryController::from_ratings(&[
+            (1, 1, 5.0),
+            (1, 2, 3.0),
+            (1, 3, 2.0),
+            (1, 4, 4.0),
+            (2, 1, 3.0),
+            (2, 2, 4.0),
+            (2, 3, 1.0),
+            (2, 4, 2.0),
+            (3, 1, 4.0),
+            (3, 2, 2.0),
+            (3, 3, 5.0),
+            (3, 4, 3.0),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn deviation_matrix_intersecting_chunks() {
+        let controller = controller();
+        let config = Config::default();
+
+        // Vertical chunk has items 1, 2, 3 and horizontal chunk has items 3, 4,
+        // item 3 belongs to both chunks
+        let mut matrix: DeviationMatrix<_, _> = DeviationMatrix::new(&controller, &config, 3, 2);
+        matrix.calculate_chunk(0, 1).unwrap();
+
+        let ratings = controller
+            .users_who_rated(&controller.items().unwrap())
+            .unwrap();
+        for item_a in &[1, 2, 3] {
+            for item_b in &[3, 4] {
+                let value = matrix.get_value(item_a, item_b);
+                let (dev, _) = slope_one(&ratings[item_a], &ratings[item_b]).unwrap();
+
+                assert!(value.is_some(), "missing pair ({}, {})", item_a, item_b);
+                assert_approx_eq!(value.unwrap(), dev);
+                assert_approx_eq!(matrix.get_value(item_b, item_a).unwrap(), -dev);
             }
         }
+    }
 
-        None
+    #[test]
+    fn deviation_matrix_cardinalities() {
+        let controller = InMemoryController::from_ratings(&[
+            (1, 1, 5.0),
+            (1, 2, 3.0),
+            (1, 3, 2.0),
+            (2, 1, 3.0),
+            (2, 3, 1.0),
+            (2, 4, 2.0),
+            (3, 2, 2.0),
+            (3, 3, 5.0),
+            (3, 4, 3.0),
+        ])
+        .unwrap();
+        let config = Config::default();
+
+        let mut matrix: DeviationMatrix<_, _> = DeviationMatrix::new(&controller, &config, 3, 2);
+        matrix.calculate_chunk(0, 1).unwrap();
+
+        let ratings = controller
+            .users_who_rated(&controller.items().unwrap())
+            .unwrap();
+        for item_a in &[1, 2, 3] {
+            for item_b in &[3, 4] {
+                if item_a == item_b {
+                    continue;
+                }
+
+                let (dev, card) = slope_one(&ratings[item_a], &ratings[item_b]).unwrap();
+                let (value, value_card) = matrix.get_deviation_and_card(item_a, item_b).unwrap();
+                assert_approx_eq!(value, dev);
+                assert_eq!(value_card, card);
+
+                let (value, value_card) = matrix.get_deviation_and_card(item_b, item_a).unwrap();
+                assert_approx_eq!(value, -dev);
+                assert_eq!(value_card, card);
+            }
+        }
+
+        // An item with itself has the number of users that rated it
+        assert_eq!(matrix.get_deviation_and_card(&3, &3), Some((0.0, 3)));
+        assert_eq!(matrix.get_deviation_and_card(&4, &4), Some((0.0, 2)));
+    }
+
+    #[test]
+    fn deviation_matrix_self_pairs() {
+        let controller = controller();
+        let config = Config::default();
+
+        let mut matrix: DeviationMatrix<_, _> = DeviationMatrix::new(&controller, &config, 3, 2);
+        matrix.calculate_chunk(0, 1).unwrap();
+
+        for item in &[1, 2, 3, 4] {
+            let value = matrix.get_value(item, item).unwrap();
+            assert!(value == 0.0 && value.is_sign_positive());
+        }
+
+        assert_eq!(matrix.get_value(&5, &5), None);
+    }
+
+    #[test]
+    fn similarity_matrix_intersecting_chunks() {
+        let controller = controller();
+        let config = Config::default();
+
+        let mut full: SimilarityMatrix<_, _, _> = SimilarityMatrix::new(&controller, &config, 4, 4);
+        full.calculate_chunk(0, 0).unwrap();
+
+        // Vertical chunk has items 1, 2, 3 and horizontal chunk has items 3, 4,
+        // item 3 belongs to both chunks
+        let mut matrix: SimilarityMatrix<_, _, _> =
+            SimilarityMatrix::new(&controller, &config, 3, 2);
+        matrix.calculate_chunk(0, 1).unwrap();
+
+        for item_a in &[1, 2, 3] {
+            for item_b in &[3, 4] {
+                let value = matrix.get_value(item_a, item_b);
+                let expected = full.get_value(item_a, item_b).unwrap();
+
+                assert!(value.is_some(), "missing pair ({}, {})", item_a, item_b);
+                assert_approx_eq!(value.unwrap(), expected);
+                assert_approx_eq!(matrix.get_value(item_b, item_a).unwrap(), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn similarity_matrix_in_f32() {
+        let controller = controller();
+        let config = Config::default();
+
+        let mut matrix_f64: SimilarityMatrix<_, _, _> =
+            SimilarityMatrix::new(&controller, &config, 4, 4);
+        let mut matrix_f32: SimilarityMatrix<_, _, _, f32> =
+            SimilarityMatrix::new(&controller, &config, 4, 4);
+
+        matrix_f64.calculate_chunk(0, 0).unwrap();
+        matrix_f32.calculate_chunk(0, 0).unwrap();
+
+        for item_a in &[1, 2, 3, 4] {
+            for item_b in &[1, 2, 3, 4] {
+                let expected = matrix_f64.get_value(item_a, item_b).unwrap();
+                let value = matrix_f32.get_value(item_a, item_b).unwrap();
+
+                assert_approx_eq!(f64::from(value), expected, 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn similarity_matrix_entries() {
+        let controller = controller();
+        let config = Config::default();
+
+        let mut matrix: SimilarityMatrix<_, _, _> =
+            SimilarityMatrix::new(&controller, &config, 4, 4);
+        assert_eq!(matrix.entries().count(), 0);
+        matrix.calculate_chunk(0, 0).unwrap();
+
+        // 4 items, every pair once and no self pairs
+        let entries: Vec<_> = matrix.entries().collect();
+        assert_eq!(entries.len(), 6);
+
+        let mut pairs = HashSet::new();
+        for (item_a, item_b, similarity) in &entries {
+            assert_ne!(item_a, item_b);
+            assert_eq!(matrix.get_value(item_a, item_b), Some(*similarity));
+            assert!(pairs.insert((*item_a.min(item_b), *item_a.max(item_b))));
+        }
+
+        let path = std::env::temp_dir().join("similarity-matrix-export-chunk.csv");
+        assert_eq!(matrix.export_chunk(&path).unwrap(), 6);
+
+        let exported = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut lines = exported.lines();
+        assert_eq!(lines.next(), Some("item_a,item_b,similarity"));
+        assert_eq!(lines.count(), 6);
+    }
+
+    #[test]
+    fn user_similarity_matrix_intersecting_chunks() {
+        let controller = controller();
+        let config = Config::default();
+
+        // Vertical chunk has users 1, 2 and horizontal chunk has user 2, user 2
+        // belongs to both chunks
+        let mut matrix: UserSimilarityMatrix<_, _, _> =
+            UserSimilarityMatrix::new(&controller, &config, UserMethod::CosineSimilarity, 2, 1);
+        matrix.calculate_chunk(0, 1).unwrap();
+
+        let ratings = controller.all_users_ratings().unwrap();
+        for user_a in &[1, 2] {
+            let expected =
+                distance(&ratings[user_a], &ratings[&2], UserMethod::CosineSimilarity).unwrap();
+
+            assert_approx_eq!(matrix.get_value(user_a, &2).unwrap(), expected);
+            assert_approx_eq!(matrix.get_value(&2, user_a).unwrap(), expected);
+        }
+
+        assert_approx_eq!(matrix.get_value(&2, &2).unwrap(), 1.0);
+        assert_eq!(matrix.get_value(&1, &3), None);
+    }
+
+    #[test]
+    fn user_similarity_matrix_optimize_chunks_size() {
+        let controller = controller();
+        let mut config = Config::default();
+        config.matrix.chunk_size_threshold = 0.5;
+
+        // Every user has 4 ratings, so a chunk is approximated by 8 * 4 + 4 * 4
+        let mut matrix: UserSimilarityMatrix<_, _, _> =
+            UserSimilarityMatrix::new(&controller, &config, UserMethod::CosineSimilarity, 4, 4);
+        assert_eq!(matrix.approximate_chunk_size(), 48);
+
+        matrix.optimize_chunks_size();
+        assert_eq!((matrix.ver_chunk_size, matrix.hor_chunk_size), (2, 2));
+        assert_eq!(matrix.approximate_chunk_size(), 20);
+
+        matrix.calculate_chunk(0, 1).unwrap();
+        assert!(matrix.get_value(&1, &3).is_some());
+
+        // Chunks are never shrunk to nothing
+        config.matrix.chunk_size_threshold = 0.0;
+        let mut matrix: UserSimilarityMatrix<_, _, _> =
+            UserSimilarityMatrix::new(&controller, &config, UserMethod::CosineSimilarity, 4, 4);
+        matrix.optimize_chunks_size();
+        assert_eq!((matrix.ver_chunk_size, matrix.hor_chunk_size), (1, 1));
+
+        config.matrix.allow_chunk_optimization = false;
+        let mut matrix: UserSimilarityMatrix<_, _, _> =
+            UserSimilarityMatrix::new(&controller, &config, UserMethod::CosineSimilarity, 4, 4);
+        matrix.optimize_chunks_size();
+        assert_eq!((matrix.ver_chunk_size, matrix.hor_chunk_size), (4, 4));
+    }
+
+    #[test]
+    fn similarity_matrix_min_similarity() {
+        let controller = controller();
+        let config = Config::default();
+
+        let mut sparse_config = Config::default();
+        sparse_config.matrix.min_similarity = Some(0.0);
+
+        let mut full: SimilarityMatrix<_, _, _> = SimilarityMatrix::new(&controller, &config, 4, 4);
+        let mut sparse: SimilarityMatrix<_, _, _> =
+            SimilarityMatrix::new(&controller, &sparse_config, 4, 4);
+
+        full.calculate_chunk(0, 0).unwrap();
+        sparse.calculate_chunk(0, 0).unwrap();
+
+        let (mut below, mut above) = (0, 0);
+        for item_a in &[1, 2, 3, 4] {
+            for item_b in &[1, 2, 3, 4] {
+                let expected = full.get_value(item_a, item_b).unwrap();
+                let value = sparse.get_value(item_a, item_b);
+
+                if expected < 0.0 {
+                    assert_eq!(value, None);
+                    below += 1;
+                } else {
+                    assert_approx_eq!(value.unwrap(), expected);
+                    above += 1;
+                }
+            }
+        }
+
+        assert!(below > 0 && above > 0);
+    }
+
+    #[test]
+    fn calculate_chunk_reports_progress() {
+        let controller = controller();
+        let config = Config::default();
+
+        let mut calls = Vec::new();
+        let mut matrix: DeviationMatrix<_, _> = DeviationMatrix::new(&controller, &config, 3, 2);
+        matrix
+            .calculate_chunk_with_progress(0, 1, |processed, total| calls.push((processed, total)))
+            .unwrap();
+
+        assert_eq!(calls, vec![(0, 3), (1, 3), (2, 3), (3, 3)]);
+    }
+
+    #[test]
+    fn calculate_chunk_cancelled() {
+        let controller = controller();
+        let config = Config::default();
+
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+
+        let mut matrix: DeviationMatrix<_, _> = DeviationMatrix::new(&controller, &config, 3, 2);
+        let err = matrix
+            .calculate_chunk_cancellable(0, 1, |_, _| {}, &cancel)
+            .unwrap_err();
+
+        assert!(matches!(
+            err.downcast_ref::<ErrorKind>(),
+            Some(ErrorKind::Cancelled)
+        ));
+        assert!(matrix.get_value(&1, &3).is_none());
     }
 }
@@ -11,6 +11,8 @@ mod tests {
     use super::users::*;
     use assert_approx_eq::*;
     use common_macros::hash_map;
+    use controller::Ratings;
+    use std::collections::HashMap;
 
     #[test]
     fn invalid_distances_should_be_none() {
@@ -117,4 +119,544 @@ mod tests {
 
         assert!(cosine_similarity(&a, &b).is_err());
     }
+
+    #[test]
+    fn adj_cosine_with_loaded_means() {
+        use super::items::AdjCosine;
+
+        let means = hash_map! {
+            0 => 3.,
+            1 => 2.,
+            2 => 4.,
+        };
+
+        let a = hash_map! {
+            0 => 4.,
+            1 => 1.,
+            2 => 5.,
+        };
+
+        let b = hash_map! {
+            0 => 5.,
+            1 => 3.,
+            2 => 3.,
+        };
+
+        let mut adj_cosine: AdjCosine<_, f64> = AdjCosine::new();
+        assert!(adj_cosine.calculate(&a, &b).is_err());
+
+        adj_cosine.load_all_means(&means);
+        assert!(means.keys().all(|id| adj_cosine.has_mean_for(id)));
+
+        // cov = 2 - 1 - 1 = 0
+        assert_approx_eq!(adj_cosine.calculate(&a, &b).unwrap(), 0.);
+    }
+
+    #[test]
+    fn adj_cosine_incremental_means() {
+        use super::items::AdjCosine;
+
+        let mut batch: HashMap<i32, Ratings<i32, f64>> = hash_map! {
+            0 => hash_map! { 0 => 4., 1 => 1. },
+            1 => hash_map! { 0 => 2. },
+        };
+
+        let mut adj_cosine: AdjCosine<_, f64> = AdjCosine::new();
+        adj_cosine.update_means(&batch);
+
+        for (user_id, item_id, score) in &[(0, 2, 5.), (0, 3, 3.5), (1, 1, 4.)] {
+            batch.get_mut(user_id).unwrap().insert(*item_id, *score);
+            assert!(adj_cosine.add_rating(user_id, *score).is_some());
+        }
+
+        assert!(adj_cosine.add_rating(&2, 3.).is_none());
+
+        let mut recomputed: AdjCosine<_, f64> = AdjCosine::new();
+        recomputed.update_means(&batch);
+
+        for user_id in batch.keys() {
+            assert_approx_eq!(
+                adj_cosine.get_mean_for(user_id).unwrap(),
+                recomputed.get_mean_for(user_id).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn adj_cosine_add_rating_to_loaded_means() {
+        use super::items::AdjCosine;
+
+        let ratings: HashMap<i32, Ratings<i32, f64>> = hash_map! {
+            0 => hash_map! { 0 => 4., 1 => 1. },
+        };
+
+        let mut adj_cosine: AdjCosine<_, f64> = AdjCosine::new();
+        adj_cosine.update_means(&ratings);
+        assert_approx_eq!(adj_cosine.add_rating(&0, 4.).unwrap(), 3.);
+
+        // The number of ratings behind a loaded mean is unknown, folding a new
+        // rating into it would give a wrong mean
+        adj_cosine.load_all_means(&hash_map! { 0 => 2.5, 1 => 2. });
+        assert!(adj_cosine.add_rating(&0, 4.).is_none());
+        assert!(adj_cosine.add_rating(&1, 4.).is_none());
+        assert_approx_eq!(adj_cosine.get_mean_for(&0).unwrap(), 2.5);
+        assert_approx_eq!(adj_cosine.get_mean_for(&1).unwrap(), 2.);
+
+        adj_cosine.push_means(&hash_map! { 2 => 3. });
+        assert!(adj_cosine.add_rating(&2, 4.).is_none());
+
+        // Recomputing the mean from the ratings makes its size known again
+        adj_cosine.update_means(&ratings);
+        assert_approx_eq!(adj_cosine.add_rating(&0, 4.).unwrap(), 3.);
+    }
+
+    #[test]
+    fn adj_cosine_stats() {
+        use super::items::{AdjCosine, AdjCosineStats};
+
+        let a = hash_map! { 0 => 4., 1 => 1., 2 => 5. };
+        let b = hash_map! { 0 => 5., 1 => 3., 2 => 3. };
+
+        let mut adj_cosine: AdjCosine<_, f64> = AdjCosine::new();
+        adj_cosine.push_means(&hash_map! { 0 => 3., 1 => 2. });
+
+        let _ = adj_cosine.calculate(&a, &b);
+        let _ = adj_cosine.calculate(&a, &b);
+
+        let expected = AdjCosineStats {
+            hits: 4,
+            misses: 2,
+            evictions: 0,
+        };
+
+        assert_eq!(adj_cosine.stats(), expected);
+        assert_approx_eq!(adj_cosine.stats().hit_rate(), 4. / 6.);
+
+        adj_cosine.reset_stats();
+        assert_eq!(adj_cosine.stats(), AdjCosineStats::default());
+    }
+
+    #[test]
+    fn jaccard_threshold_shrinks_sets() {
+        let a = hash_map! {
+            0 => 5_f64,
+            1 => 4.,
+            2 => 2.,
+            3 => 1.,
+        };
+
+        let b = hash_map! {
+            0 => 5.,
+            1 => 2.,
+            2 => 5.,
+            3 => 1.,
+        };
+
+        // Without threshold both users rated the same items
+        assert_approx_eq!(jaccard_threshold_index(&a, &b, 0.).unwrap(), 1.);
+        assert_approx_eq!(jaccard_index(&a, &b).unwrap(), 1.);
+
+        // a = {0, 1, 2}, b = {0, 1, 2}
+        assert_approx_eq!(jaccard_threshold_index(&a, &b, 1.).unwrap(), 1.);
+
+        // a = {0, 1}, b = {0, 2}
+        assert_approx_eq!(jaccard_threshold_index(&a, &b, 3.).unwrap(), 1. / 3.);
+
+        // a = {0}, b = {0, 2}
+        assert_approx_eq!(jaccard_threshold_index(&a, &b, 4.).unwrap(), 1. / 2.);
+
+        // Nothing exceeds the threshold
+        assert!(jaccard_threshold_index(&a, &b, 5.).is_err());
+    }
+
+    #[test]
+    fn pearson_or_zero_constant_ratings() {
+        let a = hash_map! {
+            0 => 5_f64,
+            1 => 3.,
+            2 => 4.,
+        };
+
+        let b = hash_map! {
+            0 => 3.,
+            1 => 3.,
+            2 => 3.,
+        };
+
+        assert!(pearson_approximation(&a, &b).is_err());
+        assert_approx_eq!(pearson_or_zero(&a, &b).unwrap(), 0.);
+        assert_approx_eq!(
+            pearson_or_zero(&a, &a).unwrap(),
+            pearson_approximation(&a, &a).unwrap()
+        );
+    }
+
+    #[test]
+    fn normalizers_roundtrip() {
+        use super::items::{Normalization, UserNormalizer};
+
+        let ratings = hash_map! {
+            0 => 5_f64,
+            1 => 3.,
+            2 => 4.,
+        };
+
+        let normalizations = [
+            Normalization::MinMax,
+            Normalization::MeanCentered,
+            Normalization::ZScore,
+            Normalization::None,
+        ];
+
+        for normalization in &normalizations {
+            let normalizer = UserNormalizer::new(&ratings, *normalization, 1., 5.).unwrap();
+            for rating in ratings.values() {
+                let normalized = normalizer.normalize(*rating).unwrap();
+                assert_approx_eq!(normalizer.denormalize(normalized).unwrap(), *rating);
+            }
+        }
+
+        let normalizer =
+            UserNormalizer::new(&ratings, Normalization::MeanCentered, 1., 5.).unwrap();
+        assert_approx_eq!(normalizer.normalize(5.).unwrap(), 1.);
+
+        let normalizer = UserNormalizer::new(&ratings, Normalization::ZScore, 1., 5.).unwrap();
+        assert_approx_eq!(normalizer.normalize(5.).unwrap(), 1.5_f64.sqrt());
+    }
+
+    #[test]
+    fn hamming_distance_test() {
+        let a = hash_map! {
+            0 => 5_f64,
+            1 => 4.,
+            2 => 2.,
+        };
+
+        let b = hash_map! {
+            1 => 2.,
+            2 => 5.,
+            3 => 1.,
+            4 => 1.,
+        };
+
+        // |A| + |B| - 2|A ∩ B| = 3 + 4 - 2 * 2
+        assert_approx_eq!(hamming_distance(&a, &b).unwrap(), 3.);
+        assert_approx_eq!(hamming_distance(&a, &a).unwrap(), 0.);
+        assert_approx_eq!(hamming_distance(&a, &hash_map! {}).unwrap(), 3.);
+
+        let empty: Ratings<usize, f64> = hash_map! {};
+        assert!(hamming_distance(&empty, &empty).is_err());
+    }
+
+    #[test]
+    fn bray_curtis_distance_test() {
+        let a = hash_map! {
+            0 => 5_f64,
+            1 => 4.,
+            2 => 2.,
+        };
+
+        let b = hash_map! {
+            1 => 2.,
+            2 => 5.,
+            3 => 1.,
+        };
+
+        // (|4 - 2| + |2 - 5|) / (4 + 2 + 2 + 5)
+        let dist = bray_curtis_distance(&a, &b).unwrap();
+        assert_approx_eq!(dist, 5. / 13.);
+        assert!((0. ..=1.).contains(&dist));
+        assert_approx_eq!(bray_curtis_distance(&a, &a).unwrap(), 0.);
+
+        // Disjoint supports on the common items are as far as they can be
+        let c = hash_map! { 1 => 0_f64, 2 => 3. };
+        let d = hash_map! { 1 => 6_f64, 2 => 0. };
+        assert_approx_eq!(bray_curtis_distance(&c, &d).unwrap(), 1.);
+
+        let zeros = hash_map! { 1 => 0_f64, 2 => 0. };
+        assert!(matches!(
+            bray_curtis_distance(&zeros, &zeros),
+            Err(crate::error::ErrorKind::DivisionByZero)
+        ));
+        assert!(bray_curtis_distance(&a, &hash_map! { 7 => 1. }).is_err());
+    }
+
+    #[test]
+    fn offline_adjusted_cosine() {
+        use super::items::{adjusted_cosine, precompute_means, AdjCosine};
+
+        let maped_ratings = hash_map! {
+            0 => hash_map! { 'a' => 5_f64, 'b' => 3., 'c' => 4. },
+            1 => hash_map! { 'a' => 3., 'b' => 1., 'c' => 2., 'd' => 3. },
+            2 => hash_map! { 'a' => 4., 'b' => 3., 'c' => 4., 'd' => 3. },
+            3 => hash_map! { 'a' => 3., 'b' => 3., 'c' => 1., 'd' => 5. },
+        };
+
+        let means = precompute_means(&maped_ratings);
+        assert_approx_eq!(means[&0], 4.);
+        assert_approx_eq!(means[&3], 3.);
+
+        // The same similarity must be obtained with the streaming cache
+        let mut adj_cosine = AdjCosine::new();
+        adj_cosine.load_all_means(&means);
+
+        let inverted = |item| -> Ratings<_, _> {
+            maped_ratings
+                .iter()
+                .filter_map(|(user, ratings)| Some((*user, *ratings.get(&item)?)))
+                .collect()
+        };
+
+        for (a, b) in &[('a', 'b'), ('a', 'd'), ('b', 'c'), ('c', 'd')] {
+            let offline = adjusted_cosine(&means, &maped_ratings, a, b).unwrap();
+            let streaming = adj_cosine.calculate(&inverted(*a), &inverted(*b)).unwrap();
+            assert_approx_eq!(offline, streaming);
+        }
+
+        assert!(adjusted_cosine(&means, &maped_ratings, &'a', &'z').is_none());
+    }
+
+    #[test]
+    fn methods_names_round_trip() {
+        use super::items::Method as ItemMethod;
+
+        for method in Method::all() {
+            assert_eq!(Method::from_name(&method.to_string()), Some(*method));
+        }
+
+        for method in ItemMethod::all() {
+            assert_eq!(ItemMethod::from_name(&method.to_string()), Some(*method));
+        }
+
+        assert_eq!(
+            Method::from_name("minkowski(5)"),
+            Some(Method::Minkowski(5))
+        );
+        assert_eq!(
+            Method::from_name("jacc_thresh(4.5)"),
+            Some(Method::JaccardThreshold(4.5))
+        );
+
+        assert_eq!(Method::from_name("minkowski"), None);
+        assert_eq!(Method::from_name("minkowski(0)"), None);
+        assert_eq!(Method::from_name("minkowski(3"), None);
+        assert_eq!(Method::from_name("cosine(3)"), None);
+        assert_eq!(ItemMethod::from_name("cosine"), None);
+    }
+
+    #[test]
+    fn kendall_tau_rankings() {
+        let a: Ratings<_> = hash_map! { 1 => 1.0, 2 => 2.0, 3 => 3.0, 4 => 4.0, 5 => 5.0 };
+        let b: Ratings<_> = hash_map! { 1 => 3.0, 2 => 1.0, 3 => 2.0, 4 => 5.0, 5 => 4.0 };
+
+        // 7 concordant and 3 discordant pairs out of 10
+        assert_approx_eq!(kendall_tau(&a, &b).unwrap(), 0.4);
+        assert_approx_eq!(kendall_tau(&a, &a).unwrap(), 1.0);
+
+        let single: Ratings<_> = hash_map! { 1 => 2.0, 9 => 4.0 };
+        assert!(kendall_tau(&a, &single).is_err());
+        assert_eq!(Method::from_name("kendall"), Some(Method::KendallTau));
+    }
+
+    #[test]
+    fn positive_cosine_clamps_opposites() {
+        // Mean centered ratings of two users with opposite tastes
+        let a: Ratings<_> = hash_map! { 1 => 2.0, 2 => -1.0, 3 => -1.0 };
+        let b: Ratings<_> = hash_map! { 1 => -2.0, 2 => 1.0, 3 => 0.5 };
+
+        let cosine = distance(&a, &b, Method::CosineSimilarity).unwrap();
+        assert!(cosine < 0.0);
+        assert_approx_eq!(distance(&a, &b, Method::CosinePositive).unwrap(), 0.0);
+
+        // Alike users keep their similarity
+        let c: Ratings<_> = hash_map! { 1 => 1.5, 2 => -0.5, 3 => -1.0 };
+        assert_approx_eq!(
+            positive_cosine_similarity(&a, &c).unwrap(),
+            cosine_similarity(&a, &c).unwrap()
+        );
+
+        assert_eq!(
+            Method::from_name("cosine_pos"),
+            Some(Method::CosinePositive)
+        );
+    }
+
+    #[test]
+    fn signed_jaccard_sentiment() {
+        let a: Ratings<_> = hash_map! { 1 => 5.0, 2 => 4.0, 3 => 1.0, 4 => 2.0, 5 => 5.0 };
+
+        // Co-rates every item but likes what `a` dislikes and vice versa
+        let b: Ratings<_> = hash_map! { 1 => 1.0, 2 => 2.0, 3 => 5.0, 4 => 4.0, 5 => 1.0 };
+        assert_approx_eq!(jaccard_index(&a, &b).unwrap(), 1.0);
+        assert_approx_eq!(signed_jaccard_index(&a, &b).unwrap(), 0.0);
+
+        // A harsher user agreeing on everything but item 4
+        let c: Ratings<_> = hash_map! { 1 => 3.0, 2 => 3.0, 3 => 1.0, 4 => 3.0, 5 => 3.0 };
+        assert_approx_eq!(signed_jaccard_index(&a, &c).unwrap(), 0.8);
+
+        let disjoint: Ratings<_> = hash_map! { 9 => 1.0 };
+        assert!(signed_jaccard_index(&a, &disjoint).is_err());
+        assert_eq!(
+            Method::from_name("signed_jacc"),
+            Some(Method::SignedJaccard)
+        );
+    }
+
+    #[test]
+    fn rbf_kernel_similarity() {
+        use crate::error::ErrorKind;
+
+        let a: Ratings<_> = hash_map! { 1 => 5.0, 2 => 3.0, 3 => 4.0 };
+        let b: Ratings<_> = hash_map! { 1 => 3.0, 2 => 3.0, 3 => 3.0, 4 => 2.0 };
+
+        // d² = 4 + 0 + 1 = 5
+        assert_approx_eq!(rbf_similarity(&a, &b, 1.0).unwrap(), (-2.5f64).exp());
+        assert_approx_eq!(rbf_similarity(&a, &a, 0.1).unwrap(), 1.0);
+
+        // A huge sigma makes every pair look alike, a tiny one tells them apart
+        assert_approx_eq!(rbf_similarity(&a, &b, 1e6).unwrap(), 1.0);
+        assert!(rbf_similarity(&a, &b, 0.5).unwrap() < 1e-4);
+
+        let disjoint: Ratings<_> = hash_map! { 9 => 1.0 };
+        assert!(matches!(
+            rbf_similarity(&a, &disjoint, 1.0),
+            Err(ErrorKind::NoMatchingRatings)
+        ));
+
+        assert_eq!(Method::from_name("rbf(1.5)"), Some(Method::Rbf(1.5)));
+        assert_eq!(Method::from_name("rbf(0)"), None);
+        assert_eq!(Method::Rbf(1.5).to_string(), "rbf(1.5)");
+    }
+
+    #[test]
+    fn bipolar_slope_one_partitions() {
+        use super::items::{bipolar_slope_one, BiPolarDeviation};
+        use crate::error::ErrorKind;
+
+        let means = hash_map! { 1 => 3.0, 2 => 3.0, 3 => 3.0 };
+        let item_a: Ratings<_> = hash_map! { 1 => 5.0, 2 => 1.0, 3 => 4.0 };
+        let item_b: Ratings<_> = hash_map! { 1 => 4.0, 2 => 2.0, 3 => 2.0 };
+
+        let dev = bipolar_slope_one(&item_a, &item_b, &means).unwrap();
+        assert_eq!(
+            dev,
+            BiPolarDeviation {
+                liked: Some((1.0, 1)),
+                disliked: Some((-1.0, 1)),
+            }
+        );
+        assert_eq!(dev.combined().unwrap(), (0.0, 2));
+
+        let item_b: Ratings<_> = hash_map! { 1 => 4.0, 3 => 2.0 };
+        let dev = bipolar_slope_one(&item_a, &item_b, &means).unwrap();
+        assert_eq!(dev.liked, Some((1.0, 1)));
+        assert_eq!(dev.disliked, None);
+
+        let item_b: Ratings<_> = hash_map! { 3 => 2.0 };
+        assert!(matches!(
+            bipolar_slope_one(&item_a, &item_b, &means),
+            Err(ErrorKind::DivisionByZero)
+        ));
+    }
+
+    #[test]
+    fn weighted_similarities() {
+        let a: Ratings<_> = hash_map! { 1 => 5.0, 2 => 2.0, 3 => 4.0, 4 => 1.0 };
+        let b: Ratings<_> = hash_map! { 1 => 1.0, 2 => 3.0, 3 => 4.0, 4 => 2.0 };
+
+        // Uniform weights are just the plain versions
+        let weights = hash_map! { 1 => 1.0, 2 => 1.0, 3 => 1.0 };
+        assert_approx_eq!(
+            weighted_cosine_similarity(&a, &b, &weights).unwrap(),
+            cosine_similarity(&a, &b).unwrap()
+        );
+        assert_approx_eq!(
+            weighted_pearson_correlation(&a, &b, &weights).unwrap(),
+            pearson_correlation(&a, &b).unwrap()
+        );
+
+        let counts = hash_map! { 1 => 4, 2 => 2 };
+        let weights: HashMap<_, f64> = inverse_user_frequency(&counts, 4).unwrap();
+        assert_approx_eq!(weights[&1], 0.0);
+        assert_approx_eq!(weights[&2], 2.0_f64.ln());
+    }
+
+    #[test]
+    fn euclidean_similarity_is_bounded() {
+        let a: Ratings<_> = hash_map! { 1 => 3.0, 2 => 3.0 };
+        assert_approx_eq!(euclidean_similarity(&a, &a).unwrap(), 1.0);
+
+        let mut last = 1.0;
+        for offset in 1..10 {
+            let b: Ratings<_> = hash_map! { 1 => 3.0 + offset as f64, 2 => 3.0 };
+
+            let dist = euclidean_distance(&a, &b).unwrap();
+            let sim = euclidean_similarity(&a, &b).unwrap();
+
+            assert_approx_eq!(sim, 1.0 / (1.0 + dist));
+            assert!(sim > 0.0 && sim < last);
+            last = sim;
+        }
+
+        assert!(Method::EuclideanSimilarity.is_similarity());
+    }
+
+    #[test]
+    fn methods_output_ranges() {
+        let unbounded = (Some(0.0), None);
+        let unit = (Some(0.0), Some(1.0));
+        let signed = (Some(-1.0), Some(1.0));
+
+        let expected = [
+            (Method::Manhattan, unbounded),
+            (Method::Euclidean, unbounded),
+            (Method::EuclideanSimilarity, unit),
+            (Method::Minkowski(3), unbounded),
+            (Method::JaccardIndex, unit),
+            (Method::JaccardDistance, unit),
+            (Method::JaccardThreshold(3.0), unit),
+            (Method::SignedJaccard, unit),
+            (Method::Hamming, unbounded),
+            (Method::BrayCurtis, unit),
+            (Method::CosineSimilarity, signed),
+            (Method::CosinePositive, unit),
+            (Method::PearsonCorrelation, signed),
+            (Method::PearsonApproximation, signed),
+            (Method::KendallTau, signed),
+            (Method::Rbf(1.0), unit),
+        ];
+
+        assert_eq!(expected.len(), Method::all().len());
+        for (method, range) in &expected {
+            assert_eq!(method.output_range(), *range, "range of {}", method);
+            assert_eq!(method.is_bounded(), range.1.is_some());
+        }
+
+        // Computed values stay inside the ranges, up to rounding errors
+        let a: Ratings<_> = hash_map! { 1 => 5.0, 2 => 1.0, 3 => 4.0, 4 => 2.0 };
+        let b: Ratings<_> = hash_map! { 1 => 1.0, 2 => 5.0, 3 => 2.0, 5 => 4.0 };
+        for method in Method::all() {
+            let value = distance(&a, &b, *method).unwrap();
+            let (lower, upper) = method.output_range();
+
+            assert!(
+                lower.is_none_or(|lower| value >= lower - 1e-9),
+                "{} below range",
+                method
+            );
+            assert!(
+                upper.is_none_or(|upper| value <= upper + 1e-9),
+                "{} above range",
+                method
+            );
+
+            let similarity = method.unit_similarity(value);
+            assert!((0.0..=1.0).contains(&similarity), "{} rescaled", method);
+        }
+
+        assert_approx_eq!(Method::CosineSimilarity.unit_similarity(0.0), 0.5);
+        assert_approx_eq!(Method::JaccardDistance.unit_similarity(0.25), 0.75);
+        assert_approx_eq!(Method::Euclidean.unit_similarity(3.0), 0.25);
+        assert_approx_eq!(Method::Euclidean.unit_similarity(0.0), 1.0);
+    }
 }
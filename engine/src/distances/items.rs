@@ -5,12 +5,14 @@
 
 use crate::error::ErrorKind;
 use crate::utils::common_keys_iter;
-use controller::{MapedRatings, Ratings};
+pub use config::Normalization;
+use controller::{MapedRatings, Means, Ratings};
 use num_traits::float::Float;
 use num_traits::Zero;
 use std::{
     cmp::{Ordering, Reverse},
     collections::{BinaryHeap, HashMap},
+    fmt::{self, Display, Formatter},
     hash::Hash,
     ops::{Add, AddAssign, Div, Mul, Sub},
 };
@@ -19,6 +21,34 @@ use std::{
 pub enum Method {
     AdjCosine,
     SlopeOne,
+    BiPolarSlopeOne,
+}
+
+impl Method {
+    /// All the available methods
+    pub fn all() -> &'static [Method] {
+        &[Method::AdjCosine, Method::SlopeOne, Method::BiPolarSlopeOne]
+    }
+
+    /// Get a method by its name, e.g. `adj_cosine`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim() {
+            "adj_cosine" => Some(Method::AdjCosine),
+            "slope_one" => Some(Method::SlopeOne),
+            "bipolar_slope_one" => Some(Method::BiPolarSlopeOne),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Method {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Method::AdjCosine => write!(f, "adj_cosine"),
+            Method::SlopeOne => write!(f, "slope_one"),
+            Method::BiPolarSlopeOne => write!(f, "bipolar_slope_one"),
+        }
+    }
 }
 
 type MinHeap<T> = BinaryHeap<Reverse<T>>;
@@ -65,14 +95,36 @@ impl<UserId> Ord for MeanUsage<UserId> {
     }
 }
 
+/// Usage of the means cache of `AdjCosine`, hits and misses are counted by
+/// `calculate` and evictions by `shrink_means`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AdjCosineStats {
+    pub hits: usize,
+    pub misses: usize,
+    pub evictions: usize,
+}
+
+impl AdjCosineStats {
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct AdjCosine<UserId, Value>
 where
     UserId: Hash + Eq,
 {
-    // The value is a tuple of (usage, size)
-    mfreq: HashMap<UserId, (u32, usize)>,
+    // The value is a tuple of (usage, size), the size is only known for means
+    // computed from the ratings themselves
+    mfreq: HashMap<UserId, (u32, Option<usize>)>,
     means: HashMap<UserId, Value>,
+    stats: AdjCosineStats,
 }
 
 impl<UserId, Value> AdjCosine<UserId, Value>
@@ -117,6 +169,28 @@ where
         }
     }
 
+    /// Fold a new rating of `user_id` into its stored mean in O(1), using the
+    /// stored size as the number of ratings behind the mean. Returns the new
+    /// mean, or `None` when there is no stored mean to update or its size is
+    /// unknown, as for the means from `push_means` or `load_all_means`
+    pub fn add_rating(&mut self, user_id: &UserId, new_score: Value) -> Option<Value>
+    where
+        Value: Float,
+    {
+        let mean = self.means.get_mut(user_id)?;
+        let (_, size) = self
+            .mfreq
+            .get_mut(user_id)
+            .expect("Broken invariant: mfreq doesn't contain an already stored mean");
+
+        let size = size.as_mut()?;
+        let n = Value::from(*size)?;
+        *mean = (*mean * n + new_score) / (n + Value::one());
+        *size += 1;
+
+        Some(*mean)
+    }
+
     pub fn del_mean_for(&mut self, user_id: &UserId) {
         self.means.remove(user_id);
         self.mfreq.remove(user_id);
@@ -133,16 +207,27 @@ where
         let mut min_heap: MinHeap<_> = self
             .mfreq
             .iter()
-            .map(|(user_id, (usage, size))| Reverse(MeanUsage(user_id.to_owned(), *usage, *size)))
+            .map(|(user_id, (usage, size))| {
+                Reverse(MeanUsage(user_id.to_owned(), *usage, size.unwrap_or(0)))
+            })
             .collect();
 
         while self.means.len() > Self::THRESHOLD {
             let Reverse(MeanUsage(uid, _, _)) = min_heap.pop().unwrap();
             self.means.remove(&uid);
             self.mfreq.remove(&uid);
+            self.stats.evictions += 1;
         }
     }
 
+    pub fn stats(&self) -> AdjCosineStats {
+        self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = AdjCosineStats::default();
+    }
+
     pub fn update_means<ItemId>(&mut self, maped_ratings: &MapedRatings<UserId, ItemId, Value>)
     where
         UserId: Clone,
@@ -160,7 +245,7 @@ where
             if let Some(mean) = mean {
                 let mean = mean / Value::from(n).unwrap();
                 self.means.insert(id.to_owned(), mean);
-                self.mfreq.insert(id.to_owned(), (0, ratings.len()));
+                self.mfreq.insert(id.to_owned(), (0, Some(ratings.len())));
             }
         }
     }
@@ -172,7 +257,22 @@ where
     {
         for (id, mean) in new_means {
             self.means.insert(id.clone(), *mean);
-            self.mfreq.insert(id.clone(), (0, 1));
+            self.mfreq.insert(id.clone(), (0, None));
+        }
+    }
+
+    /// Load precomputed means (e.g. all the means of a database), users with a
+    /// loaded mean are never queried again by the chunked paths
+    pub fn load_all_means(&mut self, means: &Means<UserId, Value>)
+    where
+        UserId: Clone,
+        Value: Float,
+    {
+        self.means.reserve(means.len());
+        for (id, mean) in means {
+            self.means.insert(id.clone(), *mean);
+            // Keep the usage but not the size, it may belong to another mean
+            self.mfreq.entry(id.clone()).or_insert((0, None)).1 = None;
         }
     }
 
@@ -190,8 +290,10 @@ where
 
         for (user_id, (val_a, val_b)) in common_keys_iter(item_a_ratings, item_b_ratings) {
             let mean = if let Some(mean) = self.get_mean_for(user_id) {
+                self.stats.hits += 1;
                 mean
             } else {
+                self.stats.misses += 1;
                 continue;
             };
 
@@ -216,6 +318,66 @@ where
     }
 }
 
+/// Compute the mean of every user in `maped_ratings` (User::Id => Item::Id)
+pub fn precompute_means<UserId, ItemId, Value>(
+    maped_ratings: &MapedRatings<UserId, ItemId, Value>,
+) -> Means<UserId, Value>
+where
+    UserId: Hash + Eq + Clone,
+    Value: Float + AddAssign,
+{
+    let mut means = Means::new();
+    for (user_id, ratings) in maped_ratings {
+        let mut sum = None;
+        for rating in ratings.values() {
+            *sum.get_or_insert_with(Value::zero) += *rating;
+        }
+
+        if let (Some(sum), Some(n)) = (sum, Value::from(ratings.len())) {
+            means.insert(user_id.clone(), sum / n);
+        }
+    }
+
+    means
+}
+
+/// Adjusted cosine similarity between two items, computed offline with
+/// the means from `precompute_means` and the normal maped ratings
+/// (User::Id => Item::Id), returns `None` if it can't be computed
+pub fn adjusted_cosine<UserId, ItemId, Value>(
+    means: &Means<UserId, Value>,
+    maped_ratings: &MapedRatings<UserId, ItemId, Value>,
+    item_a: &ItemId,
+    item_b: &ItemId,
+) -> Option<Value>
+where
+    UserId: Hash + Eq,
+    ItemId: Hash + Eq,
+    Value: Float + AddAssign,
+{
+    let mut cov = None;
+    let mut dev_a = None;
+    let mut dev_b = None;
+
+    for (user_id, ratings) in maped_ratings {
+        let (val_a, val_b, mean) = match (ratings.get(item_a), ratings.get(item_b)) {
+            (Some(val_a), Some(val_b)) => (*val_a, *val_b, *means.get(user_id)?),
+            _ => continue,
+        };
+
+        *cov.get_or_insert_with(Value::zero) += (val_a - mean) * (val_b - mean);
+        *dev_a.get_or_insert_with(Value::zero) += (val_a - mean).powi(2);
+        *dev_b.get_or_insert_with(Value::zero) += (val_b - mean).powi(2);
+    }
+
+    let res = cov? / (dev_a?.sqrt() * dev_b?.sqrt());
+    if res.is_finite() {
+        Some(res)
+    } else {
+        None
+    }
+}
+
 pub fn normalize_user_ratings<ItemId, Value>(
     ratings: &Ratings<ItemId, Value>,
     min_rating: Value,
@@ -253,6 +415,115 @@ where
     Ok((one / two) * ((normalized_rating + one) * (max_rating - min_rating)) + min_rating)
 }
 
+/// Normalizes the ratings of a single user (and denormalizes predictions for it)
+/// with the selected normalization
+#[derive(Debug, Clone, Copy)]
+pub struct UserNormalizer<Value> {
+    normalization: Normalization,
+    min_rating: Value,
+    max_rating: Value,
+    center: Value,
+    scale: Value,
+}
+
+impl<Value> UserNormalizer<Value>
+where
+    Value: Float + AddAssign,
+{
+    pub fn new<ItemId>(
+        ratings: &Ratings<ItemId, Value>,
+        normalization: Normalization,
+        min_rating: Value,
+        max_rating: Value,
+    ) -> Result<Self, ErrorKind>
+    where
+        ItemId: Hash + Eq,
+    {
+        let (center, scale) = match normalization {
+            Normalization::MinMax => {
+                if (max_rating - min_rating).is_zero() {
+                    return Err(ErrorKind::DivisionByZero);
+                }
+
+                (Value::zero(), Value::one())
+            }
+
+            Normalization::None => (Value::zero(), Value::one()),
+
+            Normalization::MeanCentered | Normalization::ZScore => {
+                let mut sum = None;
+                for rating in ratings.values() {
+                    *sum.get_or_insert_with(Value::zero) += *rating;
+                }
+
+                let n = Value::from(ratings.len()).ok_or(ErrorKind::ConvertType)?;
+                let mean = sum.ok_or(ErrorKind::EmptyRatings)? / n;
+
+                if normalization == Normalization::MeanCentered {
+                    (mean, Value::one())
+                } else {
+                    let mut var = Value::zero();
+                    for rating in ratings.values() {
+                        var += (*rating - mean).powi(2);
+                    }
+
+                    let std_dev = (var / n).sqrt();
+                    if std_dev.is_zero() {
+                        return Err(ErrorKind::DivisionByZero);
+                    }
+
+                    (mean, std_dev)
+                }
+            }
+        };
+
+        Ok(Self {
+            normalization,
+            min_rating,
+            max_rating,
+            center,
+            scale,
+        })
+    }
+
+    pub fn normalize(&self, rating: Value) -> Result<Value, ErrorKind> {
+        match self.normalization {
+            Normalization::MinMax => {
+                let two = Value::from(2.0).ok_or(ErrorKind::ConvertType)?;
+                Ok((two * rating - self.min_rating - self.max_rating)
+                    / (self.max_rating - self.min_rating))
+            }
+
+            _ => Ok((rating - self.center) / self.scale),
+        }
+    }
+
+    pub fn denormalize(&self, normalized_rating: Value) -> Result<Value, ErrorKind> {
+        match self.normalization {
+            Normalization::MinMax => {
+                denormalize_user_rating(normalized_rating, self.min_rating, self.max_rating)
+            }
+
+            _ => Ok(normalized_rating * self.scale + self.center),
+        }
+    }
+
+    pub fn normalize_ratings<'r, ItemId>(
+        &self,
+        ratings: &'r Ratings<ItemId, Value>,
+    ) -> Result<Ratings<&'r ItemId, Value>, ErrorKind>
+    where
+        ItemId: Hash + Eq,
+    {
+        let mut normalized_ratings = Ratings::new();
+        for (id, rating) in ratings {
+            normalized_ratings.insert(id, self.normalize(*rating)?);
+        }
+
+        Ok(normalized_ratings)
+    }
+}
+
 pub fn slope_one<UserId, Value>(
     item_a_ratings: &Ratings<UserId, Value>,
     item_b_ratings: &Ratings<UserId, Value>,
@@ -276,3 +547,86 @@ where
         Ok((distance, cardinality))
     }
 }
+
+/// Deviations of the "liked" and "disliked" partitions used by Bi-polar Slope One,
+/// each one is a `(deviation, cardinality)` pair, `None` if the partition is empty
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BiPolarDeviation<Value> {
+    pub liked: Option<(Value, usize)>,
+    pub disliked: Option<(Value, usize)>,
+}
+
+impl<Value> BiPolarDeviation<Value>
+where
+    Value: Float,
+{
+    /// Deviation of both partitions weighted by their cardinality
+    pub fn combined(&self) -> Result<(Value, usize), ErrorKind> {
+        let mut distance = Value::zero();
+        let mut cardinality: usize = 0;
+
+        for (dev, card) in self.liked.iter().chain(self.disliked.iter()) {
+            distance = distance + *dev * Value::from(*card).ok_or(ErrorKind::ConvertType)?;
+            cardinality += card;
+        }
+
+        if cardinality.is_zero() {
+            Err(ErrorKind::DivisionByZero)
+        } else {
+            let distance = distance / Value::from(cardinality).ok_or(ErrorKind::ConvertType)?;
+            Ok((distance, cardinality))
+        }
+    }
+}
+
+/// Bi-polar Slope One, only users that liked both items (rated them above their
+/// mean) or disliked both (rated them below their mean) are taken into account,
+/// and each group gets its own deviation. Users without a mean are skipped
+pub fn bipolar_slope_one<UserId, Value>(
+    item_a_ratings: &Ratings<UserId, Value>,
+    item_b_ratings: &Ratings<UserId, Value>,
+    means: &Means<UserId, Value>,
+) -> Result<BiPolarDeviation<Value>, ErrorKind>
+where
+    UserId: Hash + Eq,
+    Value: Float + AddAssign + Sub,
+{
+    let mut liked = (Value::zero(), 0);
+    let mut disliked = (Value::zero(), 0);
+
+    for (user_id, (val_a, val_b)) in common_keys_iter(item_a_ratings, item_b_ratings) {
+        let mean = match means.get(user_id) {
+            Some(mean) => *mean,
+            None => continue,
+        };
+
+        let partition = if *val_a > mean && *val_b > mean {
+            &mut liked
+        } else if *val_a < mean && *val_b < mean {
+            &mut disliked
+        } else {
+            continue;
+        };
+
+        partition.0 += *val_a - *val_b;
+        partition.1 += 1;
+    }
+
+    if liked.1 == 0 && disliked.1 == 0 {
+        return Err(ErrorKind::DivisionByZero);
+    }
+
+    let average = |(distance, cardinality): (Value, usize)| -> Result<_, ErrorKind> {
+        if cardinality == 0 {
+            Ok(None)
+        } else {
+            let cardinality_value = Value::from(cardinality).ok_or(ErrorKind::ConvertType)?;
+            Ok(Some((distance / cardinality_value, cardinality)))
+        }
+    };
+
+    Ok(BiPolarDeviation {
+        liked: average(liked)?,
+        disliked: average(disliked)?,
+    })
+}
@@ -10,21 +10,30 @@ use crate::utils::common_keys_iter;
 use controller::Ratings;
 use num_traits::float::Float;
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
+    fmt::{self, Display, Formatter},
     hash::Hash,
     ops::{AddAssign, Mul, MulAssign, Sub},
 };
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Method {
     Manhattan,
     Euclidean,
+    EuclideanSimilarity,
     Minkowski(usize),
     JaccardIndex,
     JaccardDistance,
+    JaccardThreshold(f64),
+    SignedJaccard,
+    Hamming,
+    BrayCurtis,
     CosineSimilarity,
+    CosinePositive,
     PearsonCorrelation,
     PearsonApproximation,
+    KendallTau,
+    Rbf(f64),
 }
 
 impl Method {
@@ -33,18 +42,163 @@ impl Method {
             Method::Manhattan
             | Method::Euclidean
             | Method::Minkowski(_)
-            | Method::JaccardDistance => false,
-
-            Method::JaccardIndex
+            | Method::JaccardDistance
+            | Method::Hamming
+            | Method::BrayCurtis => false,
+
+            Method::EuclideanSimilarity
+            | Method::JaccardIndex
+            | Method::JaccardThreshold(_)
+            | Method::SignedJaccard
             | Method::CosineSimilarity
+            | Method::CosinePositive
             | Method::PearsonCorrelation
-            | Method::PearsonApproximation => true,
+            | Method::PearsonApproximation
+            | Method::KendallTau
+            | Method::Rbf(_) => true,
         }
     }
 
     pub fn is_distance(&self) -> bool {
         !self.is_similarity()
     }
+
+    /// Theoretical `(lower, upper)` bounds of the values of the method, `None` for
+    /// an unbounded side. Useful to rescale methods before combining them
+    pub fn output_range(&self) -> (Option<f64>, Option<f64>) {
+        match self {
+            Method::Manhattan | Method::Euclidean | Method::Minkowski(_) | Method::Hamming => {
+                (Some(0.0), None)
+            }
+
+            Method::EuclideanSimilarity
+            | Method::JaccardIndex
+            | Method::JaccardDistance
+            | Method::JaccardThreshold(_)
+            | Method::SignedJaccard
+            | Method::BrayCurtis
+            | Method::CosinePositive
+            | Method::Rbf(_) => (Some(0.0), Some(1.0)),
+
+            Method::CosineSimilarity
+            | Method::PearsonCorrelation
+            | Method::PearsonApproximation
+            | Method::KendallTau => (Some(-1.0), Some(1.0)),
+        }
+    }
+
+    /// Whether the values of the method are bounded on both sides
+    pub fn is_bounded(&self) -> bool {
+        matches!(self.output_range(), (Some(_), Some(_)))
+    }
+
+    /// Rescale a `value` of the method to a similarity in `[0, 1]` using its
+    /// `output_range`, so methods can be compared and combined. Bounded ranges are
+    /// rescaled linearly, unbounded distances `d` become `1 / (1 + d)`
+    pub fn unit_similarity(&self, value: f64) -> f64 {
+        let similarity = match self.output_range() {
+            (Some(lower), Some(upper)) => (value - lower) / (upper - lower),
+            (lower, _) => {
+                let offset = value - lower.unwrap_or(0.0);
+                1.0 - 1.0 / (1.0 + offset.max(0.0))
+            }
+        };
+
+        let similarity = similarity.clamp(0.0, 1.0);
+        if self.is_similarity() {
+            similarity
+        } else {
+            1.0 - similarity
+        }
+    }
+
+    /// All the available methods, the ones with parameters are listed with a
+    /// sensible default value
+    pub fn all() -> &'static [Method] {
+        &[
+            Method::Manhattan,
+            Method::Euclidean,
+            Method::EuclideanSimilarity,
+            Method::Minkowski(3),
+            Method::JaccardIndex,
+            Method::JaccardDistance,
+            Method::JaccardThreshold(3.0),
+            Method::SignedJaccard,
+            Method::Hamming,
+            Method::BrayCurtis,
+            Method::CosineSimilarity,
+            Method::CosinePositive,
+            Method::PearsonCorrelation,
+            Method::PearsonApproximation,
+            Method::KendallTau,
+            Method::Rbf(1.0),
+        ]
+    }
+
+    /// Get a method by its name, parameters are given between parenthesis,
+    /// e.g. `cosine` or `minkowski(3)`
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim();
+        let (name, param) = match name.find('(') {
+            Some(idx) => {
+                let param = name[idx + 1..].strip_suffix(')')?.trim();
+                (name[..idx].trim(), Some(param))
+            }
+
+            None => (name, None),
+        };
+
+        let method = match (name, param) {
+            ("manhattan", None) => Method::Manhattan,
+            ("euclidean", None) => Method::Euclidean,
+            ("euclidean_sim", None) => Method::EuclideanSimilarity,
+            ("minkowski", Some(p)) => match p.parse().ok()? {
+                0 => return None,
+                p => Method::Minkowski(p),
+            },
+            ("jacc_index", None) => Method::JaccardIndex,
+            ("jacc_distance", None) => Method::JaccardDistance,
+            ("jacc_thresh", Some(threshold)) => Method::JaccardThreshold(threshold.parse().ok()?),
+            ("signed_jacc", None) => Method::SignedJaccard,
+            ("hamming", None) => Method::Hamming,
+            ("bray_curtis", None) => Method::BrayCurtis,
+            ("cosine", None) => Method::CosineSimilarity,
+            ("cosine_pos", None) => Method::CosinePositive,
+            ("pearson_c", None) => Method::PearsonCorrelation,
+            ("pearson_a", None) => Method::PearsonApproximation,
+            ("kendall", None) => Method::KendallTau,
+            ("rbf", Some(sigma)) => match sigma.parse().ok()? {
+                sigma if sigma > 0.0 => Method::Rbf(sigma),
+                _ => return None,
+            },
+            _ => return None,
+        };
+
+        Some(method)
+    }
+}
+
+impl Display for Method {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Method::Manhattan => write!(f, "manhattan"),
+            Method::Euclidean => write!(f, "euclidean"),
+            Method::EuclideanSimilarity => write!(f, "euclidean_sim"),
+            Method::Minkowski(p) => write!(f, "minkowski({})", p),
+            Method::JaccardIndex => write!(f, "jacc_index"),
+            Method::JaccardDistance => write!(f, "jacc_distance"),
+            Method::JaccardThreshold(threshold) => write!(f, "jacc_thresh({})", threshold),
+            Method::SignedJaccard => write!(f, "signed_jacc"),
+            Method::Hamming => write!(f, "hamming"),
+            Method::BrayCurtis => write!(f, "bray_curtis"),
+            Method::CosineSimilarity => write!(f, "cosine"),
+            Method::CosinePositive => write!(f, "cosine_pos"),
+            Method::PearsonCorrelation => write!(f, "pearson_c"),
+            Method::PearsonApproximation => write!(f, "pearson_a"),
+            Method::KendallTau => write!(f, "kendall"),
+            Method::Rbf(sigma) => write!(f, "rbf({})", sigma),
+        }
+    }
 }
 
 pub fn distance<ItemId, Value>(
@@ -59,12 +213,93 @@ where
     match method {
         Method::Manhattan => manhattan_distance(a, b),
         Method::Euclidean => euclidean_distance(a, b),
+        Method::EuclideanSimilarity => euclidean_similarity(a, b),
         Method::Minkowski(p) => minkowski_distance(a, b, p),
         Method::JaccardIndex => jaccard_index(a, b),
         Method::JaccardDistance => jaccard_distance(a, b),
+        Method::JaccardThreshold(threshold) => {
+            let threshold = Value::from(threshold).ok_or(ErrorKind::ConvertType)?;
+            jaccard_threshold_index(a, b, threshold)
+        }
+        Method::SignedJaccard => signed_jaccard_index(a, b),
+        Method::Hamming => hamming_distance(a, b),
+        Method::BrayCurtis => bray_curtis_distance(a, b),
         Method::CosineSimilarity => cosine_similarity(a, b),
+        Method::CosinePositive => positive_cosine_similarity(a, b),
         Method::PearsonCorrelation => pearson_correlation(a, b),
         Method::PearsonApproximation => pearson_approximation(a, b),
+        Method::KendallTau => kendall_tau(a, b),
+        Method::Rbf(sigma) => {
+            let sigma = Value::from(sigma).ok_or(ErrorKind::ConvertType)?;
+            rbf_similarity(a, b, sigma)
+        }
+    }
+}
+
+/// Distance (or similarity, see `Method::is_similarity`) between two sparse
+/// vectors, only the keys present in both of them are compared. It's the same math
+/// used by the engine, without going through a controller
+///
+/// ```
+/// use engine::distances::users::{pairwise_distance, Method};
+/// use std::collections::HashMap;
+///
+/// let a: HashMap<_, _> = vec![("x", 1.0), ("y", 3.0), ("z", 2.0)].into_iter().collect();
+/// let b: HashMap<_, _> = vec![("x", 2.0), ("y", 5.0)].into_iter().collect();
+///
+/// // Only `x` and `y` are compared
+/// assert_eq!(pairwise_distance(&a, &b, Method::Manhattan).unwrap(), 3.0);
+///
+/// // Without common keys there's nothing to compare
+/// let c: HashMap<_, _> = vec![("w", 4.0)].into_iter().collect();
+/// assert!(pairwise_distance(&a, &c, Method::Manhattan).is_err());
+/// ```
+pub fn pairwise_distance<K, V>(
+    a: &HashMap<K, V>,
+    b: &HashMap<K, V>,
+    method: Method,
+) -> Result<V, ErrorKind>
+where
+    K: Hash + Eq,
+    V: Float + AddAssign + Sub + Mul + MulAssign,
+{
+    distance(a, b, method)
+}
+
+/// Like `pairwise_distance` but higher values always mean more similar vectors.
+/// Similarities are kept as they are, bounded distances are subtracted from their
+/// upper bound and unbounded ones `d` become `1 / (1 + d)`
+///
+/// ```
+/// use engine::distances::users::{pairwise_similarity, Method};
+/// use std::collections::HashMap;
+///
+/// let a: HashMap<_, _> = vec![(1, 4.0), (2, 2.0)].into_iter().collect();
+/// let near: HashMap<_, _> = vec![(1, 4.0), (2, 3.0)].into_iter().collect();
+/// let far: HashMap<_, _> = vec![(1, 1.0), (2, 5.0)].into_iter().collect();
+///
+/// let near = pairwise_similarity(&a, &near, Method::Euclidean).unwrap();
+/// let far = pairwise_similarity(&a, &far, Method::Euclidean).unwrap();
+/// assert_eq!(near, 0.5);
+/// assert!(near > far);
+/// ```
+pub fn pairwise_similarity<K, V>(
+    a: &HashMap<K, V>,
+    b: &HashMap<K, V>,
+    method: Method,
+) -> Result<V, ErrorKind>
+where
+    K: Hash + Eq,
+    V: Float + AddAssign + Sub + Mul + MulAssign,
+{
+    let value = distance(a, b, method)?;
+    if method.is_similarity() {
+        return Ok(value);
+    }
+
+    match method.output_range() {
+        (_, Some(upper)) => Ok(V::from(upper).ok_or(ErrorKind::ConvertType)? - value),
+        _ => Ok(V::one() / (V::one() + value)),
     }
 }
 
@@ -100,6 +335,45 @@ where
     dist.map(Value::sqrt).ok_or(ErrorKind::NoMatchingRatings)
 }
 
+/// Euclidean distance turned into a similarity, `1 / (1 + d)`, bounded in `(0, 1]`
+pub fn euclidean_similarity<ItemId, Value>(
+    a: &Ratings<ItemId, Value>,
+    b: &Ratings<ItemId, Value>,
+) -> Result<Value, ErrorKind>
+where
+    ItemId: Hash + Eq,
+    Value: Float + AddAssign + Sub,
+{
+    euclidean_distance(a, b).map(|dist| Value::one() / (Value::one() + dist))
+}
+
+/// Gaussian (RBF) kernel over the euclidean distance, `exp(-d² / (2σ²))`,
+/// bounded in `(0, 1]`. A small `sigma` penalizes distance sharply while a
+/// big one makes every pair of users look alike
+pub fn rbf_similarity<ItemId, Value>(
+    a: &Ratings<ItemId, Value>,
+    b: &Ratings<ItemId, Value>,
+    sigma: Value,
+) -> Result<Value, ErrorKind>
+where
+    ItemId: Hash + Eq,
+    Value: Float + AddAssign + Sub,
+{
+    if sigma.is_zero() {
+        return Err(ErrorKind::DivisionByZero);
+    }
+
+    let mut squared_dist = None;
+    for (_, (x, y)) in common_keys_iter(a, b) {
+        *squared_dist.get_or_insert_with(Value::zero) += (*y - *x).powi(2);
+    }
+
+    let two = Value::one() + Value::one();
+    squared_dist
+        .map(|d2| (-d2 / (two * sigma * sigma)).exp())
+        .ok_or(ErrorKind::NoMatchingRatings)
+}
+
 pub fn minkowski_distance<ItemId, Value>(
     a: &Ratings<ItemId, Value>,
     b: &Ratings<ItemId, Value>,
@@ -165,6 +439,136 @@ where
     Ok(Value::one() - jaccard_index(a, b)?)
 }
 
+/// Fraction of the common items both users rated on the same side of their own
+/// mean (both above, both below or both exactly on it), unlike the plain
+/// jaccard index co-rating an item isn't enough to agree on it
+pub fn signed_jaccard_index<ItemId, Value>(
+    a: &Ratings<ItemId, Value>,
+    b: &Ratings<ItemId, Value>,
+) -> Result<Value, ErrorKind>
+where
+    ItemId: Hash + Eq,
+    Value: Float + AddAssign + Sub,
+{
+    if a.is_empty() || b.is_empty() {
+        return Err(ErrorKind::NoMatchingRatings);
+    }
+
+    let mean = |ratings: &Ratings<ItemId, Value>| {
+        let sum = ratings.values().fold(Value::zero(), |acc, r| acc + *r);
+        Value::from(ratings.len()).map(|n| sum / n)
+    };
+
+    let mean_a = mean(a).ok_or(ErrorKind::ConvertType)?;
+    let mean_b = mean(b).ok_or(ErrorKind::ConvertType)?;
+
+    let mut agreeing: usize = 0;
+    let mut shared: usize = 0;
+    for (_, (x, y)) in common_keys_iter(a, b) {
+        if x.partial_cmp(&mean_a) == y.partial_cmp(&mean_b) {
+            agreeing += 1;
+        }
+
+        shared += 1;
+    }
+
+    if shared == 0 {
+        return Err(ErrorKind::NoMatchingRatings);
+    }
+
+    let agreeing = Value::from(agreeing).ok_or(ErrorKind::ConvertType)?;
+    let shared = Value::from(shared).ok_or(ErrorKind::ConvertType)?;
+    Ok(agreeing / shared)
+}
+
+/// Jaccard index where an item only belongs to the user's set if its rating
+/// exceeds the given threshold
+pub fn jaccard_threshold_index<ItemId, Value>(
+    a: &Ratings<ItemId, Value>,
+    b: &Ratings<ItemId, Value>,
+    threshold: Value,
+) -> Result<Value, ErrorKind>
+where
+    ItemId: Hash + Eq,
+    Value: Float + AddAssign + Sub,
+{
+    let a_keys: HashSet<_> = a
+        .iter()
+        .filter(|(_, r)| **r > threshold)
+        .map(|(k, _)| k)
+        .collect();
+    let b_keys: HashSet<_> = b
+        .iter()
+        .filter(|(_, r)| **r > threshold)
+        .map(|(k, _)| k)
+        .collect();
+
+    match (a_keys.is_empty(), b_keys.is_empty()) {
+        // Both are empty, cannot compute the index
+        (true, true) => Err(ErrorKind::EmptyRatings),
+
+        // One of them is empty, the result is zero
+        (true, _) | (_, true) => Ok(Value::zero()),
+
+        // Both have at least one element, proceed
+        _ => {
+            let union = a_keys.union(&b_keys).count();
+            let inter = a_keys.intersection(&b_keys).count();
+
+            let inter = Value::from(inter).ok_or(ErrorKind::ConvertType)?;
+            let union = Value::from(union).ok_or(ErrorKind::ConvertType)?;
+
+            Ok(inter / union)
+        }
+    }
+}
+
+/// Number of items rated by exactly one of the users, i.e. the size of the
+/// symmetric difference of their rated items
+pub fn hamming_distance<ItemId, Value>(
+    a: &Ratings<ItemId, Value>,
+    b: &Ratings<ItemId, Value>,
+) -> Result<Value, ErrorKind>
+where
+    ItemId: Hash + Eq,
+    Value: Float,
+{
+    if a.is_empty() && b.is_empty() {
+        return Err(ErrorKind::EmptyRatings);
+    }
+
+    let a_keys: HashSet<_> = a.keys().collect();
+    let b_keys: HashSet<_> = b.keys().collect();
+
+    let diff = a_keys.symmetric_difference(&b_keys).count();
+    Value::from(diff).ok_or(ErrorKind::ConvertType)
+}
+
+/// Bray-Curtis dissimilarity, `sum |x - y| / sum (x + y)` over the common items.
+/// It's in `[0, 1]` as long as the ratings aren't negative
+pub fn bray_curtis_distance<ItemId, Value>(
+    a: &Ratings<ItemId, Value>,
+    b: &Ratings<ItemId, Value>,
+) -> Result<Value, ErrorKind>
+where
+    ItemId: Hash + Eq,
+    Value: Float + AddAssign + Sub,
+{
+    let mut sums = None;
+    for (_, (x, y)) in common_keys_iter(a, b) {
+        let (diff, total) = sums.get_or_insert_with(|| (Value::zero(), Value::zero()));
+        *diff += (*x - *y).abs();
+        *total += *x + *y;
+    }
+
+    let (diff, total) = sums.ok_or(ErrorKind::NoMatchingRatings)?;
+    if total == Value::zero() {
+        return Err(ErrorKind::DivisionByZero);
+    }
+
+    Ok(diff / total)
+}
+
 pub fn cosine_similarity<ItemId, Value>(
     a: &Ratings<ItemId, Value>,
     b: &Ratings<ItemId, Value>,
@@ -197,6 +601,19 @@ where
     }
 }
 
+/// Cosine similarity where opposite users (negative cosine) are just unrelated,
+/// so they don't subtract from weighted predictions
+pub fn positive_cosine_similarity<ItemId, Value>(
+    a: &Ratings<ItemId, Value>,
+    b: &Ratings<ItemId, Value>,
+) -> Result<Value, ErrorKind>
+where
+    ItemId: Hash + Eq,
+    Value: Float + AddAssign + Sub + Mul,
+{
+    cosine_similarity(a, b).map(|cos_sim| cos_sim.max(Value::zero()))
+}
+
 pub fn pearson_correlation<ItemId, Value>(
     a: &Ratings<ItemId, Value>,
     b: &Ratings<ItemId, Value>,
@@ -289,3 +706,174 @@ where
         Ok(pearson)
     }
 }
+
+/// Kendall's tau rank correlation (tau-a), concordant minus discordant pairs of
+/// common items over the total number of pairs, tied pairs are neither. Every pair
+/// is compared so it's `O(n²)` on the common items, unlike Spearman's `O(n log n)`
+/// from sorting the ranks
+pub fn kendall_tau<ItemId, Value>(
+    a: &Ratings<ItemId, Value>,
+    b: &Ratings<ItemId, Value>,
+) -> Result<Value, ErrorKind>
+where
+    ItemId: Hash + Eq,
+    Value: Float,
+{
+    let common: Vec<_> = common_keys_iter(a, b).map(|(_, (x, y))| (*x, *y)).collect();
+
+    // No pairs to compare
+    match common.len() {
+        0 => return Err(ErrorKind::NoMatchingRatings),
+        1 => return Err(ErrorKind::IndeterminateForm),
+        _ => {}
+    }
+
+    let mut concordant = 0i64;
+    let mut discordant = 0i64;
+    for (i, (x_i, y_i)) in common.iter().enumerate() {
+        for (x_j, y_j) in &common[i + 1..] {
+            let sign = (*x_i - *x_j) * (*y_i - *y_j);
+            if sign > Value::zero() {
+                concordant += 1;
+            } else if sign < Value::zero() {
+                discordant += 1;
+            }
+        }
+    }
+
+    let n = common.len() as i64;
+    let pairs = Value::from(n * (n - 1) / 2).ok_or(ErrorKind::ConvertType)?;
+    let diff = Value::from(concordant - discordant).ok_or(ErrorKind::ConvertType)?;
+
+    Ok(diff / pairs)
+}
+
+/// Same as `pearson_approximation` but returns zero (i.e. no correlation) on
+/// indeterminate forms, which happen when one of the users rated every common
+/// item with the same value
+pub fn pearson_or_zero<ItemId, Value>(
+    a: &Ratings<ItemId, Value>,
+    b: &Ratings<ItemId, Value>,
+) -> Result<Value, ErrorKind>
+where
+    ItemId: Hash + Eq,
+    Value: Float + AddAssign + Sub + Mul,
+{
+    match pearson_approximation(a, b) {
+        Err(ErrorKind::IndeterminateForm) | Err(ErrorKind::DivisionByZero) => Ok(Value::zero()),
+        res => res,
+    }
+}
+
+/// Inverse user frequency of each item, i.e. `ln(N / n_i)` where `N` is the total
+/// number of users and `n_i` the number of users who rated the item `i`. Items
+/// rated by everyone get a weight of zero
+pub fn inverse_user_frequency<ItemId, Value>(
+    counts: &HashMap<ItemId, usize>,
+    total_users: usize,
+) -> Result<HashMap<ItemId, Value>, ErrorKind>
+where
+    ItemId: Hash + Eq + Clone,
+    Value: Float,
+{
+    let total_users = Value::from(total_users).ok_or(ErrorKind::ConvertType)?;
+
+    let mut weights = HashMap::new();
+    for (item_id, count) in counts {
+        let count = Value::from(*count).ok_or(ErrorKind::ConvertType)?;
+        if count.is_zero() {
+            return Err(ErrorKind::DivisionByZero);
+        }
+
+        weights.insert(item_id.clone(), (total_users / count).ln());
+    }
+
+    Ok(weights)
+}
+
+/// Cosine similarity where each common item contributes proportionally to its
+/// weight, items without a weight have a weight of one
+pub fn weighted_cosine_similarity<ItemId, Value>(
+    a: &Ratings<ItemId, Value>,
+    b: &Ratings<ItemId, Value>,
+    weights: &HashMap<ItemId, Value>,
+) -> Result<Value, ErrorKind>
+where
+    ItemId: Hash + Eq,
+    Value: Float + AddAssign + Sub + Mul,
+{
+    let mut a_norm = None;
+    let mut b_norm = None;
+    let mut dot_prod = None;
+
+    for (item_id, (x, y)) in common_keys_iter(a, b) {
+        let w = weights.get(item_id).copied().unwrap_or_else(Value::one);
+
+        *a_norm.get_or_insert_with(Value::zero) += w * x.powi(2);
+        *b_norm.get_or_insert_with(Value::zero) += w * y.powi(2);
+        *dot_prod.get_or_insert_with(Value::zero) += w * (*x) * (*y);
+    }
+
+    let dot_prod = dot_prod.ok_or(ErrorKind::NoMatchingRatings)?;
+    let a_norm = a_norm.ok_or(ErrorKind::NoMatchingRatings)?;
+    let b_norm = b_norm.ok_or(ErrorKind::NoMatchingRatings)?;
+
+    let cos_sim = dot_prod / (a_norm.sqrt() * b_norm.sqrt());
+    if cos_sim.is_nan() {
+        Err(ErrorKind::IndeterminateForm)
+    } else if cos_sim.is_infinite() {
+        Err(ErrorKind::DivisionByZero)
+    } else {
+        Ok(cos_sim)
+    }
+}
+
+/// Pearson's correlation where each common item contributes proportionally to its
+/// weight (for the means too), items without a weight have a weight of one
+pub fn weighted_pearson_correlation<ItemId, Value>(
+    a: &Ratings<ItemId, Value>,
+    b: &Ratings<ItemId, Value>,
+    weights: &HashMap<ItemId, Value>,
+) -> Result<Value, ErrorKind>
+where
+    ItemId: Hash + Eq,
+    Value: Float + AddAssign + Sub + Mul,
+{
+    let weight = |item_id: &ItemId| weights.get(item_id).copied().unwrap_or_else(Value::one);
+
+    let mut mean_x = None;
+    let mut mean_y = None;
+    let mut total_weight = Value::zero();
+
+    for (item_id, (x, y)) in common_keys_iter(a, b) {
+        let w = weight(item_id);
+
+        *mean_x.get_or_insert_with(Value::zero) += w * *x;
+        *mean_y.get_or_insert_with(Value::zero) += w * *y;
+        total_weight += w;
+    }
+
+    let mean_x = mean_x.ok_or(ErrorKind::NoMatchingRatings)? / total_weight;
+    let mean_y = mean_y.ok_or(ErrorKind::NoMatchingRatings)? / total_weight;
+
+    let mut cov = Value::zero();
+    let mut std_dev_a = Value::zero();
+    let mut std_dev_b = Value::zero();
+
+    for (item_id, (x, y)) in common_keys_iter(a, b) {
+        let w = weight(item_id);
+
+        cov += w * (*x - mean_x) * (*y - mean_y);
+        std_dev_a += w * (*x - mean_x).powi(2);
+        std_dev_b += w * (*y - mean_y).powi(2);
+    }
+
+    let pearson = cov / (std_dev_a.sqrt() * std_dev_b.sqrt());
+    if pearson.is_nan() {
+        Err(ErrorKind::IndeterminateForm)
+    } else if pearson.is_infinite() {
+        Err(ErrorKind::DivisionByZero)
+    } else {
+        Ok(pearson)
+    }
+}
@@ -3,8 +3,30 @@
 // This software is released under the MIT License.
 // https://opensource.org/licenses/MIT
 
+use std::fmt::{self, Display, Formatter};
 use thiserror::Error as DError;
 
+/// Why a kNN search didn't find any neighbor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmptyKnnReason {
+    /// The target user doesn't have any rating to compare with
+    UserHasNoRatings,
+    /// No other user rated any of the items of the target user
+    NoOverlappingUsers,
+    /// Some users share items with the target user, but every distance failed
+    AllDistancesInvalid,
+}
+
+impl Display for EmptyKnnReason {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            EmptyKnnReason::UserHasNoRatings => write!(f, "the user has no ratings"),
+            EmptyKnnReason::NoOverlappingUsers => write!(f, "no other user shares items"),
+            EmptyKnnReason::AllDistancesInvalid => write!(f, "every distance was invalid"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, DError)]
 pub enum ErrorKind {
     #[error("Tried to divide by zero")]
@@ -25,9 +47,66 @@ pub enum ErrorKind {
     #[error("Empty k nearest neighbors")]
     EmptyKNearestNeighbors,
 
+    #[error("Empty k nearest neighbors, {0}")]
+    NoNeighbors(EmptyKnnReason),
+
+    #[error("Found {0} neighbors who rated the item, at least {1} are required")]
+    InsufficientNeighbors(usize, usize),
+
     #[error("This feature is not implemented yet")]
     NotImplemented,
 
     #[error("Indices out of bounds")]
     IndexOutOfBound,
+
+    #[error("Item({0}) doesn't have any rating")]
+    ColdItem(String),
+
+    #[error("User({0}) doesn't have any rating")]
+    ColdUser(String),
+
+    #[error("Invalid parameter: {0}")]
+    InvalidParameter(String),
+
+    #[error("The operation was cancelled")]
+    Cancelled,
+}
+
+/// Error returned by the public `Engine` methods.
+///
+/// Engine failures keep their `ErrorKind` so callers can tell a cold user
+/// apart from a database failure without downcasting.
+#[derive(Debug, DError)]
+pub enum RecommendationError {
+    #[error(transparent)]
+    Engine(#[from] ErrorKind),
+
+    #[error(transparent)]
+    Controller(anyhow::Error),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl RecommendationError {
+    pub fn kind(&self) -> Option<&ErrorKind> {
+        match self {
+            RecommendationError::Engine(kind) => Some(kind),
+            _ => None,
+        }
+    }
+}
+
+impl From<anyhow::Error> for RecommendationError {
+    fn from(error: anyhow::Error) -> Self {
+        let error = match error.downcast::<RecommendationError>() {
+            Ok(error) => return error,
+            Err(error) => error,
+        };
+
+        match error.downcast::<ErrorKind>() {
+            Ok(kind) => RecommendationError::Engine(kind),
+            Err(error) => RecommendationError::Controller(error),
+        }
+    }
 }
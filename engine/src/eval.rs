@@ -0,0 +1,164 @@
+// Copyright (c) 2020 White Leaf
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::{cmp::Ordering, collections::HashMap, hash::Hash};
+
+/// Discounted cumulative gain of a list of relevances, using the `2^rel - 1` gain
+/// and a `log2(position + 1)` discount
+fn dcg(relevances: impl Iterator<Item = f64>) -> f64 {
+    relevances
+        .enumerate()
+        .map(|(pos, rel)| (2f64.powf(rel) - 1.0) / (pos as f64 + 2.0).log2())
+        .sum()
+}
+
+/// Normalized discounted cumulative gain of the first `k` items of `ranked_items`,
+/// graded by `relevance` (missing items have no relevance). The DCG is divided by
+/// the one of the ideal ordering, so `1.0` means a perfect ranking. Returns `0.0`
+/// when no item is relevant
+pub fn ndcg_at_k<ItemId>(ranked_items: &[ItemId], relevance: &HashMap<ItemId, f64>, k: usize) -> f64
+where
+    ItemId: Hash + Eq,
+{
+    let mut ideal: Vec<_> = relevance.values().copied().collect();
+    ideal.sort_by(|a, b| b.partial_cmp(a).unwrap_or(Ordering::Equal));
+
+    let ideal_dcg = dcg(ideal.into_iter().take(k));
+    if ideal_dcg <= 0.0 {
+        return 0.0;
+    }
+
+    let actual_dcg = dcg(ranked_items
+        .iter()
+        .take(k)
+        .map(|item| relevance.get(item).copied().unwrap_or(0.0)));
+
+    actual_dcg / ideal_dcg
+}
+
+/// Accuracy of predicted scores against held out ones. `rmse` and `mae` only cover
+/// the `predicted` rows, they're `None` when nothing could be predicted
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PredictionMetrics {
+    pub rmse: Option<f64>,
+    pub mae: Option<f64>,
+    pub predicted: usize,
+    pub total: usize,
+}
+
+impl PredictionMetrics {
+    /// Metrics of `(predicted, expected)` scores, out of `total` rows
+    pub fn from_predictions(predictions: &[(f64, f64)], total: usize) -> Self {
+        let predicted = predictions.len();
+        if predicted == 0 {
+            return Self {
+                total,
+                ..Default::default()
+            };
+        }
+
+        let (squared, absolute) =
+            predictions
+                .iter()
+                .fold((0.0, 0.0), |(squared, absolute), (predicted, expected)| {
+                    let error = predicted - expected;
+                    (squared + error * error, absolute + error.abs())
+                });
+
+        Self {
+            rmse: Some((squared / predicted as f64).sqrt()),
+            mae: Some(absolute / predicted as f64),
+            predicted,
+            total,
+        }
+    }
+
+    /// Count `missed` more rows that couldn't be predicted
+    pub fn with_misses(mut self, missed: usize) -> Self {
+        self.total += missed;
+        self
+    }
+
+    /// Fraction of the rows that could be predicted
+    pub fn coverage(&self) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            self.predicted as f64 / self.total as f64
+        }
+    }
+}
+
+/// Breakdown of the `(user, item)` pairs a method could or couldn't predict, see
+/// `Engine::coverage_report`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CoverageReport {
+    pub predicted: usize,
+    /// Nobody rated the item
+    pub cold_item: usize,
+    /// The user has no ratings
+    pub cold_user: usize,
+    /// No (or not enough) neighbors of the user rated the item
+    pub no_neighbors: usize,
+    /// Any other engine failure, e.g. a division by zero
+    pub failed: usize,
+    pub total: usize,
+}
+
+impl CoverageReport {
+    /// Fraction of the pairs that could be predicted
+    pub fn coverage(&self) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            self.predicted as f64 / self.total as f64
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+    use common_macros::hash_map;
+
+    #[test]
+    fn ndcg_hand_computed() {
+        let relevance = hash_map! { 1 => 3.0, 2 => 2.0, 3 => 3.0, 4 => 0.0, 5 => 1.0 };
+
+        // DCG   = 7/log2(2) + 3/log2(3) + 7/log2(4) + 0/log2(5)
+        // IDCG  = 7/log2(2) + 7/log2(3) + 3/log2(4) + 1/log2(5)
+        assert_approx_eq!(ndcg_at_k(&[1, 2, 3, 4], &relevance, 4), 0.928_494_616);
+
+        // Only the first two positions count
+        assert_approx_eq!(ndcg_at_k(&[1, 2, 3, 4], &relevance, 2), 0.778_941_253);
+
+        assert_approx_eq!(ndcg_at_k(&[1, 3, 2, 5], &relevance, 4), 1.0);
+    }
+
+    #[test]
+    fn ndcg_without_relevant_items() {
+        let relevance = hash_map! { 1 => 0.0, 2 => 0.0 };
+        assert_approx_eq!(ndcg_at_k(&[1, 2], &relevance, 2), 0.0);
+        assert_approx_eq!(ndcg_at_k(&[7, 8], &hash_map! { 1 => 2.0 }, 2), 0.0);
+        assert_approx_eq!(ndcg_at_k::<i32>(&[], &HashMap::new(), 5), 0.0);
+    }
+
+    #[test]
+    fn prediction_metrics() {
+        let metrics = PredictionMetrics::from_predictions(&[(4.0, 5.0), (3.0, 1.0)], 3);
+        assert_approx_eq!(metrics.rmse.unwrap(), 2.5f64.sqrt());
+        assert_approx_eq!(metrics.mae.unwrap(), 1.5);
+        assert_approx_eq!(metrics.coverage(), 2.0 / 3.0);
+
+        let metrics = metrics.with_misses(1);
+        assert_eq!(metrics.total, 4);
+        assert_approx_eq!(metrics.coverage(), 0.5);
+
+        let empty = PredictionMetrics::from_predictions(&[], 2);
+        assert_eq!(empty.rmse, None);
+        assert_approx_eq!(empty.coverage(), 0.0);
+    }
+}
@@ -0,0 +1,16 @@
+// Copyright (c) 2020 White Leaf
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+/// How much a single neighbor contributed to a user based prediction
+#[derive(Debug, Clone, PartialEq)]
+pub struct Contribution<UserId> {
+    pub user_id: UserId,
+    /// Pearson's correlation between the neighbor and the target user
+    pub similarity: f64,
+    /// The rating the neighbor gave to the target item
+    pub rating: f64,
+    /// Weighted rating, all the contributions add up to the prediction
+    pub contribution: f64,
+}
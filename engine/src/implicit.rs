@@ -0,0 +1,55 @@
+// Copyright (c) 2020 White Leaf
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use controller::{MapedRatings, Ratings};
+use std::hash::Hash;
+
+/// Implicit feedback (e.g. view counts) where a rating isn't a preference but a
+/// measure of confidence. A rating `r` means a preference of `1` (the user liked
+/// the item) with a confidence of `1 + alpha * r`, unrated items have a
+/// preference of `0` with a confidence of `1`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Implicit {
+    pub alpha: f64,
+}
+
+impl Implicit {
+    pub fn new(alpha: f64) -> Self {
+        Self { alpha }
+    }
+
+    pub fn confidence(&self, rating: f64) -> f64 {
+        1.0 + self.alpha * rating
+    }
+
+    /// Confidence weighted preferences, i.e. the confidence of every rated item,
+    /// ready to be used with the distance functions
+    pub fn transform<K>(&self, ratings: &Ratings<K>) -> Ratings<K>
+    where
+        K: Hash + Eq + Clone,
+    {
+        ratings
+            .iter()
+            .map(|(id, rating)| (id.clone(), self.confidence(*rating)))
+            .collect()
+    }
+
+    pub fn transform_maped<U, K>(&self, maped_ratings: MapedRatings<U, K>) -> MapedRatings<U, K>
+    where
+        U: Hash + Eq,
+        K: Hash + Eq + Clone,
+    {
+        maped_ratings
+            .into_iter()
+            .map(|(id, ratings)| (id, self.transform(&ratings)))
+            .collect()
+    }
+}
+
+impl Default for Implicit {
+    fn default() -> Self {
+        Self::new(40.0)
+    }
+}
@@ -6,12 +6,50 @@
 use crate::{
     distances::{self, users::Method},
     maped_distance::MapedDistance,
+    utils::BoundedTopN,
 };
 use controller::{MapedRatings, Ratings};
-use std::{cmp::Reverse, collections::BinaryHeap, hash::Hash};
+use std::{cmp::Reverse, hash::Hash};
 
-type MaxHeap<T> = BinaryHeap<T>;
-type MinHeap<T> = BinaryHeap<Reverse<T>>;
+/// Counters of the users seen by the updates of a `Knn`, they tell apart the
+/// reasons of an empty result
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KnnStats {
+    pub candidates: usize,
+    pub overlapping: usize,
+    pub valid: usize,
+}
+
+impl KnnStats {
+    fn record<ItemId>(
+        &mut self,
+        user_ratings: &Ratings<ItemId>,
+        ratings: &Ratings<ItemId>,
+        valid: bool,
+    ) where
+        ItemId: Hash + Eq,
+    {
+        self.candidates += 1;
+        if valid {
+            self.valid += 1;
+        }
+
+        if ratings.keys().any(|id| user_ratings.contains_key(id)) {
+            self.overlapping += 1;
+        }
+    }
+}
+
+/// Distance between two users, `None` when it can't be computed or it's NaN (e.g.
+/// ratings with NaN scores), so a bad distance never reaches the heaps
+fn valid_distance<ItemId>(a: &Ratings<ItemId>, b: &Ratings<ItemId>, method: Method) -> Option<f64>
+where
+    ItemId: Hash + Eq,
+{
+    distances::users::distance(a, b, method)
+        .ok()
+        .filter(|distance| !distance.is_nan())
+}
 
 pub trait Knn<UserId, ItemId> {
     fn update(
@@ -19,29 +57,45 @@ pub trait Knn<UserId, ItemId> {
         user_ratings: &Ratings<ItemId>,
         maped_ratings: MapedRatings<UserId, ItemId>,
     );
+
+    /// Like `update` but the candidates are borrowed, the ratings of a candidate
+    /// are only cloned when it gets into the heap
+    fn update_borrowed<'c>(
+        &mut self,
+        user_ratings: &Ratings<ItemId>,
+        candidates: &mut dyn Iterator<Item = (&'c UserId, &'c Ratings<ItemId>)>,
+    ) where
+        UserId: 'c,
+        ItemId: 'c;
+
+    fn stats(&self) -> KnnStats;
     fn into_vec(self: Box<Self>) -> Vec<MapedDistance<UserId, ItemId>>;
 }
 
+/// Keeps the `k` users with the smallest distances
 pub struct MaxHeapKnn<UserId, ItemId> {
-    k: usize,
     method: Method,
-    max_heap: MaxHeap<MapedDistance<UserId, ItemId>>,
+    top: BoundedTopN<Reverse<MapedDistance<UserId, ItemId>>>,
+    stats: KnnStats,
 }
 
-impl<UserId, ItemId> MaxHeapKnn<UserId, ItemId> {
+impl<UserId, ItemId> MaxHeapKnn<UserId, ItemId>
+where
+    UserId: Ord,
+{
     pub fn new(k: usize, method: Method) -> Self {
         Self {
-            k,
             method,
-            max_heap: Default::default(),
+            top: BoundedTopN::new(k),
+            stats: KnnStats::default(),
         }
     }
 }
 
 impl<UserId, ItemId> Knn<UserId, ItemId> for MaxHeapKnn<UserId, ItemId>
 where
-    UserId: Hash + Eq,
-    ItemId: Hash + Eq,
+    UserId: Hash + Ord + Clone,
+    ItemId: Hash + Eq + Clone,
 {
     fn update(
         &mut self,
@@ -51,51 +105,78 @@ where
         log::info!("Updating knn computation on new maped ratings chunk");
         log::info!("Size of maped ratings chunk is {}", maped_ratings.len());
         for (user_id, ratings) in maped_ratings {
-            let distance = distances::users::distance(user_ratings, &ratings, self.method);
-
-            if let Ok(distance) = distance {
-                if self.max_heap.len() < self.k {
-                    let maped_distance = MapedDistance(user_id, distance, Some(ratings));
-                    self.max_heap.push(maped_distance);
-                } else {
-                    let maximum = self.max_heap.peek().unwrap();
-                    if distance < maximum.dist() {
-                        let maped_distance = MapedDistance(user_id, distance, Some(ratings));
-
-                        self.max_heap.pop();
-                        self.max_heap.push(maped_distance);
-                    }
+            let distance = valid_distance(user_ratings, &ratings, self.method);
+            self.stats
+                .record(user_ratings, &ratings, distance.is_some());
+
+            if let Some(distance) = distance {
+                let maped_distance = MapedDistance(user_id, distance, Some(ratings));
+                self.top.push(Reverse(maped_distance));
+            }
+        }
+    }
+
+    fn update_borrowed<'c>(
+        &mut self,
+        user_ratings: &Ratings<ItemId>,
+        candidates: &mut dyn Iterator<Item = (&'c UserId, &'c Ratings<ItemId>)>,
+    ) where
+        UserId: 'c,
+        ItemId: 'c,
+    {
+        for (user_id, ratings) in candidates {
+            let distance = valid_distance(user_ratings, ratings, self.method);
+            self.stats.record(user_ratings, ratings, distance.is_some());
+
+            if let Some(distance) = distance {
+                let candidate = Reverse(MapedDistance(user_id.clone(), distance, None));
+                if self.top.accepts(&candidate) {
+                    let Reverse(MapedDistance(user_id, distance, _)) = candidate;
+                    let maped_distance = MapedDistance(user_id, distance, Some(ratings.clone()));
+                    self.top.push(Reverse(maped_distance));
                 }
             }
         }
     }
 
+    fn stats(&self) -> KnnStats {
+        self.stats
+    }
+
     fn into_vec(self: Box<Self>) -> Vec<MapedDistance<UserId, ItemId>> {
         log::info!("Sorting knns and returning as vec");
-        self.max_heap.into_sorted_vec()
+        self.top
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(maped_distance)| maped_distance)
+            .collect()
     }
 }
 
+/// Keeps the `k` users with the largest similarities
 pub struct MinHeapKnn<UserId, ItemId> {
-    k: usize,
     method: Method,
-    min_heap: MinHeap<MapedDistance<UserId, ItemId>>,
+    top: BoundedTopN<MapedDistance<UserId, ItemId>>,
+    stats: KnnStats,
 }
 
-impl<UserId, ItemId> MinHeapKnn<UserId, ItemId> {
+impl<UserId, ItemId> MinHeapKnn<UserId, ItemId>
+where
+    UserId: Ord,
+{
     pub fn new(k: usize, method: Method) -> Self {
         Self {
-            k,
             method,
-            min_heap: Default::default(),
+            top: BoundedTopN::new(k),
+            stats: KnnStats::default(),
         }
     }
 }
 
 impl<UserId, ItemId> Knn<UserId, ItemId> for MinHeapKnn<UserId, ItemId>
 where
-    UserId: Hash + Eq,
-    ItemId: Hash + Eq,
+    UserId: Hash + Ord + Clone,
+    ItemId: Hash + Eq + Clone,
 {
     fn update(
         &mut self,
@@ -105,31 +186,164 @@ where
         log::info!("Updating knn computation on new maped ratings chunk");
         log::info!("Size of maped ratings chunk is {}", maped_ratings.len());
         for (user_id, ratings) in maped_ratings {
-            let distance = distances::users::distance(user_ratings, &ratings, self.method);
-
-            if let Ok(distance) = distance {
-                if self.min_heap.len() < self.k {
-                    let maped_distance = MapedDistance(user_id, distance, Some(ratings));
-                    self.min_heap.push(Reverse(maped_distance));
-                } else {
-                    let minimum = self.min_heap.peek().unwrap();
-                    if distance > (minimum.0).dist() {
-                        let maped_distance = MapedDistance(user_id, distance, Some(ratings));
-
-                        self.min_heap.pop();
-                        self.min_heap.push(Reverse(maped_distance));
-                    }
+            let distance = valid_distance(user_ratings, &ratings, self.method);
+            self.stats
+                .record(user_ratings, &ratings, distance.is_some());
+
+            if let Some(distance) = distance {
+                let maped_distance = MapedDistance(user_id, distance, Some(ratings));
+                self.top.push(maped_distance);
+            }
+        }
+    }
+
+    fn update_borrowed<'c>(
+        &mut self,
+        user_ratings: &Ratings<ItemId>,
+        candidates: &mut dyn Iterator<Item = (&'c UserId, &'c Ratings<ItemId>)>,
+    ) where
+        UserId: 'c,
+        ItemId: 'c,
+    {
+        for (user_id, ratings) in candidates {
+            let distance = valid_distance(user_ratings, ratings, self.method);
+            self.stats.record(user_ratings, ratings, distance.is_some());
+
+            if let Some(distance) = distance {
+                let candidate = MapedDistance(user_id.clone(), distance, None);
+                if self.top.accepts(&candidate) {
+                    let MapedDistance(user_id, distance, _) = candidate;
+                    let maped_distance = MapedDistance(user_id, distance, Some(ratings.clone()));
+                    self.top.push(maped_distance);
                 }
             }
         }
     }
 
+    fn stats(&self) -> KnnStats {
+        self.stats
+    }
+
     fn into_vec(self: Box<Self>) -> Vec<MapedDistance<UserId, ItemId>> {
         log::info!("Sorting knns and returning as vec");
-        self.min_heap
-            .into_sorted_vec()
-            .into_iter()
-            .map(|r| r.0)
-            .collect()
+        self.top.into_sorted_vec()
+    }
+}
+
+/// Whether the heap for `method` keeps the largest values, the most similar users
+/// for similarities and, reversed, the most distant ones for distances
+pub fn keeps_largest(method: Method, reverse: bool) -> bool {
+    method.is_similarity() != reverse
+}
+
+/// Heap keeping the `k` nearest users by `method`, or the `k` farthest ones when
+/// `reverse` is set
+pub fn select_heap<'a, UserId, ItemId>(
+    k: usize,
+    method: Method,
+    reverse: bool,
+) -> Box<dyn Knn<UserId, ItemId> + 'a>
+where
+    UserId: Hash + Ord + Clone + 'a,
+    ItemId: Hash + Eq + Clone + 'a,
+{
+    if keeps_largest(method, reverse) {
+        Box::new(MinHeapKnn::new(k, method))
+    } else {
+        Box::new(MaxHeapKnn::new(k, method))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common_macros::hash_map;
+
+    #[test]
+    fn heap_choice_per_method() {
+        for &method in Method::all() {
+            assert_eq!(keeps_largest(method, false), method.is_similarity());
+            assert_eq!(keeps_largest(method, true), !method.is_similarity());
+        }
+    }
+
+    #[test]
+    fn nan_distances_are_skipped() {
+        let user_ratings = hash_map! { 1 => 5.0, 2 => 3.0 };
+
+        for &method in &[Method::Manhattan, Method::CosineSimilarity] {
+            for &reverse in &[false, true] {
+                let mut knn = select_heap(2, method, reverse);
+                knn.update(
+                    &user_ratings,
+                    hash_map! {
+                        "nan" => hash_map! { 1 => f64::NAN, 2 => 3.0 },
+                        "fine" => hash_map! { 1 => 4.0, 2 => 3.0 },
+                    },
+                );
+
+                assert_eq!(knn.stats().candidates, 2);
+                assert_eq!(knn.stats().valid, 1);
+
+                let ids: Vec<_> = knn.into_vec().into_iter().map(|maped| maped.0).collect();
+                assert_eq!(ids, vec!["fine"], "{:?}", method);
+            }
+        }
+    }
+
+    #[test]
+    fn borrowed_update_matches_update() {
+        let user_ratings = hash_map! { 1 => 5.0, 2 => 3.0, 3 => 1.0 };
+        let maped_ratings = hash_map! {
+            "a" => hash_map! { 1 => 5.0, 2 => 3.0, 3 => 2.0 },
+            "b" => hash_map! { 1 => 1.0, 2 => 3.0, 3 => 5.0 },
+            "c" => hash_map! { 1 => 4.0, 2 => 2.0 },
+            "d" => hash_map! { 4 => 4.0 },
+        };
+
+        for &method in &[Method::Manhattan, Method::CosineSimilarity] {
+            for &reverse in &[false, true] {
+                let mut owned = select_heap(2, method, reverse);
+                owned.update(&user_ratings, maped_ratings.clone());
+
+                let mut borrowed = select_heap(2, method, reverse);
+                borrowed.update_borrowed(&user_ratings, &mut maped_ratings.iter());
+
+                assert_eq!(owned.stats(), borrowed.stats());
+
+                let summary = |knn: Box<dyn Knn<_, _>>| -> Vec<_> {
+                    knn.into_vec()
+                        .into_iter()
+                        .map(|MapedDistance(id, dist, ratings)| (id, dist, ratings))
+                        .collect()
+                };
+                assert_eq!(summary(owned), summary(borrowed), "{:?}", method);
+            }
+        }
+    }
+
+    #[test]
+    fn selected_heap_keeps_nearest() {
+        let user_ratings = hash_map! { 1 => 5.0, 2 => 3.0, 3 => 1.0 };
+        let maped_ratings = || {
+            hash_map! {
+                "near" => hash_map! { 1 => 5.0, 2 => 3.0, 3 => 2.0 },
+                "far" => hash_map! { 1 => 1.0, 2 => 3.0, 3 => 5.0 },
+            }
+        };
+
+        for &method in &[
+            Method::Manhattan,
+            Method::Euclidean,
+            Method::PearsonCorrelation,
+        ] {
+            let mut knn = select_heap(1, method, false);
+            knn.update(&user_ratings, maped_ratings());
+            assert_eq!(knn.into_vec()[0].0, "near", "{:?}", method);
+
+            let mut knn = select_heap(1, method, true);
+            knn.update(&user_ratings, maped_ratings());
+            assert_eq!(knn.into_vec()[0].0, "far", "{:?}", method);
+        }
     }
 }
@@ -3,11 +3,21 @@
 // This software is released under the MIT License.
 // https://opensource.org/licenses/MIT
 
+pub mod cancellation;
 pub mod chunked_matrix;
 pub mod distances;
 pub mod error;
+pub mod eval;
+pub mod explanation;
+pub mod implicit;
 pub mod knn;
+pub mod lru;
+pub mod lsh;
 pub mod maped_distance;
+pub mod postprocess;
+pub mod recency;
+pub mod timings;
+pub mod transform;
 pub mod utils;
 
 use crate::{
@@ -15,15 +25,62 @@ use crate::{
     maped_distance::MapedDistance,
 };
 use anyhow::Error;
+use cancellation::CancellationToken;
+use chunked_matrix::DeviationMatrix;
 use config::Config;
-use controller::{eid, maped_ratings, Controller, Entity, Ratings};
-use distances::items::{denormalize_user_rating, normalize_user_ratings, slope_one, AdjCosine};
-use error::ErrorKind;
-use knn::{Knn, MaxHeapKnn, MinHeapKnn};
+use controller::snapshot::UserRatingsRecord;
+use controller::{eid, maped_ratings, means, ratings, Controller, Entity, Ratings};
+use distances::items::{bipolar_slope_one, slope_one, AdjCosine, UserNormalizer};
+use error::{EmptyKnnReason, ErrorKind, RecommendationError};
+use explanation::Contribution;
+use implicit::Implicit;
+use lru::{Lru, LruStats};
+use lsh::Lsh;
 use num_traits::Zero;
-use std::cell::RefCell;
+use postprocess::PostProcess;
+use recency::RecencyDecay;
+use serde::Serialize;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
-use std::{collections::HashSet, fmt::Debug, hash::Hash, marker::PhantomData, time::Instant};
+use std::{
+    cmp::Ordering,
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    fmt::Debug,
+    fs::File,
+    hash::{Hash, Hasher},
+    io::{BufWriter, Write},
+    marker::PhantomData,
+    path::Path,
+    time::Instant,
+};
+use timings::Timings;
+use transform::{Quantize, RatingTransform};
+use utils::{common_keys_iter, BoundedTopN, Scored};
+
+type RatingsLru<U, I> = RefCell<Lru<eid!(U), ratings!(I)>>;
+type NormalizedRatings<I> = Rc<(UserNormalizer<f64>, ratings!(I))>;
+type NormalizationCache<U, I> = RefCell<HashMap<eid!(U), (u64, NormalizedRatings<I>)>>;
+
+// Order independent hash of a rating set, so the same ratings always hash the same
+// no matter how the map iterates them
+fn ratings_hash<ItemId: Hash>(ratings: &Ratings<ItemId>) -> u64 {
+    ratings.iter().fold(0u64, |acc, (item_id, score)| {
+        let mut hasher = DefaultHasher::new();
+        item_id.hash(&mut hasher);
+        score.to_bits().hash(&mut hasher);
+        acc.wrapping_add(hasher.finish())
+    })
+}
+
+// Chunking by zero would panic deep inside the controllers
+fn check_chunk_size(chunk_size: impl Into<Option<usize>>) -> Result<(), ErrorKind> {
+    match chunk_size.into() {
+        Some(0) => Err(ErrorKind::InvalidParameter(
+            "chunk_size must be greater than zero".into(),
+        )),
+        _ => Ok(()),
+    }
+}
 
 pub struct Engine<'a, C, U, I>
 where
@@ -36,6 +93,14 @@ where
     controller: &'a C,
 
     adj_cosine: Rc<RefCell<AdjCosine<eid!(U), f64>>>,
+    ratings_cache: Option<maped_ratings!(U => I)>,
+    ratings_lru: Option<RatingsLru<U, I>>,
+    rating_transform: Option<Box<dyn RatingTransform<eid!(I)>>>,
+    quantize: Option<Quantize>,
+    recency_decay: Option<RecencyDecay>,
+    normalization_cache: NormalizationCache<U, I>,
+    normalization_stats: Cell<LruStats>,
+    lsh_index: RefCell<Option<Lsh<eid!(U)>>>,
 
     user_type: PhantomData<U>,
     item_type: PhantomData<I>,
@@ -46,7 +111,7 @@ where
     C: Controller<User = U, Item = I>,
     U: Entity,
     I: Entity,
-    eid!(U): Hash + Eq + Clone + Debug + Default,
+    eid!(U): Hash + Eq + Ord + Clone + Debug + Default,
     eid!(I): Hash + Eq + Clone + Debug,
 {
     pub fn with_controller(controller: &'a C, config: &'a Config) -> Self {
@@ -54,11 +119,29 @@ where
             config,
             controller,
             adj_cosine: Rc::new(RefCell::new(AdjCosine::new())),
+            ratings_cache: None,
+            ratings_lru: None,
+            rating_transform: None,
+            quantize: None,
+            recency_decay: None,
+            normalization_cache: RefCell::new(HashMap::new()),
+            normalization_stats: Cell::new(LruStats::default()),
+            lsh_index: RefCell::new(None),
             user_type: PhantomData,
             item_type: PhantomData,
         }
     }
 
+    /// Like `with_controller` but the ratings of the last `capacity` users
+    /// queried by `user_distance` (and friends) are kept around, so repeated
+    /// distances between overlapping users in a session don't query them again
+    pub fn with_ratings_lru(controller: &'a C, config: &'a Config, capacity: usize) -> Self {
+        Self {
+            ratings_lru: Some(RefCell::new(Lru::new(capacity))),
+            ..Self::with_controller(controller, config)
+        }
+    }
+
     pub fn maybe_update_mean_for(&self, user_id: &eid!(U), new: f64) {
         self.adj_cosine.borrow_mut().set_mean_for(user_id, new);
     }
@@ -71,19 +154,419 @@ where
         Rc::clone(&self.adj_cosine)
     }
 
-    pub fn user_distance(&self, user_a: U, user_b: U, method: UserMethod) -> Result<f64, Error> {
-        let rating_a = self.controller.user_ratings(&user_a)?;
-        let rating_b = self.controller.user_ratings(&user_b)?;
+    /// Query the means of all the users and store them in the shared cache, so
+    /// later predictions can skip querying means altogether
+    pub fn load_all_means(&self) -> Result<usize, RecommendationError> {
+        self.precompute_means(self.config.engine.partial_users_chunk_size)
+    }
+
+    /// Like `load_all_means` but users are queried by chunks of `chunk_size`, so
+    /// only the means (and not every user) are held in memory. Returns how many
+    /// means were cached, they're kept until the engine is dropped
+    pub fn precompute_means(&self, chunk_size: usize) -> Result<usize, RecommendationError> {
+        check_chunk_size(chunk_size)?;
+
+        let mut cached = 0;
+        for users_chunk in self.controller.users_by_chunks(chunk_size) {
+            let means = self.controller.users_means(&users_chunk)?;
+            self.adj_cosine.borrow_mut().load_all_means(&means);
+            cached += means.len();
+        }
+
+        Ok(cached)
+    }
+
+    /// Load all the users ratings in memory, so non chunked knn and predictions
+    /// stop querying the whole ratings table on every call.
+    ///
+    /// The cache holds a full copy of the ratings (and each call clones the part
+    /// it needs), only use it when the dataset comfortably fits in memory, e.g.
+    /// when evaluating many users in a row. Invalidate it with `invalidate_cache`
+    /// after inserting, updating or removing ratings
+    pub fn warm_cache(&mut self) -> Result<usize, RecommendationError> {
+        let all_users_ratings = self.controller.all_users_ratings()?;
+        let users = all_users_ratings.len();
+
+        self.ratings_cache = Some(all_users_ratings);
+        Ok(users)
+    }
+
+    /// Drop the ratings cache, the ratings lru (if any), the normalized ratings and
+    /// the LSH index, next calls will query the controller again
+    pub fn invalidate_cache(&mut self) {
+        self.ratings_cache = None;
+        self.lsh_index = RefCell::new(None);
+        if let Some(lru) = &self.ratings_lru {
+            lru.borrow_mut().clear();
+        }
+
+        let mut normalization_cache = self.normalization_cache.borrow_mut();
+        self.count_normalization(|stats| stats.evictions += normalization_cache.len());
+        normalization_cache.clear();
+    }
+
+    /// Forget the ratings of `user_id` kept by the ratings lru and its normalized
+    /// ratings, e.g. after one of its ratings changed
+    pub fn invalidate_user_ratings(&self, user_id: &eid!(U)) {
+        if let Some(lru) = &self.ratings_lru {
+            lru.borrow_mut().remove(user_id);
+        }
+
+        if self
+            .normalization_cache
+            .borrow_mut()
+            .remove(user_id)
+            .is_some()
+        {
+            self.count_normalization(|stats| stats.evictions += 1);
+        }
+    }
+
+    /// Usage of the normalized ratings kept by the adjusted cosine predictions, an
+    /// entry is evicted when it's invalidated or the ratings of its user changed
+    pub fn normalization_cache_stats(&self) -> LruStats {
+        self.normalization_stats.get()
+    }
+
+    fn count_normalization(&self, count: impl FnOnce(&mut LruStats)) {
+        let mut stats = self.normalization_stats.get();
+        count(&mut stats);
+        self.normalization_stats.set(stats);
+    }
+
+    /// Normalizer and normalized ratings of `user_id`, reused while its ratings
+    /// (identified by their hash) don't change
+    fn normalized_user_ratings(
+        &self,
+        user_id: &eid!(U),
+        user_ratings: &ratings!(I),
+    ) -> Result<NormalizedRatings<I>, Error> {
+        let hash = ratings_hash(user_ratings);
+
+        let mut cache = self.normalization_cache.borrow_mut();
+        match cache.get(user_id) {
+            Some((cached_hash, normalized)) if *cached_hash == hash => {
+                self.count_normalization(|stats| stats.hits += 1);
+                return Ok(Rc::clone(normalized));
+            }
+
+            Some(_) => self.count_normalization(|stats| {
+                stats.misses += 1;
+                stats.evictions += 1;
+            }),
+
+            None => self.count_normalization(|stats| stats.misses += 1),
+        }
+
+        let (min_rating, max_rating) = self.controller.score_range();
+        let normalizer = UserNormalizer::new(
+            user_ratings,
+            self.config.engine.normalization,
+            min_rating,
+            max_rating,
+        )?;
+        let normalized_ratings = normalizer
+            .normalize_ratings(user_ratings)?
+            .into_iter()
+            .map(|(item_id, rating)| (item_id.clone(), rating))
+            .collect();
+
+        let normalized = Rc::new((normalizer, normalized_ratings));
+        cache.insert(user_id.clone(), (hash, Rc::clone(&normalized)));
+        Ok(normalized)
+    }
+
+    /// Preprocess the ratings of every user with `transform` before computing any
+    /// distance with them, `None` uses the stored ratings as they are
+    pub fn set_rating_transform(&mut self, transform: Option<Box<dyn RatingTransform<eid!(I)>>>) {
+        self.rating_transform = transform;
+        self.lsh_index = RefCell::new(None);
+    }
+
+    /// Snap every rating to the nearest of `levels` evenly spaced values across the
+    /// controller `score_range` as it enters the engine, before `set_rating_transform`
+    /// is applied. `None` keeps the stored scores, less than two levels is an error
+    pub fn set_quantize_levels(&mut self, levels: Option<usize>) -> Result<(), ErrorKind> {
+        self.quantize = match levels {
+            Some(levels) => {
+                let (min, max) = self.controller.score_range();
+                Some(Quantize::new(levels, min, max)?)
+            }
+
+            None => None,
+        };
+
+        self.lsh_index = RefCell::new(None);
+        Ok(())
+    }
+
+    /// Weight the rating of each neighbor in user based predictions by its age with
+    /// `decay`, so recent ratings count more than old ones. The controller must
+    /// implement `ratings_with_time_by`, `None` weights every rating the same
+    pub fn set_recency_decay(&mut self, decay: Option<RecencyDecay>) {
+        self.recency_decay = decay;
+    }
+
+    pub fn ratings_lru_stats(&self) -> Option<LruStats> {
+        self.ratings_lru.as_ref().map(|lru| lru.borrow().stats())
+    }
+
+    pub fn is_cache_warm(&self) -> bool {
+        self.ratings_cache.is_some()
+    }
+
+    fn user_ratings(&self, user: &U) -> Result<ratings!(I), Error> {
+        let mut ratings = self.stored_user_ratings(user)?;
+        if let Some(quantize) = &self.quantize {
+            quantize.transform(&mut ratings);
+        }
+
+        if let Some(transform) = &self.rating_transform {
+            transform.transform(&mut ratings);
+        }
+
+        Ok(ratings)
+    }
+
+    fn transform_maped(&self, mut maped_ratings: maped_ratings!(U => I)) -> maped_ratings!(U => I) {
+        if let Some(quantize) = &self.quantize {
+            for ratings in maped_ratings.values_mut() {
+                quantize.transform(ratings);
+            }
+        }
+
+        if let Some(transform) = &self.rating_transform {
+            for ratings in maped_ratings.values_mut() {
+                transform.transform(ratings);
+            }
+        }
+
+        maped_ratings
+    }
+
+    fn stored_user_ratings(&self, user: &U) -> Result<ratings!(I), Error> {
+        if let Some(cache) = &self.ratings_cache {
+            return Ok(cache.get(&user.get_id()).cloned().unwrap_or_default());
+        }
+
+        let lru = match &self.ratings_lru {
+            Some(lru) => lru,
+            None => return self.controller.user_ratings(user),
+        };
+
+        let user_id = user.get_id();
+        if let Some(ratings) = lru.borrow_mut().get(&user_id) {
+            return Ok(ratings.clone());
+        }
+
+        let ratings = self.controller.user_ratings(user)?;
+        lru.borrow_mut().insert(user_id, ratings.clone());
+        Ok(ratings)
+    }
+
+    /// Ratings of a chunk of `users`, transformed like the ones of `user_ratings` so
+    /// chunked and unchunked computations compare the same values
+    fn users_ratings_chunk(&self, users: &[U]) -> Result<maped_ratings!(U => I), Error> {
+        Ok(self.transform_maped(self.controller.users_ratings(users)?))
+    }
+
+    fn all_users_ratings(&self) -> Result<maped_ratings!(U => I), Error> {
+        let maped_ratings = match &self.ratings_cache {
+            Some(cache) => cache.clone(),
+            None => self.controller.all_users_ratings()?,
+        };
+
+        Ok(self.transform_maped(maped_ratings))
+    }
+
+    fn users_ratings_except(&self, user: &U) -> Result<maped_ratings!(U => I), Error> {
+        let maped_ratings = match &self.ratings_cache {
+            Some(cache) => {
+                let user_id = user.get_id();
+                cache
+                    .iter()
+                    .filter(|(id, _)| *id != &user_id)
+                    .map(|(id, ratings)| (id.clone(), ratings.clone()))
+                    .collect()
+            }
+
+            None => self.controller.users_ratings_except(user)?,
+        };
+
+        Ok(self.transform_maped(maped_ratings))
+    }
+
+    /// Get the means of the specified users, means already loaded in the shared
+    /// cache are reused and only the missing ones are queried
+    fn users_means_by_ids(&self, user_ids: &[eid!(U)]) -> Result<means!(U), Error> {
+        let mut means = HashMap::new();
+        let mut missing = Vec::new();
+
+        for user_id in user_ids {
+            match self.adj_cosine.borrow_mut().get_mean_for(user_id) {
+                Some(mean) => {
+                    means.insert(user_id.clone(), mean);
+                }
+                None => missing.push(user_id.clone()),
+            }
+        }
+
+        if !missing.is_empty() {
+            let all_partial_users = self.controller.create_partial_users(&missing)?;
+
+            let partial_users_chunk_size = self.config.engine.partial_users_chunk_size;
+            for partial_users_chunk in all_partial_users.chunks(partial_users_chunk_size) {
+                means.extend(self.controller.users_means(partial_users_chunk)?);
+            }
+        }
+
+        Ok(means)
+    }
+
+    pub fn user_distance(
+        &self,
+        user_a: U,
+        user_b: U,
+        method: UserMethod,
+    ) -> Result<f64, RecommendationError> {
+        let rating_a = self.user_ratings(&user_a)?;
+        let rating_b = self.user_ratings(&user_b)?;
 
         distances::users::distance(&rating_a, &rating_b, method).map_err(Into::into)
     }
 
+    /// Weighted sum of several methods between `user_a` and `user_b`, each value is
+    /// first rescaled to a similarity in `[0, 1]` (see `Method::unit_similarity`) so
+    /// the higher the more similar, whatever the methods are. Weights can't be
+    /// negative and must add up to more than zero, adding up to one keeps the result
+    /// in `[0, 1]`
+    pub fn user_distance_ensemble(
+        &self,
+        user_a: U,
+        user_b: U,
+        methods: &[(UserMethod, f64)],
+    ) -> Result<f64, RecommendationError> {
+        let valid_weight = |weight: f64| weight.is_finite() && weight >= 0.0;
+        if !methods.iter().all(|(_, weight)| valid_weight(*weight)) {
+            return Err(ErrorKind::InvalidParameter(
+                "ensemble weights must be finite and non negative".into(),
+            )
+            .into());
+        }
+
+        if methods.iter().map(|(_, weight)| weight).sum::<f64>() <= 0.0 {
+            return Err(ErrorKind::InvalidParameter(
+                "ensemble weights must add up to more than zero".into(),
+            )
+            .into());
+        }
+
+        let rating_a = self.user_ratings(&user_a)?;
+        let rating_b = self.user_ratings(&user_b)?;
+
+        let mut ensemble = 0.0;
+        for (method, weight) in methods {
+            let value = distances::users::distance(&rating_a, &rating_b, *method)?;
+            ensemble += weight * method.unit_similarity(value);
+        }
+
+        Ok(ensemble)
+    }
+
+    /// Number of items rated by both `user_a` and `user_b`, a cheap way to know if
+    /// two users are comparable at all before computing a distance
+    pub fn overlap(&self, user_a: U, user_b: U) -> Result<usize, RecommendationError> {
+        let rating_a = self.user_ratings(&user_a)?;
+        let rating_b = self.user_ratings(&user_b)?;
+
+        Ok(common_keys_iter(&rating_a, &rating_b).count())
+    }
+
+    /// Pairwise overlaps of `users`, the value at `[i][j]` is the number of items
+    /// rated by both `users[i]` and `users[j]`, so the diagonal holds the number
+    /// of ratings of each user
+    pub fn overlap_matrix(&self, users: &[U]) -> Result<Vec<Vec<usize>>, RecommendationError> {
+        let ratings = users
+            .iter()
+            .map(|user| self.user_ratings(user))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut matrix = vec![vec![0; users.len()]; users.len()];
+        for i in 0..ratings.len() {
+            matrix[i][i] = ratings[i].len();
+            for j in (i + 1)..ratings.len() {
+                let overlap = common_keys_iter(&ratings[i], &ratings[j]).count();
+                matrix[i][j] = overlap;
+                matrix[j][i] = overlap;
+            }
+        }
+
+        Ok(matrix)
+    }
+
+    /// Like `user_distance` but each common item is weighted by its inverse user
+    /// frequency (IUF), `ln(N / n_i)`, so items rated by almost everyone carry
+    /// little weight. Only cosine similarity and Pearson's correlation support it
+    pub fn user_distance_idf(
+        &self,
+        user_a: U,
+        user_b: U,
+        method: UserMethod,
+    ) -> Result<f64, RecommendationError> {
+        let rating_a = self.user_ratings(&user_a)?;
+        let rating_b = self.user_ratings(&user_b)?;
+
+        let common_items: Vec<_> = rating_a
+            .keys()
+            .filter(|item_id| rating_b.contains_key(item_id))
+            .cloned()
+            .collect();
+
+        let partial_items = self.controller.create_partial_items(&common_items)?;
+        let counts: HashMap<_, _> = self
+            .controller
+            .users_who_rated(&partial_items)?
+            .into_iter()
+            .map(|(item_id, ratings)| (item_id, ratings.len()))
+            .collect();
+
+        let total_users = self.controller.users_count()?;
+        let weights = distances::users::inverse_user_frequency(&counts, total_users)?;
+
+        match method {
+            UserMethod::CosineSimilarity => Ok(distances::users::weighted_cosine_similarity(
+                &rating_a, &rating_b, &weights,
+            )?),
+            UserMethod::PearsonCorrelation => Ok(distances::users::weighted_pearson_correlation(
+                &rating_a, &rating_b, &weights,
+            )?),
+            _ => Err(ErrorKind::NotImplemented.into()),
+        }
+    }
+
+    /// Pearson's correlation between `user_a` and `user_b` where each common item
+    /// contributes proportionally to its confidence in `weights` (how recently or
+    /// how often it was rated, for example). Items without a weight weigh one, so
+    /// uniform weights give the plain correlation
+    pub fn user_distance_weighted_pearson(
+        &self,
+        user_a: U,
+        user_b: U,
+        weights: &HashMap<eid!(I), f64>,
+    ) -> Result<f64, RecommendationError> {
+        let rating_a = self.user_ratings(&user_a)?;
+        let rating_b = self.user_ratings(&user_b)?;
+
+        distances::users::weighted_pearson_correlation(&rating_a, &rating_b, weights)
+            .map_err(Into::into)
+    }
+
     pub fn item_distance(
         &mut self,
         item_a: I,
         item_b: I,
         method: ItemMethod,
-    ) -> Result<f64, Error> {
+    ) -> Result<f64, RecommendationError> {
         match method {
             ItemMethod::AdjCosine => {
                 let item_a_id = item_a.get_id();
@@ -107,18 +590,28 @@ where
                     .filter(|uid| !self.adj_cosine.borrow().has_mean_for(uid))
                     .collect();
 
-                let all_partial_users = self.controller.create_partial_users(&all_users)?;
+                // Every mean could be already loaded, avoid querying for nothing
+                if !all_users.is_empty() {
+                    let all_partial_users = self.controller.create_partial_users(&all_users)?;
 
-                let partial_users_chunk_size = self.config.engine.partial_users_chunk_size;
-                for partial_users_chunk in all_partial_users.chunks(partial_users_chunk_size) {
-                    let mean_chunk = self.controller.users_means(partial_users_chunk)?;
-                    self.adj_cosine.borrow_mut().push_means(&mean_chunk);
+                    let partial_users_chunk_size = self.config.engine.partial_users_chunk_size;
+                    for partial_users_chunk in all_partial_users.chunks(partial_users_chunk_size) {
+                        let mean_chunk = self.controller.users_means(partial_users_chunk)?;
+                        self.adj_cosine.borrow_mut().push_means(&mean_chunk);
+                    }
                 }
 
+                let item_a_ratings = users_who_rated
+                    .get(&item_a_id)
+                    .ok_or_else(|| ErrorKind::ColdItem(format!("{:?}", item_a_id)))?;
+                let item_b_ratings = users_who_rated
+                    .get(&item_b_id)
+                    .ok_or_else(|| ErrorKind::ColdItem(format!("{:?}", item_b_id)))?;
+
                 let sim = self
                     .adj_cosine
                     .borrow_mut()
-                    .calculate(&users_who_rated[&item_a_id], &users_who_rated[&item_b_id])?;
+                    .calculate(item_a_ratings, item_b_ratings)?;
 
                 Ok(sim)
             }
@@ -127,633 +620,4191 @@ where
                 let item_a_id = item_a.get_id();
                 let item_b_id = item_b.get_id();
                 let users_who_rated = self.controller.users_who_rated(&[item_a, item_b])?;
+                let item_a_ratings = users_who_rated
+                    .get(&item_a_id)
+                    .ok_or_else(|| ErrorKind::ColdItem(format!("{:?}", item_a_id)))?;
+                let item_b_ratings = users_who_rated
+                    .get(&item_b_id)
+                    .ok_or_else(|| ErrorKind::ColdItem(format!("{:?}", item_b_id)))?;
+
+                let (dev, _) = slope_one(item_a_ratings, item_b_ratings)?;
+
+                Ok(dev)
+            }
+
+            ItemMethod::BiPolarSlopeOne => {
+                let item_a_id = item_a.get_id();
+                let item_b_id = item_b.get_id();
+                let users_who_rated = self.controller.users_who_rated(&[item_a, item_b])?;
+                let item_a_ratings = users_who_rated
+                    .get(&item_a_id)
+                    .ok_or_else(|| ErrorKind::ColdItem(format!("{:?}", item_a_id)))?;
+                let item_b_ratings = users_who_rated
+                    .get(&item_b_id)
+                    .ok_or_else(|| ErrorKind::ColdItem(format!("{:?}", item_b_id)))?;
+
+                let common_users: Vec<_> = item_a_ratings
+                    .keys()
+                    .filter(|user_id| item_b_ratings.contains_key(user_id))
+                    .cloned()
+                    .collect();
+
+                let means = self.users_means_by_ids(&common_users)?;
                 let (dev, _) =
-                    slope_one(&users_who_rated[&item_a_id], &users_who_rated[&item_b_id])?;
+                    bipolar_slope_one(item_a_ratings, item_b_ratings, &means)?.combined()?;
 
                 Ok(dev)
             }
         }
     }
 
+    /// The `n` items most similar to `item`, the item based counterpart of `user_knn`.
+    /// The users who rated `item` and their means are fetched once, then the other
+    /// items are visited by chunks of `chunk_size`. Only `adj_cosine` is a similarity,
+    /// the slope one methods are deviations and can't rank neighbors
+    pub fn item_neighbors(
+        &self,
+        item: I,
+        n: usize,
+        method: ItemMethod,
+        chunk_size: usize,
+    ) -> Result<Vec<(eid!(I), f64)>, RecommendationError> {
+        check_chunk_size(chunk_size)?;
+
+        if n == 0 {
+            return Err(ErrorKind::EmptyKNearestNeighbors.into());
+        }
+
+        if method != ItemMethod::AdjCosine {
+            return Err(ErrorKind::InvalidParameter(format!(
+                "{} isn't a similarity between items",
+                method
+            ))
+            .into());
+        }
+
+        let item_id = item.get_id();
+        let item_users = self
+            .controller
+            .users_who_rated(&[item])?
+            .remove(&item_id)
+            .unwrap_or_default();
+
+        if item_users.is_empty() {
+            return Err(ErrorKind::ColdItem(format!("{:?}", item_id)).into());
+        }
+
+        // Only the common users are compared, so the means of the users who rated
+        // the target are all that is needed
+        let mut adj_cosine = self.adj_cosine.borrow_mut();
+        adj_cosine.shrink_means();
+
+        let missing_users: Vec<_> = item_users
+            .keys()
+            .filter(|user_id| !adj_cosine.has_mean_for(user_id))
+            .cloned()
+            .collect();
+        if !missing_users.is_empty() {
+            let partial_users = self.controller.create_partial_users(&missing_users)?;
+
+            let partial_users_chunk_size = self.config.engine.partial_users_chunk_size;
+            for partial_users_chunk in partial_users.chunks(partial_users_chunk_size) {
+                let mean_chunk = self.controller.users_means(partial_users_chunk)?;
+                adj_cosine.push_means(&mean_chunk);
+            }
+        }
+
+        let mut neighbors = BoundedTopN::new(n);
+        for items in self.controller.items_by_chunks(chunk_size) {
+            let items: Vec<_> = items
+                .into_iter()
+                .filter(|other| other.get_id() != item_id)
+                .collect();
+
+            if items.is_empty() {
+                continue;
+            }
+
+            for (other_id, other_users) in self.controller.users_who_rated(&items)? {
+                if let Ok(similarity) = adj_cosine.calculate(&item_users, &other_users) {
+                    neighbors.push(Scored(similarity, other_id));
+                }
+            }
+        }
+
+        Ok(neighbors
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Scored(similarity, other_id)| (other_id, similarity))
+            .collect())
+    }
+
+    /// Baseline prediction `μ + b_u + b_i`, where `μ` is the global mean, `b_u`
+    /// the deviation of the user mean and `b_i` the deviation of the item mean.
+    /// An unrated user or item simply has no deviation, the result is clamped to
+    /// the controller score range
+    pub fn baseline_predict(&self, user: U, item: I) -> Result<f64, RecommendationError> {
+        let item_id = item.get_id();
+        let global_mean = self
+            .controller
+            .global_mean()?
+            .ok_or(ErrorKind::EmptyRatings)?;
+
+        fn mean_deviation<K>(ratings: &Ratings<K>, global_mean: f64) -> f64 {
+            if ratings.is_empty() {
+                0.0
+            } else {
+                ratings.values().sum::<f64>() / ratings.len() as f64 - global_mean
+            }
+        }
+
+        let user_ratings = self.user_ratings(&user)?;
+        let item_ratings = self
+            .controller
+            .users_who_rated(&[item])?
+            .remove(&item_id)
+            .unwrap_or_default();
+
+        let user_bias = mean_deviation(&user_ratings, global_mean);
+        let item_bias = mean_deviation(&item_ratings, global_mean);
+
+        let (min_rating, max_rating) = self.controller.score_range();
+        let prediction = global_mean + user_bias + item_bias;
+
+        Ok(prediction.max(min_rating).min(max_rating))
+    }
+
+    /// Find the `k` nearest neighbors of `user`, or with `reverse` the `k` least
+    /// similar users (the most distant ones for distance methods). When no neighbor
+    /// is found the error tells why (see `EmptyKnnReason`)
     pub fn user_knn(
         &self,
         k: usize,
         user: U,
         method: UserMethod,
         chunk_size: Option<usize>,
-    ) -> Result<Vec<(eid!(U), f64)>, Error> {
+        reverse: bool,
+    ) -> Result<Vec<(eid!(U), f64)>, RecommendationError> {
+        check_chunk_size(chunk_size)?;
+
         if k == 0 {
             return Err(ErrorKind::EmptyKNearestNeighbors.into());
         }
 
-        let user_ratings = self.controller.user_ratings(&user)?;
-        let mut knn: Box<dyn Knn<eid!(U), eid!(I)>> = if method.is_similarity() {
-            Box::new(MinHeapKnn::new(k, method))
-        } else {
-            Box::new(MaxHeapKnn::new(k, method))
-        };
+        // Reversed, the heaps keep the least similar (most distant) users instead
+        let user_id = user.get_id();
+        let user_ratings = self.user_ratings(&user)?;
+        let mut knn = knn::select_heap(k, method, reverse);
 
         if let Some(chunk_size) = chunk_size {
+            // The chunks contain every user, the target too
             let users_chunks = self.controller.users_by_chunks(chunk_size);
             for users in users_chunks {
-                let maped_ratings = self.controller.users_ratings(&users)?;
+                let maped_ratings = self
+                    .users_ratings_chunk(&users)?
+                    .into_iter()
+                    .filter(|(id, _)| id != &user_id)
+                    .collect();
+
                 knn.update(&user_ratings, maped_ratings);
             }
         } else {
-            let maped_ratings = self.controller.users_ratings_except(&user)?;
+            let maped_ratings = self.users_ratings_except(&user)?;
             knn.update(&user_ratings, maped_ratings);
         }
 
+        let stats = knn.stats();
         let knn: Vec<_> = knn
             .into_vec()
             .into_iter()
             .map(|MapedDistance(id, dist, _)| (id, dist))
             .collect();
 
-        if knn.is_empty() {
-            Err(ErrorKind::EmptyKNearestNeighbors.into())
-        } else {
+        if !knn.is_empty() {
             Ok(knn)
+        } else if user_ratings.is_empty() {
+            Err(ErrorKind::NoNeighbors(EmptyKnnReason::UserHasNoRatings).into())
+        } else if stats.overlapping == 0 {
+            Err(ErrorKind::NoNeighbors(EmptyKnnReason::NoOverlappingUsers).into())
+        } else {
+            Err(ErrorKind::NoNeighbors(EmptyKnnReason::AllDistancesInvalid).into())
         }
     }
 
-    pub fn user_based_predict(
+    /// The `k` nearest neighbors of every user, like calling `user_knn` for each one
+    /// of them but each chunk of `chunk_size` users is loaded once for a whole chunk
+    /// of targets, instead of once per target. Users without neighbors are left out
+    #[allow(clippy::type_complexity)]
+    pub fn all_user_knn(
         &self,
         k: usize,
-        user: U,
-        item: I,
         method: UserMethod,
-        chunk_size: Option<usize>,
-    ) -> Result<f64, Error> {
-        let item_id = item.get_id();
-        let user_ratings = self.controller.user_ratings(&user)?;
+        chunk_size: usize,
+    ) -> Result<HashMap<eid!(U), Vec<(eid!(U), f64)>>, RecommendationError> {
+        check_chunk_size(chunk_size)?;
 
-        let mut knn: Box<dyn Knn<eid!(U), eid!(I)>> = if method.is_similarity() {
-            Box::new(MinHeapKnn::new(k, method))
-        } else {
-            Box::new(MaxHeapKnn::new(k, method))
-        };
+        if k == 0 {
+            return Err(ErrorKind::EmptyKNearestNeighbors.into());
+        }
 
-        if let Some(chunk_size) = chunk_size {
-            let users_chunks = self.controller.users_by_chunks(chunk_size);
-            for users in users_chunks {
-                let maped_ratings = self
-                    .controller
-                    .users_ratings(&users)?
+        let mut all_knn = HashMap::new();
+        for targets in self.controller.users_by_chunks(chunk_size) {
+            let targets_ratings = self.users_ratings_chunk(&targets)?;
+            let mut heaps: Vec<_> = targets_ratings
+                .into_iter()
+                .map(|(id, ratings)| (id, ratings, knn::select_heap(k, method, false)))
+                .collect();
+
+            // Each chunk of candidates is loaded and transformed once for all the
+            // targets, their ratings are only cloned when they get into a heap
+            for users in self.controller.users_by_chunks(chunk_size) {
+                let maped_ratings = self.users_ratings_chunk(&users)?;
+                for (target_id, target_ratings, knn) in heaps.iter_mut() {
+                    let mut candidates = maped_ratings.iter().filter(|(id, _)| *id != target_id);
+                    knn.update_borrowed(target_ratings, &mut candidates);
+                }
+            }
+
+            for (target_id, _, knn) in heaps {
+                let knn: Vec<_> = knn
+                    .into_vec()
                     .into_iter()
-                    .filter(|(_, ratings)| ratings.contains_key(&item_id))
+                    .map(|MapedDistance(id, dist, _)| (id, dist))
                     .collect();
 
-                knn.update(&user_ratings, maped_ratings);
+                if !knn.is_empty() {
+                    all_knn.insert(target_id, knn);
+                }
             }
-        } else {
-            let maped_ratings = self
-                .controller
-                .users_ratings_except(&user)?
-                .into_iter()
-                .filter(|(_id, ratings)| ratings.contains_key(&item_id))
-                .collect();
-
-            knn.update(&user_ratings, maped_ratings);
         }
 
-        #[allow(clippy::type_complexity)]
-        let pearson_knn: Vec<_> = knn
+        Ok(all_knn)
+    }
+
+    /// Approximate version of `user_knn`, exact distances are only computed against
+    /// the users that share an LSH bucket with `user` (see `Lsh`). More bands find
+    /// more candidates (better recall), more hashes per band make buckets smaller
+    /// (faster). The index is built on the first call and reused while `n_hashes`
+    /// and `n_bands` don't change
+    pub fn user_knn_approx(
+        &self,
+        k: usize,
+        user: U,
+        method: UserMethod,
+        n_hashes: usize,
+        n_bands: usize,
+    ) -> Result<Vec<(eid!(U), f64)>, RecommendationError> {
+        if k == 0 {
+            return Err(ErrorKind::EmptyKNearestNeighbors.into());
+        }
+
+        let user_id = user.get_id();
+        let user_ratings = self.user_ratings(&user)?;
+        if user_ratings.is_empty() {
+            return Err(ErrorKind::NoNeighbors(EmptyKnnReason::UserHasNoRatings).into());
+        }
+
+        self.build_lsh_index(n_hashes, n_bands)?;
+        let candidates: Vec<_> = match &*self.lsh_index.borrow() {
+            Some(index) => index
+                .candidates(&user_ratings)
+                .into_iter()
+                .filter(|id| id != &user_id)
+                .collect(),
+            None => Vec::new(),
+        };
+
+        let mut knn = knn::select_heap(k, method, false);
+
+        let partial_users = self.controller.create_partial_users(&candidates)?;
+        let maped_ratings = self.users_ratings_chunk(&partial_users)?;
+        knn.update(&user_ratings, maped_ratings);
+
+        let stats = knn.stats();
+        let knn: Vec<_> = knn
             .into_vec()
             .into_iter()
-            .filter_map(
-                |MapedDistance(id, _, ratings)| -> Option<(MapedDistance<eid!(U), eid!(I)>, f64)> {
-                    let nn_ratings = ratings?;
+            .map(|MapedDistance(id, dist, _)| (id, dist))
+            .collect();
 
-                    if !nn_ratings.contains_key(&item_id) {
-                        return None;
-                    }
+        if !knn.is_empty() {
+            Ok(knn)
+        } else if stats.overlapping == 0 {
+            Err(ErrorKind::NoNeighbors(EmptyKnnReason::NoOverlappingUsers).into())
+        } else {
+            Err(ErrorKind::NoNeighbors(EmptyKnnReason::AllDistancesInvalid).into())
+        }
+    }
 
-                    let coef = distances::users::distance(
-                        &user_ratings,
-                        &nn_ratings,
-                        UserMethod::PearsonApproximation,
-                    )
-                    .ok()?;
+    /// Stream the edges of the user kNN graph, `emit` is called with a
+    /// `(user, neighbor, value)` edge for each of the `k` nearest neighbors of every
+    /// user, so the graph never has to fit in memory. Users are visited by chunks of
+    /// `chunk_size` (also used by `user_knn`), those without neighbors have no edges.
+    /// Returns the number of edges
+    pub fn knn_graph_with<F>(
+        &self,
+        k: usize,
+        method: UserMethod,
+        chunk_size: usize,
+        mut emit: F,
+    ) -> Result<usize, RecommendationError>
+    where
+        F: FnMut(eid!(U), eid!(U), f64) -> Result<(), Error>,
+    {
+        check_chunk_size(chunk_size)?;
 
-                    Some((MapedDistance(id, coef, None), *nn_ratings.get(&item_id)?))
-                },
-            )
-            .collect();
+        if k == 0 {
+            return Err(ErrorKind::EmptyKNearestNeighbors.into());
+        }
 
-        let total = pearson_knn
-            .iter()
-            .fold(0.0, |acc, (maped_distance, _)| acc + maped_distance.dist());
+        let mut edges = 0;
+        for users_chunk in self.controller.users_by_chunks(chunk_size) {
+            for user in users_chunk {
+                let user_id = user.get_id();
 
-        let mut prediction = None;
-        for (maped_distance, nn_rating) in pearson_knn {
-            *prediction.get_or_insert(0.0) += nn_rating * (maped_distance.dist() / total);
+                let neighbors = match self.user_knn(k, user, method, Some(chunk_size), false) {
+                    Ok(neighbors) => neighbors,
+                    Err(e) if e.kind().is_some() => continue,
+                    Err(e) => return Err(e),
+                };
+
+                for (neighbor_id, value) in neighbors {
+                    emit(user_id.clone(), neighbor_id, value)?;
+                    edges += 1;
+                }
+            }
         }
 
-        prediction.ok_or_else(|| ErrorKind::EmptyKNearestNeighbors.into())
+        Ok(edges)
     }
 
-    fn adj_cosine_predict(&self, user: U, item: I, chunk_size: usize) -> Result<f64, Error> {
-        let user_id = user.get_id();
-        let item_id = item.get_id();
+    /// The whole user kNN graph as a `(user, neighbor, value)` edge list, see
+    /// `knn_graph_with` to stream it instead
+    #[allow(clippy::type_complexity)]
+    pub fn knn_graph(
+        &self,
+        k: usize,
+        method: UserMethod,
+        chunk_size: usize,
+    ) -> Result<Vec<(eid!(U), eid!(U), f64)>, RecommendationError> {
+        let mut edges = Vec::new();
+        self.knn_graph_with(k, method, chunk_size, |user_id, neighbor_id, value| {
+            edges.push((user_id, neighbor_id, value));
+            Ok(())
+        })?;
+
+        Ok(edges)
+    }
 
-        log::info!(
-            "Predicting score for user({:?}) for item({:?})",
-            user_id,
-            item_id
-        );
+    /// Hash every user into a new LSH index, unless the current one already uses
+    /// `n_hashes` and `n_bands`
+    fn build_lsh_index(&self, n_hashes: usize, n_bands: usize) -> Result<(), Error> {
+        if let Some(index) = &*self.lsh_index.borrow() {
+            if index.n_hashes() == n_hashes && index.n_bands() == n_bands {
+                return Ok(());
+            }
+        }
 
-        log::info!("Gathering user({:?}) ratings", user_id);
-        let user_ratings = self.controller.user_ratings(&user)?;
-        let (min_rating, max_rating) = self.controller.score_range();
-        log::info!("Normalizing user({:?}) ratings", user_id);
-        let normalized_ratings = normalize_user_ratings(&user_ratings, min_rating, max_rating)?;
+        let mut index = Lsh::new(n_hashes, n_bands)?;
+        let chunk_size = self.config.engine.partial_users_chunk_size;
+        for users in self.controller.users_by_chunks(chunk_size) {
+            let maped_ratings = self.users_ratings_chunk(&users)?;
+            for (user_id, ratings) in maped_ratings {
+                index.insert(user_id, &ratings);
+            }
+        }
 
-        log::info!("Gathering users who rated for target item");
-        let target_items_users = self.controller.users_who_rated(&[item])?;
-        log::info!(
-            "Gathered {} scores for this item",
-            target_items_users[&item_id].len()
-        );
+        *self.lsh_index.borrow_mut() = Some(index);
+        Ok(())
+    }
 
-        let mut num = 0.0;
-        let mut dem = 0.0;
+    /// Find the k nearest neighbors of `user` that rated `item`, returns each
+    /// neighbor with its Pearson's correlation and its rating for `item`, along
+    /// with the sum of all the correlations
+    #[allow(clippy::type_complexity)]
+    fn user_based_neighbors(
+        &self,
+        k: usize,
+        user: U,
+        item: I,
+        method: UserMethod,
+        chunk_size: Option<usize>,
+    ) -> Result<(Vec<(eid!(U), f64, f64)>, f64), Error> {
+        let user_ratings = self.user_ratings(&user)?;
+        if user_ratings.is_empty() {
+            return Err(ErrorKind::ColdUser(format!("{:?}", user.get_id())).into());
+        }
 
-        let mut adj_cosine = AdjCosine::new();
+        self.neighbors_for_ratings(k, &user_ratings, item, method, chunk_size, Some(&user))
+    }
 
-        let mut means_time = 0.0;
-        let mut iters_time = 0.0;
-        let mut uwrs_time = 0.0;
+    /// Same as `user_based_neighbors` but for an arbitrary set of ratings, the user
+    /// in `except` (if any) is never considered a neighbor
+    #[allow(clippy::type_complexity)]
+    fn neighbors_for_ratings(
+        &self,
+        k: usize,
+        user_ratings: &ratings!(I),
+        item: I,
+        method: UserMethod,
+        chunk_size: Option<usize>,
+        except: Option<&U>,
+    ) -> Result<(Vec<(eid!(U), f64, f64)>, f64), Error> {
+        let item_id = item.get_id();
 
-        log::info!("Iterating items by chunks of size {}", chunk_size);
-        let items_chunks = self.controller.items_by_chunks(chunk_size);
-        for item_chunk_base in items_chunks {
-            log::info!("Initial chunk size: {}", item_chunk_base.len());
-            let now = Instant::now();
-            let item_chunk: Vec<_> = item_chunk_base
-                .into_iter()
-                .filter(|other_item| user_ratings.contains_key(&other_item.get_id()))
-                .collect();
-            log::info!("Chunk size after filter: {}", item_chunk.len());
-            log::info!(
-                "Filtering chunk took {} seconds",
-                now.elapsed().as_secs_f64()
-            );
+        let mut knn = knn::select_heap(k, method, false);
 
-            if item_chunk.is_empty() {
-                continue;
+        if let Some(chunk_size) = chunk_size {
+            let except_id = except.map(Entity::get_id);
+            let users_chunks = self.controller.users_by_chunks(chunk_size);
+            for users in users_chunks {
+                let maped_ratings = self
+                    .users_ratings_chunk(&users)?
+                    .into_iter()
+                    .filter(|(id, ratings)| {
+                        Some(id) != except_id.as_ref() && ratings.contains_key(&item_id)
+                    })
+                    .collect();
+
+                knn.update(user_ratings, maped_ratings);
             }
+        } else {
+            let maped_ratings = match except {
+                Some(user) => self.users_ratings_except(user)?,
+                None => self.all_users_ratings()?,
+            };
 
-            log::info!("Gathering users who rated for {} items", item_chunk.len());
-            let now = Instant::now();
-            let mut users_who_rated: maped_ratings!(I => U) = self
-                .controller
-                .users_who_rated(&item_chunk)?
+            let maped_ratings = maped_ratings
                 .into_iter()
-                .filter(|(_, ratings)| ratings.contains_key(&user_id))
+                .filter(|(_id, ratings)| ratings.contains_key(&item_id))
                 .collect();
 
-            let uwr_time = now.elapsed().as_secs_f64();
-            uwrs_time += uwr_time;
-            log::info!(
-                "Gathered a total of {} inverted maped ratings",
-                users_who_rated.len()
-            );
-            log::info!("Gathering users who rated took {} seconds", uwr_time);
-
-            users_who_rated.insert(item_id.clone(), target_items_users[&item_id].clone());
+            knn.update(user_ratings, maped_ratings);
+        }
 
-            let all_users_iter = users_who_rated.values();
-            let mut all_users = HashSet::new();
+        let pearson_knn: Vec<_> = knn
+            .into_vec()
+            .into_iter()
+            .filter_map(|MapedDistance(id, _, ratings)| {
+                let nn_ratings = ratings?;
 
-            log::info!("Collecting all unique users ids");
-            for users in all_users_iter {
-                for user_id in users.keys() {
-                    all_users.insert(user_id.clone());
+                if !nn_ratings.contains_key(&item_id) {
+                    return None;
                 }
-            }
 
-            // Shrink some means by their usage frequency
-            log::info!("Shrinking means based on their usage");
-            adj_cosine.shrink_means();
+                // A neighbor with constant ratings is uncorrelated, keep it with no weight
+                let coef = distances::users::pearson_or_zero(user_ratings, &nn_ratings).ok()?;
 
-            // Collect all the users that doesn't have a calculated mean
-            log::info!("Filtering users that have a cached mean");
-            let all_users: Vec<_> = all_users
-                .into_iter()
-                .filter(|user_id| !adj_cosine.has_mean_for(user_id))
-                .collect();
-            let all_partial_users = self.controller.create_partial_users(&all_users)?;
+                Some((id, coef, *nn_ratings.get(&item_id)?))
+            })
+            .collect();
 
-            log::info!("Gathering means for {} users", all_partial_users.len());
-            let now = Instant::now();
-            let partial_users_chunk_size = self.config.engine.partial_users_chunk_size;
-            for partial_users_chunk in all_partial_users.chunks(partial_users_chunk_size) {
-                let mean_chunk = self.controller.users_means(partial_users_chunk)?;
-                adj_cosine.push_means(&mean_chunk);
+        let pearson_knn = match &self.recency_decay {
+            Some(decay) => {
+                let times = self.controller.ratings_with_time_by(&item)?;
+                pearson_knn
+                    .into_iter()
+                    .map(|(id, coef, nn_rating)| {
+                        let weight = times
+                            .get(&id)
+                            .map_or(1.0, |(_, timestamp)| decay.weight(*timestamp));
+                        (id, coef * weight, nn_rating)
+                    })
+                    .collect()
             }
-            let mean_time = now.elapsed().as_secs_f64();
-            log::info!("Obtaining took {} seconds", mean_time);
-            means_time += mean_time;
-
-            log::info!("Iterating over all the items of this chunk");
-            let now = Instant::now();
-            for other_item in &item_chunk {
-                let other_item_id = other_item.get_id();
 
-                if !normalized_ratings.contains_key(&other_item_id) || item_id == other_item_id {
-                    continue;
-                }
+            None => pearson_knn,
+        };
 
-                if let Ok(similarity) = adj_cosine
-                    .calculate(&users_who_rated[&item_id], &users_who_rated[&other_item_id])
-                {
-                    num += similarity * normalized_ratings[&other_item_id];
-                    dem += similarity.abs();
-                }
-            }
+        let total = pearson_knn.iter().fold(0.0, |acc, (_, coef, _)| acc + coef);
 
-            let iter_time = now.elapsed().as_secs_f64();
-            log::info!("Iterating over the items took {} seconds", iter_time);
-            iters_time += iter_time;
+        if pearson_knn.is_empty() {
+            return Err(ErrorKind::EmptyKNearestNeighbors.into());
         }
 
-        log::info!("Gathering means took in total {} seconds", means_time);
-        log::info!(
-            "Gathering users who rated took in total {} seconds",
-            uwrs_time
-        );
-        log::info!("Computing distances took in total {} seconds", iters_time);
-        if dem.is_zero() {
+        if total.is_zero() {
             return Err(ErrorKind::DivisionByZero.into());
         }
 
-        log::info!("Denormalizing the final score");
-        Ok(denormalize_user_rating(num / dem, min_rating, max_rating)?)
+        Ok((pearson_knn, total))
     }
 
-    pub fn slope_one_predict(&self, user: U, item: I, chunk_size: usize) -> Result<f64, Error> {
-        let target_item_id = item.get_id();
-        let target_item_ratings = &self.controller.users_who_rated(&[item])?[&target_item_id];
+    /// Predict the score of `user` for `item` using its k nearest neighbors, each
+    /// neighbor is weighted by its Pearson's correlation with `user`. Neighbors whose
+    /// correlation is indeterminate (e.g. they rated everything with the same value)
+    /// are considered uncorrelated and don't contribute to the prediction. Fails with
+    /// `InsufficientNeighbors` when less than `min_neighbors` neighbors rated `item`.
+    /// The prediction is adjusted with `post`, clamped to the score range by default
+    #[allow(clippy::too_many_arguments)]
+    pub fn user_based_predict(
+        &self,
+        k: usize,
+        user: U,
+        item: I,
+        method: UserMethod,
+        chunk_size: Option<usize>,
+        min_neighbors: usize,
+        post: Option<PostProcess>,
+    ) -> Result<f64, RecommendationError> {
+        check_chunk_size(chunk_size)?;
+
+        let (pearson_knn, total) = self.user_based_neighbors(k, user, item, method, chunk_size)?;
+        if pearson_knn.len() < min_neighbors {
+            return Err(ErrorKind::InsufficientNeighbors(pearson_knn.len(), min_neighbors).into());
+        }
 
-        let user_ratings: Ratings<_, _> = self
-            .controller
-            .user_ratings(&user)?
+        let prediction = pearson_knn
             .into_iter()
-            .filter(|(id, _)| id != &target_item_id)
-            .collect();
+            .fold(0.0, |acc, (_, coef, nn_rating)| {
+                acc + nn_rating * (coef / total)
+            });
 
-        let items_ids: Vec<_> = user_ratings.iter().map(|(id, _)| id.to_owned()).collect();
-        let all_partial_items = self.controller.create_partial_items(&items_ids)?;
+        Ok(self.predict_postprocess(prediction, post.unwrap_or_default()))
+    }
+
+    /// Fold-in prediction for a user that isn't stored yet (e.g. it just signed up),
+    /// works like `user_based_predict` but the neighbors are found for `ratings`.
+    /// The prediction is clamped to the score range
+    pub fn predict_for_new_user(
+        &self,
+        ratings: &ratings!(I),
+        item: I,
+        k: usize,
+        method: UserMethod,
+        chunk_size: Option<usize>,
+    ) -> Result<f64, RecommendationError> {
+        check_chunk_size(chunk_size)?;
+
+        if ratings.is_empty() {
+            return Err(ErrorKind::ColdUser("new user".into()).into());
+        }
+
+        let (pearson_knn, total) =
+            self.neighbors_for_ratings(k, ratings, item, method, chunk_size, None)?;
+
+        let prediction = pearson_knn
+            .into_iter()
+            .fold(0.0, |acc, (_, coef, nn_rating)| {
+                acc + nn_rating * (coef / total)
+            });
+
+        Ok(self.predict_postprocess(prediction, PostProcess::Clamp))
+    }
+
+    /// Adjust a raw prediction to the score range of the controller
+    pub fn predict_postprocess(&self, raw: f64, post: PostProcess) -> f64 {
+        post.apply(raw, self.controller.score_range())
+    }
+
+    /// Same as `user_based_predict` but also returns a 95% confidence interval,
+    /// as `(prediction, lower, upper)`.
+    ///
+    /// The neighbors ratings are treated as a weighted sample around the prediction,
+    /// weighted by the magnitude of their correlation. The interval is built from
+    /// the weighted variance of that sample and its effective size (Kish's), assuming
+    /// the ratings are normally distributed, so few (or unevenly weighted) neighbors
+    /// widen it. The bounds aren't clamped to the score range
+    pub fn user_based_predict_ci(
+        &self,
+        k: usize,
+        user: U,
+        item: I,
+        method: UserMethod,
+        chunk_size: Option<usize>,
+    ) -> Result<(f64, f64, f64), RecommendationError> {
+        check_chunk_size(chunk_size)?;
+
+        const Z_95: f64 = 1.96;
+
+        let (pearson_knn, total) = self.user_based_neighbors(k, user, item, method, chunk_size)?;
+
+        let prediction = pearson_knn.iter().fold(0.0, |acc, (_, coef, nn_rating)| {
+            acc + nn_rating * (coef / total)
+        });
+
+        let (sum_w, sum_w2, sum_wd2) =
+            pearson_knn
+                .iter()
+                .fold((0.0, 0.0, 0.0), |(sw, sw2, swd2), (_, coef, nn_rating)| {
+                    let w = coef.abs();
+                    let d = nn_rating - prediction;
+                    (sw + w, sw2 + w * w, swd2 + w * d * d)
+                });
+
+        if sum_w2.is_zero() {
+            return Err(ErrorKind::DivisionByZero.into());
+        }
+
+        let variance = sum_wd2 / sum_w;
+        let effective_size = sum_w * sum_w / sum_w2;
+        let margin = Z_95 * (variance / effective_size).sqrt();
+
+        Ok((prediction, prediction - margin, prediction + margin))
+    }
+
+    /// Predict the preference (between 0 and 1) of `user` for `item` treating the
+    /// ratings as implicit feedback (see `Implicit`). The k nearest neighbors are
+    /// found over the confidence weighted preferences, and each neighbor contributes
+    /// its preference for `item` weighted by its similarity and its confidence, so
+    /// neighbors with higher counts pull the prediction harder. Only similarity
+    /// methods are supported
+    pub fn implicit_predict(
+        &self,
+        k: usize,
+        user: U,
+        item: I,
+        method: UserMethod,
+        implicit: Implicit,
+        chunk_size: Option<usize>,
+    ) -> Result<f64, RecommendationError> {
+        check_chunk_size(chunk_size)?;
+
+        if !method.is_similarity() {
+            return Err(ErrorKind::NotImplemented.into());
+        }
+
+        let item_id = item.get_id();
+        let user_ratings = implicit.transform(&self.user_ratings(&user)?);
+        if user_ratings.is_empty() {
+            return Err(ErrorKind::ColdUser(format!("{:?}", user.get_id())).into());
+        }
+
+        let mut knn = knn::select_heap(k, method, false);
+
+        if let Some(chunk_size) = chunk_size {
+            let user_id = user.get_id();
+            for users in self.controller.users_by_chunks(chunk_size) {
+                let mut maped_ratings = self.users_ratings_chunk(&users)?;
+                maped_ratings.remove(&user_id);
+
+                knn.update(&user_ratings, implicit.transform_maped(maped_ratings));
+            }
+        } else {
+            let maped_ratings = self.users_ratings_except(&user)?;
+            knn.update(&user_ratings, implicit.transform_maped(maped_ratings));
+        }
 
         let mut num = 0.0;
         let mut den = 0.0;
 
-        for partial_items_chunk in all_partial_items.chunks(chunk_size) {
-            let users_who_rated = self.controller.users_who_rated(partial_items_chunk)?;
-            for (item_id, ratings) in users_who_rated {
-                if let Ok((dev, card)) = slope_one(target_item_ratings, &ratings) {
-                    num += (dev + user_ratings[&item_id]) * card as f64;
-                    den += card as f64;
-                }
-            }
+        for MapedDistance(_, similarity, ratings) in knn.into_vec() {
+            // Unrated items have a preference of 0 with a confidence of 1
+            let (preference, confidence) = match ratings.and_then(|r| r.get(&item_id).copied()) {
+                Some(confidence) => (1.0, confidence),
+                None => (0.0, 1.0),
+            };
+
+            num += similarity * confidence * preference;
+            den += similarity.abs() * confidence;
         }
 
         if den.is_zero() {
-            Err(ErrorKind::DivisionByZero.into())
+            Err(ErrorKind::EmptyKNearestNeighbors.into())
         } else {
             Ok(num / den)
         }
     }
 
-    pub fn item_based_predict(
+    /// Explain a `user_based_predict` prediction, returns the neighbors that
+    /// contributed to it sorted by the magnitude of their contribution
+    pub fn explain_user_based(
         &self,
         user: U,
         item: I,
-        method: ItemMethod,
-        chunk_size: usize,
-    ) -> Result<f64, Error> {
-        match method {
-            ItemMethod::AdjCosine => self.adj_cosine_predict(user, item, chunk_size),
-            ItemMethod::SlopeOne => self.slope_one_predict(user, item, chunk_size),
-        }
-    }
-}
+        k: usize,
+        method: UserMethod,
+    ) -> Result<Vec<Contribution<eid!(U)>>, RecommendationError> {
+        let (pearson_knn, total) = self.user_based_neighbors(k, user, item, method, None)?;
+
+        let mut contributions: Vec<_> = pearson_knn
+            .into_iter()
+            .map(|(user_id, similarity, rating)| Contribution {
+                user_id,
+                similarity,
+                rating,
+                contribution: rating * (similarity / total),
+            })
+            .collect();
+
+        contributions.sort_by(|a, b| {
+            b.contribution
+                .abs()
+                .partial_cmp(&a.contribution.abs())
+                .unwrap_or(Ordering::Equal)
+        });
+
+        Ok(contributions)
+    }
+
+    /// Recommend the `n` items with the highest predicted score for `user`, the
+    /// candidates are the items rated by its k nearest neighbors (and not rated by
+    /// `user`), each prediction is weighted by the neighbors Pearson's correlation.
+    /// With `novelty` the candidates are ranked by `prediction - beta * ln(popularity)`
+    /// instead, where popularity is the number of ratings of the item, so a higher
+    /// `beta` favours less popular items (the returned scores are still predictions)
+    #[allow(clippy::too_many_arguments)]
+    pub fn user_based_top_n(
+        &self,
+        k: usize,
+        n: usize,
+        user: U,
+        method: UserMethod,
+        chunk_size: Option<usize>,
+        max_candidates: Option<usize>,
+        novelty: Option<f64>,
+    ) -> Result<Vec<(eid!(I), f64)>, RecommendationError> {
+        check_chunk_size(chunk_size)?;
+
+        if k == 0 {
+            return Err(ErrorKind::EmptyKNearestNeighbors.into());
+        }
+
+        let user_id = user.get_id();
+        let user_ratings = self.user_ratings(&user)?;
+        if user_ratings.is_empty() {
+            return Err(ErrorKind::ColdUser(format!("{:?}", user_id)).into());
+        }
+
+        let mut knn = knn::select_heap(k, method, false);
+
+        if let Some(chunk_size) = chunk_size {
+            let users_chunks = self.controller.users_by_chunks(chunk_size);
+            for users in users_chunks {
+                let maped_ratings = self
+                    .users_ratings_chunk(&users)?
+                    .into_iter()
+                    .filter(|(id, _)| id != &user_id)
+                    .collect();
+
+                knn.update(&user_ratings, maped_ratings);
+            }
+        } else {
+            let maped_ratings = self.users_ratings_except(&user)?;
+            knn.update(&user_ratings, maped_ratings);
+        }
+
+        // Accumulate (numerator, denominator) of the prediction for every candidate,
+        // along with the number of neighbors that rated it
+        let mut candidates: HashMap<eid!(I), (f64, f64, usize)> = HashMap::new();
+        for MapedDistance(_, _, ratings) in knn.into_vec() {
+            let nn_ratings = match ratings {
+                Some(nn_ratings) => nn_ratings,
+                None => continue,
+            };
+
+            let coef = match distances::users::pearson_or_zero(&user_ratings, &nn_ratings) {
+                Ok(coef) => coef,
+                Err(_) => continue,
+            };
+
+            for (item_id, score) in nn_ratings {
+                if user_ratings.contains_key(&item_id) {
+                    continue;
+                }
+
+                let (num, den, support) = candidates.entry(item_id).or_insert((0.0, 0.0, 0));
+                *num += score * coef;
+                *den += coef;
+                *support += 1;
+            }
+        }
+
+        let mut candidates: Vec<_> = candidates.into_iter().collect();
+        if let Some(max_candidates) = max_candidates {
+            // Keep the candidates rated by most of the neighbors
+            candidates.sort_by(|(_, (_, _, a)), (_, (_, _, b))| b.cmp(a));
+            candidates.truncate(max_candidates);
+        }
+
+        let top_n: Vec<_> = candidates
+            .into_iter()
+            .filter(|(_, (_, den, _))| !den.is_zero())
+            .map(|(item_id, (num, den, _))| (item_id, num / den))
+            .collect();
+
+        if top_n.is_empty() {
+            return Err(ErrorKind::EmptyKNearestNeighbors.into());
+        }
+
+        let mut top = BoundedTopN::new(n);
+        match novelty {
+            Some(beta) if !beta.is_zero() => {
+                // The popularity of all the candidates is queried at once
+                let items_ids: Vec<_> = top_n.iter().map(|(item_id, _)| item_id.clone()).collect();
+                let partial_items = self.controller.create_partial_items(&items_ids)?;
+                let popularity: HashMap<_, _> = self
+                    .controller
+                    .users_who_rated(&partial_items)?
+                    .into_iter()
+                    .map(|(item_id, ratings)| (item_id, ratings.len().max(1)))
+                    .collect();
+
+                for (item_id, prediction) in top_n {
+                    let count = popularity.get(&item_id).copied().unwrap_or(1);
+                    let score = prediction - beta * (count as f64).ln();
+                    top.push(Scored(score, (item_id, prediction)));
+                }
+            }
+
+            _ => {
+                for (item_id, prediction) in top_n {
+                    top.push(Scored(prediction, (item_id, prediction)));
+                }
+            }
+        }
+
+        Ok(top
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Scored(_, scored)| scored)
+            .collect())
+    }
+
+    /// Average nDCG@`n` of the `user_based_top_n` recommendations of each test user,
+    /// graded by the relevance of its held out items (which shouldn't be rated by
+    /// the user in the controller). Users without any relevant item are skipped, and
+    /// users the engine can't recommend for count as an empty ranking
+    pub fn evaluate_top_n_ndcg(
+        &self,
+        test_set: Vec<(U, Ratings<eid!(I)>)>,
+        k: usize,
+        n: usize,
+        method: UserMethod,
+        chunk_size: Option<usize>,
+    ) -> Result<f64, RecommendationError> {
+        let mut total = 0.0;
+        let mut evaluated = 0;
+
+        for (user, relevance) in test_set {
+            if relevance.values().all(|rel| *rel <= 0.0) {
+                continue;
+            }
+
+            let ranked: Vec<_> =
+                match self.user_based_top_n(k, n, user, method, chunk_size, None, None) {
+                    Ok(top_n) => top_n.into_iter().map(|(item_id, _)| item_id).collect(),
+                    Err(e) if e.kind().is_some() => Vec::new(),
+                    Err(e) => return Err(e),
+                };
+
+            total += eval::ndcg_at_k(&ranked, &relevance, n);
+            evaluated += 1;
+        }
+
+        if evaluated == 0 {
+            return Err(ErrorKind::EmptyRatings.into());
+        }
+
+        Ok(total / evaluated as f64)
+    }
+
+    /// Leave-one-out diagnosis of `user_based_predict` for `user`, each one of its
+    /// ratings is held out in turn and predicted from the rest of them. Returns the
+    /// `(item, actual, predicted)` triples ordered by item, the ratings that can't be
+    /// predicted are left out
+    #[allow(clippy::type_complexity)]
+    pub fn loo_user_report(
+        &self,
+        user: U,
+        method: UserMethod,
+        k: usize,
+    ) -> Result<Vec<(eid!(I), f64, f64)>, RecommendationError>
+    where
+        eid!(I): Ord,
+    {
+        let user_ratings = self.user_ratings(&user)?;
+        if user_ratings.is_empty() {
+            return Err(ErrorKind::ColdUser(format!("{:?}", user.get_id())).into());
+        }
+
+        let item_ids: Vec<_> = user_ratings.keys().cloned().collect();
+        let items = self.controller.create_partial_items(&item_ids)?;
+
+        let mut report = Vec::with_capacity(items.len());
+        for item in items {
+            let item_id = item.get_id();
+            let mut known_ratings = user_ratings.clone();
+            let actual = known_ratings.remove(&item_id).unwrap_or_default();
+
+            let neighbors =
+                self.neighbors_for_ratings(k, &known_ratings, item, method, None, Some(&user));
+            let (pearson_knn, total) = match neighbors.map_err(RecommendationError::from) {
+                Ok(neighbors) => neighbors,
+                Err(e) if e.kind().is_some() => continue,
+                Err(e) => return Err(e),
+            };
+
+            let prediction = pearson_knn
+                .into_iter()
+                .fold(0.0, |acc, (_, coef, nn_rating)| {
+                    acc + nn_rating * (coef / total)
+                });
+
+            let predicted = self.predict_postprocess(prediction, PostProcess::Clamp);
+            report.push((item_id, actual, predicted));
+        }
+
+        report.sort_by(|(a, _, _), (b, _, _)| a.cmp(b));
+        Ok(report)
+    }
+
+    /// Accuracy of `user_based_predict` on held out `(user, item, score)` rows, the
+    /// held out scores shouldn't be in the controller. Rows the engine can't
+    /// predict count as misses of the coverage
+    pub fn evaluate_user_based(
+        &self,
+        test_set: Vec<(U, I, f64)>,
+        k: usize,
+        method: UserMethod,
+        chunk_size: Option<usize>,
+    ) -> Result<eval::PredictionMetrics, RecommendationError> {
+        let total = test_set.len();
+        let mut predictions = Vec::with_capacity(total);
+
+        for (user, item, score) in test_set {
+            match self.user_based_predict(k, user, item, method, chunk_size, 1, None) {
+                Ok(predicted) => predictions.push((predicted, score)),
+                Err(e) if e.kind().is_some() => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(eval::PredictionMetrics::from_predictions(
+            &predictions,
+            total,
+        ))
+    }
+
+    /// How many of the `users` x `items` pairs `user_based_predict` can predict with
+    /// `k` neighbors, pairs that fail are counted by the reason they failed
+    pub fn coverage_report(
+        &self,
+        users: &[U],
+        items: &[I],
+        method: UserMethod,
+        k: usize,
+    ) -> Result<eval::CoverageReport, RecommendationError> {
+        let mut report = eval::CoverageReport::default();
+
+        let item_ids: Vec<_> = items.iter().map(|item| item.get_id()).collect();
+        let users_who_rated = self.controller.users_who_rated(items)?;
+
+        for user in users {
+            let user_ids = vec![user.get_id(); item_ids.len()];
+            let partial_users = self.controller.create_partial_users(&user_ids)?;
+            let partial_items = self.controller.create_partial_items(&item_ids)?;
+
+            for (user, item) in partial_users.into_iter().zip(partial_items) {
+                report.total += 1;
+
+                // Nobody can predict an item without ratings, don't look for neighbors
+                let rated = users_who_rated
+                    .get(&item.get_id())
+                    .is_some_and(|ratings| !ratings.is_empty());
+                if !rated {
+                    report.cold_item += 1;
+                    continue;
+                }
+
+                match self.user_based_predict(k, user, item, method, None, 1, None) {
+                    Ok(_) => report.predicted += 1,
+                    Err(e) => match e.kind() {
+                        Some(ErrorKind::ColdUser(_)) => report.cold_user += 1,
+                        Some(
+                            ErrorKind::EmptyKNearestNeighbors
+                            | ErrorKind::NoNeighbors(_)
+                            | ErrorKind::InsufficientNeighbors(..),
+                        ) => report.no_neighbors += 1,
+                        Some(_) => report.failed += 1,
+                        None => return Err(e),
+                    },
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    fn adj_cosine_predict(
+        &self,
+        user: U,
+        item: I,
+        chunk_size: usize,
+        cancel: &CancellationToken,
+        timings: &mut Timings,
+    ) -> Result<f64, Error> {
+        let user_id = user.get_id();
+        let item_id = item.get_id();
+
+        log::info!(
+            "Predicting score for user({:?}) for item({:?})",
+            user_id,
+            item_id
+        );
+
+        log::info!("Gathering user({:?}) ratings", user_id);
+        let user_ratings = self.controller.user_ratings(&user)?;
+        log::info!("Normalizing user({:?}) ratings", user_id);
+        let normalized = self.normalized_user_ratings(&user_id, &user_ratings)?;
+        let (normalizer, normalized_ratings) = &*normalized;
+
+        log::info!("Gathering users who rated for target item");
+        let target_item_users = self
+            .controller
+            .users_who_rated(&[item])?
+            .remove(&item_id)
+            .filter(|ratings| !ratings.is_empty())
+            .ok_or_else(|| ErrorKind::ColdItem(format!("{:?}", item_id)))?;
+        log::info!("Gathered {} scores for this item", target_item_users.len());
+
+        let mut num = 0.0;
+        let mut dem = 0.0;
+
+        // Means are shared with the engine, so repeated predictions (or means loaded
+        // with `load_all_means`) don't query them again
+        let mut adj_cosine = self.adj_cosine.borrow_mut();
+
+        log::info!("Iterating items by chunks of size {}", chunk_size);
+        let items_chunks = self.controller.items_by_chunks(chunk_size);
+        for item_chunk_base in items_chunks {
+            cancel.check()?;
+            log::info!("Initial chunk size: {}", item_chunk_base.len());
+            let now = Instant::now();
+            let item_chunk: Vec<_> = item_chunk_base
+                .into_iter()
+                .filter(|other_item| user_ratings.contains_key(&other_item.get_id()))
+                .collect();
+            log::info!("Chunk size after filter: {}", item_chunk.len());
+            log::info!(
+                "Filtering chunk took {} seconds",
+                now.elapsed().as_secs_f64()
+            );
+
+            if item_chunk.is_empty() {
+                continue;
+            }
+
+            log::info!("Gathering users who rated for {} items", item_chunk.len());
+            let now = Instant::now();
+            let mut users_who_rated: maped_ratings!(I => U) = self
+                .controller
+                .users_who_rated(&item_chunk)?
+                .into_iter()
+                .filter(|(_, ratings)| ratings.contains_key(&user_id))
+                .collect();
+
+            let uwr_time = now.elapsed().as_secs_f64();
+            timings.users_who_rated += uwr_time;
+            log::info!(
+                "Gathered a total of {} inverted maped ratings",
+                users_who_rated.len()
+            );
+            log::info!("Gathering users who rated took {} seconds", uwr_time);
+
+            users_who_rated.insert(item_id.clone(), target_item_users.clone());
+
+            let all_users_iter = users_who_rated.values();
+            let mut all_users = HashSet::new();
+
+            log::info!("Collecting all unique users ids");
+            for users in all_users_iter {
+                for user_id in users.keys() {
+                    all_users.insert(user_id.clone());
+                }
+            }
+
+            // Shrink some means by their usage frequency
+            log::info!("Shrinking means based on their usage");
+            adj_cosine.shrink_means();
+
+            // Collect all the users that doesn't have a calculated mean
+            log::info!("Filtering users that have a cached mean");
+            let all_users: Vec<_> = all_users
+                .into_iter()
+                .filter(|user_id| !adj_cosine.has_mean_for(user_id))
+                .collect();
+            if all_users.is_empty() {
+                log::info!("All the needed means are already cached");
+            } else {
+                let all_partial_users = self.controller.create_partial_users(&all_users)?;
+
+                log::info!("Gathering means for {} users", all_partial_users.len());
+                let now = Instant::now();
+                let partial_users_chunk_size = self.config.engine.partial_users_chunk_size;
+                for partial_users_chunk in all_partial_users.chunks(partial_users_chunk_size) {
+                    let mean_chunk = self.controller.users_means(partial_users_chunk)?;
+                    adj_cosine.push_means(&mean_chunk);
+                }
+                let mean_time = now.elapsed().as_secs_f64();
+                log::info!("Obtaining took {} seconds", mean_time);
+                timings.means += mean_time;
+            }
+
+            log::info!("Iterating over all the items of this chunk");
+            let now = Instant::now();
+            for other_item in &item_chunk {
+                let other_item_id = other_item.get_id();
+
+                if !normalized_ratings.contains_key(&other_item_id) || item_id == other_item_id {
+                    continue;
+                }
+
+                let item_ratings = users_who_rated
+                    .get(&item_id)
+                    .ok_or_else(|| ErrorKind::ColdItem(format!("{:?}", item_id)))?;
+                let other_item_ratings = users_who_rated
+                    .get(&other_item_id)
+                    .ok_or_else(|| ErrorKind::ColdItem(format!("{:?}", other_item_id)))?;
+
+                if let Ok(similarity) = adj_cosine.calculate(item_ratings, other_item_ratings) {
+                    num += similarity * normalized_ratings[&other_item_id];
+                    dem += similarity.abs();
+                }
+            }
+
+            let iter_time = now.elapsed().as_secs_f64();
+            log::info!("Iterating over the items took {} seconds", iter_time);
+            timings.similarities += iter_time;
+        }
+
+        log::info!("Gathering means took in total {} seconds", timings.means);
+        log::info!(
+            "Gathering users who rated took in total {} seconds",
+            timings.users_who_rated
+        );
+        log::info!(
+            "Computing distances took in total {} seconds",
+            timings.similarities
+        );
+
+        let stats = adj_cosine.stats();
+        log::info!(
+            "Means cache: {} hits, {} misses, {} evictions (hit rate {:.2}%)",
+            stats.hits,
+            stats.misses,
+            stats.evictions,
+            stats.hit_rate() * 100.0
+        );
+        if dem.is_zero() {
+            return Err(ErrorKind::DivisionByZero.into());
+        }
+
+        log::info!("Denormalizing the final score");
+        Ok(normalizer.denormalize(num / dem)?)
+    }
+
+    /// Classic item kNN, find the `k` items most similar (by adjusted cosine) to
+    /// `item` among the ones rated by `user`, and predict the similarity weighted
+    /// average of the user ratings for them. Only positively correlated items are
+    /// considered neighbors
+    pub fn item_based_knn_predict(
+        &self,
+        user: U,
+        item: I,
+        k: usize,
+        chunk_size: usize,
+    ) -> Result<f64, RecommendationError> {
+        check_chunk_size(chunk_size)?;
+
+        if k == 0 {
+            return Err(ErrorKind::EmptyKNearestNeighbors.into());
+        }
+
+        let target_item_id = item.get_id();
+        let target_item_ratings = self
+            .controller
+            .users_who_rated(&[item])?
+            .remove(&target_item_id)
+            .filter(|ratings| !ratings.is_empty())
+            .ok_or_else(|| ErrorKind::ColdItem(format!("{:?}", target_item_id)))?;
+
+        let user_ratings: Ratings<_, _> = self
+            .user_ratings(&user)?
+            .into_iter()
+            .filter(|(id, _)| id != &target_item_id)
+            .collect();
+
+        let items_ids: Vec<_> = user_ratings.keys().cloned().collect();
+        let all_partial_items = self.controller.create_partial_items(&items_ids)?;
+
+        let mut neighbors = BoundedTopN::new(k);
+        for partial_items_chunk in all_partial_items.chunks(chunk_size) {
+            let users_who_rated = self.controller.users_who_rated(partial_items_chunk)?;
+
+            let missing: Vec<_> = {
+                let adj_cosine = self.adj_cosine.borrow();
+                users_who_rated
+                    .values()
+                    .chain(std::iter::once(&target_item_ratings))
+                    .flat_map(|ratings| ratings.keys())
+                    .filter(|user_id| !adj_cosine.has_mean_for(user_id))
+                    .cloned()
+                    .collect::<HashSet<_>>()
+                    .into_iter()
+                    .collect()
+            };
+
+            if !missing.is_empty() {
+                let means = self.users_means_by_ids(&missing)?;
+                self.adj_cosine.borrow_mut().push_means(&means);
+            }
+
+            let mut adj_cosine = self.adj_cosine.borrow_mut();
+            for (item_id, ratings) in users_who_rated {
+                if let Ok(similarity) = adj_cosine.calculate(&target_item_ratings, &ratings) {
+                    if similarity > 0.0 {
+                        neighbors.push(Scored(similarity, user_ratings[&item_id]));
+                    }
+                }
+            }
+        }
+
+        if neighbors.is_empty() {
+            return Err(ErrorKind::EmptyKNearestNeighbors.into());
+        }
+
+        let (num, den) = neighbors
+            .into_sorted_vec()
+            .into_iter()
+            .fold((0.0, 0.0), |(num, den), Scored(similarity, rating)| {
+                (num + similarity * rating, den + similarity)
+            });
+
+        Ok(num / den)
+    }
+
+    /// Blend of `user_based_predict` and `item_based_knn_predict`, weighting the
+    /// user based prediction by `alpha` and the item based one by `1 - alpha`.
+    /// When one of them fails (e.g. a cold user with a warm item) the other one
+    /// is returned alone, only if both fail the user based error is returned
+    pub fn hybrid_knn_predict(
+        &self,
+        user: U,
+        item: I,
+        k: usize,
+        user_method: UserMethod,
+        alpha: f64,
+        chunk_size: Option<usize>,
+    ) -> Result<f64, RecommendationError> {
+        check_chunk_size(chunk_size)?;
+
+        if !(0.0..=1.0).contains(&alpha) {
+            return Err(ErrorKind::InvalidParameter("alpha must be within [0, 1]".into()).into());
+        }
+
+        // Both predictors take the entities by value, partial ones only carry the id
+        let user_ids = [user.get_id()];
+        let item_ids = [item.get_id()];
+        let user_copy = self.controller.create_partial_users(&user_ids)?.remove(0);
+        let item_copy = self.controller.create_partial_items(&item_ids)?.remove(0);
+
+        let item_chunk_size = chunk_size.unwrap_or(self.config.engine.partial_users_chunk_size);
+        let user_based = self.user_based_predict(k, user, item, user_method, chunk_size, 1, None);
+        let item_based = self.item_based_knn_predict(user_copy, item_copy, k, item_chunk_size);
+
+        let prediction = match (user_based, item_based) {
+            (Ok(user_based), Ok(item_based)) => alpha * user_based + (1.0 - alpha) * item_based,
+            (Ok(user_based), Err(e)) => {
+                log::info!(
+                    "Item based prediction failed ({}), using user based only",
+                    e
+                );
+                user_based
+            }
+
+            (Err(e), Ok(item_based)) => {
+                log::info!(
+                    "User based prediction failed ({}), using item based only",
+                    e
+                );
+                item_based
+            }
+
+            (Err(e), Err(_)) => return Err(e),
+        };
+
+        Ok(self.predict_postprocess(prediction, PostProcess::default()))
+    }
+
+    /// Weighted Slope One, each deviation is weighted by the number of users who
+    /// rated both items. With `popularity_weighted` it's also scaled by the total
+    /// number of ratings of the other item, so niche items with a few strong
+    /// co-ratings don't dominate the prediction
+    pub fn slope_one_predict(
+        &self,
+        user: U,
+        item: I,
+        chunk_size: usize,
+        popularity_weighted: bool,
+        cancel: &CancellationToken,
+        timings: &mut Timings,
+    ) -> Result<f64, RecommendationError> {
+        check_chunk_size(chunk_size)?;
+
+        let target_item_id = item.get_id();
+        let target_item_ratings = self
+            .controller
+            .users_who_rated(&[item])?
+            .remove(&target_item_id)
+            .filter(|ratings| !ratings.is_empty())
+            .ok_or_else(|| ErrorKind::ColdItem(format!("{:?}", target_item_id)))?;
+
+        let user_ratings: Ratings<_, _> = self
+            .controller
+            .user_ratings(&user)?
+            .into_iter()
+            .filter(|(id, _)| id != &target_item_id)
+            .collect();
+
+        let items_ids: Vec<_> = user_ratings.iter().map(|(id, _)| id.to_owned()).collect();
+        let all_partial_items = self.controller.create_partial_items(&items_ids)?;
+
+        let mut num = 0.0;
+        let mut den = 0.0;
+
+        for partial_items_chunk in all_partial_items.chunks(chunk_size) {
+            cancel.check()?;
+            let now = Instant::now();
+            let users_who_rated = self.controller.users_who_rated(partial_items_chunk)?;
+            timings.users_who_rated += now.elapsed().as_secs_f64();
+
+            let now = Instant::now();
+            for (item_id, ratings) in users_who_rated {
+                if let Ok((dev, card)) = slope_one(&target_item_ratings, &ratings) {
+                    let weight = if popularity_weighted {
+                        card as f64 * ratings.len() as f64
+                    } else {
+                        card as f64
+                    };
+
+                    num += (dev + user_ratings[&item_id]) * weight;
+                    den += weight;
+                }
+            }
+            timings.similarities += now.elapsed().as_secs_f64();
+        }
+
+        if den.is_zero() {
+            Err(ErrorKind::DivisionByZero.into())
+        } else {
+            Ok(num / den)
+        }
+    }
+
+    /// Same as `slope_one_predict` but the deviations and their cardinalities are
+    /// read from `matrix` instead of being computed, only the items of its current
+    /// chunk are used
+    pub fn slope_one_predict_from_matrix(
+        &self,
+        user: U,
+        item: I,
+        matrix: &DeviationMatrix<'_, C, I>,
+    ) -> Result<f64, RecommendationError> {
+        let target_item_id = item.get_id();
+        let user_ratings = self.controller.user_ratings(&user)?;
+
+        let mut num = 0.0;
+        let mut den = 0.0;
+
+        for (item_id, rating) in user_ratings {
+            if item_id == target_item_id {
+                continue;
+            }
+
+            if let Some((dev, card)) = matrix.get_deviation_and_card(&target_item_id, &item_id) {
+                num += (dev + rating) * card as f64;
+                den += card as f64;
+            }
+        }
+
+        if den.is_zero() {
+            Err(ErrorKind::DivisionByZero.into())
+        } else {
+            Ok(num / den)
+        }
+    }
+
+    /// Bi-polar Slope One prediction, the items that `user` liked (rated above its
+    /// mean) are only compared against the deviations of other users that liked
+    /// both items, and the same goes for the disliked ones. Items rated exactly
+    /// with the user mean don't contribute to the prediction
+    pub fn bipolar_slope_one_predict(
+        &self,
+        user: U,
+        item: I,
+        chunk_size: usize,
+        cancel: &CancellationToken,
+        timings: &mut Timings,
+    ) -> Result<f64, RecommendationError> {
+        check_chunk_size(chunk_size)?;
+
+        let target_item_id = item.get_id();
+        let target_item_ratings = self
+            .controller
+            .users_who_rated(&[item])?
+            .remove(&target_item_id)
+            .filter(|ratings| !ratings.is_empty())
+            .ok_or_else(|| ErrorKind::ColdItem(format!("{:?}", target_item_id)))?;
+
+        let all_user_ratings = self.controller.user_ratings(&user)?;
+        let user_mean = all_user_ratings.values().sum::<f64>() / all_user_ratings.len() as f64;
+
+        let user_ratings: Ratings<_, _> = all_user_ratings
+            .into_iter()
+            .filter(|(id, _)| id != &target_item_id)
+            .collect();
+
+        let now = Instant::now();
+        let raters: Vec<_> = target_item_ratings.keys().cloned().collect();
+        let means = self.users_means_by_ids(&raters)?;
+        timings.means += now.elapsed().as_secs_f64();
+
+        let items_ids: Vec<_> = user_ratings.keys().cloned().collect();
+        let all_partial_items = self.controller.create_partial_items(&items_ids)?;
+
+        let mut num = 0.0;
+        let mut den = 0.0;
+
+        for partial_items_chunk in all_partial_items.chunks(chunk_size) {
+            cancel.check()?;
+            let now = Instant::now();
+            let users_who_rated = self.controller.users_who_rated(partial_items_chunk)?;
+            timings.users_who_rated += now.elapsed().as_secs_f64();
+
+            let now = Instant::now();
+            for (item_id, ratings) in users_who_rated {
+                let dev = match bipolar_slope_one(&target_item_ratings, &ratings, &means) {
+                    Ok(dev) => dev,
+                    Err(_) => continue,
+                };
+
+                let rating = user_ratings[&item_id];
+                let partition = if rating > user_mean {
+                    dev.liked
+                } else if rating < user_mean {
+                    dev.disliked
+                } else {
+                    None
+                };
+
+                if let Some((dev, card)) = partition {
+                    num += (dev + rating) * card as f64;
+                    den += card as f64;
+                }
+            }
+            timings.similarities += now.elapsed().as_secs_f64();
+        }
+
+        if den.is_zero() {
+            Err(ErrorKind::DivisionByZero.into())
+        } else {
+            Ok(num / den)
+        }
+    }
+
+    /// Stream every item by chunks (of `partial_users_chunk_size`) computing `f`
+    /// over its ratings, only the `n` items with the highest values are kept
+    fn top_items_by<F>(&self, n: usize, f: F) -> Result<Vec<(eid!(I), f64)>, Error>
+    where
+        F: Fn(&Ratings<eid!(U)>) -> Option<f64>,
+    {
+        let mut top = BoundedTopN::new(n);
+        if n == 0 {
+            return Ok(Vec::new());
+        }
+
+        let chunk_size = self.config.engine.partial_users_chunk_size;
+        for items in self.controller.items_by_chunks(chunk_size) {
+            for (item_id, ratings) in self.controller.users_who_rated(&items)? {
+                if let Some(value) = f(&ratings) {
+                    top.push(Scored(value, item_id));
+                }
+            }
+        }
+
+        Ok(top
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Scored(value, item_id)| (item_id, value))
+            .collect())
+    }
+
+    /// Distribution of the ratings of `user` over the score range, split in `bins`
+    /// buckets of the same width, each bucket is returned with its lower bound.
+    /// The maximum score falls in the last bucket, and a user without ratings gets
+    /// every bucket empty
+    pub fn user_rating_histogram(
+        &self,
+        user: U,
+        bins: usize,
+    ) -> Result<Vec<(f64, usize)>, RecommendationError> {
+        if bins == 0 {
+            return Err(
+                ErrorKind::InvalidParameter("bins must be greater than zero".into()).into(),
+            );
+        }
+
+        let (min_rating, max_rating) = self.controller.score_range();
+        let width = (max_rating - min_rating) / bins as f64;
+
+        let mut counts = vec![0; bins];
+        for rating in self.stored_user_ratings(&user)?.values() {
+            let bin = if width > 0.0 {
+                ((rating - min_rating) / width).floor().max(0.0) as usize
+            } else {
+                0
+            };
+
+            counts[bin.min(bins - 1)] += 1;
+        }
+
+        Ok(counts
+            .into_iter()
+            .enumerate()
+            .map(|(i, count)| (min_rating + i as f64 * width, count))
+            .collect())
+    }
+
+    /// The `n` items with the most ratings, along with their number of ratings
+    pub fn popular_items(&self, n: usize) -> Result<Vec<(eid!(I), usize)>, RecommendationError> {
+        let popular = self.top_items_by(n, |ratings| Some(ratings.len() as f64))?;
+
+        Ok(popular
+            .into_iter()
+            .map(|(item_id, count)| (item_id, count as usize))
+            .collect())
+    }
+
+    /// The `n` items with the highest ratings variance, along with their variance.
+    /// Items with less than two ratings aren't considered controversial
+    pub fn controversial_items(
+        &self,
+        n: usize,
+    ) -> Result<Vec<(eid!(I), f64)>, RecommendationError> {
+        let controversial = self.top_items_by(n, |ratings| {
+            if ratings.len() < 2 {
+                return None;
+            }
+
+            let count = ratings.len() as f64;
+            let mean = ratings.values().sum::<f64>() / count;
+            let variance = ratings.values().map(|r| (r - mean).powi(2)).sum::<f64>() / count;
+
+            Some(variance)
+        })?;
+
+        Ok(controversial)
+    }
+
+    /// Predict the score of `user` for `item` from the items rated by `user`, the
+    /// prediction is adjusted with `post`, clamped to the score range by default.
+    /// `popularity_weighted` is only used by Slope One, see `slope_one_predict`.
+    /// The prediction stops with `ErrorKind::Cancelled` once `cancel` is set
+    #[allow(clippy::too_many_arguments)]
+    pub fn item_based_predict(
+        &self,
+        user: U,
+        item: I,
+        method: ItemMethod,
+        chunk_size: usize,
+        popularity_weighted: bool,
+        post: Option<PostProcess>,
+        cancel: &CancellationToken,
+    ) -> Result<f64, RecommendationError> {
+        let (prediction, _) = self.item_based_predict_with_timings(
+            user,
+            item,
+            method,
+            chunk_size,
+            popularity_weighted,
+            post,
+            cancel,
+        )?;
+
+        Ok(prediction)
+    }
+
+    /// Same as `item_based_predict` but when there's nothing to predict from (e.g.
+    /// nobody else rated the item) the user's own mean rating is returned instead.
+    /// Cancellations and controller errors aren't covered by the fallback
+    #[allow(clippy::too_many_arguments)]
+    pub fn item_based_predict_or_user_mean(
+        &self,
+        user: U,
+        item: I,
+        method: ItemMethod,
+        chunk_size: usize,
+        popularity_weighted: bool,
+        post: Option<PostProcess>,
+        cancel: &CancellationToken,
+    ) -> Result<f64, RecommendationError> {
+        let user_ratings = self.stored_user_ratings(&user)?;
+
+        let prediction = self.item_based_predict(
+            user,
+            item,
+            method,
+            chunk_size,
+            popularity_weighted,
+            post,
+            cancel,
+        );
+
+        match prediction {
+            Err(e) if e.kind().is_some() && !matches!(e.kind(), Some(ErrorKind::Cancelled)) => {
+                if user_ratings.is_empty() {
+                    return Err(e);
+                }
+
+                log::info!("Item based prediction failed ({}), using the user mean", e);
+                let mean = user_ratings.values().sum::<f64>() / user_ratings.len() as f64;
+
+                Ok(self.predict_postprocess(mean, post.unwrap_or_default()))
+            }
+
+            prediction => prediction,
+        }
+    }
+
+    /// Same as `item_based_predict` but also returns how long each phase of the
+    /// prediction took
+    #[allow(clippy::too_many_arguments)]
+    pub fn item_based_predict_with_timings(
+        &self,
+        user: U,
+        item: I,
+        method: ItemMethod,
+        chunk_size: usize,
+        popularity_weighted: bool,
+        post: Option<PostProcess>,
+        cancel: &CancellationToken,
+    ) -> Result<(f64, Timings), RecommendationError> {
+        check_chunk_size(chunk_size)?;
+
+        let mut timings = Timings::default();
+        let now = Instant::now();
+
+        let prediction = match method {
+            ItemMethod::AdjCosine => {
+                self.adj_cosine_predict(user, item, chunk_size, cancel, &mut timings)?
+            }
+            ItemMethod::SlopeOne => self.slope_one_predict(
+                user,
+                item,
+                chunk_size,
+                popularity_weighted,
+                cancel,
+                &mut timings,
+            )?,
+            ItemMethod::BiPolarSlopeOne => {
+                self.bipolar_slope_one_predict(user, item, chunk_size, cancel, &mut timings)?
+            }
+        };
+
+        timings.total = now.elapsed().as_secs_f64();
+        Ok((
+            self.predict_postprocess(prediction, post.unwrap_or_default()),
+            timings,
+        ))
+    }
+}
+
+impl<'a, C, U, I> Engine<'a, C, U, I>
+where
+    C: Controller<User = U, Item = I>,
+    U: Entity,
+    I: Entity,
+    eid!(U): Hash + Eq + Ord + Clone + Debug + Default + Serialize,
+    eid!(I): Hash + Eq + Clone + Debug + Serialize,
+{
+    /// Write all the ratings to `path` as a JSON Lines snapshot (see `UserRatingsRecord`),
+    /// users are loaded by chunks so the whole dataset is never in memory.
+    /// Returns the number of users written
+    pub fn export_ratings<P: AsRef<Path>>(&self, path: P) -> Result<usize, RecommendationError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let chunk_size = self.config.engine.partial_users_chunk_size;
+
+        let mut exported = 0;
+        for users in self.controller.users_by_chunks(chunk_size) {
+            let mut maped_ratings: Vec<_> =
+                self.controller.users_ratings(&users)?.into_iter().collect();
+            maped_ratings.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+            for (user_id, ratings) in maped_ratings {
+                let record = UserRatingsRecord {
+                    user_id,
+                    ratings: ratings.into_iter().collect(),
+                };
+
+                serde_json::to_writer(&mut writer, &record).map_err(std::io::Error::from)?;
+                writer.write_all(b"\n")?;
+                exported += 1;
+            }
+        }
+
+        writer.flush()?;
+        Ok(exported)
+    }
+}
+
+#[cfg(feature = "test-engine")]
+#[cfg(test)]
+mod tests {
+    use super::distances::users::Method;
+    use super::*;
+    use anyhow::Error;
+    use books::BooksController;
+    use config::Config;
+    use controller::SearchBy;
+    use movie_lens::MovieLensController;
+    use movie_lens_small::MovieLensSmallController;
+    use simple_movie::SimpleMovieController;
+    use std::time::Instant;
+
+    #[test]
+    fn euclidean_distance() -> Result<(), Error> {
+        let config = Config::default();
+        let controller = SimpleMovieController::from_config(&config, "simple-movie")?;
+        let engine = Engine::with_controller(&controller, &config);
+
+        let user_a = controller
+            .users_by(&SearchBy::id("52"))?
+            .drain(..1)
+            .next()
+            .unwrap();
+
+        let user_b = controller
+            .users_by(&SearchBy::id("53"))?
+            .drain(..1)
+            .next()
+            .unwrap();
+
+        println!(
+            "euclidean(52, 53): {:?}",
+            engine.user_distance(user_a, user_b, Method::Euclidean)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn manhattan_distance() -> Result<(), Error> {
+        let config = Config::default();
+        let controller = SimpleMovieController::from_config(&config, "simple-movie")?;
+        let engine = Engine::with_controller(&controller, &config);
+
+        let user_a = controller
+            .users_by(&SearchBy::id("52"))?
+            .drain(..1)
+            .next()
+            .unwrap();
+
+        let user_b = controller
+            .users_by(&SearchBy::id("53"))?
+            .drain(..1)
+            .next()
+            .unwrap();
+
+        println!(
+            "manhattan(52, 53): {:?}",
+            engine.user_distance(user_a, user_b, Method::Manhattan)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn cosine_similarity_distance() -> Result<(), Error> {
+        let config = Config::default();
+        let controller = SimpleMovieController::from_config(&config, "simple-movie")?;
+        let engine = Engine::with_controller(&controller, &config);
+
+        let user_a = controller
+            .users_by(&SearchBy::id("52"))?
+            .drain(..1)
+            .next()
+            .unwrap();
+
+        let user_b = controller
+            .users_by(&SearchBy::id("53"))?
+            .drain(..1)
+            .next()
+            .unwrap();
+
+        println!(
+            "cosine(52, 53): {:?}",
+            engine.user_distance(user_a, user_b, Method::CosineSimilarity)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn knn_with_manhattan() -> Result<(), Error> {
+        let config = Config::default();
+        let controller = SimpleMovieController::from_config(&config, "simple-movie")?;
+        let engine = Engine::with_controller(&controller, &config);
+
+        let user = controller
+            .users_by(&SearchBy::id("52"))?
+            .drain(..1)
+            .next()
+            .unwrap();
+
+        println!(
+            "kNN(52, manhattan): {:?}",
+            engine.user_knn(4, user, Method::Manhattan, None, false)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn knn_with_euclidean() -> Result<(), Error> {
+        let config = Config::default();
+        let controller = SimpleMovieController::from_config(&config, "simple-movie")?;
+        let engine = Engine::with_controller(&controller, &config);
+
+        let user = controller
+            .users_by(&SearchBy::id("52"))?
+            .drain(..1)
+            .next()
+            .unwrap();
+
+        println!(
+            "kNN(52, 3, euclidean): {:?}",
+            engine.user_knn(3, user, Method::Euclidean, None, false)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn knn_with_cosine() -> Result<(), Error> {
+        let config = Config::default();
+        let controller = SimpleMovieController::from_config(&config, "simple-movie")?;
+        let engine = Engine::with_controller(&controller, &config);
+
+        let user = controller
+            .users_by(&SearchBy::id("52"))?
+            .drain(..1)
+            .next()
+            .unwrap();
+
+        println!(
+            "kNN(52, 3, cosine): {:?}",
+            engine.user_knn(3, user, Method::CosineSimilarity, None, false)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn knn_in_books() -> Result<(), Error> {
+        let config = Config::default();
+        let controller = BooksController::from_config(&config, "books")?;
+        let engine = Engine::with_controller(&controller, &config);
+
+        let user = controller
+            .users_by(&SearchBy::id("242"))?
+            .drain(..1)
+            .next()
+            .unwrap();
+
+        println!(
+            "kNN(242, 5, manhattan): {:?}",
+            engine.user_knn(5, user, Method::JaccardDistance, None, false)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn similarity_matrix() -> Result<(), Error> {
+        use super::chunked_matrix::{ChunkedMatrix, SimilarityMatrix};
+        use movie_lens_small::MovieLensSmallController;
+        use std::time::Instant;
+
+        let config = Config::default();
+        let controller = MovieLensSmallController::from_config(&config, "movie-lens")?;
+
+        let mut sim_matrix: SimilarityMatrix<_, _, _> =
+            SimilarityMatrix::new(&controller, &config, 10000, 10000);
+
+        let now = Instant::now();
+        sim_matrix.calculate_chunk(0, 0)?;
+        println!("Elapsed: {}", now.elapsed().as_secs_f64());
+
+        Ok(())
+    }
+
+    #[test]
+    fn item_based_pred() -> Result<(), Error> {
+        let config = Config::default();
+
+        let controller = BooksController::from_config(&config, "books")?;
+        let engine = Engine::with_controller(&controller, &config);
+
+        let user = controller
+            .users_by(&SearchBy::id("243"))?
+            .drain(..1)
+            .next()
+            .unwrap();
+
+        let item = controller
+            .items_by(&SearchBy::name("Flesh Tones: A Novel"))?
+            .drain(..1)
+            .next()
+            .unwrap();
+
+        let now = Instant::now();
+        println!(
+            "Item based prediction Books: {:?}",
+            engine.item_based_predict(
+                user,
+                item,
+                ItemMethod::SlopeOne,
+                2500,
+                false,
+                None,
+                &CancellationToken::new(),
+            )?
+        );
+        println!("Elapsed: {}", now.elapsed().as_secs_f64());
+
+        let controller = SimpleMovieController::from_config(&config, "simple-movie")?;
+        let engine = Engine::with_controller(&controller, &config);
+
+        let user = controller
+            .users_by(&SearchBy::name("Josh"))?
+            .drain(..1)
+            .next()
+            .unwrap();
+
+        let item = controller
+            .items_by(&SearchBy::name("Blade Runner"))?
+            .drain(..1)
+            .next()
+            .unwrap();
+
+        let now = Instant::now();
+        println!(
+            "\nItem based prediction SimpleMovie: {:?}",
+            engine.item_based_predict(
+                user,
+                item,
+                ItemMethod::SlopeOne,
+                2500,
+                false,
+                None,
+                &CancellationToken::new(),
+            )?
+        );
+        println!("Elapsed: {}", now.elapsed().as_secs_f64());
+
+        let controller = MovieLensSmallController::from_config(&config, "movie-lens-small")?;
+        let engine = Engine::with_controller(&controller, &config);
+
+        let user = controller
+            .users_by(&SearchBy::id("2"))?
+            .drain(..1)
+            .next()
+            .unwrap();
+
+        let item = controller
+            .items_by(&SearchBy::name("Suture (1993)"))?
+            .drain(..1)
+            .next()
+            .unwrap();
+
+        let now = Instant::now();
+        println!(
+            "\nItem based prediction MovieLensSmall: {:?}",
+            engine.item_based_predict(
+                user,
+                item,
+                ItemMethod::SlopeOne,
+                2500,
+                false,
+                None,
+                &CancellationToken::new(),
+            )?
+        );
+        println!("Elapsed: {}", now.elapsed().as_secs_f64());
+
+        let controller = MovieLensController::from_config(&config, "movie-lens")?;
+        let engine = Engine::with_controller(&controller, &config);
+
+        let user = controller
+            .users_by(&SearchBy::id("35826"))?
+            .drain(..1)
+            .next()
+            .unwrap();
+
+        let item = controller
+            .items_by(&SearchBy::id("307"))?
+            .drain(..1)
+            .next()
+            .unwrap();
+
+        let now = Instant::now();
+        println!(
+            "\nItem based prediction MovieLens: {:?}",
+            engine.item_based_predict(
+                user,
+                item,
+                ItemMethod::SlopeOne,
+                2500,
+                false,
+                None,
+                &CancellationToken::new(),
+            )?
+        );
+        println!("Elapsed: {}", now.elapsed().as_secs_f64());
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore]
+    fn shelves_item_based_pred() -> Result<(), Error> {
+        use shelves::ShelvesController;
+        use std::time::Instant;
+
+        let config = Config::default();
+        let controller = ShelvesController::from_config(&config, "shelves")?;
+        let engine = Engine::with_controller(&controller, &config);
+
+        let user = controller
+            .users_by(&SearchBy::id("0"))?
+            .drain(..1)
+            .next()
+            .unwrap();
+
+        let item = controller
+            .items_by(&SearchBy::id("1000"))?
+            .drain(..1)
+            .next()
+            .unwrap();
+
+        let now = Instant::now();
+        println!(
+            "Item based prediction (UserId 0, ItemId 1000, 1): {:?}",
+            engine.item_based_predict(
+                user,
+                item,
+                ItemMethod::AdjCosine,
+                1,
+                false,
+                None,
+                &CancellationToken::new(),
+            )?
+        );
+        println!("Elapsed: {}", now.elapsed().as_secs_f64());
+
+        Ok(())
+    }
+}
 
-#[cfg(feature = "test-engine")]
 #[cfg(test)]
-mod tests {
-    use super::distances::users::Method;
+mod in_memory_tests {
     use super::*;
-    use anyhow::Error;
-    use books::BooksController;
-    use config::Config;
+    use assert_approx_eq::assert_approx_eq;
+    use common_macros::hash_map;
     use controller::SearchBy;
-    use movie_lens::MovieLensController;
-    use movie_lens_small::MovieLensSmallController;
-    use simple_movie::SimpleMovieController;
-    use std::time::Instant;
+    use in_memory::InMemoryController;
+
+    fn controller() -> InMemoryController {
+        InMemoryController::from_ratings(&[
+            (1, 1, 5.0),
+            (1, 2, 3.0),
+            (1, 3, 4.0),
+            // User 2 rated every common item with the same value
+            (2, 1, 3.0),
+            (2, 2, 3.0),
+            (2, 3, 3.0),
+            (2, 4, 2.0),
+            (2, 5, 4.0),
+            (3, 1, 4.0),
+            (3, 2, 2.0),
+            (3, 3, 3.0),
+            (3, 4, 5.0),
+        ])
+        .unwrap()
+    }
+
+    fn user(controller: &InMemoryController, id: &str) -> in_memory::models::users::User {
+        controller.users_by(&SearchBy::id(id)).unwrap().remove(0)
+    }
+
+    fn item(controller: &InMemoryController, id: &str) -> in_memory::models::items::Item {
+        controller.items_by(&SearchBy::id(id)).unwrap().remove(0)
+    }
+
+    #[test]
+    fn user_based_predict_confidence_interval() {
+        let config = Config::default();
+        let controller = InMemoryController::from_ratings(&[
+            (1, 1, 5.0),
+            (1, 2, 3.0),
+            (1, 3, 1.0),
+            (2, 1, 4.0),
+            (2, 2, 3.0),
+            (2, 3, 2.0),
+            (2, 4, 4.0),
+            (3, 1, 5.0),
+            (3, 2, 2.0),
+            (3, 3, 1.0),
+            (3, 4, 4.0),
+        ])
+        .unwrap();
+        let engine = Engine::with_controller(&controller, &config);
+
+        // Both neighbors agree, there's nothing uncertain about the prediction
+        let (prediction, lower, upper) = engine
+            .user_based_predict_ci(
+                2,
+                user(&controller, "1"),
+                item(&controller, "4"),
+                UserMethod::CosineSimilarity,
+                None,
+            )
+            .unwrap();
+
+        assert_approx_eq!(prediction, 4.0);
+        assert_approx_eq!(lower, 4.0);
+        assert_approx_eq!(upper, 4.0);
+
+        controller.update_rating(&3, &4, 2.0).unwrap();
+        let (prediction, lower, upper) = engine
+            .user_based_predict_ci(
+                2,
+                user(&controller, "1"),
+                item(&controller, "4"),
+                UserMethod::CosineSimilarity,
+                None,
+            )
+            .unwrap();
+
+        assert!(lower < prediction && prediction < upper);
+    }
+
+    #[test]
+    fn user_knn_with_tied_distances() {
+        let config = Config::default();
+
+        let mut ratings = vec![(1, 1, 5.0), (1, 2, 3.0)];
+        for user_id in 2..=8 {
+            ratings.push((user_id, 1, 4.0));
+            ratings.push((user_id, 2, 2.0));
+        }
+
+        let mut previous: Option<Vec<_>> = None;
+        for _ in 0..10 {
+            // A new controller on each run, so users are visited in a different order
+            let controller = InMemoryController::from_ratings(&ratings).unwrap();
+            let engine = Engine::with_controller(&controller, &config);
+
+            let knn: Vec<_> = [UserMethod::Euclidean, UserMethod::CosineSimilarity]
+                .iter()
+                .map(|method| {
+                    engine
+                        .user_knn(3, user(&controller, "1"), *method, None, false)
+                        .unwrap()
+                })
+                .collect();
+
+            let ids: Vec<_> = knn[0].iter().map(|(id, _)| *id).collect();
+            assert_eq!(ids, vec![2, 3, 4]);
+
+            if let Some(previous) = &previous {
+                assert_eq!(previous, &knn);
+            }
+
+            previous = Some(knn);
+        }
+    }
+
+    #[test]
+    fn item_based_knn_prediction() {
+        let config = Config::default();
+        let controller = InMemoryController::from_ratings(&[
+            (1, 1, 5.0),
+            (1, 2, 4.0),
+            (1, 3, 1.0),
+            (2, 1, 4.0),
+            (2, 2, 5.0),
+            (2, 3, 2.0),
+            (2, 4, 5.0),
+            (3, 1, 2.0),
+            (3, 2, 1.0),
+            (3, 3, 4.0),
+            (3, 4, 2.0),
+            (4, 1, 5.0),
+            (4, 2, 3.0),
+            (4, 3, 2.0),
+            (4, 4, 4.0),
+        ])
+        .unwrap();
+        let engine = Engine::with_controller(&controller, &config);
+
+        // Item 2 is the most similar to item 4, then item 1, item 3 is negatively correlated
+        let predict = |k| {
+            engine
+                .item_based_knn_predict(user(&controller, "1"), item(&controller, "4"), k, 2)
+                .unwrap()
+        };
+
+        assert_approx_eq!(predict(1), 4.0);
+        assert_approx_eq!(predict(2), 4.45750484044417);
+        assert_approx_eq!(predict(3), 4.45750484044417);
+    }
+
+    #[test]
+    fn user_distance_idf_ignores_popular_items() {
+        let config = Config::default();
+        let controller = InMemoryController::from_ratings(&[
+            (1, 1, 5.0),
+            (1, 2, 2.0),
+            (1, 3, 4.0),
+            (2, 1, 1.0),
+            (2, 2, 2.0),
+            (2, 3, 4.0),
+            (3, 1, 3.0),
+            (3, 4, 3.0),
+        ])
+        .unwrap();
+        let engine = Engine::with_controller(&controller, &config);
+
+        // Everyone rated item 1, so it says nothing about how similar users 1 and 2 are
+        let plain = engine
+            .user_distance(
+                user(&controller, "1"),
+                user(&controller, "2"),
+                UserMethod::CosineSimilarity,
+            )
+            .unwrap();
+        let weighted = engine
+            .user_distance_idf(
+                user(&controller, "1"),
+                user(&controller, "2"),
+                UserMethod::CosineSimilarity,
+            )
+            .unwrap();
+
+        assert!(plain < 0.9);
+        assert_approx_eq!(weighted, 1.0);
+    }
+
+    #[test]
+    fn user_distance_weighted_pearson() {
+        let config = Config::default();
+        let controller = controller();
+        let engine = Engine::with_controller(&controller, &config);
+
+        let pearson = |a: &str, b: &str| {
+            engine
+                .user_distance(
+                    user(&controller, a),
+                    user(&controller, b),
+                    UserMethod::PearsonCorrelation,
+                )
+                .unwrap()
+        };
+        let weighted = |a: &str, b: &str, weights: &HashMap<i32, f64>| {
+            engine
+                .user_distance_weighted_pearson(user(&controller, a), user(&controller, b), weights)
+                .unwrap()
+        };
+
+        // Uniform weights are exactly the plain correlation
+        let uniform = hash_map! { 1 => 1.0, 2 => 1.0, 3 => 1.0, 4 => 1.0 };
+        assert_eq!(weighted("1", "3", &uniform), pearson("1", "3"));
+        assert_eq!(weighted("1", "3", &HashMap::new()), pearson("1", "3"));
+
+        // A zero weight is the same as dropping the item
+        let mut rating_a = controller.user_ratings(&user(&controller, "2")).unwrap();
+        let mut rating_b = controller.user_ratings(&user(&controller, "3")).unwrap();
+        rating_a.remove(&3);
+        rating_b.remove(&3);
+
+        let expected = distances::users::pearson_correlation(&rating_a, &rating_b).unwrap();
+        assert_approx_eq!(weighted("2", "3", &hash_map! { 3 => 0.0 }), expected);
+        assert!((pearson("2", "3") - expected).abs() > 1e-3);
+    }
+
+    #[test]
+    fn popular_and_controversial_items() {
+        let mut config = Config::default();
+        config.engine.partial_users_chunk_size = 2;
+
+        let controller = controller();
+        let engine = Engine::with_controller(&controller, &config);
+
+        let popular = engine.popular_items(4).unwrap();
+        assert_eq!(popular.len(), 4);
+        assert!(popular[..3].iter().all(|(_, count)| *count == 3));
+        assert_eq!(popular[3], (4, 2));
+
+        let controversial = engine.controversial_items(2).unwrap();
+        assert_eq!(controversial.len(), 2);
+        assert_eq!(controversial[0].0, 4);
+        assert_approx_eq!(controversial[0].1, 2.25);
+        assert_eq!(controversial[1].0, 1);
+        assert_approx_eq!(controversial[1].1, 2.0 / 3.0);
+
+        // Item 5 only has one rating
+        let controversial = engine.controversial_items(10).unwrap();
+        assert_eq!(controversial.len(), 4);
+    }
+
+    #[test]
+    fn implicit_prediction_weighted_by_confidence() {
+        let config = Config::default();
+
+        let predict = |count| {
+            // Counts aren't bounded like ratings
+            let controller = InMemoryController::from_ratings_with_score_range(
+                &[
+                    (1, 1, 3.0),
+                    (1, 2, 3.0),
+                    (2, 1, 3.0),
+                    (2, 2, 3.0),
+                    (2, 3, count),
+                    (3, 1, 3.0),
+                    (3, 2, 3.0),
+                ],
+                0.0,
+                f64::MAX,
+            )
+            .unwrap();
+            let engine = Engine::with_controller(&controller, &config);
+
+            engine
+                .implicit_predict(
+                    2,
+                    user(&controller, "1"),
+                    item(&controller, "3"),
+                    UserMethod::CosineSimilarity,
+                    Implicit::new(1.0),
+                    None,
+                )
+                .unwrap()
+        };
+
+        // User 2 has a confidence of 1 + count, while user 3 didn't see the item
+        assert_approx_eq!(predict(1.0), 2.0 / 3.0);
+        assert_approx_eq!(predict(10.0), 11.0 / 12.0);
+    }
+
+    #[test]
+    fn explain_user_based_prediction() {
+        let config = Config::default();
+        let controller = controller();
+        let engine = Engine::with_controller(&controller, &config);
+
+        let explanation = engine
+            .explain_user_based(
+                user(&controller, "1"),
+                item(&controller, "4"),
+                2,
+                UserMethod::CosineSimilarity,
+            )
+            .unwrap();
+
+        // User 3 carries the whole prediction, user 2 is uncorrelated
+        assert_eq!(explanation.len(), 2);
+        assert_eq!(explanation[0].user_id, 3);
+        assert_approx_eq!(explanation[0].rating, 5.0);
+        assert_approx_eq!(explanation[0].contribution, 5.0);
+        assert_eq!(explanation[1].user_id, 2);
+        assert_approx_eq!(explanation[1].similarity, 0.0);
+
+        let prediction = engine
+            .user_based_predict(
+                2,
+                user(&controller, "1"),
+                item(&controller, "4"),
+                UserMethod::CosineSimilarity,
+                None,
+                1,
+                None,
+            )
+            .unwrap();
+
+        let total: f64 = explanation.iter().map(|c| c.contribution).sum();
+        assert_approx_eq!(total, prediction);
+    }
+
+    #[test]
+    fn export_ratings_round_trip() {
+        let config = Config::default();
+        let controller = controller();
+        let engine = Engine::with_controller(&controller, &config);
+
+        let path = std::env::temp_dir().join("engine-export-ratings-round-trip.jsonl");
+        assert_eq!(engine.export_ratings(&path).unwrap(), 3);
+
+        let loaded = InMemoryController::new();
+        assert_eq!(loaded.load_snapshot(&path).unwrap(), 12);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            loaded.all_users_ratings().unwrap(),
+            controller.all_users_ratings().unwrap()
+        );
+    }
+
+    #[test]
+    fn item_based_predict_timings() {
+        let config = Config::default();
+        let controller = controller();
+        let engine = Engine::with_controller(&controller, &config);
+
+        let (prediction, timings) = engine
+            .item_based_predict_with_timings(
+                user(&controller, "1"),
+                item(&controller, "4"),
+                ItemMethod::AdjCosine,
+                1,
+                false,
+                None,
+                &CancellationToken::new(),
+            )
+            .unwrap();
+
+        let expected = engine
+            .item_based_predict(
+                user(&controller, "1"),
+                item(&controller, "4"),
+                ItemMethod::AdjCosine,
+                1,
+                false,
+                None,
+                &CancellationToken::new(),
+            )
+            .unwrap();
+
+        assert_approx_eq!(prediction, expected);
+        assert!(timings.users_who_rated > 0.0);
+        assert!(timings.means + timings.users_who_rated + timings.similarities <= timings.total);
+    }
+
+    #[test]
+    fn predict_for_new_user() {
+        let config = Config::default();
+        let controller = controller();
+        let engine = Engine::with_controller(&controller, &config);
+
+        // Same ratings as user 1, who never rated item 4
+        let ratings: Ratings<_> = vec![(1, 5.0), (2, 3.0), (3, 4.0)].into_iter().collect();
+
+        let prediction = engine
+            .predict_for_new_user(
+                &ratings,
+                item(&controller, "4"),
+                2,
+                UserMethod::CosineSimilarity,
+                None,
+            )
+            .unwrap();
+
+        let expected = engine
+            .user_based_predict(
+                2,
+                user(&controller, "1"),
+                item(&controller, "4"),
+                UserMethod::CosineSimilarity,
+                None,
+                1,
+                None,
+            )
+            .unwrap();
+
+        assert_approx_eq!(prediction, expected);
+
+        let empty = Ratings::new();
+        assert!(engine
+            .predict_for_new_user(
+                &empty,
+                item(&controller, "4"),
+                2,
+                UserMethod::CosineSimilarity,
+                Some(2),
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn predict_postprocess() {
+        let config = Config::default();
+        let controller = controller();
+        let engine = Engine::with_controller(&controller, &config);
+
+        assert_approx_eq!(engine.predict_postprocess(5.3, PostProcess::Clamp), 5.0);
+        assert_approx_eq!(engine.predict_postprocess(0.2, PostProcess::Clamp), 1.0);
+        assert_approx_eq!(
+            engine.predict_postprocess(3.3, PostProcess::RoundToNearestHalf),
+            3.5
+        );
+        assert_approx_eq!(
+            engine.predict_postprocess(3.3, PostProcess::RoundToInt),
+            3.0
+        );
+        assert_approx_eq!(engine.predict_postprocess(5.3, PostProcess::None), 5.3);
+    }
+
+    #[test]
+    fn user_based_predict_constant_neighbor() {
+        let config = Config::default();
+        let controller = controller();
+        let engine = Engine::with_controller(&controller, &config);
+
+        // User 2 is uncorrelated, so only user 3 contributes to the prediction
+        let prediction = engine
+            .user_based_predict(
+                2,
+                user(&controller, "1"),
+                item(&controller, "4"),
+                UserMethod::CosineSimilarity,
+                None,
+                1,
+                None,
+            )
+            .unwrap();
+
+        assert_approx_eq!(prediction, 5.0);
+
+        // User 2 is the only one who rated item 5, nothing to weight with
+        let prediction = engine.user_based_predict(
+            2,
+            user(&controller, "1"),
+            item(&controller, "5"),
+            UserMethod::CosineSimilarity,
+            None,
+            1,
+            None,
+        );
+
+        assert!(prediction.is_err());
+    }
+
+    #[test]
+    fn knn_with_warm_cache() {
+        let config = Config::default();
+        let controller = controller();
+        let mut engine = Engine::with_controller(&controller, &config);
+
+        let knn = |engine: &Engine<_, _, _>| {
+            let mut knn = engine
+                .user_knn(
+                    2,
+                    user(&controller, "1"),
+                    UserMethod::Manhattan,
+                    None,
+                    false,
+                )
+                .unwrap();
+            knn.sort_by_key(|(id, _)| *id);
+            knn
+        };
+
+        let cold = knn(&engine);
+
+        assert_eq!(engine.warm_cache().unwrap(), 3);
+        assert!(engine.is_cache_warm());
+        assert_eq!(cold, knn(&engine));
+
+        // Cache becomes stale after a new rating, until it's invalidated
+        controller.add_user(4);
+        controller.insert_rating(&4, &1, 5.0).unwrap();
+        assert_eq!(cold, knn(&engine));
+
+        engine.invalidate_cache();
+        assert!(!engine.is_cache_warm());
+        assert!(knn(&engine).contains(&(4, 0.0)));
+    }
+
+    #[test]
+    fn baseline_prediction() {
+        let config = Config::default();
+        let controller = controller();
+        let engine = Engine::with_controller(&controller, &config);
+
+        // μ = 41 / 12, b_u = 4 - μ and b_i = 3.5 - μ
+        let prediction = engine
+            .baseline_predict(user(&controller, "1"), item(&controller, "4"))
+            .unwrap();
+        assert_approx_eq!(prediction, 7.5 - 41.0 / 12.0);
+
+        // Unrated items have no deviation
+        controller.add_item(6);
+        let prediction = engine
+            .baseline_predict(user(&controller, "1"), item(&controller, "6"))
+            .unwrap();
+        assert_approx_eq!(prediction, 4.0);
+
+        // μ = 4, b_u = 1 and b_i = 1, clamped to the max score
+        let controller =
+            InMemoryController::from_ratings(&[(1, 1, 5.0), (1, 2, 5.0), (2, 1, 5.0), (2, 3, 1.0)])
+                .unwrap();
+        let engine = Engine::with_controller(&controller, &config);
+        let prediction = engine
+            .baseline_predict(user(&controller, "1"), item(&controller, "1"))
+            .unwrap();
+        assert_approx_eq!(prediction, 5.0);
+    }
+
+    #[test]
+    fn top_n_for_user() {
+        let config = Config::default();
+        let controller = controller();
+        let engine = Engine::with_controller(&controller, &config);
+
+        // User 2 is uncorrelated, item 5 is only rated by it
+        let top_n = engine
+            .user_based_top_n(
+                2,
+                5,
+                user(&controller, "1"),
+                UserMethod::Manhattan,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+        assert_eq!(top_n.len(), 1);
+        assert_eq!(top_n[0].0, 4);
+        assert_approx_eq!(top_n[0].1, 5.0);
+
+        // Chunked and non chunked must agree
+        let chunked = engine
+            .user_based_top_n(
+                2,
+                5,
+                user(&controller, "1"),
+                UserMethod::Manhattan,
+                Some(2),
+                None,
+                None,
+            )
+            .unwrap();
+        assert_eq!(top_n, chunked);
+    }
+
+    // Two groups of users with opposite tastes over 30 items, with a little noise
+    // so users of the same group aren't identical
+    fn two_tastes_controller() -> InMemoryController {
+        let mut ratings = Vec::new();
+        for user_id in 1..=60 {
+            for item_id in 1..=30 {
+                let likes = (item_id <= 15) == (user_id <= 30);
+                let noise = ((user_id * item_id) % 3) as f64 * 0.3;
+                let score = if likes { 5.0 - noise } else { 1.0 + noise };
+                ratings.push((user_id, item_id, score));
+            }
+        }
+
+        InMemoryController::from_ratings(&ratings).unwrap()
+    }
+
+    #[test]
+    fn all_user_knn_matches_user_knn() {
+        let config = Config::default();
+        let ratings: Vec<_> = (1..=8)
+            .flat_map(|user_id| {
+                (1..=6)
+                    .filter(move |item_id| (user_id + item_id) % 3 != 0)
+                    .map(move |item_id| (user_id, item_id, ((user_id * item_id) % 5 + 1) as f64))
+            })
+            .collect();
+        let controller = InMemoryController::from_ratings(&ratings).unwrap();
+        controller.add_user(9);
+        let mut engine = Engine::with_controller(&controller, &config);
+
+        // (transform, quantize levels), the candidates go through them like the targets
+        let transforms: Vec<(Option<Box<dyn RatingTransform<_>>>, _)> = vec![
+            (None, None),
+            (Some(Box::new(transform::MeanCenter)), Some(3)),
+            (Some(Box::new(transform::Binarize(3.0))), None),
+        ];
+        for (i, (transform, levels)) in transforms.into_iter().enumerate() {
+            engine.set_rating_transform(transform);
+            engine.set_quantize_levels(levels).unwrap();
+
+            for &method in &[
+                UserMethod::Manhattan,
+                UserMethod::CosineSimilarity,
+                UserMethod::PearsonCorrelation,
+            ] {
+                let all_knn = engine.all_user_knn(3, method, 3).unwrap();
+
+                for user_id in 1..=9 {
+                    let knn = engine.user_knn(
+                        3,
+                        user(&controller, &user_id.to_string()),
+                        method,
+                        None,
+                        false,
+                    );
+
+                    match knn {
+                        Ok(knn) => assert_eq!(all_knn[&user_id], knn, "{:?} {}", method, i),
+                        Err(_) => assert!(!all_knn.contains_key(&user_id)),
+                    }
+                }
+            }
+        }
+
+        assert!(engine.all_user_knn(0, UserMethod::Manhattan, 3).is_err());
+        assert!(engine.all_user_knn(3, UserMethod::Manhattan, 0).is_err());
+    }
+
+    #[test]
+    fn user_knn_never_contains_target() {
+        let config = Config::default();
+        let controller = controller();
+        let engine = Engine::with_controller(&controller, &config);
+
+        for &method in &[UserMethod::Manhattan, UserMethod::CosineSimilarity] {
+            for chunk_size in &[None, Some(1), Some(2), Some(10)] {
+                for user_id in 1..=3 {
+                    let knn = engine
+                        .user_knn(
+                            3,
+                            user(&controller, &user_id.to_string()),
+                            method,
+                            *chunk_size,
+                            false,
+                        )
+                        .unwrap();
+
+                    assert_eq!(knn.len(), 2, "{:?} {:?}", method, chunk_size);
+                    assert!(knn.iter().all(|(id, _)| *id != user_id));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn user_knn_graph() {
+        let config = Config::default();
+        let controller = controller();
+        let engine = Engine::with_controller(&controller, &config);
+
+        let graph = engine.knn_graph(1, UserMethod::Manhattan, 2).unwrap();
+
+        // Every user has exactly one edge, to its nearest neighbor
+        let mut sources: Vec<_> = graph.iter().map(|(user_id, _, _)| *user_id).collect();
+        sources.sort_unstable();
+        assert_eq!(sources, vec![1, 2, 3]);
+
+        for (user_id, neighbor_id, value) in &graph {
+            let knn = engine
+                .user_knn(
+                    1,
+                    user(&controller, &user_id.to_string()),
+                    UserMethod::Manhattan,
+                    None,
+                    false,
+                )
+                .unwrap();
+            assert_eq!(knn, vec![(*neighbor_id, *value)]);
+        }
+
+        let mut streamed = 0;
+        let edges = engine
+            .knn_graph_with(2, UserMethod::Manhattan, 2, |user_id, neighbor_id, _| {
+                assert_ne!(user_id, neighbor_id);
+                streamed += 1;
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!((edges, streamed), (6, 6));
+    }
+
+    #[test]
+    fn approx_user_knn_recall() {
+        let config = Config::default();
+        let controller = two_tastes_controller();
+        let engine = Engine::with_controller(&controller, &config);
+
+        let k = 5;
+        let mut found = 0;
+        for id in &["1", "7", "31", "45"] {
+            let exact: HashSet<_> = engine
+                .user_knn(
+                    k,
+                    user(&controller, id),
+                    UserMethod::CosineSimilarity,
+                    None,
+                    false,
+                )
+                .unwrap()
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect();
+
+            let approx = engine
+                .user_knn_approx(
+                    k,
+                    user(&controller, id),
+                    UserMethod::CosineSimilarity,
+                    16,
+                    8,
+                )
+                .unwrap();
+            assert!(approx.len() <= k);
+
+            // Neighbors are always from the same group
+            let group = |id: i32| id <= 30;
+            let target: i32 = id.parse().unwrap();
+            assert!(approx.iter().all(|(id, _)| group(*id) == group(target)));
+
+            found += approx.iter().filter(|(id, _)| exact.contains(id)).count();
+        }
+
+        let recall = found as f64 / (4 * k) as f64;
+        assert!(recall >= 0.8, "recall was {}", recall);
+
+        let invalid =
+            engine.user_knn_approx(k, user(&controller, "1"), UserMethod::Manhattan, 10, 4);
+        assert!(matches!(
+            invalid.map_err(|e| e.kind().cloned()),
+            Err(Some(ErrorKind::InvalidParameter(_)))
+        ));
+    }
+
+    #[test]
+    fn users_overlap() {
+        let config = Config::default();
+        let controller = controller();
+        let engine = Engine::with_controller(&controller, &config);
+
+        let overlap = engine
+            .overlap(user(&controller, "2"), user(&controller, "3"))
+            .unwrap();
+        assert_eq!(overlap, 4);
+
+        let users = vec![
+            user(&controller, "1"),
+            user(&controller, "2"),
+            user(&controller, "3"),
+        ];
+        let matrix = engine.overlap_matrix(&users).unwrap();
+        assert_eq!(matrix, vec![vec![3, 3, 3], vec![3, 5, 4], vec![3, 4, 4]]);
+    }
+
+    #[test]
+    fn evaluate_ndcg_over_test_users() {
+        let config = Config::default();
+        let controller = controller();
+        let engine = Engine::with_controller(&controller, &config);
+
+        // User 1 is only recommended item 4, the ideal ranking puts item 5 first
+        let relevance = hash_map! { 4 => 1.0, 5 => 2.0 };
+        let expected = eval::ndcg_at_k(&[4], &relevance, 2);
+        assert_approx_eq!(expected, 1.0 / (3.0 + 1.0 / 3f64.log2()));
+
+        let test_set = vec![
+            (user(&controller, "1"), relevance),
+            // Skipped, none of its items is relevant
+            (user(&controller, "3"), hash_map! { 5 => 0.0 }),
+        ];
+        let ndcg = engine
+            .evaluate_top_n_ndcg(test_set, 2, 2, UserMethod::Manhattan, None)
+            .unwrap();
+        assert_approx_eq!(ndcg, expected);
+
+        let skipped = engine.evaluate_top_n_ndcg(
+            vec![(user(&controller, "3"), hash_map! { 5 => 0.0 })],
+            2,
+            2,
+            UserMethod::Manhattan,
+            None,
+        );
+        assert!(matches!(
+            skipped.map_err(|e| e.kind().cloned()),
+            Err(Some(ErrorKind::EmptyRatings))
+        ));
+    }
+
+    #[test]
+    fn evaluate_user_based_predictions() {
+        let config = Config::default();
+        let controller = controller();
+        let engine = Engine::with_controller(&controller, &config);
+
+        let expected = engine
+            .user_based_predict(
+                2,
+                user(&controller, "1"),
+                item(&controller, "4"),
+                UserMethod::Manhattan,
+                None,
+                1,
+                None,
+            )
+            .unwrap();
+
+        // Nobody rated item 6, there are no neighbors to predict it
+        controller.add_item(6);
+        let test_set = vec![
+            (user(&controller, "1"), item(&controller, "4"), 3.0),
+            (user(&controller, "1"), item(&controller, "6"), 5.0),
+        ];
+        let metrics = engine
+            .evaluate_user_based(test_set, 2, UserMethod::Manhattan, None)
+            .unwrap();
+
+        assert_eq!((metrics.predicted, metrics.total), (1, 2));
+        assert_approx_eq!(metrics.mae.unwrap(), (expected - 3.0).abs());
+    }
+
+    #[test]
+    fn top_n_with_novelty() {
+        let config = Config::default();
+        let mut ratings = vec![(1, 1, 5.0), (1, 2, 3.0), (1, 3, 4.0)];
+        for user_id in 2..=6 {
+            ratings.extend(&[(user_id, 1, 5.0), (user_id, 2, 3.0), (user_id, 3, 4.0)]);
+            ratings.push((user_id, 4, 5.0));
+        }
+        // Only one user knows about item 5
+        ratings.push((6, 5, 4.5));
+
+        let controller = InMemoryController::from_ratings(&ratings).unwrap();
+        let engine = Engine::with_controller(&controller, &config);
+
+        let top_n = |novelty| {
+            engine
+                .user_based_top_n(
+                    5,
+                    2,
+                    user(&controller, "1"),
+                    UserMethod::CosineSimilarity,
+                    None,
+                    None,
+                    novelty,
+                )
+                .unwrap()
+        };
+
+        let ids = |top_n: Vec<(i32, f64)>| top_n.into_iter().map(|(id, _)| id).collect::<Vec<_>>();
+        assert_eq!(ids(top_n(None)), vec![4, 5]);
+        assert_eq!(ids(top_n(Some(0.0))), vec![4, 5]);
+        assert_eq!(ids(top_n(Some(0.1))), vec![4, 5]);
+
+        // 5 - ln(5) < 4.5 - ln(1), the niche item goes first
+        let novel = top_n(Some(1.0));
+        assert_eq!(ids(novel.clone()), vec![5, 4]);
+        assert_approx_eq!(novel[0].1, 4.5);
+        assert_approx_eq!(novel[1].1, 5.0);
+    }
+
+    #[test]
+    fn top_n_with_max_candidates() {
+        let config = Config::default();
+        let controller = InMemoryController::from_ratings(&[
+            (1, 1, 5.0),
+            (1, 2, 3.0),
+            (1, 3, 4.0),
+            (2, 1, 5.0),
+            (2, 2, 3.0),
+            (2, 3, 4.0),
+            (2, 4, 4.0),
+            (2, 5, 3.0),
+            (2, 6, 2.0),
+            (3, 1, 4.0),
+            (3, 2, 2.0),
+            (3, 3, 3.0),
+            (3, 4, 5.0),
+            (3, 5, 4.0),
+            (4, 1, 5.0),
+            (4, 2, 2.0),
+            (4, 3, 4.0),
+            (4, 4, 3.0),
+        ])
+        .unwrap();
+        let engine = Engine::with_controller(&controller, &config);
+
+        let top_n_ids = |max_candidates| {
+            let mut ids: Vec<_> = engine
+                .user_based_top_n(
+                    3,
+                    10,
+                    user(&controller, "1"),
+                    UserMethod::CosineSimilarity,
+                    None,
+                    max_candidates,
+                    None,
+                )
+                .unwrap()
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect();
+            ids.sort_unstable();
+            ids
+        };
+
+        // Item 4 is rated by the 3 neighbors, item 5 by 2 of them and item 6 by one
+        assert_eq!(top_n_ids(None), vec![4, 5, 6]);
+        assert_eq!(top_n_ids(Some(2)), vec![4, 5]);
+        assert_eq!(top_n_ids(Some(1)), vec![4]);
+    }
+
+    #[test]
+    fn predict_for_cold_item() {
+        let config = Config::default();
+        let controller = controller();
+        let engine = Engine::with_controller(&controller, &config);
+
+        controller.add_item(6);
+
+        for method in ItemMethod::all() {
+            let prediction = engine.item_based_predict(
+                user(&controller, "1"),
+                item(&controller, "6"),
+                *method,
+                100,
+                false,
+                None,
+                &CancellationToken::new(),
+            );
+
+            match prediction.map_err(|e| e.kind().cloned()) {
+                Err(Some(ErrorKind::ColdItem(id))) => assert_eq!(id, "6"),
+                res => panic!("Expected a cold item error, got {:?}", res),
+            }
+        }
+    }
+
+    #[test]
+    fn bipolar_slope_one_prediction() {
+        let config = Config::default();
+        let controller = controller();
+        let mut engine = Engine::with_controller(&controller, &config);
+
+        // Only user 3 liked both items 4 and 1, user 1 liked item 1
+        let prediction = engine
+            .item_based_predict(
+                user(&controller, "1"),
+                item(&controller, "4"),
+                ItemMethod::BiPolarSlopeOne,
+                100,
+                false,
+                Some(PostProcess::None),
+                &CancellationToken::new(),
+            )
+            .unwrap();
+        assert_approx_eq!(prediction, 6.0);
+
+        let distance = engine
+            .item_distance(
+                item(&controller, "4"),
+                item(&controller, "1"),
+                ItemMethod::BiPolarSlopeOne,
+            )
+            .unwrap();
+        assert_approx_eq!(distance, 1.0);
+    }
+
+    #[test]
+    fn item_neighbors_match_item_distance() {
+        let config = Config::default();
+        let controller = controller();
+        let mut engine = Engine::with_controller(&controller, &config);
+
+        let mut expected: Vec<_> = (2..=5)
+            .filter_map(|id| {
+                engine
+                    .item_distance(
+                        item(&controller, "1"),
+                        item(&controller, &id.to_string()),
+                        ItemMethod::AdjCosine,
+                    )
+                    .ok()
+                    .map(|similarity| (id, similarity))
+            })
+            .collect();
+        expected.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap());
+        expected.truncate(2);
+
+        for chunk_size in &[1, 2, 10] {
+            let neighbors = engine
+                .item_neighbors(
+                    item(&controller, "1"),
+                    2,
+                    ItemMethod::AdjCosine,
+                    *chunk_size,
+                )
+                .unwrap();
+
+            assert_eq!(neighbors.len(), expected.len());
+            for ((id, similarity), (expected_id, expected_similarity)) in
+                neighbors.iter().zip(&expected)
+            {
+                assert_eq!(id, expected_id);
+                assert_approx_eq!(similarity, expected_similarity);
+            }
+        }
+
+        let neighbors = |n, method| engine.item_neighbors(item(&controller, "1"), n, method, 2);
+        assert!(neighbors(0, ItemMethod::AdjCosine).is_err());
+        assert!(neighbors(2, ItemMethod::SlopeOne).is_err());
+
+        controller.add_item(6);
+        let cold = engine.item_neighbors(item(&controller, "6"), 2, ItemMethod::AdjCosine, 2);
+        assert!(matches!(
+            cold.unwrap_err().kind(),
+            Some(ErrorKind::ColdItem(_))
+        ));
+    }
+
+    fn adj_cosine_expected<F>(controller: &InMemoryController, user_id: &str, f: F) -> f64
+    where
+        F: Fn(&[(f64, f64)]) -> f64,
+    {
+        let config = Config::default();
+        let mut engine = Engine::with_controller(controller, &config);
+
+        let user = user(controller, user_id);
+        let mut sims_and_ratings = Vec::new();
+        for (item_id, rating) in controller.user_ratings(&user).unwrap() {
+            let sim = engine.item_distance(
+                item(controller, "4"),
+                item(controller, &item_id.to_string()),
+                ItemMethod::AdjCosine,
+            );
+
+            if let Ok(sim) = sim {
+                sims_and_ratings.push((sim, rating));
+            }
+        }
+
+        f(&sims_and_ratings)
+    }
+
+    #[test]
+    fn item_based_predict_without_normalization() {
+        let mut config = Config::default();
+        config.engine.normalization = distances::items::Normalization::None;
+
+        let controller = controller();
+        let engine = Engine::with_controller(&controller, &config);
+
+        let prediction = engine
+            .item_based_predict(
+                user(&controller, "1"),
+                item(&controller, "4"),
+                ItemMethod::AdjCosine,
+                100,
+                false,
+                Some(PostProcess::None),
+                &CancellationToken::new(),
+            )
+            .unwrap();
+
+        let expected = adj_cosine_expected(&controller, "1", |sims_and_ratings| {
+            let num: f64 = sims_and_ratings.iter().map(|(sim, r)| sim * r).sum();
+            let dem: f64 = sims_and_ratings.iter().map(|(sim, _)| sim.abs()).sum();
+            num / dem
+        });
+
+        assert_approx_eq!(prediction, expected);
+    }
+
+    #[test]
+    fn item_based_predict_min_max_normalization() {
+        use distances::items::{denormalize_user_rating, normalize_user_ratings};
+
+        let config = Config::default();
+        let controller = controller();
+        let engine = Engine::with_controller(&controller, &config);
+
+        let prediction = engine
+            .item_based_predict(
+                user(&controller, "1"),
+                item(&controller, "4"),
+                ItemMethod::AdjCosine,
+                100,
+                false,
+                None,
+                &CancellationToken::new(),
+            )
+            .unwrap();
+
+        let expected = adj_cosine_expected(&controller, "1", |sims_and_ratings| {
+            let ratings: Ratings<_> = sims_and_ratings
+                .iter()
+                .enumerate()
+                .map(|(i, (_, r))| (i, *r))
+                .collect();
+            let normalized = normalize_user_ratings(&ratings, 1.0, 5.0).unwrap();
+
+            let mut num = 0.0;
+            let mut dem = 0.0;
+            for (i, (sim, _)) in sims_and_ratings.iter().enumerate() {
+                num += sim * normalized[&i];
+                dem += sim.abs();
+            }
+
+            denormalize_user_rating(num / dem, 1.0, 5.0).unwrap()
+        });
+
+        assert_approx_eq!(prediction, expected);
+    }
+
+    #[test]
+    fn item_distance_with_unrated_item() {
+        let config = Config::default();
+        let controller = controller();
+        let mut engine = Engine::with_controller(&controller, &config);
+
+        controller.add_item(6);
+
+        for method in ItemMethod::all() {
+            let distance =
+                engine.item_distance(item(&controller, "1"), item(&controller, "6"), *method);
+
+            match distance.map_err(|e| e.kind().cloned()) {
+                Err(Some(ErrorKind::ColdItem(id))) => assert_eq!(id, "6"),
+                res => panic!("Expected a cold item error, got {:?}", res),
+            }
+        }
+    }
+
+    #[test]
+    fn predict_for_cold_user() {
+        let config = Config::default();
+        let controller = controller();
+        let engine = Engine::with_controller(&controller, &config);
+
+        controller.add_user(4);
+
+        let prediction = engine.user_based_predict(
+            2,
+            user(&controller, "4"),
+            item(&controller, "4"),
+            UserMethod::CosineSimilarity,
+            None,
+            1,
+            None,
+        );
+
+        match prediction.map_err(|e| e.kind().cloned()) {
+            Err(Some(ErrorKind::ColdUser(id))) => assert_eq!(id, "4"),
+            res => panic!("Expected a cold user error, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn popularity_weighted_slope_one() {
+        let config = Config::default();
+        let controller = InMemoryController::from_ratings(&[
+            // Item 20 is niche, every one of its raters also rated item 10
+            (1, 20, 5.0),
+            (1, 30, 1.0),
+            (2, 10, 3.0),
+            (2, 20, 3.0),
+            (2, 30, 3.0),
+            (3, 10, 3.0),
+            (3, 20, 3.0),
+            (3, 30, 3.0),
+            (4, 10, 3.0),
+            (4, 20, 3.0),
+            // Item 30 is popular but shares less raters with item 10
+            (5, 30, 2.0),
+            (6, 30, 2.0),
+            (7, 30, 2.0),
+            (8, 30, 2.0),
+        ])
+        .unwrap();
+        let engine = Engine::with_controller(&controller, &config);
+
+        let predict = |popularity_weighted| {
+            engine
+                .item_based_predict(
+                    user(&controller, "1"),
+                    item(&controller, "10"),
+                    ItemMethod::SlopeOne,
+                    100,
+                    popularity_weighted,
+                    Some(PostProcess::None),
+                    &CancellationToken::new(),
+                )
+                .unwrap()
+        };
+
+        // Cardinalities 3 and 2 against popularities 4 and 7
+        assert_approx_eq!(predict(false), (5.0 * 3.0 + 1.0 * 2.0) / 5.0);
+        assert_approx_eq!(predict(true), (5.0 * 12.0 + 1.0 * 14.0) / 26.0);
+        assert!(predict(true) < predict(false));
+    }
+
+    #[test]
+    fn zero_chunk_size_is_rejected() {
+        let config = Config::default();
+        let controller = controller();
+        let engine = Engine::with_controller(&controller, &config);
+        let method = UserMethod::CosineSimilarity;
+
+        let is_invalid = |error: RecommendationError| {
+            matches!(error.kind(), Some(ErrorKind::InvalidParameter(_)))
+        };
+
+        let knn = engine.user_knn(2, user(&controller, "1"), method, Some(0), false);
+        assert!(is_invalid(knn.unwrap_err()));
+
+        let prediction = engine.user_based_predict(
+            2,
+            user(&controller, "1"),
+            item(&controller, "4"),
+            method,
+            Some(0),
+            1,
+            None,
+        );
+        assert!(is_invalid(prediction.unwrap_err()));
+
+        let top_n =
+            engine.user_based_top_n(2, 1, user(&controller, "1"), method, Some(0), None, None);
+        assert!(is_invalid(top_n.unwrap_err()));
+
+        for item_method in ItemMethod::all() {
+            let prediction = engine.item_based_predict(
+                user(&controller, "1"),
+                item(&controller, "4"),
+                *item_method,
+                0,
+                false,
+                None,
+                &CancellationToken::new(),
+            );
+            assert!(is_invalid(prediction.unwrap_err()));
+        }
+    }
+
+    #[test]
+    fn hybrid_knn_predict_fallbacks() {
+        let config = Config::default();
+        let controller = InMemoryController::from_ratings(&[
+            (1, 1, 5.0),
+            (1, 2, 4.0),
+            (1, 3, 1.0),
+            (2, 1, 4.0),
+            (2, 2, 5.0),
+            (2, 3, 2.0),
+            (2, 4, 5.0),
+            (3, 1, 2.0),
+            (3, 2, 1.0),
+            (3, 3, 4.0),
+            (3, 4, 2.0),
+            (4, 1, 5.0),
+            (4, 2, 3.0),
+            (4, 3, 2.0),
+            (4, 4, 4.0),
+            (2, 5, 1.0),
+            (3, 5, 5.0),
+            // Only rated item 2, the most similar to item 4
+            (5, 2, 4.0),
+            // Only rated items 3 and 5, both negatively correlated with item 4
+            (6, 3, 3.0),
+            (6, 5, 4.0),
+        ])
+        .unwrap();
+        let engine = Engine::with_controller(&controller, &config);
+
+        let hybrid = |user_id, item_id, method, alpha| {
+            engine.hybrid_knn_predict(
+                user(&controller, user_id),
+                item(&controller, item_id),
+                2,
+                method,
+                alpha,
+                None,
+            )
+        };
+
+        let user_based = |user_id, method| {
+            engine.user_based_predict(
+                2,
+                user(&controller, user_id),
+                item(&controller, "4"),
+                method,
+                None,
+                1,
+                None,
+            )
+        };
+
+        let item_based = |user_id| {
+            engine.item_based_knn_predict(
+                user(&controller, user_id),
+                item(&controller, "4"),
+                2,
+                100,
+            )
+        };
+
+        let cosine = UserMethod::CosineSimilarity;
+        let pearson = UserMethod::PearsonCorrelation;
+
+        // Both succeed, they are blended
+        let (by_user, by_item) = (user_based("1", cosine).unwrap(), item_based("1").unwrap());
+        assert_approx_eq!(
+            hybrid("1", "4", cosine, 0.25).unwrap(),
+            0.25 * by_user + 0.75 * by_item
+        );
+
+        // Pearson's correlation needs more than one common item, so there are no
+        // user neighbors but the item based prediction works
+        assert!(user_based("5", pearson).is_err());
+        assert_approx_eq!(hybrid("5", "4", pearson, 0.5).unwrap(), 4.0);
+
+        // No item similar to item 4, but the user neighbors work
+        assert!(item_based("6").is_err());
+        assert_approx_eq!(
+            hybrid("6", "4", cosine, 0.5).unwrap(),
+            user_based("6", cosine).unwrap()
+        );
+
+        // Cold item, neither of them can predict it
+        controller.add_item(6);
+        assert!(hybrid("1", "6", cosine, 0.5).is_err());
+
+        assert!(hybrid("1", "4", cosine, 1.5).is_err());
+    }
+
+    #[test]
+    fn precompute_means_by_chunks() {
+        let config = Config::default();
+        let controller = controller();
+        let engine = Engine::with_controller(&controller, &config);
+
+        assert_eq!(engine.precompute_means(2).unwrap(), 3);
+
+        let adj_cosine = engine.clone_rc_adj_cosine();
+        assert!((1..=3).all(|id| adj_cosine.borrow().has_mean_for(&id)));
+        assert_approx_eq!(adj_cosine.borrow_mut().get_mean_for(&2).unwrap(), 3.0);
+
+        assert!(engine.precompute_means(0).is_err());
+    }
+
+    #[test]
+    fn slope_one_predict_from_matrix() {
+        use chunked_matrix::ChunkedMatrix;
+
+        let config = Config::default();
+        let controller = controller();
+        let engine = Engine::with_controller(&controller, &config);
+
+        let mut matrix: DeviationMatrix<_, _> = DeviationMatrix::new(&controller, &config, 5, 5);
+        matrix.calculate_chunk(0, 0).unwrap();
+
+        for (user_id, item_id) in &[("1", "4"), ("1", "5"), ("3", "5")] {
+            let streamed = engine
+                .slope_one_predict(
+                    user(&controller, user_id),
+                    item(&controller, item_id),
+                    2,
+                    false,
+                    &CancellationToken::new(),
+                    &mut Timings::default(),
+                )
+                .unwrap();
+
+            let from_matrix = engine
+                .slope_one_predict_from_matrix(
+                    user(&controller, user_id),
+                    item(&controller, item_id),
+                    &matrix,
+                )
+                .unwrap();
+
+            assert_approx_eq!(from_matrix, streamed);
+        }
+    }
+
+    #[test]
+    fn loo_user_report_holds_out_each_rating() {
+        let config = Config::default();
+        let controller = controller();
+        let engine = Engine::with_controller(&controller, &config);
+
+        let report = engine
+            .loo_user_report(user(&controller, "3"), UserMethod::Manhattan, 2)
+            .unwrap();
+
+        // User 3 rated items 1 to 4, but item 4 was only rated by user 2 whose
+        // ratings of the other items are constant, so it can't be predicted
+        let items: Vec<_> = report.iter().map(|(item_id, _, _)| *item_id).collect();
+        assert_eq!(items, vec![1, 2, 3]);
+
+        for (item_id, actual, predicted) in &report {
+            let stored = controller.user_ratings(&user(&controller, "3")).unwrap()[item_id];
+            assert_approx_eq!(*actual, stored);
+
+            // Held out ratings aren't known by the neighbors search
+            let mut known = controller.user_ratings(&user(&controller, "3")).unwrap();
+            known.remove(item_id);
+            let (pearson_knn, total) = engine
+                .neighbors_for_ratings(
+                    2,
+                    &known,
+                    item(&controller, &item_id.to_string()),
+                    UserMethod::Manhattan,
+                    None,
+                    Some(&user(&controller, "3")),
+                )
+                .unwrap();
+            let expected = pearson_knn
+                .iter()
+                .fold(0.0, |acc, (_, coef, rating)| acc + rating * coef / total);
+            assert_approx_eq!(*predicted, expected.clamp(1.0, 5.0));
+        }
+
+        controller.add_user(4);
+        assert!(engine
+            .loo_user_report(user(&controller, "4"), UserMethod::Manhattan, 2)
+            .is_err());
+    }
+
+    #[test]
+    fn normalized_ratings_cache() {
+        let config = Config::default();
+        let controller = controller();
+        let mut engine = Engine::with_controller(&controller, &config);
+        let cancel = CancellationToken::new();
+
+        let predict = |engine: &Engine<_, _, _>| {
+            engine
+                .item_based_predict(
+                    user(&controller, "1"),
+                    item(&controller, "4"),
+                    ItemMethod::AdjCosine,
+                    10,
+                    false,
+                    None,
+                    &cancel,
+                )
+                .unwrap()
+        };
+
+        let first = predict(&engine);
+        assert_approx_eq!(predict(&engine), first);
+        let stats = engine.normalization_cache_stats();
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (1, 1, 0));
+
+        // Changed ratings hash differently, the stale entry isn't reused
+        controller.update_rating(&1, &1, 1.0).unwrap();
+        let updated = predict(&engine);
+        let stats = engine.normalization_cache_stats();
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (1, 2, 1));
+
+        let fresh = Engine::with_controller(&controller, &config);
+        assert_approx_eq!(updated, predict(&fresh));
+
+        engine.invalidate_cache();
+        assert_approx_eq!(predict(&engine), updated);
+        let stats = engine.normalization_cache_stats();
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (1, 3, 2));
+    }
 
     #[test]
-    fn euclidean_distance() -> Result<(), Error> {
+    fn coverage_report_breakdown() {
+        // Item 3 was only rated by user 1, nobody rated item 4 and user 5 has no ratings
+        let controller = InMemoryController::from_ratings(&[
+            (1, 1, 5.),
+            (1, 2, 3.),
+            (1, 3, 4.),
+            (2, 1, 4.),
+            (2, 2, 2.),
+            (3, 1, 3.),
+            (3, 2, 4.),
+            (4, 1, 2.),
+            (4, 2, 5.),
+        ])
+        .unwrap();
+        controller.add_item(4);
+        controller.add_user(5);
+
         let config = Config::default();
-        let controller = SimpleMovieController::from_config(&config, "simple-movie")?;
         let engine = Engine::with_controller(&controller, &config);
 
-        let user_a = controller
-            .users_by(&SearchBy::id("52"))?
-            .drain(..1)
-            .next()
+        let users = controller.users().unwrap();
+        let items = controller.items().unwrap();
+        let report = engine
+            .coverage_report(&users, &items, UserMethod::Manhattan, 3)
             .unwrap();
 
-        let user_b = controller
-            .users_by(&SearchBy::id("53"))?
-            .drain(..1)
-            .next()
+        assert_eq!(
+            report,
+            eval::CoverageReport {
+                predicted: 11,
+                cold_item: 5,
+                cold_user: 3,
+                no_neighbors: 1,
+                failed: 0,
+                total: 20,
+            }
+        );
+        assert_approx_eq!(report.coverage(), 0.55);
+    }
+
+    #[test]
+    fn recent_rating_outweighs_old_one() {
+        let controller = InMemoryController::from_ratings(&[
+            (1, 1, 5.),
+            (1, 2, 3.),
+            (1, 3, 4.),
+            (2, 1, 5.),
+            (2, 2, 3.),
+            (2, 3, 4.),
+            (2, 4, 5.),
+            (3, 1, 5.),
+            (3, 2, 3.),
+            (3, 3, 4.),
+            (3, 4, 1.),
+        ])
+        .unwrap();
+
+        let now = 1_600_000_000;
+        controller.set_rating_timestamp(2, 4, now).unwrap();
+        controller
+            .set_rating_timestamp(3, 4, now - 100 * 86_400)
             .unwrap();
 
-        println!(
-            "euclidean(52, 53): {:?}",
-            engine.user_distance(user_a, user_b, Method::Euclidean)
-        );
+        let config = Config::default();
+        let mut engine = Engine::with_controller(&controller, &config);
+        let predict = |engine: &Engine<_, _, _>| {
+            engine
+                .user_based_predict(
+                    2,
+                    user(&controller, "1"),
+                    item(&controller, "4"),
+                    UserMethod::Manhattan,
+                    None,
+                    2,
+                    None,
+                )
+                .unwrap()
+        };
 
-        Ok(())
+        // Both neighbors are identical to user 1, their ratings weigh the same
+        assert_approx_eq!(predict(&engine), 3.0);
+
+        engine.set_recency_decay(Some(RecencyDecay::at(0.1, now)));
+        let prediction = predict(&engine);
+        assert!(prediction > 4.9, "{}", prediction);
+
+        engine.set_recency_decay(None);
+        assert_approx_eq!(predict(&engine), 3.0);
     }
 
     #[test]
-    fn manhattan_distance() -> Result<(), Error> {
+    fn item_based_predict_falls_back_to_user_mean() {
+        let controller = controller();
+        controller.add_item(6);
+
         let config = Config::default();
-        let controller = SimpleMovieController::from_config(&config, "simple-movie")?;
         let engine = Engine::with_controller(&controller, &config);
+        let cancel = CancellationToken::new();
+
+        // Nobody rated item 6, user 1 rated items 1, 2 and 3 with 5, 3 and 4
+        for method in ItemMethod::all() {
+            let strict = engine.item_based_predict(
+                user(&controller, "1"),
+                item(&controller, "6"),
+                *method,
+                10,
+                false,
+                None,
+                &cancel,
+            );
+            assert!(strict.is_err());
+
+            let prediction = engine
+                .item_based_predict_or_user_mean(
+                    user(&controller, "1"),
+                    item(&controller, "6"),
+                    *method,
+                    10,
+                    false,
+                    None,
+                    &cancel,
+                )
+                .unwrap();
+            assert_approx_eq!(prediction, 4.0);
+        }
 
-        let user_a = controller
-            .users_by(&SearchBy::id("52"))?
-            .drain(..1)
-            .next()
+        // Warm items are predicted as usual
+        let expected = engine
+            .item_based_predict(
+                user(&controller, "1"),
+                item(&controller, "4"),
+                ItemMethod::SlopeOne,
+                10,
+                false,
+                None,
+                &cancel,
+            )
             .unwrap();
-
-        let user_b = controller
-            .users_by(&SearchBy::id("53"))?
-            .drain(..1)
-            .next()
+        let prediction = engine
+            .item_based_predict_or_user_mean(
+                user(&controller, "1"),
+                item(&controller, "4"),
+                ItemMethod::SlopeOne,
+                10,
+                false,
+                None,
+                &cancel,
+            )
             .unwrap();
-
-        println!(
-            "manhattan(52, 53): {:?}",
-            engine.user_distance(user_a, user_b, Method::Manhattan)
-        );
-
-        Ok(())
+        assert_approx_eq!(prediction, expected);
     }
 
     #[test]
-    fn cosine_similarity_distance() -> Result<(), Error> {
+    fn predictions_stop_when_cancelled() {
+        let controller = controller();
         let config = Config::default();
-        let controller = SimpleMovieController::from_config(&config, "simple-movie")?;
         let engine = Engine::with_controller(&controller, &config);
 
-        let user_a = controller
-            .users_by(&SearchBy::id("52"))?
-            .drain(..1)
-            .next()
-            .unwrap();
-
-        let user_b = controller
-            .users_by(&SearchBy::id("53"))?
-            .drain(..1)
-            .next()
-            .unwrap();
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+
+        for method in &[
+            ItemMethod::AdjCosine,
+            ItemMethod::SlopeOne,
+            ItemMethod::BiPolarSlopeOne,
+        ] {
+            let prediction = engine.item_based_predict(
+                user(&controller, "1"),
+                item(&controller, "4"),
+                *method,
+                1,
+                false,
+                None,
+                &cancel,
+            );
 
-        println!(
-            "cosine(52, 53): {:?}",
-            engine.user_distance(user_a, user_b, Method::CosineSimilarity)
-        );
+            match prediction.map_err(|e| e.kind().cloned()) {
+                Err(Some(ErrorKind::Cancelled)) => {}
+                other => panic!("Expected a cancelled prediction, got {:?}", other),
+            }
+        }
 
-        Ok(())
+        cancel.reset();
+        assert!(engine
+            .item_based_predict(
+                user(&controller, "1"),
+                item(&controller, "4"),
+                ItemMethod::SlopeOne,
+                1,
+                false,
+                None,
+                &cancel,
+            )
+            .is_ok());
     }
 
     #[test]
-    fn knn_with_manhattan() -> Result<(), Error> {
+    fn user_based_predict_min_neighbors() {
         let config = Config::default();
-        let controller = SimpleMovieController::from_config(&config, "simple-movie")?;
+        // Only user 3 rated item 4
+        let controller = InMemoryController::from_ratings(&[
+            (1, 1, 5.0),
+            (1, 2, 3.0),
+            (1, 3, 4.0),
+            (2, 1, 4.0),
+            (2, 2, 2.0),
+            (2, 3, 5.0),
+            (3, 1, 4.0),
+            (3, 2, 2.0),
+            (3, 3, 3.0),
+            (3, 4, 5.0),
+        ])
+        .unwrap();
         let engine = Engine::with_controller(&controller, &config);
 
-        let user = controller
-            .users_by(&SearchBy::id("52"))?
-            .drain(..1)
-            .next()
-            .unwrap();
+        let predict = |min_neighbors| {
+            engine.user_based_predict(
+                2,
+                user(&controller, "1"),
+                item(&controller, "4"),
+                UserMethod::CosineSimilarity,
+                None,
+                min_neighbors,
+                None,
+            )
+        };
 
-        println!(
-            "kNN(52, manhattan): {:?}",
-            engine.user_knn(4, user, Method::Manhattan, None)
-        );
+        assert_approx_eq!(predict(1).unwrap(), 5.0);
 
-        Ok(())
+        match predict(2).map_err(|e| e.kind().cloned()) {
+            Err(Some(ErrorKind::InsufficientNeighbors(found, required))) => {
+                assert_eq!((found, required), (1, 2))
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
     }
 
     #[test]
-    fn knn_with_euclidean() -> Result<(), Error> {
+    fn empty_user_knn_reasons() {
         let config = Config::default();
-        let controller = SimpleMovieController::from_config(&config, "simple-movie")?;
-        let engine = Engine::with_controller(&controller, &config);
+        let reason = |controller: &InMemoryController, method| {
+            let engine = Engine::with_controller(controller, &config);
+            match engine.user_knn(2, user(controller, "1"), method, None, false) {
+                Err(e) => match e.kind() {
+                    Some(ErrorKind::NoNeighbors(reason)) => *reason,
+                    kind => panic!("unexpected error {:?}", kind),
+                },
+                Ok(knn) => panic!("unexpected neighbors {:?}", knn),
+            }
+        };
 
-        let user = controller
-            .users_by(&SearchBy::id("52"))?
-            .drain(..1)
-            .next()
-            .unwrap();
+        let controller = InMemoryController::from_ratings(&[(2, 1, 5.0)]).unwrap();
+        controller.add_user(1);
+        assert_eq!(
+            reason(&controller, UserMethod::Manhattan),
+            EmptyKnnReason::UserHasNoRatings
+        );
 
-        println!(
-            "kNN(52, 3, euclidean): {:?}",
-            engine.user_knn(3, user, Method::Euclidean, None)
+        let controller = InMemoryController::from_ratings(&[(1, 1, 5.0), (2, 2, 3.0)]).unwrap();
+        assert_eq!(
+            reason(&controller, UserMethod::Manhattan),
+            EmptyKnnReason::NoOverlappingUsers
         );
 
-        Ok(())
+        // User 2 rated everything with the same value, so Pearson is undefined
+        let controller =
+            InMemoryController::from_ratings(&[(1, 1, 5.0), (1, 2, 3.0), (2, 1, 3.0), (2, 2, 3.0)])
+                .unwrap();
+        assert_eq!(
+            reason(&controller, UserMethod::PearsonCorrelation),
+            EmptyKnnReason::AllDistancesInvalid
+        );
     }
 
     #[test]
-    fn knn_with_cosine() -> Result<(), Error> {
+    fn reverse_user_knn() {
         let config = Config::default();
-        let controller = SimpleMovieController::from_config(&config, "simple-movie")?;
+        let controller = controller();
         let engine = Engine::with_controller(&controller, &config);
 
-        let user = controller
-            .users_by(&SearchBy::id("52"))?
-            .drain(..1)
-            .next()
-            .unwrap();
+        let ids = |reverse, method| -> Vec<_> {
+            engine
+                .user_knn(1, user(&controller, "1"), method, None, reverse)
+                .unwrap()
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect()
+        };
 
-        println!(
-            "kNN(52, 3, cosine): {:?}",
-            engine.user_knn(3, user, Method::CosineSimilarity, None)
-        );
+        assert_eq!(ids(false, UserMethod::CosineSimilarity), vec![3]);
+        assert_eq!(ids(true, UserMethod::CosineSimilarity), vec![2]);
 
-        Ok(())
+        // Distances are flipped the other way around
+        assert_eq!(ids(false, UserMethod::Euclidean), vec![3]);
+        assert_eq!(ids(true, UserMethod::Euclidean), vec![2]);
     }
 
     #[test]
-    fn knn_in_books() -> Result<(), Error> {
+    fn user_rating_histogram() {
         let config = Config::default();
-        let controller = BooksController::from_config(&config, "books")?;
+        let controller = controller();
+        controller.add_user(4);
         let engine = Engine::with_controller(&controller, &config);
 
-        let user = controller
-            .users_by(&SearchBy::id("242"))?
-            .drain(..1)
-            .next()
+        // User 3 rated 4, 2, 3 and 5 over a score range of 1 to 5
+        let histogram = engine
+            .user_rating_histogram(user(&controller, "3"), 4)
             .unwrap();
+        assert_eq!(histogram, vec![(1.0, 0), (2.0, 1), (3.0, 1), (4.0, 2)]);
 
-        println!(
-            "kNN(242, 5, manhattan): {:?}",
-            engine.user_knn(5, user, Method::JaccardDistance, None)
-        );
+        let histogram = engine
+            .user_rating_histogram(user(&controller, "4"), 2)
+            .unwrap();
+        assert_eq!(histogram, vec![(1.0, 0), (3.0, 0)]);
 
-        Ok(())
+        let error = engine
+            .user_rating_histogram(user(&controller, "3"), 0)
+            .unwrap_err();
+        assert!(matches!(error.kind(), Some(ErrorKind::InvalidParameter(_))));
     }
 
     #[test]
-    fn similarity_matrix() -> Result<(), Error> {
-        use super::chunked_matrix::{ChunkedMatrix, SimilarityMatrix};
-        use movie_lens_small::MovieLensSmallController;
-        use std::time::Instant;
+    fn user_distance_with_rating_transform() {
+        use transform::Binarize;
 
         let config = Config::default();
-        let controller = MovieLensSmallController::from_config(&config, "movie-lens")?;
+        let controller = controller();
+        let mut engine = Engine::with_ratings_lru(&controller, &config, 2);
+        let distance = |engine: &Engine<_, _, _>| {
+            engine
+                .user_distance(
+                    user(&controller, "1"),
+                    user(&controller, "3"),
+                    UserMethod::Manhattan,
+                )
+                .unwrap()
+        };
 
-        let mut sim_matrix = SimilarityMatrix::new(&controller, &config, 10000, 10000);
+        assert_approx_eq!(distance(&engine), 3.0);
 
-        let now = Instant::now();
-        sim_matrix.calculate_chunk(0, 0)?;
-        println!("Elapsed: {}", now.elapsed().as_secs_f64());
+        // Binarized, users 1 and 3 only disagree on item 3
+        engine.set_rating_transform(Some(Box::new(Binarize(4.0))));
+        assert_approx_eq!(distance(&engine), 1.0);
 
-        Ok(())
+        engine.set_rating_transform(None);
+        assert_approx_eq!(distance(&engine), 3.0);
     }
 
     #[test]
-    fn item_based_pred() -> Result<(), Error> {
+    fn chunked_user_knn_with_rating_transform() {
+        use transform::{Binarize, LogScale, MeanCenter};
+
         let config = Config::default();
+        let controller = controller();
+        let mut engine = Engine::with_controller(&controller, &config);
+
+        let transforms: Vec<Box<dyn RatingTransform<_>>> = vec![
+            Box::new(Binarize(4.0)),
+            Box::new(LogScale),
+            Box::new(MeanCenter),
+        ];
+        for transform in transforms {
+            engine.set_rating_transform(Some(transform));
+
+            for &method in &[UserMethod::Manhattan, UserMethod::CosineSimilarity] {
+                let knn = |chunk_size| {
+                    engine
+                        .user_knn(2, user(&controller, "1"), method, chunk_size, false)
+                        .unwrap()
+                };
+
+                let unchunked = knn(None);
+                for chunk_size in &[1, 2, 10] {
+                    let chunked = knn(Some(*chunk_size));
+                    assert_eq!(chunked.len(), unchunked.len());
+                    for ((id, dist), (expected_id, expected_dist)) in chunked.iter().zip(&unchunked)
+                    {
+                        assert_eq!(id, expected_id);
+                        assert_approx_eq!(dist, expected_dist);
+                    }
+                }
+            }
+        }
+    }
 
-        let controller = BooksController::from_config(&config, "books")?;
+    #[test]
+    fn chunked_user_based_predict_excludes_target() {
+        let config = Config::default();
+        let controller = controller();
         let engine = Engine::with_controller(&controller, &config);
 
-        let user = controller
-            .users_by(&SearchBy::id("243"))?
-            .drain(..1)
-            .next()
-            .unwrap();
-
-        let item = controller
-            .items_by(&SearchBy::name("Flesh Tones: A Novel"))?
-            .drain(..1)
-            .next()
-            .unwrap();
+        // User 2 already rated item 4, it must not be its own neighbor
+        let predict = |chunk_size| {
+            engine
+                .user_based_predict(
+                    2,
+                    user(&controller, "2"),
+                    item(&controller, "4"),
+                    UserMethod::CosineSimilarity,
+                    chunk_size,
+                    1,
+                    None,
+                )
+                .unwrap()
+        };
 
-        let now = Instant::now();
-        println!(
-            "Item based prediction Books: {:?}",
-            engine.item_based_predict(user, item, ItemMethod::SlopeOne, 2500)?
-        );
-        println!("Elapsed: {}", now.elapsed().as_secs_f64());
+        let unchunked = predict(None);
+        assert_approx_eq!(unchunked, 5.0);
+        for chunk_size in &[1, 2, 10] {
+            assert_approx_eq!(predict(Some(*chunk_size)), unchunked);
+        }
+    }
 
-        let controller = SimpleMovieController::from_config(&config, "simple-movie")?;
+    #[test]
+    fn user_distance_ensemble_of_two_methods() {
+        let config = Config::default();
+        let controller = controller();
         let engine = Engine::with_controller(&controller, &config);
+        let distance = |method| {
+            engine
+                .user_distance(user(&controller, "1"), user(&controller, "2"), method)
+                .unwrap()
+        };
 
-        let user = controller
-            .users_by(&SearchBy::name("Josh"))?
-            .drain(..1)
-            .next()
-            .unwrap();
+        let cosine = distance(UserMethod::CosineSimilarity);
+        let euclidean = distance(UserMethod::Euclidean);
+        let expected = 0.5 * (cosine + 1.0) / 2.0 + 0.5 / (1.0 + euclidean);
 
-        let item = controller
-            .items_by(&SearchBy::name("Blade Runner"))?
-            .drain(..1)
-            .next()
+        let methods = [
+            (UserMethod::CosineSimilarity, 0.5),
+            (UserMethod::Euclidean, 0.5),
+        ];
+        let ensemble = engine
+            .user_distance_ensemble(user(&controller, "1"), user(&controller, "2"), &methods)
             .unwrap();
+        assert_approx_eq!(ensemble, expected);
+        assert!((0.0..=1.0).contains(&ensemble));
+
+        for methods in &[
+            vec![],
+            vec![(UserMethod::Euclidean, 0.0)],
+            vec![(UserMethod::Euclidean, 1.5), (UserMethod::Manhattan, -0.5)],
+        ] {
+            let error = engine
+                .user_distance_ensemble(user(&controller, "1"), user(&controller, "2"), methods)
+                .unwrap_err();
+            assert!(matches!(error.kind(), Some(ErrorKind::InvalidParameter(_))));
+        }
+    }
 
-        let now = Instant::now();
-        println!(
-            "\nItem based prediction SimpleMovie: {:?}",
-            engine.item_based_predict(user, item, ItemMethod::SlopeOne, 2500)?
+    #[test]
+    fn user_distance_with_quantized_ratings() {
+        let config = Config::default();
+        let controller = controller();
+        let mut engine = Engine::with_controller(&controller, &config);
+        let distance = |engine: &Engine<_, _, _>| {
+            engine
+                .user_distance(
+                    user(&controller, "1"),
+                    user(&controller, "2"),
+                    UserMethod::Manhattan,
+                )
+                .unwrap()
+        };
+
+        let stored = distance(&engine);
+        let stored_ratings = engine.user_ratings(&user(&controller, "2")).unwrap();
+
+        // Integer ratings in 1..=5 already sit on the five levels
+        engine.set_quantize_levels(Some(5)).unwrap();
+        assert_eq!(
+            engine.user_ratings(&user(&controller, "2")).unwrap(),
+            stored_ratings
         );
-        println!("Elapsed: {}", now.elapsed().as_secs_f64());
+        assert_approx_eq!(distance(&engine), stored);
 
-        let controller = MovieLensSmallController::from_config(&config, "movie-lens-small")?;
-        let engine = Engine::with_controller(&controller, &config);
+        // Two levels leave only the ends of the range
+        engine.set_quantize_levels(Some(2)).unwrap();
+        let all_ratings = engine.all_users_ratings().unwrap();
+        for ratings in all_ratings.values() {
+            assert!(ratings.values().all(|score| *score == 1.0 || *score == 5.0));
+        }
+        assert_eq!(all_ratings[&2][&4], 1.0);
+        assert_eq!(all_ratings[&2][&3], 5.0);
+
+        // u1 (5, 5, 5) against u2 (5, 5, 5, 1, 5) on the common items
+        assert_approx_eq!(distance(&engine), 0.0);
+
+        // Chunked loads are quantized too, u3 becomes (5, 1, 5, 5)
+        for chunk_size in &[None, Some(1), Some(2)] {
+            let knn = engine
+                .user_knn(
+                    2,
+                    user(&controller, "1"),
+                    UserMethod::Manhattan,
+                    *chunk_size,
+                    false,
+                )
+                .unwrap();
+            assert_eq!(knn, vec![(2, 0.0), (3, 4.0)], "{:?}", chunk_size);
+        }
 
-        let user = controller
-            .users_by(&SearchBy::id("2"))?
-            .drain(..1)
-            .next()
-            .unwrap();
+        let top_n = |engine: &Engine<_, _, _>, chunk_size| {
+            engine
+                .user_based_top_n(
+                    2,
+                    5,
+                    user(&controller, "1"),
+                    UserMethod::CosineSimilarity,
+                    chunk_size,
+                    None,
+                    None,
+                )
+                .unwrap()
+        };
+        // With three levels u1 (5, 3, 5) isn't constant, so it has a correlation
+        engine.set_quantize_levels(Some(3)).unwrap();
+        let unchunked = top_n(&engine, None);
+        assert!(!unchunked.is_empty());
+        for chunk_size in &[1, 2] {
+            let chunked = top_n(&engine, Some(*chunk_size));
+            assert_eq!(chunked.len(), unchunked.len());
+            for ((id, score), (expected_id, expected_score)) in chunked.iter().zip(&unchunked) {
+                assert_eq!(id, expected_id);
+                assert_approx_eq!(score, expected_score);
+            }
+        }
 
-        let item = controller
-            .items_by(&SearchBy::name("Suture (1993)"))?
-            .drain(..1)
-            .next()
-            .unwrap();
+        assert!(engine.set_quantize_levels(Some(1)).is_err());
+        engine.set_quantize_levels(None).unwrap();
+        assert_approx_eq!(distance(&engine), stored);
+    }
 
-        let now = Instant::now();
-        println!(
-            "\nItem based prediction MovieLensSmall: {:?}",
-            engine.item_based_predict(user, item, ItemMethod::SlopeOne, 2500)?
-        );
-        println!("Elapsed: {}", now.elapsed().as_secs_f64());
+    #[test]
+    fn user_distance_with_ratings_lru() {
+        let config = Config::default();
+        let controller = controller();
+        let mut engine = Engine::with_ratings_lru(&controller, &config, 2);
+        let method = UserMethod::CosineSimilarity;
 
-        let controller = MovieLensController::from_config(&config, "movie-lens")?;
-        let engine = Engine::with_controller(&controller, &config);
+        let first = engine.user_distance(user(&controller, "1"), user(&controller, "2"), method);
+        let second = engine.user_distance(user(&controller, "2"), user(&controller, "1"), method);
+        assert_approx_eq!(first.unwrap(), second.unwrap());
 
-        let user = controller
-            .users_by(&SearchBy::id("35826"))?
-            .drain(..1)
-            .next()
-            .unwrap();
+        let stats = engine.ratings_lru_stats().unwrap();
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (2, 2, 0));
 
-        let item = controller
-            .items_by(&SearchBy::id("307"))?
-            .drain(..1)
-            .next()
+        // User 3 evicts user 2, the least recently used
+        engine
+            .user_distance(user(&controller, "1"), user(&controller, "3"), method)
+            .unwrap();
+        engine
+            .user_distance(user(&controller, "1"), user(&controller, "2"), method)
             .unwrap();
 
-        let now = Instant::now();
-        println!(
-            "\nItem based prediction MovieLens: {:?}",
-            engine.item_based_predict(user, item, ItemMethod::SlopeOne, 2500)?
-        );
-        println!("Elapsed: {}", now.elapsed().as_secs_f64());
+        let stats = engine.ratings_lru_stats().unwrap();
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (4, 4, 2));
 
-        Ok(())
+        controller.insert_rating(&1, &4, 1.0).unwrap();
+        engine.invalidate_cache();
+
+        engine
+            .user_distance(user(&controller, "1"), user(&controller, "2"), method)
+            .unwrap();
+        assert_eq!(engine.ratings_lru_stats().unwrap().misses, 6);
+
+        assert!(Engine::with_controller(&controller, &config)
+            .ratings_lru_stats()
+            .is_none());
     }
 
     #[test]
-    #[ignore]
-    fn shelves_item_based_pred() -> Result<(), Error> {
-        use shelves::ShelvesController;
-        use std::time::Instant;
-
+    fn controller_errors_are_kept_apart() {
         let config = Config::default();
-        let controller = ShelvesController::from_config(&config, "shelves")?;
+        let controller = controller();
         let engine = Engine::with_controller(&controller, &config);
 
-        let user = controller
-            .users_by(&SearchBy::id("0"))?
-            .drain(..1)
-            .next()
-            .unwrap();
+        let error = RecommendationError::from(anyhow::Error::from(ErrorKind::EmptyRatings));
+        assert!(matches!(
+            error,
+            RecommendationError::Engine(ErrorKind::EmptyRatings)
+        ));
 
-        let item = controller
-            .items_by(&SearchBy::id("1000"))?
-            .drain(..1)
-            .next()
-            .unwrap();
+        let error = anyhow::Error::from(RecommendationError::from(ErrorKind::ColdUser("1".into())));
+        assert!(matches!(
+            RecommendationError::from(error).kind(),
+            Some(ErrorKind::ColdUser(_))
+        ));
 
-        let now = Instant::now();
-        println!(
-            "Item based prediction (UserId 0, ItemId 1000, 1): {:?}",
-            engine.item_based_predict(user, item, ItemMethod::AdjCosine, 1)?
-        );
-        println!("Elapsed: {}", now.elapsed().as_secs_f64());
+        let error = engine
+            .export_ratings("/nonexistent/dir/ratings.jsonl")
+            .unwrap_err();
 
-        Ok(())
+        assert!(matches!(error, RecommendationError::Io(_)));
+        assert!(error.kind().is_none());
     }
 }
@@ -0,0 +1,100 @@
+// Copyright (c) 2020 White Leaf
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::{collections::HashMap, hash::Hash};
+
+/// Usage of a `Lru`, hits and misses are counted by `get` and evictions by `insert`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LruStats {
+    pub hits: usize,
+    pub misses: usize,
+    pub evictions: usize,
+}
+
+/// Small least recently used cache, meant for a handful of entries (eviction
+/// is a linear scan over the stored keys)
+#[derive(Debug, Clone)]
+pub struct Lru<K, V>
+where
+    K: Hash + Eq,
+{
+    capacity: usize,
+    tick: u64,
+    // The value is a tuple of (value, last use)
+    entries: HashMap<K, (V, u64)>,
+    stats: LruStats,
+}
+
+impl<K, V> Lru<K, V>
+where
+    K: Hash + Eq + Clone,
+{
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            tick: 0,
+            entries: HashMap::with_capacity(capacity),
+            stats: LruStats::default(),
+        }
+    }
+
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        self.tick += 1;
+        match self.entries.get_mut(key) {
+            Some((value, last_use)) => {
+                *last_use = self.tick;
+                self.stats.hits += 1;
+                Some(value)
+            }
+
+            None => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if !self.entries.contains_key(&key) && self.entries.len() >= self.capacity {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, last_use))| *last_use)
+                .map(|(key, _)| key.clone());
+
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+                self.stats.evictions += 1;
+            }
+        }
+
+        self.tick += 1;
+        self.entries.insert(key, (value, self.tick));
+    }
+
+    pub fn remove(&mut self, key: &K) {
+        self.entries.remove(key);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn stats(&self) -> LruStats {
+        self.stats
+    }
+}
@@ -0,0 +1,149 @@
+// Copyright (c) 2020 White Leaf
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use crate::error::ErrorKind;
+use controller::Ratings;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
+};
+
+/// Maximum number of hashes per band, the bits of a band are packed in a `u64`
+const MAX_ROWS_PER_BAND: usize = 64;
+
+/// Component of the `plane`-th random hyperplane for `item_id`, either `1` or `-1`.
+/// Hyperplanes are derived from a hash instead of being stored, so the item space
+/// doesn't need to be known beforehand and signatures are the same between runs
+fn plane_component<ItemId: Hash>(item_id: &ItemId, plane: usize) -> f64 {
+    let mut hasher = DefaultHasher::new();
+    plane.hash(&mut hasher);
+    item_id.hash(&mut hasher);
+
+    if hasher.finish() & 1 == 0 {
+        1.0
+    } else {
+        -1.0
+    }
+}
+
+/// Random hyperplane locality sensitive hashing of users ratings. Each user gets a
+/// signature of `n_hashes` bits (on which side of every hyperplane its ratings
+/// vector lies), split in `n_bands` bands. Users sharing at least one band are
+/// candidates to be neighbors, the closer the angle between two vectors the more
+/// likely they share a band
+#[derive(Debug, Clone)]
+pub struct Lsh<UserId> {
+    n_hashes: usize,
+    n_bands: usize,
+    buckets: Vec<HashMap<u64, Vec<UserId>>>,
+}
+
+impl<UserId> Lsh<UserId>
+where
+    UserId: Hash + Eq + Clone,
+{
+    /// `n_hashes` must be a multiple of `n_bands`, and each band can't have more
+    /// than 64 hashes
+    pub fn new(n_hashes: usize, n_bands: usize) -> Result<Self, ErrorKind> {
+        if n_bands == 0 || n_hashes == 0 || !n_hashes.is_multiple_of(n_bands) {
+            return Err(ErrorKind::InvalidParameter(
+                "n_hashes must be a non zero multiple of n_bands".into(),
+            ));
+        }
+
+        if n_hashes / n_bands > MAX_ROWS_PER_BAND {
+            return Err(ErrorKind::InvalidParameter(format!(
+                "bands can't have more than {} hashes",
+                MAX_ROWS_PER_BAND
+            )));
+        }
+
+        Ok(Self {
+            n_hashes,
+            n_bands,
+            buckets: vec![HashMap::new(); n_bands],
+        })
+    }
+
+    pub fn n_hashes(&self) -> usize {
+        self.n_hashes
+    }
+
+    pub fn n_bands(&self) -> usize {
+        self.n_bands
+    }
+
+    /// Bucket of every band for `ratings`
+    fn band_keys<ItemId: Hash>(&self, ratings: &Ratings<ItemId>) -> Vec<u64> {
+        let rows = self.n_hashes / self.n_bands;
+
+        (0..self.n_bands)
+            .map(|band| {
+                (0..rows).fold(0u64, |key, row| {
+                    let plane = band * rows + row;
+                    let dot: f64 = ratings
+                        .iter()
+                        .map(|(item_id, score)| score * plane_component(item_id, plane))
+                        .sum();
+
+                    (key << 1) | (dot >= 0.0) as u64
+                })
+            })
+            .collect()
+    }
+
+    /// Add `user_id` to the buckets of its `ratings`
+    pub fn insert<ItemId: Hash>(&mut self, user_id: UserId, ratings: &Ratings<ItemId>) {
+        let keys = self.band_keys(ratings);
+        for (band, key) in keys.into_iter().enumerate() {
+            self.buckets[band]
+                .entry(key)
+                .or_default()
+                .push(user_id.clone());
+        }
+    }
+
+    /// Users that share at least one band with `ratings`
+    pub fn candidates<ItemId: Hash>(&self, ratings: &Ratings<ItemId>) -> HashSet<UserId> {
+        let keys = self.band_keys(ratings);
+
+        keys.into_iter()
+            .enumerate()
+            .filter_map(|(band, key)| self.buckets[band].get(&key))
+            .flatten()
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common_macros::hash_map;
+
+    #[test]
+    fn invalid_bands() {
+        assert!(Lsh::<i32>::new(0, 1).is_err());
+        assert!(Lsh::<i32>::new(8, 0).is_err());
+        assert!(Lsh::<i32>::new(10, 4).is_err());
+        assert!(Lsh::<i32>::new(130, 2).is_err());
+        assert!(Lsh::<i32>::new(128, 2).is_ok());
+    }
+
+    #[test]
+    fn same_direction_same_buckets() {
+        let mut lsh = Lsh::new(16, 4).unwrap();
+
+        let ratings = hash_map! { 1 => 5.0, 2 => 2.0, 3 => 4.0 };
+        lsh.insert(1, &ratings);
+
+        // A scaled vector has the same angle with every hyperplane
+        let scaled = hash_map! { 1 => 2.5, 2 => 1.0, 3 => 2.0 };
+        assert!(lsh.candidates(&scaled).contains(&1));
+
+        let opposite = hash_map! { 1 => -5.0, 2 => -2.0, 3 => -4.0 };
+        assert!(!lsh.candidates(&opposite).contains(&1));
+    }
+}
@@ -19,22 +19,51 @@ impl<UserId, ItemId> MapedDistance<UserId, ItemId> {
     }
 }
 
-impl<UserId, ItemId> PartialEq for MapedDistance<UserId, ItemId> {
+// NaN sorts below every other distance (instead of panicking), but the knn heaps
+// skip them anyway. Ties on the distance are broken by the user id, so knn results
+// don't depend on the order in which the users were visited
+impl<UserId: Ord, ItemId> PartialEq for MapedDistance<UserId, ItemId> {
     fn eq(&self, other: &Self) -> bool {
-        self.dist().eq(&other.dist())
+        self.cmp(other) == Ordering::Equal
     }
 }
 
-impl<UserId, ItemId> Eq for MapedDistance<UserId, ItemId> {}
+impl<UserId: Ord, ItemId> Eq for MapedDistance<UserId, ItemId> {}
 
-impl<UserId, ItemId> PartialOrd for MapedDistance<UserId, ItemId> {
+impl<UserId: Ord, ItemId> PartialOrd for MapedDistance<UserId, ItemId> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.dist().partial_cmp(&other.dist())
+        Some(self.cmp(other))
     }
 }
 
-impl<UserId, ItemId> Ord for MapedDistance<UserId, ItemId> {
+impl<UserId: Ord, ItemId> Ord for MapedDistance<UserId, ItemId> {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.dist().partial_cmp(&other.dist()).unwrap()
+        let by_dist = match (self.dist().is_nan(), other.dist().is_nan()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            (false, false) => self.dist().partial_cmp(&other.dist()).unwrap(),
+        };
+
+        by_dist.then_with(|| self.0.cmp(&other.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nan_sorts_first() {
+        let mut distances: Vec<MapedDistance<i32, i32>> = vec![
+            MapedDistance(1, 2.0, None),
+            MapedDistance(2, f64::NAN, None),
+            MapedDistance(3, -1.0, None),
+            MapedDistance(4, f64::NAN, None),
+        ];
+        distances.sort();
+
+        let ids: Vec<_> = distances.iter().map(|maped| maped.0).collect();
+        assert_eq!(ids, vec![2, 4, 3, 1]);
     }
 }
@@ -0,0 +1,26 @@
+// Copyright (c) 2020 White Leaf
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+/// How a raw prediction is adjusted before being returned. Every variant except
+/// `None` keeps the prediction inside the score range of the controller
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PostProcess {
+    #[default]
+    Clamp,
+    RoundToNearestHalf,
+    RoundToInt,
+    None,
+}
+
+impl PostProcess {
+    pub fn apply(self, raw: f64, (min, max): (f64, f64)) -> f64 {
+        match self {
+            PostProcess::Clamp => raw.max(min).min(max),
+            PostProcess::RoundToNearestHalf => ((raw * 2.0).round() / 2.0).max(min).min(max),
+            PostProcess::RoundToInt => raw.round().max(min).min(max),
+            PostProcess::None => raw,
+        }
+    }
+}
@@ -0,0 +1,61 @@
+// Copyright (c) 2020 White Leaf
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SECONDS_PER_DAY: f64 = 86_400.0;
+
+/// Exponential decay of the weight of a rating with its age, a rating given `age`
+/// days before `now` weighs `exp(-lambda * age)`, see `Engine::set_recency_decay`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecencyDecay {
+    pub lambda: f64,
+    /// Unix timestamp (in seconds) the ages are measured from
+    pub now: i64,
+}
+
+impl RecencyDecay {
+    /// Decay measuring the ages from the current time
+    pub fn new(lambda: f64) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs() as i64)
+            .unwrap_or_default();
+
+        Self::at(lambda, now)
+    }
+
+    /// Decay measuring the ages from `now`, e.g. the time of the last rating of a
+    /// dataset, so the weights don't change between runs
+    pub fn at(lambda: f64, now: i64) -> Self {
+        Self { lambda, now }
+    }
+
+    /// Weight of a rating given at `timestamp`, ratings from the future are as
+    /// recent as they can be
+    pub fn weight(&self, timestamp: i64) -> f64 {
+        let age = (self.now - timestamp).max(0) as f64 / SECONDS_PER_DAY;
+        (-self.lambda * age).exp()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn weight_decays_with_age() {
+        let decay = RecencyDecay::at(0.5, 10 * 86_400);
+
+        assert_approx_eq!(decay.weight(10 * 86_400), 1.0);
+        assert_approx_eq!(decay.weight(11 * 86_400), 1.0);
+        assert_approx_eq!(decay.weight(8 * 86_400), (-1.0f64).exp());
+        assert!(decay.weight(0) < decay.weight(86_400));
+
+        // Without decay every rating weighs the same
+        assert_approx_eq!(RecencyDecay::at(0.0, 10 * 86_400).weight(0), 1.0);
+    }
+}
@@ -0,0 +1,18 @@
+// Copyright (c) 2020 White Leaf
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+/// Time spent (in seconds) on each phase of a prediction, phases that a method
+/// doesn't go through are left as zero
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Timings {
+    /// Gathering the users means
+    pub means: f64,
+    /// Gathering the users who rated the items
+    pub users_who_rated: f64,
+    /// Computing the similarities (or deviations) between items
+    pub similarities: f64,
+    /// The whole prediction, including the phases above
+    pub total: f64,
+}
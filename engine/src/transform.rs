@@ -0,0 +1,147 @@
+// Copyright (c) 2020 White Leaf
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use crate::error::ErrorKind;
+use controller::Ratings;
+
+/// Preprocessing applied to the ratings of a user before any distance is
+/// computed with them, see `Engine::set_rating_transform`
+pub trait RatingTransform<ItemId> {
+    fn transform(&self, ratings: &mut Ratings<ItemId>);
+}
+
+/// Ratings at or above the threshold become `1`, the rest become `0`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Binarize(pub f64);
+
+impl<ItemId> RatingTransform<ItemId> for Binarize {
+    fn transform(&self, ratings: &mut Ratings<ItemId>) {
+        for rating in ratings.values_mut() {
+            *rating = if *rating >= self.0 { 1.0 } else { 0.0 };
+        }
+    }
+}
+
+/// Ratings `r` become `ln(1 + r)`, e.g. to tame implicit counts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LogScale;
+
+impl<ItemId> RatingTransform<ItemId> for LogScale {
+    fn transform(&self, ratings: &mut Ratings<ItemId>) {
+        for rating in ratings.values_mut() {
+            *rating = rating.ln_1p();
+        }
+    }
+}
+
+/// The mean of the ratings is subtracted from each one of them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MeanCenter;
+
+impl<ItemId> RatingTransform<ItemId> for MeanCenter {
+    fn transform(&self, ratings: &mut Ratings<ItemId>) {
+        if ratings.is_empty() {
+            return;
+        }
+
+        let mean = ratings.values().sum::<f64>() / ratings.len() as f64;
+        for rating in ratings.values_mut() {
+            *rating -= mean;
+        }
+    }
+}
+
+/// Ratings snap to the nearest of `levels` evenly spaced values between `min` and
+/// `max` (both included), e.g. to study how coarse ratings affect the distances
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quantize {
+    levels: usize,
+    min: f64,
+    max: f64,
+}
+
+impl Quantize {
+    /// There must be at least two levels and `min` must be lower than `max`
+    pub fn new(levels: usize, min: f64, max: f64) -> Result<Self, ErrorKind> {
+        if levels < 2 {
+            return Err(ErrorKind::InvalidParameter(
+                "quantization needs at least two levels".into(),
+            ));
+        }
+
+        if min.partial_cmp(&max) != Some(std::cmp::Ordering::Less) {
+            return Err(ErrorKind::InvalidParameter(format!(
+                "invalid score range ({}, {}) to quantize",
+                min, max
+            )));
+        }
+
+        Ok(Self { levels, min, max })
+    }
+
+    pub fn levels(&self) -> usize {
+        self.levels
+    }
+
+    /// Nearest level to `score`, scores out of the range get the closest end
+    pub fn quantize(&self, score: f64) -> f64 {
+        let last = (self.levels - 1) as f64;
+        let step = (self.max - self.min) / last;
+        let level = ((score - self.min) / step).round().clamp(0.0, last);
+
+        self.min + level * step
+    }
+}
+
+impl<ItemId> RatingTransform<ItemId> for Quantize {
+    fn transform(&self, ratings: &mut Ratings<ItemId>) {
+        for rating in ratings.values_mut() {
+            *rating = self.quantize(*rating);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+    use common_macros::hash_map;
+
+    #[test]
+    fn binarize_ratings() {
+        let mut ratings = hash_map! { 1 => 1.0, 2 => 2.0, 3 => 3.0, 4 => 4.0, 5 => 5.0 };
+        Binarize(3.0).transform(&mut ratings);
+
+        let expected = hash_map! { 1 => 0.0, 2 => 0.0, 3 => 1.0, 4 => 1.0, 5 => 1.0 };
+        assert_eq!(ratings, expected);
+    }
+
+    #[test]
+    fn quantize_ratings() {
+        let quantize = Quantize::new(3, 1.0, 5.0).unwrap();
+        let mut ratings = hash_map! { 1 => 1.0, 2 => 1.9, 3 => 2.1, 4 => 4.5, 5 => 7.0 };
+        quantize.transform(&mut ratings);
+
+        let expected = hash_map! { 1 => 1.0, 2 => 1.0, 3 => 3.0, 4 => 5.0, 5 => 5.0 };
+        assert_eq!(ratings, expected);
+
+        assert!(Quantize::new(1, 1.0, 5.0).is_err());
+        assert!(Quantize::new(2, 5.0, 5.0).is_err());
+    }
+
+    #[test]
+    fn log_scale_and_mean_center_ratings() {
+        let mut ratings = hash_map! { 1 => 0.0, 2 => 9.0 };
+        LogScale.transform(&mut ratings);
+        assert_approx_eq!(ratings[&1], 0.0);
+        assert_approx_eq!(ratings[&2], 10f64.ln());
+
+        let mut ratings = hash_map! { 1 => 2.0, 2 => 3.0, 3 => 7.0 };
+        MeanCenter.transform(&mut ratings);
+        assert_approx_eq!(ratings[&1], -2.0);
+        assert_approx_eq!(ratings[&2], -1.0);
+        assert_approx_eq!(ratings[&3], 3.0);
+    }
+}
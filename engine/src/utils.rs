@@ -3,7 +3,8 @@
 // This software is released under the MIT License.
 // https://opensource.org/licenses/MIT
 
-use std::collections::{hash_map::Iter as MapIter, HashMap};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{hash_map::Iter as MapIter, BinaryHeap, HashMap};
 use std::hash::Hash;
 
 // Creating a common key iterator is kinda interesting since it'll
@@ -68,6 +69,90 @@ where
     }
 }
 
+/// Keeps the `n` largest values pushed into it, once full the smallest value is
+/// evicted whenever a larger one comes in
+#[derive(Debug, Clone)]
+pub struct BoundedTopN<T> {
+    n: usize,
+    min_heap: BinaryHeap<Reverse<T>>,
+}
+
+impl<T: Ord> BoundedTopN<T> {
+    pub fn new(n: usize) -> Self {
+        Self {
+            n,
+            min_heap: BinaryHeap::new(),
+        }
+    }
+
+    pub fn push(&mut self, value: T) {
+        if !self.accepts(&value) {
+            return;
+        }
+
+        if self.min_heap.len() == self.n {
+            self.min_heap.pop();
+        }
+
+        self.min_heap.push(Reverse(value));
+    }
+
+    /// Whether pushing `value` would keep it, so expensive values can be built
+    /// only when they get in
+    pub fn accepts(&self, value: &T) -> bool {
+        if self.min_heap.len() < self.n {
+            return true;
+        }
+
+        match self.min_heap.peek() {
+            Some(Reverse(minimum)) => value > minimum,
+            None => false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.min_heap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min_heap.is_empty()
+    }
+
+    /// The kept values, from the largest to the smallest
+    pub fn into_sorted_vec(self) -> Vec<T> {
+        self.min_heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(value)| value)
+            .collect()
+    }
+}
+
+/// A value ranked only by its score, so floats can be kept in a `BoundedTopN`.
+/// Incomparable scores (NaN) are considered equal
+#[derive(Debug, Clone)]
+pub struct Scored<T>(pub f64, pub T);
+
+impl<T> PartialEq for Scored<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T> Eq for Scored<T> {}
+
+impl<T> PartialOrd for Scored<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Scored<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.partial_cmp(&other.0).unwrap_or(Ordering::Equal)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -95,4 +180,49 @@ mod tests {
             assert!(k == &2 || k == &0 || k == &5);
         }
     }
+
+    #[test]
+    fn bounded_top_n_capacity() {
+        let mut top = BoundedTopN::new(3);
+        for value in &[4, 1, 7, 3, 9, 2] {
+            top.push(*value);
+            assert!(top.len() <= 3);
+        }
+
+        assert_eq!(top.len(), 3);
+        assert_eq!(top.into_sorted_vec(), vec![9, 7, 4]);
+
+        let mut empty = BoundedTopN::new(0);
+        assert!(!empty.accepts(&1));
+        empty.push(1);
+        assert!(empty.is_empty());
+
+        let mut full = BoundedTopN::new(2);
+        full.push(5);
+        assert!(full.accepts(&1));
+        full.push(3);
+        assert!(full.accepts(&4) && !full.accepts(&3) && !full.accepts(&2));
+    }
+
+    #[test]
+    fn bounded_top_n_ordering() {
+        let mut top = BoundedTopN::new(2);
+        top.push(Scored(0.5, "b"));
+        top.push(Scored(-1.0, "c"));
+        top.push(Scored(2.5, "a"));
+
+        let ids: Vec<_> = top
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Scored(_, id)| id)
+            .collect();
+        assert_eq!(ids, vec!["a", "b"]);
+
+        // Fewer values than the capacity are still sorted
+        let mut top = BoundedTopN::new(10);
+        top.push(1);
+        top.push(3);
+        top.push(2);
+        assert_eq!(top.into_sorted_vec(), vec![3, 2, 1]);
+    }
 }
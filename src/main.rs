@@ -4,18 +4,21 @@
 // https://opensource.org/licenses/MIT
 
 pub mod parser;
+pub mod server;
 pub mod utils;
 
 use anyhow::Error;
 use books::BooksController;
-use clap::{App, Arg};
+use clap::{App, Arg, SubCommand};
 use config::Config;
-use controller::{eid, Controller, Entity, ToTable};
+use controller::{eid, Controller, Entity, SearchBy, ToTable};
 use engine::{
-    chunked_matrix::{ChunkedMatrix, DeviationMatrix, SimilarityMatrix},
+    cancellation::CancellationToken,
+    chunked_matrix::{ChunkedMatrix, DeviationMatrix, SimilarityMatrix, UserSimilarityMatrix},
     distances::items::Method as ItemMethod,
-    Engine,
+    eval, Engine,
 };
+use indicatif::ProgressBar;
 use movie_lens::MovieLensController;
 use movie_lens_small::MovieLensSmallController;
 use parser::{Database, Statement};
@@ -30,6 +33,8 @@ use std::{
     fmt::{Debug, Display},
     fs::File,
     hash::Hash,
+    str::FromStr,
+    sync::OnceLock,
     time::Instant,
 };
 use utils::build_prototype;
@@ -73,25 +78,85 @@ macro_rules! prompt {
     }};
 }
 
-fn chunked_matrix_prompt<'a, M, C, U, I>(
-    controller: &C,
+static INTERRUPT_TOKEN: OnceLock<CancellationToken> = OnceLock::new();
+
+extern "C" fn cancel_on_interrupt(_: libc::c_int) {
+    if let Some(token) = INTERRUPT_TOKEN.get() {
+        token.cancel();
+    }
+}
+
+/// While alive, Ctrl-C cancels the running statement instead of killing the
+/// process, the previous SIGINT handler is restored on drop
+struct InterruptGuard {
+    token: CancellationToken,
+    previous: libc::sighandler_t,
+}
+
+impl InterruptGuard {
+    fn install() -> Self {
+        let token = INTERRUPT_TOKEN.get_or_init(CancellationToken::new).clone();
+        token.reset();
+
+        let handler = cancel_on_interrupt as extern "C" fn(libc::c_int);
+        // Safety: the handler only touches an atomic flag
+        let previous = unsafe { libc::signal(libc::SIGINT, handler as libc::sighandler_t) };
+
+        InterruptGuard { token, previous }
+    }
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        // Safety: restores the handler replaced in `install`
+        unsafe {
+            libc::signal(libc::SIGINT, self.previous);
+        }
+    }
+}
+
+fn calculate_chunk_with_bar<'a, M, C, E>(matrix: &mut M, i: usize, j: usize) -> Result<(), Error>
+where
+    C: Controller,
+    M: ChunkedMatrix<'a, C, E>,
+    E: Entity,
+{
+    let bar = ProgressBar::new(0);
+    let interrupt = InterruptGuard::install();
+    let res = matrix.calculate_chunk_cancellable(
+        i,
+        j,
+        |processed, total| {
+            bar.set_length(total as u64);
+            bar.set_position(processed as u64);
+        },
+        &interrupt.token,
+    );
+
+    bar.finish_and_clear();
+    res
+}
+
+/// Sub shell of a chunked matrix, `find` looks up the entities (items or users) the
+/// matrix is made of
+fn chunked_matrix_prompt<'a, M, C, E, F>(
+    find: F,
     mut matrix: M,
     name: &str,
     rl: &mut Editor<()>,
 ) -> Result<(), Error>
 where
-    C: Controller<User = U, Item = I>,
-    M: ChunkedMatrix<'a, C, I>,
-    U: Entity,
-    I: Entity,
-    eid!(U): Hash + Eq + Display + Clone + Debug + Default,
-    eid!(I): Hash + Eq + Display + Clone + Debug,
+    C: Controller,
+    M: ChunkedMatrix<'a, C, E>,
+    E: Entity,
+    eid!(E): Display,
+    F: Fn(&SearchBy) -> Result<Vec<E>, Error>,
 {
     let mut curr_i = 0;
     let mut curr_j = 0;
 
     let now = Instant::now();
-    match matrix.calculate_chunk(curr_i, curr_j) {
+    match calculate_chunk_with_bar(&mut matrix, curr_i, curr_j) {
         Ok(chunk) => chunk,
         Err(e) => {
             log::error!("{}", e);
@@ -115,35 +180,41 @@ where
                 println!("version: {}", VERSION);
             }
 
+            "h" | "help" => print_help(("e | exit", "Exit the matrix")),
+
+            explain if explain.starts_with("explain_parse") => {
+                explain_parse(explain["explain_parse".len()..].trim())
+            }
+
             empty if empty.is_empty() => {}
 
             comment if comment.starts_with('#') => {}
 
             line => match parser::parse_line(line) {
-                Some(stmt) => match stmt {
+                Ok(stmt) => match stmt {
                     Statement::MatrixGet(searchby_a, searchby_b) => {
-                        let item_id_a = match controller.items_by(&searchby_a) {
-                            Ok(items) => items[0].get_id(),
+                        let id_a = match find(&searchby_a) {
+                            Ok(entities) => entities[0].get_id(),
                             Err(e) => {
                                 log::error!("{}", e);
                                 continue;
                             }
                         };
 
-                        let item_id_b = match controller.items_by(&searchby_b) {
-                            Ok(items) => items[0].get_id(),
+                        let id_b = match find(&searchby_b) {
+                            Ok(entities) => entities[0].get_id(),
                             Err(e) => {
                                 log::error!("{}", e);
                                 continue;
                             }
                         };
 
-                        let val = matrix.get_value(&item_id_a, &item_id_b);
+                        let val = matrix.get_value(&id_a, &id_b);
 
                         if let Some(val) = val {
-                            println!("Value for ({}, {}) is {}", item_id_a, item_id_b, val);
+                            println!("Value for ({}, {}) is {}", id_a, id_b, val);
                         } else {
-                            log::error!("No value found for ({}, {})", item_id_a, item_id_b);
+                            log::error!("No value found for ({}, {})", id_a, id_b);
                         }
                     }
 
@@ -152,7 +223,7 @@ where
                         curr_j = j;
 
                         let now = Instant::now();
-                        match matrix.calculate_chunk(curr_i, curr_j) {
+                        match calculate_chunk_with_bar(&mut matrix, curr_i, curr_j) {
                             Ok(chunk) => chunk,
                             Err(e) => {
                                 log::error!("{}", e);
@@ -168,7 +239,7 @@ where
                     }
                 },
 
-                None => log::error!("Invalid syntax!"),
+                Err(e) => log::error!("Invalid syntax: {}", e),
             },
         }
     }
@@ -176,6 +247,62 @@ where
     Ok(())
 }
 
+/// Print a histogram as a bar chart, bars are scaled to the fullest bucket
+fn print_histogram(histogram: &[(f64, usize)]) {
+    const BAR_WIDTH: usize = 40;
+
+    let max_count = histogram.iter().map(|(_, count)| *count).max().unwrap_or(0);
+    for (lower, count) in histogram {
+        let bar = (count * BAR_WIDTH).checked_div(max_count).unwrap_or(0);
+
+        println!("{:>8.2} | {} {}", lower, "#".repeat(bar), count);
+    }
+}
+
+/// Print how `line` would be parsed, without running it
+fn explain_parse(line: &str) {
+    match parser::parse_line(line) {
+        Ok(stmt) => println!("{:#?}", stmt),
+        Err(e) => log::error!("Invalid syntax: {}", e),
+    }
+}
+
+/// Print the meta-commands of a prompt, `leave` is the one that leaves it
+fn print_help(leave: (&str, &str)) {
+    let commands = [
+        leave,
+        ("v | version", "Print the version"),
+        ("h | help", "Print this help"),
+        (
+            "explain_parse <line>",
+            "Print how <line> is parsed, without running it",
+        ),
+    ];
+
+    for (command, description) in commands.iter() {
+        println!("  {:<24}{}", command, description);
+    }
+
+    println!("Any other line is parsed as a statement, see the README for the full list");
+}
+
+fn describe<E>(entity: &E)
+where
+    E: Entity,
+    eid!(E): Display,
+{
+    println!("id: {}", entity.get_id());
+
+    let mut data: Vec<_> = entity.get_data().into_iter().collect();
+    data.sort();
+
+    for (key, value) in data {
+        println!("{}: {}", key, value);
+    }
+
+    println!();
+}
+
 fn database_connected_prompt<C, U, I, R>(
     config: &Config,
     controller: C,
@@ -187,11 +314,17 @@ where
     U: Entity,
     I: Entity,
     R: Entity,
-    eid!(U): Hash + Eq + Display + Clone + Debug + Default,
-    eid!(I): Hash + Eq + Display + Clone + Debug,
+    eid!(U): Hash + Eq + Ord + Display + Clone + Debug + Default + FromStr,
+    eid!(I): Hash + Eq + Ord + Display + Clone + Debug + FromStr,
     eid!(R): Display,
 {
-    let mut engine = Engine::with_controller(&controller, config);
+    let mut engine = Engine::with_ratings_lru(&controller, config, RATINGS_LRU_CAPACITY);
+
+    match utils::empty_database_warning(&controller) {
+        Ok(Some(warning)) => log::warn!("{}", warning),
+        Ok(None) => {}
+        Err(e) => log::warn!("Couldn't count the ratings of {}: {}", name, e),
+    }
 
     loop {
         let opt: String = prompt!(rl, name)?;
@@ -206,12 +339,18 @@ where
                 println!("version: {}", VERSION);
             }
 
+            "h" | "help" => print_help(("d | disconnect", "Disconnect from the database")),
+
+            explain if explain.starts_with("explain_parse") => {
+                explain_parse(explain["explain_parse".len()..].trim())
+            }
+
             empty if empty.is_empty() => {}
 
             comment if comment.starts_with('#') => {}
 
             line => match parser::parse_line(line) {
-                Some(stmt) => match stmt {
+                Ok(stmt) => match stmt {
                     Statement::Connect(_) => {
                         log::error!("Invalid statement in this context.");
                         log::error!("Disconnect from current database first!");
@@ -240,6 +379,24 @@ where
                         Err(e) => log::error!("{}", e),
                     },
 
+                    Statement::DescribeUser(searchby) => match controller.users_by(&searchby) {
+                        Ok(users) => {
+                            for user in users {
+                                describe(&user);
+                            }
+                        }
+                        Err(e) => log::error!("{}", e),
+                    },
+
+                    Statement::DescribeItem(searchby) => match controller.items_by(&searchby) {
+                        Ok(items) => {
+                            for item in items {
+                                describe(&item);
+                            }
+                        }
+                        Err(e) => log::error!("{}", e),
+                    },
+
                     Statement::QueryRatings(searchby) => match controller.users_by(&searchby) {
                         Ok(users) => {
                             for user in users {
@@ -347,6 +504,7 @@ where
                             Ok(rating) => {
                                 println!("Successfully inserted! Yay!");
                                 println!("{}", rating.to_table());
+                                engine.invalidate_cache();
 
                                 match controller.users_means(&[user]) {
                                     Ok(means) => {
@@ -382,6 +540,197 @@ where
                         }
                     }
 
+                    Statement::RefreshMeans => {
+                        let now = Instant::now();
+                        let mut refreshed = 0;
+                        for users in
+                            controller.users_by_chunks(config.engine.partial_users_chunk_size)
+                        {
+                            match controller.recompute_means(&users) {
+                                Ok(updated) => refreshed += updated,
+                                Err(e) => {
+                                    log::error!("Failed to refresh means!");
+                                    log::error!("Reason: {}", e);
+                                    break;
+                                }
+                            }
+
+                            // Cached means are lazily reloaded from the refreshed table
+                            for user in &users {
+                                engine.maybe_delete_mean_for(&user.get_id());
+                            }
+                        }
+
+                        println!("Refreshed the means of {} users", refreshed);
+                        println!("Operation took {:.4} seconds", now.elapsed().as_secs_f64());
+                    }
+
+                    Statement::ImportRatings(path) => {
+                        let now = Instant::now();
+                        match utils::import_ratings(&controller, &path, utils::IMPORT_BATCH_SIZE) {
+                            Ok(summary) => {
+                                println!(
+                                    "Inserted {} ratings, skipped {} rows and failed {}",
+                                    summary.inserted, summary.skipped, summary.failed
+                                );
+                                engine.invalidate_cache();
+                            }
+
+                            Err(e) => {
+                                log::error!("Failed to import ratings from {}", path);
+                                log::error!("Reason: {}", e);
+                            }
+                        }
+
+                        println!("Operation took {:.4} seconds", now.elapsed().as_secs_f64());
+                    }
+
+                    Statement::Evaluate(path, k, method) => {
+                        let now = Instant::now();
+                        let evaluated =
+                            utils::read_test_set(&controller, &path).and_then(|test_set| {
+                                let metrics =
+                                    engine.evaluate_user_based(test_set.rows, k, method, None)?;
+                                Ok((metrics.with_misses(test_set.unknown), test_set.skipped))
+                            });
+
+                        match evaluated {
+                            Ok((metrics, skipped)) => {
+                                let format = |value: Option<f64>| match value {
+                                    Some(value) => format!("{:.4}", value),
+                                    None => "-".into(),
+                                };
+
+                                println!("RMSE: {}", format(metrics.rmse));
+                                println!("MAE: {}", format(metrics.mae));
+                                println!(
+                                    "Coverage: {:.2}% ({} of {} rows predicted, {} malformed rows skipped)",
+                                    metrics.coverage() * 100.0,
+                                    metrics.predicted,
+                                    metrics.total,
+                                    skipped
+                                );
+                            }
+
+                            Err(e) => {
+                                log::error!("Failed to evaluate with {}", path);
+                                log::error!("Reason: {}", e);
+                            }
+                        }
+
+                        println!("Operation took {:.4} seconds", now.elapsed().as_secs_f64());
+                    }
+
+                    Statement::Coverage(method) => {
+                        let now = Instant::now();
+                        let report =
+                            controller
+                                .sample_users(COVERAGE_SAMPLE_SIZE, 0)
+                                .and_then(|users| {
+                                    let items = controller.sample_items(COVERAGE_SAMPLE_SIZE, 0)?;
+                                    Ok(engine
+                                        .coverage_report(&users, &items, method, COVERAGE_K)?)
+                                });
+
+                        match report {
+                            Ok(report) => {
+                                println!(
+                                    "Coverage: {:.2}% ({} of {} pairs predicted)",
+                                    report.coverage() * 100.0,
+                                    report.predicted,
+                                    report.total
+                                );
+                                println!(
+                                    "Cold items: {}, cold users: {}, no neighbors: {}, other failures: {}",
+                                    report.cold_item,
+                                    report.cold_user,
+                                    report.no_neighbors,
+                                    report.failed
+                                );
+                            }
+
+                            Err(e) => {
+                                log::error!("Failed to compute the coverage!");
+                                log::error!("Reason: {}", e);
+                            }
+                        }
+
+                        println!("Operation took {:.4} seconds", now.elapsed().as_secs_f64());
+                    }
+
+                    Statement::Diagnose(searchby, method, k) => {
+                        let user = match controller
+                            .users_by(&searchby)
+                            .map(|mut users| users.drain(..1).next().unwrap())
+                        {
+                            Ok(user) => user,
+                            Err(e) => {
+                                log::error!("{}", e);
+                                continue;
+                            }
+                        };
+
+                        let now = Instant::now();
+                        match engine.loo_user_report(user, method, k) {
+                            Ok(report) => {
+                                for (item_id, actual, predicted) in &report {
+                                    println!(
+                                        "Item with id({}): actual {}, predicted {:.4}",
+                                        item_id, actual, predicted
+                                    );
+                                }
+
+                                let pairs: Vec<_> = report
+                                    .iter()
+                                    .map(|(_, actual, predicted)| (*predicted, *actual))
+                                    .collect();
+                                let metrics =
+                                    eval::PredictionMetrics::from_predictions(&pairs, pairs.len());
+                                match metrics.rmse {
+                                    Some(rmse) => println!("RMSE: {:.4}", rmse),
+                                    None => println!("RMSE: -"),
+                                }
+                            }
+
+                            Err(e) => {
+                                log::error!("Failed to diagnose the user predictions");
+                                log::error!("Reason: {}", e);
+                            }
+                        }
+
+                        println!("Operation took {:.4} seconds", now.elapsed().as_secs_f64());
+                    }
+
+                    Statement::ExportKnnGraph(k, method, path) => {
+                        let now = Instant::now();
+                        let chunk_size = config.engine.partial_users_chunk_size;
+                        let exported =
+                            utils::export_knn_graph(&engine, k, method, chunk_size, &path);
+
+                        match exported {
+                            Ok(edges) => println!("Exported {} edges to {}", edges, path),
+                            Err(e) => {
+                                log::error!("Failed to export the knn graph to {}", path);
+                                log::error!("Reason: {}", e);
+                            }
+                        }
+
+                        println!("Operation took {:.4} seconds", now.elapsed().as_secs_f64());
+                    }
+
+                    Statement::PrecomputeMeans => {
+                        let now = Instant::now();
+                        match engine.precompute_means(config.engine.partial_users_chunk_size) {
+                            Ok(cached) => println!("Cached the means of {} users", cached),
+                            Err(e) => {
+                                log::error!("Failed to precompute means!");
+                                log::error!("Reason: {}", e);
+                            }
+                        }
+
+                        println!("Operation took {:.4} seconds", now.elapsed().as_secs_f64());
+                    }
+
                     Statement::UpdateRating(searchby_user, searchby_item, score) => {
                         let (lower_limit, upper_limit) = controller.score_range();
                         if score < lower_limit || score > upper_limit {
@@ -418,6 +767,7 @@ where
                             Ok(rating) => {
                                 println!("Successfully updated! Yay!");
                                 println!("{}", rating.to_table());
+                                engine.invalidate_cache();
 
                                 match controller.users_means(&[user]) {
                                     Ok(means) => {
@@ -483,6 +833,7 @@ where
                             Ok(rating) => {
                                 println!("Successfully removed! Yay?");
                                 println!("{}", rating.to_table());
+                                engine.invalidate_cache();
 
                                 match controller.users_means(&[user]) {
                                     Ok(means) => {
@@ -582,7 +933,80 @@ where
                         println!("Operation took {:.4} seconds", now.elapsed().as_secs_f64());
                     }
 
-                    Statement::UserKnn(k, searchby, method, chunks_opt) => {
+                    Statement::Overlap(searchby_a, searchby_b) => {
+                        let user_a = match controller
+                            .users_by(&searchby_a)
+                            .map(|mut users| users.drain(..1).next().unwrap())
+                        {
+                            Ok(user) => user,
+                            Err(e) => {
+                                log::error!("{}", e);
+                                continue;
+                            }
+                        };
+
+                        let user_b = match controller
+                            .users_by(&searchby_b)
+                            .map(|mut users| users.drain(..1).next().unwrap())
+                        {
+                            Ok(user) => user,
+                            Err(e) => {
+                                log::error!("{}", e);
+                                continue;
+                            }
+                        };
+
+                        match engine.overlap(user_a, user_b) {
+                            Ok(overlap) => println!("Both users rated {} items", overlap),
+                            Err(e) => {
+                                log::error!("Overlap couldn't be calculated");
+                                log::error!("Reason: {}", e);
+                            }
+                        }
+                    }
+
+                    Statement::ItemKnn(n, searchby, method, chunks_opt) => {
+                        let item = match controller
+                            .items_by(&searchby)
+                            .map(|mut items| items.drain(..1).next().unwrap())
+                        {
+                            Ok(item) => item,
+                            Err(e) => {
+                                log::error!("{}", e);
+                                continue;
+                            }
+                        };
+
+                        let chunk_size = chunks_opt.unwrap_or(ITEM_KNN_CHUNK_SIZE);
+
+                        let now = Instant::now();
+                        let neighbors = engine.item_neighbors(item, n, method, chunk_size);
+                        let elapsed = now.elapsed().as_secs_f64();
+
+                        match neighbors {
+                            Ok(neighbors) if neighbors.is_empty() => {
+                                println!("No item is comparable with this one");
+                            }
+
+                            Ok(neighbors) => {
+                                for (nn_id, similarity) in neighbors {
+                                    println!(
+                                        "Similarity with item with id({}) is {}",
+                                        nn_id, similarity
+                                    );
+                                }
+                            }
+
+                            Err(e) => {
+                                log::error!("Failed to find the {} most similar items", n);
+                                log::error!("Reason: {}", e);
+                            }
+                        }
+
+                        println!("Operation took {:.4} seconds", elapsed);
+                    }
+
+                    Statement::UserKnn(k, searchby, method, chunks_opt, reverse) => {
                         let user = match controller
                             .users_by(&searchby)
                             .map(|mut users| users.drain(..1).next().unwrap())
@@ -595,7 +1019,7 @@ where
                         };
 
                         let now = Instant::now();
-                        let knn = engine.user_knn(k, user, method, chunks_opt);
+                        let knn = engine.user_knn(k, user, method, chunks_opt, reverse);
 
                         let elapsed = now.elapsed().as_secs_f64();
 
@@ -621,6 +1045,65 @@ where
                         searchby_item,
                         method,
                         chunks_opt,
+                        min_neighbors,
+                    ) => {
+                        let user = match controller
+                            .users_by(&searchby_user)
+                            .map(|mut users| users.drain(..1).next().unwrap())
+                        {
+                            Ok(user) => user,
+                            Err(e) => {
+                                log::error!("{}", e);
+                                continue;
+                            }
+                        };
+
+                        let item = match controller
+                            .items_by(&searchby_item)
+                            .map(|mut items| items.drain(..1).next().unwrap())
+                        {
+                            Ok(item) => item,
+                            Err(e) => {
+                                log::error!("{}", e);
+                                continue;
+                            }
+                        };
+
+                        let item_id = item.get_id();
+
+                        let now = Instant::now();
+                        let prediction = engine.user_based_predict(
+                            k,
+                            user,
+                            item,
+                            method,
+                            chunks_opt,
+                            min_neighbors,
+                            None,
+                        );
+
+                        match prediction {
+                            Ok(predicted) => println!(
+                                "Predicted score for item with id({}) is {}",
+                                item_id, predicted
+                            ),
+
+                            Err(e) => {
+                                log::error!("Failed to predict the score");
+                                log::error!("Reason: {}", e);
+                            }
+                        }
+
+                        println!("Operation took {:.4} seconds", now.elapsed().as_secs_f64());
+                    }
+
+                    Statement::HybridPredict(
+                        k,
+                        searchby_user,
+                        searchby_item,
+                        method,
+                        alpha,
+                        chunks_opt,
                     ) => {
                         let user = match controller
                             .users_by(&searchby_user)
@@ -648,7 +1131,7 @@ where
 
                         let now = Instant::now();
                         let prediction =
-                            engine.user_based_predict(k, user, item, method, chunks_opt);
+                            engine.hybrid_knn_predict(user, item, k, method, alpha, chunks_opt);
 
                         match prediction {
                             Ok(predicted) => println!(
@@ -665,6 +1148,163 @@ where
                         println!("Operation took {:.4} seconds", now.elapsed().as_secs_f64());
                     }
 
+                    Statement::Explain(k, searchby_user, searchby_item, method) => {
+                        let user = match controller
+                            .users_by(&searchby_user)
+                            .map(|mut users| users.drain(..1).next().unwrap())
+                        {
+                            Ok(user) => user,
+                            Err(e) => {
+                                log::error!("{}", e);
+                                continue;
+                            }
+                        };
+
+                        let item = match controller
+                            .items_by(&searchby_item)
+                            .map(|mut items| items.drain(..1).next().unwrap())
+                        {
+                            Ok(item) => item,
+                            Err(e) => {
+                                log::error!("{}", e);
+                                continue;
+                            }
+                        };
+
+                        let now = Instant::now();
+                        let explanation = engine.explain_user_based(user, item, k, method);
+
+                        match explanation {
+                            Ok(contributions) => {
+                                for contribution in contributions {
+                                    println!(
+                                        "Neighbor {} (sim {:.4}) rated it {}",
+                                        contribution.user_id,
+                                        contribution.similarity,
+                                        contribution.rating
+                                    );
+                                }
+                            }
+
+                            Err(e) => {
+                                log::error!("Failed to explain the prediction");
+                                log::error!("Reason: {}", e);
+                            }
+                        }
+
+                        println!("Operation took {:.4} seconds", now.elapsed().as_secs_f64());
+                    }
+
+                    Statement::Popular(n) => {
+                        let now = Instant::now();
+                        match engine.popular_items(n) {
+                            Ok(popular) => {
+                                for (item_id, count) in popular {
+                                    println!("Item with id({}) has {} ratings", item_id, count);
+                                }
+                            }
+
+                            Err(e) => {
+                                log::error!("Failed to find the most popular items");
+                                log::error!("Reason: {}", e);
+                            }
+                        }
+
+                        println!("Operation took {:.4} seconds", now.elapsed().as_secs_f64());
+                    }
+
+                    Statement::Histogram(searchby, bins) => {
+                        let user = match controller
+                            .users_by(&searchby)
+                            .map(|mut users| users.drain(..1).next().unwrap())
+                        {
+                            Ok(user) => user,
+                            Err(e) => {
+                                log::error!("{}", e);
+                                continue;
+                            }
+                        };
+
+                        match engine.user_rating_histogram(user, bins) {
+                            Ok(histogram) => print_histogram(&histogram),
+                            Err(e) => {
+                                log::error!("Failed to compute the histogram");
+                                log::error!("Reason: {}", e);
+                            }
+                        }
+                    }
+
+                    Statement::Controversial(n) => {
+                        let now = Instant::now();
+                        match engine.controversial_items(n) {
+                            Ok(controversial) => {
+                                for (item_id, variance) in controversial {
+                                    println!(
+                                        "Item with id({}) has a variance of {:.4}",
+                                        item_id, variance
+                                    );
+                                }
+                            }
+
+                            Err(e) => {
+                                log::error!("Failed to find the most controversial items");
+                                log::error!("Reason: {}", e);
+                            }
+                        }
+
+                        println!("Operation took {:.4} seconds", now.elapsed().as_secs_f64());
+                    }
+
+                    Statement::UserBasedTopN(
+                        k,
+                        n,
+                        searchby_user,
+                        method,
+                        chunks_opt,
+                        max_candidates,
+                        novelty,
+                    ) => {
+                        let user = match controller
+                            .users_by(&searchby_user)
+                            .map(|mut users| users.drain(..1).next().unwrap())
+                        {
+                            Ok(user) => user,
+                            Err(e) => {
+                                log::error!("{}", e);
+                                continue;
+                            }
+                        };
+
+                        let now = Instant::now();
+                        let top_n = engine.user_based_top_n(
+                            k,
+                            n,
+                            user,
+                            method,
+                            chunks_opt,
+                            max_candidates,
+                            novelty,
+                        );
+
+                        match top_n {
+                            Ok(top_n) => {
+                                for (item_id, predicted) in top_n {
+                                    println!(
+                                        "Predicted score for item with id({}) is {}",
+                                        item_id, predicted
+                                    );
+                                }
+                            }
+
+                            Err(e) => {
+                                log::error!("Failed to find the top {} items", n);
+                                log::error!("Reason: {}", e);
+                            }
+                        }
+
+                        println!("Operation took {:.4} seconds", now.elapsed().as_secs_f64());
+                    }
+
                     Statement::ItemBasedPredict(
                         searchby_user,
                         searchby_item,
@@ -696,7 +1336,17 @@ where
                         let item_id = item.get_id();
 
                         let now = Instant::now();
-                        let prediction = engine.item_based_predict(user, item, method, chunk_size);
+                        let interrupt = InterruptGuard::install();
+                        let prediction = engine.item_based_predict(
+                            user,
+                            item,
+                            method,
+                            chunk_size,
+                            false,
+                            None,
+                            &interrupt.token,
+                        );
+                        drop(interrupt);
 
                         match prediction {
                             Ok(predicted) => println!(
@@ -723,17 +1373,27 @@ where
                                 m,
                                 n,
                             );
-                            chunked_matrix_prompt(&controller, matrix, name, rl)?;
+                            chunked_matrix_prompt(|by| controller.items_by(by), matrix, name, rl)?;
                         }
 
                         ItemMethod::SlopeOne => {
                             let matrix = DeviationMatrix::new(&controller, &config, m, n);
-                            chunked_matrix_prompt(&controller, matrix, name, rl)?;
+                            chunked_matrix_prompt(|by| controller.items_by(by), matrix, name, rl)?;
+                        }
+
+                        ItemMethod::BiPolarSlopeOne => {
+                            log::error!("There's no matrix available for {}", method);
                         }
                     },
+
+                    Statement::EnterUserMatrix(m, n, method) => {
+                        let matrix: UserSimilarityMatrix<_, _, _> =
+                            UserSimilarityMatrix::new(&controller, config, method, m, n);
+                        chunked_matrix_prompt(|by| controller.users_by(by), matrix, name, rl)?;
+                    }
                 },
 
-                None => log::error!("Invalid syntax!"),
+                Err(e) => log::error!("Invalid syntax: {}", e),
             },
         }
     }
@@ -747,6 +1407,16 @@ const AUTHORS: &str = env!("CARGO_PKG_AUTHORS");
 const ABOUT: &str = env!("CARGO_PKG_DESCRIPTION");
 const PROMPT: &str = ">> ";
 
+/// Users whose ratings are kept around between distance queries in the prompt
+const RATINGS_LRU_CAPACITY: usize = 64;
+
+/// Users and items sampled by `coverage`, and neighbors used for each prediction
+const COVERAGE_SAMPLE_SIZE: usize = 50;
+const COVERAGE_K: usize = 20;
+
+/// Items compared at once by `item_knn` when no chunk size is given
+const ITEM_KNN_CHUNK_SIZE: usize = 1000;
+
 fn to_level_filter(level: usize) -> LevelFilter {
     match level {
         0 => LevelFilter::Error,
@@ -769,6 +1439,18 @@ fn main() -> Result<(), Error> {
                 .default_value("config.toml")
                 .help("Set custom config file path"),
         )
+        .subcommand(
+            SubCommand::with_name("serve")
+                .about("Serve knn, predictions and top-n recommendations over HTTP")
+                .arg(
+                    Arg::with_name("address")
+                        .short("a")
+                        .long("address")
+                        .value_name("ADDRESS")
+                        .default_value("127.0.0.1:8080")
+                        .help("Set the address to listen on"),
+                ),
+        )
         .get_matches();
 
     let config_path = matches.value_of("config").unwrap();
@@ -793,6 +1475,11 @@ fn main() -> Result<(), Error> {
         WriteLogger::new(file_level, LogConfig::default(), file_log),
     ])?;
 
+    if let Some(matches) = matches.subcommand_matches("serve") {
+        let address = matches.value_of("address").unwrap();
+        return server::serve(&config, address);
+    }
+
     println!("Welcome to recommendation-system {}", VERSION);
     let mut rl = rustyline::Editor::<()>::new();
 
@@ -809,12 +1496,18 @@ fn main() -> Result<(), Error> {
                 println!("version: {}", VERSION);
             }
 
+            "h" | "help" => print_help(("q | quit", "Quit")),
+
+            explain if explain.starts_with("explain_parse") => {
+                explain_parse(explain["explain_parse".len()..].trim())
+            }
+
             empty if empty.is_empty() => {}
 
             comment if comment.starts_with('#') => {}
 
             line => match parser::parse_line(line) {
-                Some(stmt) => {
+                Ok(stmt) => {
                     if let Statement::Connect(db) = stmt {
                         let name = db.to_string();
 
@@ -860,7 +1553,7 @@ fn main() -> Result<(), Error> {
                     }
                 }
 
-                None => log::error!("Invalid syntax!"),
+                Err(e) => log::error!("Invalid syntax: {}", e),
             },
         }
     }
@@ -4,16 +4,23 @@
 // https://opensource.org/licenses/MIT
 
 pub mod basics;
+pub mod error;
 
-use crate::parser::basics::{parse_ident, parse_int, parse_separator, parse_string};
+use crate::parser::basics::{
+    parse_ident, parse_int, parse_positive_int, parse_separator, parse_string, ParseResult,
+};
 use basics::parse_float;
 use controller::SearchBy;
 use engine::distances::items::Method as ItemMethod;
 use engine::distances::users::Method as UserMethod;
-use nom::combinator::opt;
-use nom::sequence::{delimited, tuple};
+pub use error::ParseError;
+use nom::bytes::complete::is_not;
+use nom::combinator::{cut, map_opt, opt, recognize};
+use nom::error::context;
+use nom::multi::separated_nonempty_list;
+use nom::sequence::{delimited, pair, preceded, tuple};
 use nom::{branch::alt, character::complete::char};
-use nom::{bytes::complete::tag, IResult};
+use nom::{bytes::complete::tag, Err as NomErr};
 use std::fmt::{self, Display, Formatter};
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -25,15 +32,15 @@ pub enum Database {
     MovieLensSmall,
 }
 
-impl From<&str> for Database {
-    fn from(s: &str) -> Self {
-        match s {
-            "books" => Self::Books,
-            "shelves" => Self::Shelves,
-            "simple-movie" => Self::SimpleMovie,
-            "movie-lens" => Self::MovieLens,
-            "movie-lens-small" => Self::MovieLensSmall,
-            _ => unreachable!(),
+impl Database {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "books" => Some(Self::Books),
+            "shelves" => Some(Self::Shelves),
+            "simple-movie" => Some(Self::SimpleMovie),
+            "movie-lens" => Some(Self::MovieLens),
+            "movie-lens-small" => Some(Self::MovieLensSmall),
+            _ => None,
         }
     }
 }
@@ -57,15 +64,37 @@ pub enum Statement {
     Connect(Database),
     QueryUser(SearchBy),
     QueryItem(SearchBy),
+    DescribeUser(SearchBy),
+    DescribeItem(SearchBy),
     QueryRatings(SearchBy),
     UserDistance(SearchBy, SearchBy, UserMethod),
+    Overlap(SearchBy, SearchBy),
     ItemDistance(SearchBy, SearchBy, ItemMethod),
-    UserKnn(usize, SearchBy, UserMethod, Option<usize>),
-    UserBasedPredict(usize, SearchBy, SearchBy, UserMethod, Option<usize>),
+    ItemKnn(usize, SearchBy, ItemMethod, Option<usize>),
+    UserKnn(usize, SearchBy, UserMethod, Option<usize>, bool),
+    UserBasedPredict(usize, SearchBy, SearchBy, UserMethod, Option<usize>, usize),
+    HybridPredict(usize, SearchBy, SearchBy, UserMethod, f64, Option<usize>),
+    UserBasedTopN(
+        usize,
+        usize,
+        SearchBy,
+        UserMethod,
+        Option<usize>,
+        Option<usize>,
+        Option<f64>,
+    ),
+    Explain(usize, SearchBy, SearchBy, UserMethod),
+    Popular(usize),
+    Histogram(SearchBy, usize),
+    Controversial(usize),
     ItemBasedPredict(SearchBy, SearchBy, ItemMethod, usize),
+    Evaluate(String, usize, UserMethod),
+    Coverage(UserMethod),
+    Diagnose(SearchBy, UserMethod, usize),
 
     // Specific for similarity matrix
     EnterMatrix(usize, usize, ItemMethod),
+    EnterUserMatrix(usize, usize, UserMethod),
     MatrixGet(SearchBy, SearchBy),
     MatrixMoveTo(usize, usize),
 
@@ -75,88 +104,108 @@ pub enum Statement {
     InsertRating(SearchBy, SearchBy, f64),
     UpdateRating(SearchBy, SearchBy, f64),
     RemoveRating(SearchBy, SearchBy),
+    RefreshMeans,
+    PrecomputeMeans,
+    ImportRatings(String),
+    ExportKnnGraph(usize, UserMethod, String),
 }
 
-fn parse_user_method(input: &str) -> IResult<&str, UserMethod> {
-    let (input, method) = alt((
-        tag("cosine"),
-        tag("pearson_c"),
-        tag("pearson_a"),
-        tag("euclidean"),
-        tag("manhattan"),
-        tag("minkowski"),
-        tag("jacc_index"),
-        tag("jacc_distance"),
-    ))(input)?;
-
-    let (input, method) = match method {
-        "cosine" => (input, UserMethod::CosineSimilarity),
-        "pearson_c" => (input, UserMethod::PearsonCorrelation),
-        "pearson_a" => (input, UserMethod::PearsonApproximation),
-        "euclidean" => (input, UserMethod::Euclidean),
-        "manhattan" => (input, UserMethod::Manhattan),
-        "minkowski" => {
-            let (input, number) = delimited(char('('), parse_int, char(')'))(input)?;
-            (input, UserMethod::Minkowski(number as usize))
-        }
-        "jacc_index" => (input, UserMethod::JaccardIndex),
-        "jacc_distance" => (input, UserMethod::JaccardDistance),
-        _ => unreachable!(),
-    };
-
-    Ok((input, method))
+fn parse_method_name(input: &str) -> ParseResult<'_, &str> {
+    recognize(pair(
+        parse_ident,
+        opt(delimited(char('('), is_not(")"), char(')'))),
+    ))(input)
 }
 
-fn parse_item_method(input: &str) -> IResult<&str, ItemMethod> {
-    let (input, method) = alt((tag("slope_one"), tag("adj_cosine")))(input)?;
-
-    let (input, method) = match method {
-        "slope_one" => (input, ItemMethod::SlopeOne),
-        "adj_cosine" => (input, ItemMethod::AdjCosine),
-        _ => unreachable!(),
-    };
+fn parse_user_method(input: &str) -> ParseResult<'_, UserMethod> {
+    map_opt(parse_method_name, UserMethod::from_name)(input)
+}
 
-    Ok((input, method))
+fn parse_item_method(input: &str) -> ParseResult<'_, ItemMethod> {
+    map_opt(parse_method_name, ItemMethod::from_name)(input)
 }
 
-fn parse_searchby(input: &str) -> IResult<&str, SearchBy> {
+fn parse_searchby(input: &str) -> ParseResult<'_, SearchBy> {
     let (input, ident) = parse_ident(input)?;
+    if ident == "ids" {
+        let (input, ids) = delimited(
+            char('('),
+            separated_nonempty_list(parse_separator, parse_string),
+            char(')'),
+        )(input)?;
+
+        return Ok((input, SearchBy::ids(&ids)));
+    }
+
     let (input, value) = delimited(char('('), parse_string, char(')'))(input)?;
 
     let index = match ident {
         "id" => SearchBy::id(value),
         "name" => SearchBy::name(value),
+        "like" => SearchBy::name_like(value),
         custom => SearchBy::custom(custom, value),
     };
 
     Ok((input, index))
 }
 
-fn parse_statement(input: &str) -> IResult<&str, Statement> {
+fn parse_statement(input: &str) -> ParseResult<'_, Statement> {
+    // `alt` is limited in size, statements are grouped by what they operate on
     let (input, statement_type) = alt((
-        tag("get"),
-        tag("move_to"),
-        tag("connect"),
-        tag("user_knn"),
-        tag("query_user"),
-        tag("query_item"),
-        tag("insert_user"),
-        tag("insert_item"),
-        tag("enter_matrix"),
-        tag("insert_rating"),
-        tag("update_rating"),
-        tag("remove_rating"),
-        tag("query_ratings"),
-        tag("user_distance"),
-        tag("item_distance"),
-        tag("user_based_predict"),
-        tag("item_based_predict"),
+        alt((
+            tag("get"),
+            tag("move_to"),
+            tag("connect"),
+            tag("enter_matrix"),
+            tag("enter_user_matrix"),
+        )),
+        alt((
+            tag("query_user"),
+            tag("query_item"),
+            tag("query_ratings"),
+            tag("describe_user"),
+            tag("describe_item"),
+            tag("insert_user"),
+            tag("insert_item"),
+            tag("insert_rating"),
+            tag("update_rating"),
+            tag("remove_rating"),
+            tag("refresh_means"),
+            tag("precompute_means"),
+            tag("import_ratings"),
+            tag("export_knn_graph"),
+        )),
+        alt((
+            tag("user_knn"),
+            tag("user_distance"),
+            tag("overlap"),
+            tag("item_distance"),
+            tag("item_knn"),
+            tag("user_based_predict"),
+            tag("hybrid_predict"),
+            tag("user_based_top_n"),
+            tag("explain"),
+            tag("popular"),
+            tag("histogram"),
+            tag("controversial"),
+            tag("item_based_predict"),
+            tag("evaluate"),
+            tag("coverage"),
+            tag("diagnose"),
+        )),
     ))(input)?;
 
     let (input, statement) = match statement_type {
         "connect" => {
-            let (input, database) = delimited(char('('), parse_ident, char(')'))(input)?;
-            (input, Statement::Connect(database.into()))
+            let (input, database) = delimited(
+                char('('),
+                cut(context(
+                    "database",
+                    map_opt(parse_ident, Database::from_name),
+                )),
+                char(')'),
+            )(input)?;
+            (input, Statement::Connect(database))
         }
 
         "query_user" => {
@@ -169,6 +218,16 @@ fn parse_statement(input: &str) -> IResult<&str, Statement> {
             (input, Statement::QueryItem(item_searchby))
         }
 
+        "describe_user" => {
+            let (input, user_searchby) = delimited(char('('), parse_searchby, char(')'))(input)?;
+            (input, Statement::DescribeUser(user_searchby))
+        }
+
+        "describe_item" => {
+            let (input, item_searchby) = delimited(char('('), parse_searchby, char(')'))(input)?;
+            (input, Statement::DescribeItem(item_searchby))
+        }
+
         "query_ratings" => {
             let (input, user_searchby) = delimited(char('('), parse_searchby, char(')'))(input)?;
             (input, Statement::QueryRatings(user_searchby))
@@ -194,6 +253,16 @@ fn parse_statement(input: &str) -> IResult<&str, Statement> {
             )
         }
 
+        "overlap" => {
+            let (input, (user_a_searchby, _, user_b_searchby)) = delimited(
+                char('('),
+                tuple((parse_searchby, parse_separator, parse_searchby)),
+                char(')'),
+            )(input)?;
+
+            (input, Statement::Overlap(user_a_searchby, user_b_searchby))
+        }
+
         "item_distance" => {
             let (input, (item_a_searchby, _, item_b_searchby, _, item_method)) =
                 delimited(
@@ -215,26 +284,63 @@ fn parse_statement(input: &str) -> IResult<&str, Statement> {
         }
 
         "user_knn" => {
-            let (input, (k, _, user_searchby, _, user_method, chunks_opt)) = delimited(
+            let (input, (k, _, user_searchby, _, user_method, reverse_opt, chunks_opt)) =
+                delimited(
+                    char('('),
+                    tuple((
+                        context("k", parse_int),
+                        parse_separator,
+                        parse_searchby,
+                        parse_separator,
+                        parse_user_method,
+                        opt(preceded(parse_separator, tag("reverse"))),
+                        opt(tuple((
+                            parse_separator,
+                            cut(context("chunk_size", parse_positive_int)),
+                            opt(preceded(parse_separator, cut(tag("reverse")))),
+                        ))),
+                    )),
+                    char(')'),
+                )(input)?;
+
+            let reverse = reverse_opt.is_some() || matches!(chunks_opt, Some((_, _, Some(_))));
+
+            (
+                input,
+                Statement::UserKnn(
+                    k as usize,
+                    user_searchby,
+                    user_method,
+                    chunks_opt.map(|(_, chunk_size, _)| chunk_size as usize),
+                    reverse,
+                ),
+            )
+        }
+
+        "item_knn" => {
+            let (input, (n, _, item_searchby, _, item_method, chunks_opt)) = delimited(
                 char('('),
                 tuple((
-                    parse_int,
+                    context("n", parse_int),
                     parse_separator,
                     parse_searchby,
                     parse_separator,
-                    parse_user_method,
-                    opt(tuple((parse_separator, parse_int))),
+                    parse_item_method,
+                    opt(preceded(
+                        parse_separator,
+                        cut(context("chunk_size", parse_positive_int)),
+                    )),
                 )),
                 char(')'),
             )(input)?;
 
             (
                 input,
-                Statement::UserKnn(
-                    k as usize,
-                    user_searchby,
-                    user_method,
-                    chunks_opt.map(|(_, chunk_size)| chunk_size as usize),
+                Statement::ItemKnn(
+                    n as usize,
+                    item_searchby,
+                    item_method,
+                    chunks_opt.map(|chunk_size| chunk_size as usize),
                 ),
             )
         }
@@ -243,9 +349,9 @@ fn parse_statement(input: &str) -> IResult<&str, Statement> {
             let (input, (m, _, n, _, item_method)) = delimited(
                 char('('),
                 tuple((
-                    parse_int,
+                    context("m", parse_int),
                     parse_separator,
-                    parse_int,
+                    context("n", parse_int),
                     parse_separator,
                     parse_item_method,
                 )),
@@ -258,6 +364,25 @@ fn parse_statement(input: &str) -> IResult<&str, Statement> {
             )
         }
 
+        "enter_user_matrix" => {
+            let (input, (m, _, n, _, user_method)) = delimited(
+                char('('),
+                tuple((
+                    context("m", parse_int),
+                    parse_separator,
+                    context("n", parse_int),
+                    parse_separator,
+                    parse_user_method,
+                )),
+                char(')'),
+            )(input)?;
+
+            (
+                input,
+                Statement::EnterUserMatrix(m as usize, n as usize, user_method),
+            )
+        }
+
         "get" => {
             let (input, (item_a_searchby, _, item_b_searchby)) = delimited(
                 char('('),
@@ -274,7 +399,11 @@ fn parse_statement(input: &str) -> IResult<&str, Statement> {
         "move_to" => {
             let (input, (i, _, j)) = delimited(
                 char('('),
-                tuple((parse_int, parse_separator, parse_int)),
+                tuple((
+                    context("i", parse_int),
+                    parse_separator,
+                    context("j", parse_int),
+                )),
                 char(')'),
             )(input)?;
 
@@ -282,34 +411,198 @@ fn parse_statement(input: &str) -> IResult<&str, Statement> {
         }
 
         "user_based_predict" => {
-            let (input, (k, _, user_searchby, _, item_searchby, _, user_method, chunks_opt)) =
+            fn parse_min_neighbors(input: &str) -> ParseResult<'_, i64> {
+                preceded(
+                    tuple((parse_separator, tag("min_neighbors"))),
+                    cut(delimited(
+                        char('('),
+                        context("min_neighbors", parse_positive_int),
+                        char(')'),
+                    )),
+                )(input)
+            }
+
+            let (
+                input,
+                (k, _, user_searchby, _, item_searchby, _, user_method, min_opt, chunks_opt),
+            ) = delimited(
+                char('('),
+                tuple((
+                    context("k", parse_int),
+                    parse_separator,
+                    parse_searchby,
+                    parse_separator,
+                    parse_searchby,
+                    parse_separator,
+                    parse_user_method,
+                    opt(parse_min_neighbors),
+                    opt(tuple((
+                        parse_separator,
+                        cut(context("chunk_size", parse_positive_int)),
+                        opt(parse_min_neighbors),
+                    ))),
+                )),
+                char(')'),
+            )(input)?;
+
+            let min_neighbors = min_opt
+                .or_else(|| chunks_opt.and_then(|(_, _, min_opt)| min_opt))
+                .unwrap_or(1);
+
+            (
+                input,
+                Statement::UserBasedPredict(
+                    k as usize,
+                    user_searchby,
+                    item_searchby,
+                    user_method,
+                    chunks_opt.map(|(_, chunk_size, _)| chunk_size as usize),
+                    min_neighbors as usize,
+                ),
+            )
+        }
+
+        "hybrid_predict" => {
+            let (
+                input,
+                (k, _, user_searchby, _, item_searchby, _, user_method, _, alpha, chunks_opt),
+            ) = delimited(
+                char('('),
+                tuple((
+                    context("k", parse_int),
+                    parse_separator,
+                    parse_searchby,
+                    parse_separator,
+                    parse_searchby,
+                    parse_separator,
+                    parse_user_method,
+                    parse_separator,
+                    context("alpha", parse_float),
+                    opt(tuple((
+                        parse_separator,
+                        cut(context("chunk_size", parse_positive_int)),
+                    ))),
+                )),
+                char(')'),
+            )(input)?;
+
+            (
+                input,
+                Statement::HybridPredict(
+                    k as usize,
+                    user_searchby,
+                    item_searchby,
+                    user_method,
+                    alpha,
+                    chunks_opt.map(|(_, chunk_size)| chunk_size as usize),
+                ),
+            )
+        }
+
+        "user_based_top_n" => {
+            fn parse_novelty(input: &str) -> ParseResult<'_, f64> {
+                preceded(
+                    tuple((parse_separator, tag("novelty"))),
+                    cut(delimited(
+                        char('('),
+                        context("beta", parse_float),
+                        char(')'),
+                    )),
+                )(input)
+            }
+
+            let (input, (k, _, n, _, user_searchby, _, user_method, novelty_opt, chunks_opt)) =
                 delimited(
                     char('('),
                     tuple((
-                        parse_int,
+                        context("k", parse_int),
                         parse_separator,
-                        parse_searchby,
+                        context("n", parse_int),
                         parse_separator,
                         parse_searchby,
                         parse_separator,
                         parse_user_method,
-                        opt(tuple((parse_separator, parse_int))),
+                        opt(parse_novelty),
+                        opt(tuple((
+                            parse_separator,
+                            cut(context("chunk_size", parse_positive_int)),
+                            opt(parse_novelty),
+                            opt(tuple((
+                                parse_separator,
+                                cut(context("max_candidates", parse_int)),
+                                opt(parse_novelty),
+                            ))),
+                        ))),
                     )),
                     char(')'),
                 )(input)?;
 
+            let max_opt = chunks_opt.and_then(|(_, _, _, max_opt)| max_opt);
+            let max_candidates = max_opt.map(|(_, max_candidates, _)| max_candidates as usize);
+            let novelty = novelty_opt
+                .or_else(|| chunks_opt.and_then(|(_, _, novelty_opt, _)| novelty_opt))
+                .or_else(|| max_opt.and_then(|(_, _, novelty_opt)| novelty_opt));
+
             (
                 input,
-                Statement::UserBasedPredict(
+                Statement::UserBasedTopN(
                     k as usize,
+                    n as usize,
                     user_searchby,
-                    item_searchby,
                     user_method,
-                    chunks_opt.map(|(_, chunk_size)| chunk_size as usize),
+                    chunks_opt.map(|(_, chunk_size, _, _)| chunk_size as usize),
+                    max_candidates,
+                    novelty,
                 ),
             )
         }
 
+        "explain" => {
+            let (input, (k, _, user_searchby, _, item_searchby, _, user_method)) =
+                delimited(
+                    char('('),
+                    tuple((
+                        context("k", parse_int),
+                        parse_separator,
+                        parse_searchby,
+                        parse_separator,
+                        parse_searchby,
+                        parse_separator,
+                        parse_user_method,
+                    )),
+                    char(')'),
+                )(input)?;
+
+            (
+                input,
+                Statement::Explain(k as usize, user_searchby, item_searchby, user_method),
+            )
+        }
+
+        "popular" => {
+            let (input, n) = delimited(char('('), context("n", parse_int), char(')'))(input)?;
+            (input, Statement::Popular(n as usize))
+        }
+
+        "histogram" => {
+            let (input, (user_searchby, _, bins)) = delimited(
+                char('('),
+                tuple((
+                    parse_searchby,
+                    parse_separator,
+                    cut(context("bins", parse_positive_int)),
+                )),
+                char(')'),
+            )(input)?;
+
+            (input, Statement::Histogram(user_searchby, bins as usize))
+        }
+
+        "controversial" => {
+            let (input, n) = delimited(char('('), context("n", parse_int), char(')'))(input)?;
+            (input, Statement::Controversial(n as usize))
+        }
+
         "item_based_predict" => {
             let (input, (user_searchby, _, item_searchby, _, item_method, _, chunk_size)) =
                 delimited(
@@ -321,7 +614,7 @@ fn parse_statement(input: &str) -> IResult<&str, Statement> {
                         parse_separator,
                         parse_item_method,
                         parse_separator,
-                        parse_int,
+                        context("chunk_size", parse_positive_int),
                     )),
                     char(')'),
                 )(input)?;
@@ -339,6 +632,75 @@ fn parse_statement(input: &str) -> IResult<&str, Statement> {
 
         "insert_user" => (input, Statement::InsertUser),
         "insert_item" => (input, Statement::InsertItem),
+        "refresh_means" => (input, Statement::RefreshMeans),
+        "precompute_means" => (input, Statement::PrecomputeMeans),
+
+        "import_ratings" => {
+            let (input, path) = delimited(char('('), parse_string, char(')'))(input)?;
+            (input, Statement::ImportRatings(path.into()))
+        }
+
+        "evaluate" => {
+            let (input, (path, _, k, _, user_method)) = delimited(
+                char('('),
+                tuple((
+                    parse_string,
+                    parse_separator,
+                    context("k", parse_int),
+                    parse_separator,
+                    parse_user_method,
+                )),
+                char(')'),
+            )(input)?;
+
+            (
+                input,
+                Statement::Evaluate(path.into(), k as usize, user_method),
+            )
+        }
+
+        "coverage" => {
+            let (input, user_method) = delimited(char('('), parse_user_method, char(')'))(input)?;
+            (input, Statement::Coverage(user_method))
+        }
+
+        "diagnose" => {
+            let (input, (user_searchby, _, user_method, _, k)) = delimited(
+                char('('),
+                tuple((
+                    parse_searchby,
+                    parse_separator,
+                    parse_user_method,
+                    parse_separator,
+                    context("k", parse_int),
+                )),
+                char(')'),
+            )(input)?;
+
+            (
+                input,
+                Statement::Diagnose(user_searchby, user_method, k as usize),
+            )
+        }
+
+        "export_knn_graph" => {
+            let (input, (k, _, user_method, _, path)) = delimited(
+                char('('),
+                tuple((
+                    context("k", parse_int),
+                    parse_separator,
+                    parse_user_method,
+                    parse_separator,
+                    parse_string,
+                )),
+                char(')'),
+            )(input)?;
+
+            (
+                input,
+                Statement::ExportKnnGraph(k as usize, user_method, path.into()),
+            )
+        }
 
         "insert_rating" => {
             let (input, (searchby_user, _, searchby_item, _, score)) = delimited(
@@ -348,7 +710,7 @@ fn parse_statement(input: &str) -> IResult<&str, Statement> {
                     parse_separator,
                     parse_searchby,
                     parse_separator,
-                    parse_float,
+                    context("score", parse_float),
                 )),
                 char(')'),
             )(input)?;
@@ -367,7 +729,7 @@ fn parse_statement(input: &str) -> IResult<&str, Statement> {
                     parse_separator,
                     parse_searchby,
                     parse_separator,
-                    parse_float,
+                    context("score", parse_float),
                 )),
                 char(')'),
             )(input)?;
@@ -394,14 +756,17 @@ fn parse_statement(input: &str) -> IResult<&str, Statement> {
     Ok((input, statement))
 }
 
-pub fn parse_line(input: &str) -> Option<Statement> {
+pub fn parse_line(input: &str) -> Result<Statement, ParseError> {
     let input = input.trim();
-    let (rest, statement) = parse_statement(input).ok()?;
+    let (rest, statement) = parse_statement(input).map_err(|e| match e {
+        NomErr::Error(e) | NomErr::Failure(e) => ParseError::from_verbose(e),
+        NomErr::Incomplete(_) => ParseError::UnexpectedEnd,
+    })?;
 
     if rest.is_empty() {
-        Some(statement)
+        Ok(statement)
     } else {
-        None
+        Err(ParseError::InvalidSyntax(rest.to_string()))
     }
 }
 
@@ -420,6 +785,16 @@ mod tests {
         let expected = ("", SearchBy::name("Patrick C"));
 
         assert_eq!(parsed, Ok(expected));
+
+        let parsed = parse_searchby("like('blade')");
+        let expected = ("", SearchBy::name_like("blade"));
+
+        assert_eq!(parsed, Ok(expected));
+
+        let parsed = parse_searchby("ids('1', '2','3')");
+        let expected = ("", SearchBy::ids(&["1", "2", "3"]));
+
+        assert_eq!(parsed, Ok(expected));
     }
 
     #[test]
@@ -459,6 +834,19 @@ mod tests {
         assert_eq!(parsed, Ok(expected));
     }
 
+    #[test]
+    fn describe_statements() {
+        let parsed = parse_statement("describe_user(id('32'))");
+        let expected = ("", Statement::DescribeUser(SearchBy::id("32")));
+
+        assert_eq!(parsed, Ok(expected));
+
+        let parsed = parse_statement("describe_item(name('Alien'))");
+        let expected = ("", Statement::DescribeItem(SearchBy::name("Alien")));
+
+        assert_eq!(parsed, Ok(expected));
+    }
+
     #[test]
     fn query_ratings_statement() {
         let parsed = parse_statement("query_ratings(id('12345'))");
@@ -503,64 +891,427 @@ mod tests {
     }
 
     #[test]
-    fn user_knn_statement() {
-        let parsed = parse_statement("user_knn(4, id('324x'), minkowski(3))");
+    fn item_knn_statement() {
+        let parsed = parse_statement("item_knn(5, id('10'), adj_cosine)");
         let expected = (
             "",
-            Statement::UserKnn(4, SearchBy::id("324x"), UserMethod::Minkowski(3), None),
+            Statement::ItemKnn(5, SearchBy::id("10"), ItemMethod::AdjCosine, None),
         );
-
         assert_eq!(parsed, Ok(expected));
 
-        let parsed = parse_statement("user_knn(4, id('324x'), minkowski(3), 10)");
+        let parsed = parse_statement("item_knn(5, id('10'), adj_cosine, 100)");
         let expected = (
             "",
-            Statement::UserKnn(4, SearchBy::id("324x"), UserMethod::Minkowski(3), Some(10)),
+            Statement::ItemKnn(5, SearchBy::id("10"), ItemMethod::AdjCosine, Some(100)),
         );
-
         assert_eq!(parsed, Ok(expected));
+
+        assert!(parse_statement("item_knn(5, id('10'), adj_cosine, 0)").is_err());
     }
 
     #[test]
-    fn user_predict_statement() {
-        let parsed =
-            parse_statement("user_based_predict(4, id('324x'), name('Alien'), minkowski(3))");
+    fn user_knn_statement() {
+        let parsed = parse_statement("user_knn(4, id('324x'), minkowski(3))");
         let expected = (
             "",
-            Statement::UserBasedPredict(
+            Statement::UserKnn(
                 4,
                 SearchBy::id("324x"),
-                SearchBy::name("Alien"),
                 UserMethod::Minkowski(3),
                 None,
+                false,
             ),
         );
 
         assert_eq!(parsed, Ok(expected));
 
-        let parsed =
-            parse_statement("user_based_predict(4, id('324x'), name('Alien'), minkowski(3), 100)");
+        let parsed = parse_statement("user_knn(4, id('324x'), minkowski(3), 10)");
         let expected = (
             "",
-            Statement::UserBasedPredict(
+            Statement::UserKnn(
                 4,
                 SearchBy::id("324x"),
-                SearchBy::name("Alien"),
                 UserMethod::Minkowski(3),
-                Some(100),
+                Some(10),
+                false,
             ),
         );
 
         assert_eq!(parsed, Ok(expected));
-    }
-
-    #[test]
-    fn item_predict_statement() {
-        let parsed =
-            parse_statement("item_based_predict(id('324x'), name('Alien'), adj_cosine, 100)");
+        let parsed = parse_statement("user_knn(5, id('1'), cosine, reverse)");
         let expected = (
             "",
-            Statement::ItemBasedPredict(
+            Statement::UserKnn(
+                5,
+                SearchBy::id("1"),
+                UserMethod::CosineSimilarity,
+                None,
+                true,
+            ),
+        );
+
+        assert_eq!(parsed, Ok(expected));
+
+        let parsed = parse_statement("user_knn(5, id('1'), cosine, 100, reverse)");
+        let expected = (
+            "",
+            Statement::UserKnn(
+                5,
+                SearchBy::id("1"),
+                UserMethod::CosineSimilarity,
+                Some(100),
+                true,
+            ),
+        );
+
+        assert_eq!(parsed, Ok(expected));
+    }
+
+    #[test]
+    fn jaccard_threshold_method() {
+        let parsed = parse_statement("user_knn(4, id('324x'), jacc_thresh(4.0))");
+        let expected = (
+            "",
+            Statement::UserKnn(
+                4,
+                SearchBy::id("324x"),
+                UserMethod::JaccardThreshold(4.0),
+                None,
+                false,
+            ),
+        );
+
+        assert_eq!(parsed, Ok(expected));
+    }
+
+    #[test]
+    fn hamming_method() {
+        let parsed = parse_statement("user_distance(id('32a'), id('32b'), hamming)");
+        let expected = (
+            "",
+            Statement::UserDistance(
+                SearchBy::id("32a"),
+                SearchBy::id("32b"),
+                UserMethod::Hamming,
+            ),
+        );
+
+        assert_eq!(parsed, Ok(expected));
+    }
+
+    #[test]
+    fn bray_curtis_method() {
+        let parsed = parse_statement("user_distance(id('32a'), id('32b'), bray_curtis)");
+        let expected = (
+            "",
+            Statement::UserDistance(
+                SearchBy::id("32a"),
+                SearchBy::id("32b"),
+                UserMethod::BrayCurtis,
+            ),
+        );
+
+        assert_eq!(parsed, Ok(expected));
+    }
+
+    #[test]
+    fn user_top_n_statement() {
+        let parsed = parse_statement("user_based_top_n(10, 5, id('324x'), cosine)");
+        let expected = (
+            "",
+            Statement::UserBasedTopN(
+                10,
+                5,
+                SearchBy::id("324x"),
+                UserMethod::CosineSimilarity,
+                None,
+                None,
+                None,
+            ),
+        );
+
+        assert_eq!(parsed, Ok(expected));
+
+        let parsed = parse_statement("user_based_top_n(10, 5, id('324x'), cosine, 100)");
+        let expected = (
+            "",
+            Statement::UserBasedTopN(
+                10,
+                5,
+                SearchBy::id("324x"),
+                UserMethod::CosineSimilarity,
+                Some(100),
+                None,
+                None,
+            ),
+        );
+
+        assert_eq!(parsed, Ok(expected));
+
+        let parsed = parse_statement("user_based_top_n(10, 5, id('324x'), cosine, 100, 500)");
+        let expected = (
+            "",
+            Statement::UserBasedTopN(
+                10,
+                5,
+                SearchBy::id("324x"),
+                UserMethod::CosineSimilarity,
+                Some(100),
+                Some(500),
+                None,
+            ),
+        );
+
+        assert_eq!(parsed, Ok(expected));
+
+        let parsed = parse_statement("user_based_top_n(10, 5, id('324x'), cosine, novelty(0.5))");
+        let expected = (
+            "",
+            Statement::UserBasedTopN(
+                10,
+                5,
+                SearchBy::id("324x"),
+                UserMethod::CosineSimilarity,
+                None,
+                None,
+                Some(0.5),
+            ),
+        );
+
+        assert_eq!(parsed, Ok(expected));
+
+        let parsed =
+            parse_statement("user_based_top_n(10, 5, id('324x'), cosine, 100, 500, novelty(2))");
+        let expected = (
+            "",
+            Statement::UserBasedTopN(
+                10,
+                5,
+                SearchBy::id("324x"),
+                UserMethod::CosineSimilarity,
+                Some(100),
+                Some(500),
+                Some(2.0),
+            ),
+        );
+
+        assert_eq!(parsed, Ok(expected));
+        assert!(parse_statement("user_based_top_n(10, 5, id('324x'), cosine, novelty)").is_err());
+    }
+
+    #[test]
+    fn user_predict_statement() {
+        let parsed =
+            parse_statement("user_based_predict(4, id('324x'), name('Alien'), minkowski(3))");
+        let expected = (
+            "",
+            Statement::UserBasedPredict(
+                4,
+                SearchBy::id("324x"),
+                SearchBy::name("Alien"),
+                UserMethod::Minkowski(3),
+                None,
+                1,
+            ),
+        );
+
+        assert_eq!(parsed, Ok(expected));
+
+        let parsed =
+            parse_statement("user_based_predict(4, id('324x'), name('Alien'), minkowski(3), 100)");
+        let expected = (
+            "",
+            Statement::UserBasedPredict(
+                4,
+                SearchBy::id("324x"),
+                SearchBy::name("Alien"),
+                UserMethod::Minkowski(3),
+                Some(100),
+                1,
+            ),
+        );
+
+        assert_eq!(parsed, Ok(expected));
+
+        let parsed = parse_statement(
+            "user_based_predict(4, id('324x'), name('Alien'), cosine, min_neighbors(3))",
+        );
+        let expected = (
+            "",
+            Statement::UserBasedPredict(
+                4,
+                SearchBy::id("324x"),
+                SearchBy::name("Alien"),
+                UserMethod::CosineSimilarity,
+                None,
+                3,
+            ),
+        );
+
+        assert_eq!(parsed, Ok(expected));
+
+        let parsed = parse_statement(
+            "user_based_predict(4, id('324x'), name('Alien'), cosine, 100, min_neighbors(2))",
+        );
+        let expected = (
+            "",
+            Statement::UserBasedPredict(
+                4,
+                SearchBy::id("324x"),
+                SearchBy::name("Alien"),
+                UserMethod::CosineSimilarity,
+                Some(100),
+                2,
+            ),
+        );
+
+        assert_eq!(parsed, Ok(expected));
+    }
+
+    #[test]
+    fn hybrid_predict_statement() {
+        let parsed = parse_statement("hybrid_predict(10, id('324x'), name('Alien'), cosine, 0.7)");
+        let expected = (
+            "",
+            Statement::HybridPredict(
+                10,
+                SearchBy::id("324x"),
+                SearchBy::name("Alien"),
+                UserMethod::CosineSimilarity,
+                0.7,
+                None,
+            ),
+        );
+
+        assert_eq!(parsed, Ok(expected));
+
+        let parsed =
+            parse_statement("hybrid_predict(10, id('324x'), name('Alien'), cosine, 0.5, 100)");
+        let expected = (
+            "",
+            Statement::HybridPredict(
+                10,
+                SearchBy::id("324x"),
+                SearchBy::name("Alien"),
+                UserMethod::CosineSimilarity,
+                0.5,
+                Some(100),
+            ),
+        );
+
+        assert_eq!(parsed, Ok(expected));
+    }
+
+    #[test]
+    fn explain_statement() {
+        let parsed = parse_statement("explain(10, id('324x'), name('Alien'), pearson_c)");
+        let expected = (
+            "",
+            Statement::Explain(
+                10,
+                SearchBy::id("324x"),
+                SearchBy::name("Alien"),
+                UserMethod::PearsonCorrelation,
+            ),
+        );
+
+        assert_eq!(parsed, Ok(expected));
+    }
+
+    #[test]
+    fn popular_and_controversial_statements() {
+        let parsed = parse_statement("popular(10)");
+        assert_eq!(parsed, Ok(("", Statement::Popular(10))));
+
+        let parsed = parse_statement("controversial(5)");
+        assert_eq!(parsed, Ok(("", Statement::Controversial(5))));
+    }
+
+    #[test]
+    fn means_statements() {
+        let parsed = parse_statement("refresh_means");
+        assert_eq!(parsed, Ok(("", Statement::RefreshMeans)));
+
+        let parsed = parse_statement("precompute_means");
+        assert_eq!(parsed, Ok(("", Statement::PrecomputeMeans)));
+    }
+
+    #[test]
+    fn overlap_statement() {
+        let parsed = parse_statement("overlap(id('1'), name('Josh'))");
+        let expected = (
+            "",
+            Statement::Overlap(SearchBy::id("1"), SearchBy::name("Josh")),
+        );
+
+        assert_eq!(parsed, Ok(expected));
+    }
+
+    #[test]
+    fn histogram_statement() {
+        let parsed = parse_statement("histogram(id('5'), 10)");
+        let expected = ("", Statement::Histogram(SearchBy::id("5"), 10));
+
+        assert_eq!(parsed, Ok(expected));
+        assert!(parse_statement("histogram(id('5'), 0)").is_err());
+    }
+
+    #[test]
+    fn import_ratings_statement() {
+        let parsed = parse_statement("import_ratings('data/ratings.csv')");
+        let expected = ("", Statement::ImportRatings("data/ratings.csv".into()));
+
+        assert_eq!(parsed, Ok(expected));
+    }
+
+    #[test]
+    fn export_knn_graph_statement() {
+        let parsed = parse_statement("export_knn_graph(5, cosine, 'graph.csv')");
+        let expected = (
+            "",
+            Statement::ExportKnnGraph(5, UserMethod::CosineSimilarity, "graph.csv".into()),
+        );
+
+        assert_eq!(parsed, Ok(expected));
+    }
+
+    #[test]
+    fn evaluate_statement() {
+        let parsed = parse_statement("evaluate('data/test.csv', 10, pearson_c)");
+        let expected = (
+            "",
+            Statement::Evaluate("data/test.csv".into(), 10, UserMethod::PearsonCorrelation),
+        );
+
+        assert_eq!(parsed, Ok(expected));
+    }
+
+    #[test]
+    fn coverage_statement() {
+        let parsed = parse_statement("coverage(manhattan)");
+        let expected = ("", Statement::Coverage(UserMethod::Manhattan));
+
+        assert_eq!(parsed, Ok(expected));
+    }
+
+    #[test]
+    fn diagnose_statement() {
+        let parsed = parse_statement("diagnose(id('5'), cosine, 10)");
+        let expected = (
+            "",
+            Statement::Diagnose(SearchBy::id("5"), UserMethod::CosineSimilarity, 10),
+        );
+
+        assert_eq!(parsed, Ok(expected));
+    }
+
+    #[test]
+    fn item_predict_statement() {
+        let parsed =
+            parse_statement("item_based_predict(id('324x'), name('Alien'), adj_cosine, 100)");
+        let expected = (
+            "",
+            Statement::ItemBasedPredict(
                 SearchBy::id("324x"),
                 SearchBy::name("Alien"),
                 ItemMethod::AdjCosine,
@@ -569,6 +1320,21 @@ mod tests {
         );
 
         assert_eq!(parsed, Ok(expected));
+
+        let parsed = parse_statement(
+            "item_based_predict(id('324x'), name('Alien'), bipolar_slope_one, 100)",
+        );
+        let expected = (
+            "",
+            Statement::ItemBasedPredict(
+                SearchBy::id("324x"),
+                SearchBy::name("Alien"),
+                ItemMethod::BiPolarSlopeOne,
+                100,
+            ),
+        );
+
+        assert_eq!(parsed, Ok(expected));
     }
 
     #[test]
@@ -579,6 +1345,17 @@ mod tests {
         assert_eq!(parsed, Ok(expected));
     }
 
+    #[test]
+    fn enter_user_matrix_statement() {
+        let parsed = parse_statement("enter_user_matrix(50, 20, cosine)");
+        let expected = (
+            "",
+            Statement::EnterUserMatrix(50, 20, UserMethod::CosineSimilarity),
+        );
+
+        assert_eq!(parsed, Ok(expected));
+    }
+
     #[test]
     fn matrix_get_statement() {
         let parsed = parse_statement("get(id('10'), name('Alien'))");
@@ -601,7 +1378,88 @@ mod tests {
     #[test]
     fn parse_invalid_line() {
         let parsed = parse_line("query_user(id())xx");
-        assert!(parsed.is_none());
+        assert!(parsed.is_err());
+    }
+
+    #[test]
+    fn parse_unknown_database() {
+        let parsed = parse_line("connect(foo)");
+        assert_eq!(parsed, Err(ParseError::UnknownDatabase("foo".into())));
+
+        let parsed = parse_line("connect(movie-lens-large)");
+        assert_eq!(
+            parsed,
+            Err(ParseError::UnknownDatabase("movie-lens-large".into()))
+        );
+    }
+
+    #[test]
+    fn parse_malformed_numbers() {
+        let parsed = parse_line("user_knn(foo, name('Patrick C'), cosine)");
+        assert_eq!(
+            parsed,
+            Err(ParseError::ExpectedInteger {
+                argument: "k",
+                found: "foo".into()
+            })
+        );
+
+        let parsed = parse_line("user_knn(-5, name('Patrick C'), cosine)");
+        assert_eq!(
+            parsed,
+            Err(ParseError::ExpectedInteger {
+                argument: "k",
+                found: "-5".into()
+            })
+        );
+
+        let parsed = parse_line("user_knn(99999999999999999999, name('Patrick C'), cosine)");
+        assert_eq!(
+            parsed,
+            Err(ParseError::IntegerOverflow {
+                argument: "k",
+                found: "99999999999999999999".into()
+            })
+        );
+
+        let parsed = parse_line("user_knn(5, name('Patrick C'), cosine, 1x0)");
+        assert_eq!(parsed, Err(ParseError::InvalidSyntax("x0)".into())));
+
+        let parsed = parse_line("user_knn(5, name('Patrick C'), cosine, bar)");
+        assert_eq!(
+            parsed,
+            Err(ParseError::ExpectedInteger {
+                argument: "chunk_size",
+                found: "bar".into()
+            })
+        );
+
+        let parsed = parse_line("user_knn(5, name('Patrick C'), cosine, 0)");
+        assert_eq!(
+            parsed,
+            Err(ParseError::ExpectedPositive {
+                argument: "chunk_size",
+                found: "0".into()
+            })
+        );
+
+        let parsed = parse_line("item_based_predict(id('1'), id('2'), slope_one, 0)");
+        assert_eq!(
+            parsed,
+            Err(ParseError::ExpectedPositive {
+                argument: "chunk_size",
+                found: "0".into()
+            })
+        );
+
+        let parsed = parse_line("insert_rating(id('1'), id('2'), high)");
+        assert_eq!(
+            parsed,
+            Err(ParseError::ExpectedFloat {
+                argument: "score",
+                found: "high".into()
+            })
+        );
     }
 
     #[test]
@@ -609,11 +1467,12 @@ mod tests {
         let parsed = parse_line("user_knn(5, name('Patrick C'), cosine)");
         assert_eq!(
             parsed,
-            Some(Statement::UserKnn(
+            Ok(Statement::UserKnn(
                 5,
                 SearchBy::name("Patrick C"),
                 UserMethod::CosineSimilarity,
-                None
+                None,
+                false
             ))
         );
     }
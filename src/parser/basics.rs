@@ -5,26 +5,33 @@
 
 use nom::bytes::complete::{tag, take_till1, take_while, take_while1};
 use nom::character::complete::{char, digit1};
-use nom::combinator::map_res;
+use nom::combinator::{map_res, verify};
+use nom::error::VerboseError;
 use nom::{number::complete::double, sequence::delimited, IResult};
 
-pub(crate) fn parse_ident(input: &str) -> IResult<&str, &str> {
+pub(crate) type ParseResult<'a, T> = IResult<&'a str, T, VerboseError<&'a str>>;
+
+pub(crate) fn parse_ident(input: &str) -> ParseResult<'_, &str> {
     take_while1(|c: char| c.is_alphanumeric() || c == '_' || c == '-')(input)
 }
 
-pub(crate) fn parse_string(input: &str) -> IResult<&str, &str> {
+pub(crate) fn parse_string(input: &str) -> ParseResult<'_, &str> {
     delimited(char('\''), take_till1(|c: char| c == '\''), char('\''))(input)
 }
 
-pub(crate) fn parse_int(input: &str) -> IResult<&str, i64> {
+pub(crate) fn parse_int(input: &str) -> ParseResult<'_, i64> {
     map_res(digit1, |s: &str| s.parse::<i64>())(input)
 }
 
-pub(crate) fn parse_float(input: &str) -> IResult<&str, f64> {
+pub(crate) fn parse_positive_int(input: &str) -> ParseResult<'_, i64> {
+    verify(parse_int, |n: &i64| *n > 0)(input)
+}
+
+pub(crate) fn parse_float(input: &str) -> ParseResult<'_, f64> {
     double(input)
 }
 
-pub(crate) fn parse_separator(input: &str) -> IResult<&str, &str> {
+pub(crate) fn parse_separator(input: &str) -> ParseResult<'_, &str> {
     delimited(
         take_while(|c: char| c == ' '),
         tag(","),
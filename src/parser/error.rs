@@ -0,0 +1,91 @@
+// Copyright (c) 2020 White Leaf
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use nom::error::{ErrorKind as NomErrorKind, VerboseError, VerboseErrorKind};
+use thiserror::Error as DError;
+
+#[derive(Debug, Clone, PartialEq, DError)]
+pub enum ParseError {
+    #[error("expected an integer for {argument}, got '{found}'")]
+    ExpectedInteger {
+        argument: &'static str,
+        found: String,
+    },
+
+    #[error("expected a positive integer for {argument}, got '{found}'")]
+    ExpectedPositive {
+        argument: &'static str,
+        found: String,
+    },
+
+    #[error("integer '{found}' is too large for {argument}")]
+    IntegerOverflow {
+        argument: &'static str,
+        found: String,
+    },
+
+    #[error("expected a number for {argument}, got '{found}'")]
+    ExpectedFloat {
+        argument: &'static str,
+        found: String,
+    },
+
+    #[error("unknown database '{0}'")]
+    UnknownDatabase(String),
+
+    #[error("invalid syntax near '{0}'")]
+    InvalidSyntax(String),
+
+    #[error("unexpected end of input")]
+    UnexpectedEnd,
+}
+
+// The offending token, i.e. everything until the next separator
+fn token(input: &str) -> String {
+    input
+        .trim_start()
+        .split(|c: char| c == ',' || c == ')' || c.is_whitespace())
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+impl ParseError {
+    pub(crate) fn from_verbose(error: VerboseError<&str>) -> Self {
+        let (input, kind) = match error.errors.first() {
+            Some(first) => first,
+            None => return ParseError::UnexpectedEnd,
+        };
+
+        let argument = error.errors.iter().find_map(|(_, kind)| match kind {
+            VerboseErrorKind::Context(argument) => Some(*argument),
+            _ => None,
+        });
+
+        let found = token(input);
+        match (argument, kind) {
+            (Some("database"), VerboseErrorKind::Nom(NomErrorKind::MapOpt)) => {
+                ParseError::UnknownDatabase(found)
+            }
+
+            (Some(argument), VerboseErrorKind::Nom(NomErrorKind::MapRes)) => {
+                ParseError::IntegerOverflow { argument, found }
+            }
+
+            (Some(argument), VerboseErrorKind::Nom(NomErrorKind::Verify)) => {
+                ParseError::ExpectedPositive { argument, found }
+            }
+
+            (Some(argument), VerboseErrorKind::Nom(NomErrorKind::Digit)) => {
+                ParseError::ExpectedInteger { argument, found }
+            }
+
+            (Some(argument), _) => ParseError::ExpectedFloat { argument, found },
+
+            (None, _) if input.trim().is_empty() => ParseError::UnexpectedEnd,
+            (None, _) => ParseError::InvalidSyntax(input.trim().to_string()),
+        }
+    }
+}
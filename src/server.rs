@@ -0,0 +1,619 @@
+// Copyright (c) 2020 White Leaf
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use crate::parser::Database;
+use anyhow::Error;
+use books::BooksController;
+use config::Config;
+use controller::{eid, error::ErrorKind as ControllerErrorKind, Controller, Entity, SearchBy};
+use engine::{
+    cancellation::CancellationToken,
+    distances::{items::Method as ItemMethod, users::Method as UserMethod},
+    error::{ErrorKind, RecommendationError},
+    Engine,
+};
+use movie_lens::MovieLensController;
+use movie_lens_small::MovieLensSmallController;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use shelves::ShelvesController;
+use simple_movie::SimpleMovieController;
+use std::{
+    fmt::{Debug, Display},
+    hash::Hash,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    time::Duration,
+};
+
+/// Largest request body accepted, bigger ones are rejected before being read
+pub const MAX_BODY_SIZE: usize = 1 << 20;
+
+/// Time a connection may stay idle while its request is being read
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct KnnRequest {
+    pub database: String,
+    pub user_id: String,
+    pub method: String,
+    pub k: usize,
+    pub chunk_size: Option<usize>,
+    #[serde(default)]
+    pub reverse: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct PredictRequest {
+    pub database: String,
+    pub user_id: String,
+    pub item_id: String,
+    pub method: String,
+    pub k: Option<usize>,
+    pub chunk_size: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct TopNRequest {
+    pub database: String,
+    pub user_id: String,
+    pub method: String,
+    pub k: usize,
+    pub n: usize,
+    pub chunk_size: Option<usize>,
+    pub max_candidates: Option<usize>,
+    pub novelty: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Neighbor {
+    pub user_id: String,
+    pub distance: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct KnnResponse {
+    pub neighbors: Vec<Neighbor>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PredictResponse {
+    pub user_id: String,
+    pub item_id: String,
+    pub score: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScoredItem {
+    pub item_id: String,
+    pub score: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TopNResponse {
+    pub items: Vec<ScoredItem>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Request {
+    Knn(KnnRequest),
+    Predict(PredictRequest),
+    TopN(TopNRequest),
+}
+
+impl Request {
+    pub fn database(&self) -> &str {
+        match self {
+            Request::Knn(req) => &req.database,
+            Request::Predict(req) => &req.database,
+            Request::TopN(req) => &req.database,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+impl HttpResponse {
+    fn ok<T: Serialize>(value: &T) -> Self {
+        match serde_json::to_string(value) {
+            Ok(body) => Self { status: 200, body },
+            Err(e) => Self::error(500, e),
+        }
+    }
+
+    fn error<E: Display>(status: u16, error: E) -> Self {
+        let body = serde_json::to_string(&ErrorResponse {
+            error: error.to_string(),
+        })
+        .unwrap_or_default();
+
+        Self { status, body }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            413 => "Payload Too Large",
+            422 => "Unprocessable Entity",
+            501 => "Not Implemented",
+            _ => "Internal Server Error",
+        }
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        write!(
+            writer,
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.status,
+            self.reason(),
+            self.body.len(),
+            self.body
+        )?;
+
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Read a single HTTP/1.1 request, only the request line, the `Content-Length`
+/// header and the body are taken into account. Bodies larger than
+/// `MAX_BODY_SIZE` are rejected with a 413 without being read
+pub fn read_request<R: BufRead>(reader: &mut R) -> Result<HttpRequest, HttpResponse> {
+    let bad_request = |e| HttpResponse::error(400, e);
+
+    let mut line = String::new();
+    reader.read_line(&mut line).map_err(bad_request)?;
+
+    let mut parts = line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => {
+            return Err(HttpResponse::error(
+                400,
+                format!("Malformed request line: {:?}", line.trim()),
+            ))
+        }
+    };
+
+    let mut content_length = 0;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).map_err(bad_request)? == 0 {
+            break;
+        }
+
+        let header = line.trim();
+        if header.is_empty() {
+            break;
+        }
+
+        if let Some(idx) = header.find(':') {
+            let (name, value) = header.split_at(idx);
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value[1..]
+                    .trim()
+                    .parse()
+                    .map_err(|e| HttpResponse::error(400, e))?;
+            }
+        }
+    }
+
+    if content_length > MAX_BODY_SIZE {
+        return Err(HttpResponse::error(
+            413,
+            format!(
+                "Body of {} bytes exceeds the limit of {} bytes",
+                content_length, MAX_BODY_SIZE
+            ),
+        ));
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).map_err(bad_request)?;
+
+    Ok(HttpRequest { method, path, body })
+}
+
+fn parse_body<T: DeserializeOwned>(body: &[u8]) -> Result<T, HttpResponse> {
+    serde_json::from_slice(body).map_err(|e| HttpResponse::error(400, e))
+}
+
+/// Map an HTTP request to one of the supported endpoints
+pub fn route(http: &HttpRequest) -> Result<Request, HttpResponse> {
+    let path = http.path.as_str();
+    if !matches!(path, "/knn" | "/predict" | "/top_n") {
+        return Err(HttpResponse::error(
+            404,
+            format!("Unknown endpoint {}", path),
+        ));
+    }
+
+    if http.method != "POST" {
+        return Err(HttpResponse::error(
+            405,
+            format!("Method {} not allowed", http.method),
+        ));
+    }
+
+    match path {
+        "/knn" => Ok(Request::Knn(parse_body(&http.body)?)),
+        "/predict" => Ok(Request::Predict(parse_body(&http.body)?)),
+        _ => Ok(Request::TopN(parse_body(&http.body)?)),
+    }
+}
+
+fn find_user<C: Controller>(controller: &C, user_id: &str) -> Result<C::User, RecommendationError> {
+    let user = controller
+        .users_by(&SearchBy::id(user_id))
+        .map(|mut users| users.drain(..1).next().unwrap())?;
+
+    Ok(user)
+}
+
+fn find_item<C: Controller>(controller: &C, item_id: &str) -> Result<C::Item, RecommendationError> {
+    let item = controller
+        .items_by(&SearchBy::id(item_id))
+        .map(|mut items| items.drain(..1).next().unwrap())?;
+
+    Ok(item)
+}
+
+/// HTTP status code for a failed recommendation
+fn status_for(error: &RecommendationError) -> u16 {
+    match error {
+        RecommendationError::Engine(kind) => match kind {
+            ErrorKind::ColdUser(_) | ErrorKind::ColdItem(_) => 404,
+            ErrorKind::InvalidParameter(_) => 400,
+            ErrorKind::NotImplemented => 501,
+            ErrorKind::EmptyRatings
+            | ErrorKind::NoMatchingRatings
+            | ErrorKind::EmptyKNearestNeighbors
+            | ErrorKind::NoNeighbors(_)
+            | ErrorKind::InsufficientNeighbors(_, _)
+            | ErrorKind::DivisionByZero
+            | ErrorKind::IndeterminateForm => 422,
+            ErrorKind::ConvertType | ErrorKind::IndexOutOfBound | ErrorKind::Cancelled => 500,
+        },
+        RecommendationError::Controller(e) => match e.downcast_ref::<ControllerErrorKind>() {
+            Some(ControllerErrorKind::NotFoundById(_))
+            | Some(ControllerErrorKind::NotFoundByName(_))
+            | Some(ControllerErrorKind::NotFoundByCustom(_, _)) => 404,
+            _ => 500,
+        },
+        RecommendationError::Io(_) => 500,
+    }
+}
+
+fn handle_request<C, U, I, R>(config: &Config, controller: C, request: Request) -> HttpResponse
+where
+    C: Controller<User = U, Item = I, Rating = R>,
+    U: Entity,
+    I: Entity,
+    R: Entity,
+    eid!(U): Hash + Eq + Ord + Display + Clone + Debug + Default,
+    eid!(I): Hash + Eq + Display + Clone + Debug,
+{
+    let engine = Engine::with_controller(&controller, config);
+
+    let response = match request {
+        Request::Knn(req) => {
+            let method = match UserMethod::from_name(&req.method) {
+                Some(method) => method,
+                None => return HttpResponse::error(400, format!("Unknown method {}", req.method)),
+            };
+
+            find_user(&controller, &req.user_id)
+                .and_then(|user| engine.user_knn(req.k, user, method, req.chunk_size, req.reverse))
+                .map(|knn| {
+                    let neighbors = knn
+                        .into_iter()
+                        .map(|(user_id, distance)| Neighbor {
+                            user_id: user_id.to_string(),
+                            distance,
+                        })
+                        .collect();
+
+                    HttpResponse::ok(&KnnResponse { neighbors })
+                })
+        }
+
+        Request::Predict(req) => {
+            let predicted = if let Some(method) = UserMethod::from_name(&req.method) {
+                let k = match req.k {
+                    Some(k) => k,
+                    None => return HttpResponse::error(400, "User based methods require k"),
+                };
+
+                find_user(&controller, &req.user_id).and_then(|user| {
+                    let item = find_item(&controller, &req.item_id)?;
+                    engine.user_based_predict(k, user, item, method, req.chunk_size, 1, None)
+                })
+            } else if let Some(method) = ItemMethod::from_name(&req.method) {
+                let chunk_size = req
+                    .chunk_size
+                    .unwrap_or(config.engine.partial_users_chunk_size);
+
+                find_user(&controller, &req.user_id).and_then(|user| {
+                    let item = find_item(&controller, &req.item_id)?;
+                    engine.item_based_predict(
+                        user,
+                        item,
+                        method,
+                        chunk_size,
+                        false,
+                        None,
+                        &CancellationToken::new(),
+                    )
+                })
+            } else {
+                return HttpResponse::error(400, format!("Unknown method {}", req.method));
+            };
+
+            predicted.map(|score| {
+                HttpResponse::ok(&PredictResponse {
+                    user_id: req.user_id,
+                    item_id: req.item_id,
+                    score,
+                })
+            })
+        }
+
+        Request::TopN(req) => {
+            let method = match UserMethod::from_name(&req.method) {
+                Some(method) => method,
+                None => return HttpResponse::error(400, format!("Unknown method {}", req.method)),
+            };
+
+            find_user(&controller, &req.user_id)
+                .and_then(|user| {
+                    engine.user_based_top_n(
+                        req.k,
+                        req.n,
+                        user,
+                        method,
+                        req.chunk_size,
+                        req.max_candidates,
+                        req.novelty,
+                    )
+                })
+                .map(|top_n| {
+                    let items = top_n
+                        .into_iter()
+                        .map(|(item_id, score)| ScoredItem {
+                            item_id: item_id.to_string(),
+                            score,
+                        })
+                        .collect();
+
+                    HttpResponse::ok(&TopNResponse { items })
+                })
+        }
+    };
+
+    response.unwrap_or_else(|e| HttpResponse::error(status_for(&e), e))
+}
+
+fn dispatch(config: &Config, request: Request) -> Result<HttpResponse, Error> {
+    let name = request.database().to_string();
+    let db = match Database::from_name(&name) {
+        Some(db) => db,
+        None => {
+            return Ok(HttpResponse::error(
+                400,
+                format!("Unknown database {}", name),
+            ))
+        }
+    };
+
+    let response = match db {
+        Database::Books => handle_request(
+            config,
+            BooksController::from_config(config, &name)?,
+            request,
+        ),
+
+        Database::Shelves => handle_request(
+            config,
+            ShelvesController::from_config(config, &name)?,
+            request,
+        ),
+
+        Database::SimpleMovie => handle_request(
+            config,
+            SimpleMovieController::from_config(config, &name)?,
+            request,
+        ),
+
+        Database::MovieLens => handle_request(
+            config,
+            MovieLensController::from_config(config, &name)?,
+            request,
+        ),
+
+        Database::MovieLensSmall => handle_request(
+            config,
+            MovieLensSmallController::from_config(config, &name)?,
+            request,
+        ),
+    };
+
+    Ok(response)
+}
+
+fn handle_connection(config: &Config, stream: TcpStream) -> Result<(), Error> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut stream = stream;
+
+    let response = match read_request(&mut reader) {
+        Ok(http) => {
+            log::info!("{} {}", http.method, http.path);
+            match route(&http) {
+                Ok(request) => {
+                    dispatch(config, request).unwrap_or_else(|e| HttpResponse::error(500, e))
+                }
+                Err(response) => response,
+            }
+        }
+
+        Err(response) => response,
+    };
+
+    response.write_to(&mut stream)
+}
+
+/// Serve the engine over HTTP, requests are handled one at a time and each one
+/// connects to the database given in its body
+pub fn serve(config: &Config, address: &str) -> Result<(), Error> {
+    let listener = TcpListener::bind(address)?;
+    println!("Listening on http://{}", listener.local_addr()?);
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::error!("Failed to accept connection: {}", e);
+                continue;
+            }
+        };
+
+        // Requests are handled one at a time, don't let a client that never
+        // finishes sending its request block the rest
+        if let Err(e) = stream.set_read_timeout(Some(READ_TIMEOUT)) {
+            log::error!("Failed to set read timeout: {}", e);
+            continue;
+        }
+
+        if let Err(e) = handle_connection(config, stream) {
+            log::error!("Failed to handle connection: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use engine::error::EmptyKnnReason;
+
+    fn http(raw: &str) -> HttpRequest {
+        read_request(&mut raw.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn read_post_request() {
+        let body = r#"{"database":"books","user_id":"1","method":"cosine","k":5}"#;
+        let raw = format!(
+            "POST /knn HTTP/1.1\r\nHost: localhost\r\ncontent-length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+
+        let parsed = http(&raw);
+        assert_eq!(parsed.method, "POST");
+        assert_eq!(parsed.path, "/knn");
+        assert_eq!(parsed.body, body.as_bytes());
+    }
+
+    #[test]
+    fn route_requests() {
+        let body = r#"{"database":"books","user_id":"1","method":"cosine","k":5,"n":3}"#;
+        let raw = format!(
+            "POST /top_n HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+
+        let expected = Request::TopN(TopNRequest {
+            database: "books".into(),
+            user_id: "1".into(),
+            method: "cosine".into(),
+            k: 5,
+            n: 3,
+            chunk_size: None,
+            max_candidates: None,
+            novelty: None,
+        });
+
+        assert_eq!(route(&http(&raw)), Ok(expected));
+    }
+
+    #[test]
+    fn read_request_errors() {
+        let raw = format!(
+            "POST /knn HTTP/1.1\r\nContent-Length: {}\r\n\r\n{{}}",
+            MAX_BODY_SIZE + 1
+        );
+        assert_eq!(read_request(&mut raw.as_bytes()).unwrap_err().status, 413);
+
+        let raw = "POST /knn HTTP/1.1\r\nContent-Length: 18446744073709551615\r\n\r\n";
+        assert_eq!(read_request(&mut raw.as_bytes()).unwrap_err().status, 413);
+
+        let raw = "POST /knn HTTP/1.1\r\nContent-Length: ten\r\n\r\n";
+        assert_eq!(read_request(&mut raw.as_bytes()).unwrap_err().status, 400);
+
+        let raw = "POST /knn HTTP/1.1\r\nContent-Length: 10\r\n\r\n{}";
+        assert_eq!(read_request(&mut raw.as_bytes()).unwrap_err().status, 400);
+
+        let raw = "\r\n";
+        assert_eq!(read_request(&mut raw.as_bytes()).unwrap_err().status, 400);
+    }
+
+    #[test]
+    fn route_errors() {
+        let raw = "POST /unknown HTTP/1.1\r\n\r\n";
+        assert_eq!(route(&http(raw)).unwrap_err().status, 404);
+
+        let raw = "POST /knn HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}";
+        assert_eq!(route(&http(raw)).unwrap_err().status, 400);
+
+        let body = r#"{"database":"books","user_id":"1","method":"cosine","k":5}"#;
+        let raw = format!(
+            "GET /knn HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+
+        assert_eq!(route(&http(&raw)).unwrap_err().status, 405);
+    }
+
+    #[test]
+    fn status_for_errors() {
+        let cold = RecommendationError::from(ErrorKind::ColdUser("1".into()));
+        assert_eq!(status_for(&cold), 404);
+
+        let empty = RecommendationError::from(ErrorKind::EmptyKNearestNeighbors);
+        assert_eq!(status_for(&empty), 422);
+
+        let no_overlap = ErrorKind::NoNeighbors(EmptyKnnReason::NoOverlappingUsers);
+        assert_eq!(status_for(&no_overlap.into()), 422);
+
+        let not_found = RecommendationError::from(anyhow::Error::from(
+            ControllerErrorKind::NotFoundById("1".into()),
+        ));
+        assert_eq!(status_for(&not_found), 404);
+
+        let db = RecommendationError::from(anyhow::anyhow!("connection refused"));
+        assert_eq!(status_for(&db), 500);
+    }
+}
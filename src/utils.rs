@@ -4,9 +4,18 @@
 // https://opensource.org/licenses/MIT
 
 use anyhow::Error;
-use controller::{Field, Value};
+use controller::{eid, error::ErrorKind, Controller, Entity, Field, SearchBy, Value};
+use engine::{distances::users::Method as UserMethod, Engine};
 use rustyline::Editor;
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Display},
+    hash::Hash,
+    str::FromStr,
+};
+
+/// Ratings sent to `insert_ratings` at once by `import_ratings`
+pub(crate) const IMPORT_BATCH_SIZE: usize = 1000;
 
 macro_rules! field {
     ($ed:ident, $name:expr, $opt:expr, $ty:expr) => {{
@@ -74,3 +83,387 @@ pub(crate) fn build_prototype<'a>(
 
     Ok(prototype)
 }
+
+/// Rows of an `import_ratings` call, malformed rows and out of range scores are
+/// skipped while rows the controller refused to insert are failed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct ImportSummary {
+    pub inserted: usize,
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+fn parse_rating_record<U, I>(record: &csv::StringRecord) -> Option<(U, I, f64)>
+where
+    U: FromStr,
+    I: FromStr,
+{
+    if record.len() != 3 {
+        return None;
+    }
+
+    let user_id = record[0].parse().ok()?;
+    let item_id = record[1].parse().ok()?;
+    let score = record[2].parse().ok()?;
+
+    Some((user_id, item_id, score))
+}
+
+#[allow(clippy::type_complexity)]
+fn insert_batch<C>(
+    controller: &C,
+    batch: &mut Vec<(eid!(C::User), eid!(C::Item), f64)>,
+    summary: &mut ImportSummary,
+) where
+    C: Controller,
+{
+    if batch.is_empty() {
+        return;
+    }
+
+    match controller.insert_ratings(batch) {
+        Ok(inserted) => summary.inserted += inserted,
+
+        // Retry one by one to find the culprits, note that controllers that don't
+        // insert batches atomically will report their partial inserts as failed
+        Err(e) => {
+            log::warn!(
+                "Failed to insert a batch of ratings ({}), retrying one by one",
+                e
+            );
+            for (user_id, item_id, score) in batch.iter() {
+                match controller.insert_rating(user_id, item_id, *score) {
+                    Ok(_) => summary.inserted += 1,
+                    Err(e) => {
+                        log::warn!("Failed to insert rating: {}", e);
+                        summary.failed += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    batch.clear();
+}
+
+/// Insert the ratings of a CSV file with `user_id,item_id,score` rows, by batches
+/// of `batch_size`. A first row that doesn't parse is taken as the header
+pub(crate) fn import_ratings<C>(
+    controller: &C,
+    path: &str,
+    batch_size: usize,
+) -> Result<ImportSummary, Error>
+where
+    C: Controller,
+    eid!(C::User): FromStr,
+    eid!(C::Item): FromStr,
+{
+    let mut csv = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+
+    let mut summary = ImportSummary::default();
+    let mut batch = Vec::with_capacity(batch_size);
+
+    for (row, record) in csv.records().enumerate() {
+        let rating = match &record {
+            Ok(record) => parse_rating_record(record),
+            Err(_) => None,
+        };
+
+        let (user_id, item_id, score) = match rating {
+            Some(rating) => rating,
+            None if row == 0 => continue,
+            None => {
+                log::warn!("Skipping malformed row {}: {:?}", row + 1, record);
+                summary.skipped += 1;
+                continue;
+            }
+        };
+
+        if let Err(e) = controller.validate_score(score) {
+            log::warn!("Skipping row {}: {}", row + 1, e);
+            summary.skipped += 1;
+            continue;
+        }
+
+        batch.push((user_id, item_id, score));
+        if batch.len() >= batch_size {
+            insert_batch(controller, &mut batch, &mut summary);
+        }
+    }
+
+    insert_batch(controller, &mut batch, &mut summary);
+    Ok(summary)
+}
+
+/// Held out rows of an `evaluate` call, rows of users or items that aren't in the
+/// database are only counted as `unknown` since they can't be predicted
+pub(crate) struct TestSet<U, I> {
+    pub rows: Vec<(U, I, f64)>,
+    pub unknown: usize,
+    pub skipped: usize,
+}
+
+/// Whether an entity with `id` exists, the answers are cached so each id is only
+/// searched once
+fn is_known<K, E, F>(cache: &mut HashMap<K, bool>, id: &K, find: F) -> Result<bool, Error>
+where
+    K: Hash + Eq + Clone,
+    F: FnOnce(&K) -> Result<Vec<E>, Error>,
+{
+    if let Some(known) = cache.get(id) {
+        return Ok(*known);
+    }
+
+    let known = match find(id) {
+        Ok(entities) => !entities.is_empty(),
+        Err(e) => match e.downcast_ref::<ErrorKind>() {
+            Some(ErrorKind::NotFoundById(_)) => false,
+            _ => return Err(e),
+        },
+    };
+
+    cache.insert(id.clone(), known);
+    Ok(known)
+}
+
+/// Read a CSV file of held out `user_id,item_id,score` rows, malformed rows are
+/// skipped and a first row that doesn't parse is taken as the header
+pub(crate) fn read_test_set<C>(
+    controller: &C,
+    path: &str,
+) -> Result<TestSet<C::User, C::Item>, Error>
+where
+    C: Controller,
+    eid!(C::User): FromStr + Display + Hash + Eq + Clone,
+    eid!(C::Item): FromStr + Display + Hash + Eq + Clone,
+{
+    let mut csv = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+
+    let mut users = HashMap::new();
+    let mut items = HashMap::new();
+    let mut test_set = TestSet {
+        rows: Vec::new(),
+        unknown: 0,
+        skipped: 0,
+    };
+
+    for (row, record) in csv.records().enumerate() {
+        let rating = match &record {
+            Ok(record) => parse_rating_record(record),
+            Err(_) => None,
+        };
+
+        let (user_id, item_id, score) = match rating {
+            Some(rating) => rating,
+            None if row == 0 => continue,
+            None => {
+                log::warn!("Skipping malformed row {}: {:?}", row + 1, record);
+                test_set.skipped += 1;
+                continue;
+            }
+        };
+
+        let known_user = is_known(&mut users, &user_id, |id: &eid!(C::User)| {
+            controller.users_by(&SearchBy::id(&id.to_string()))
+        })?;
+        let known_item = is_known(&mut items, &item_id, |id: &eid!(C::Item)| {
+            controller.items_by(&SearchBy::id(&id.to_string()))
+        })?;
+
+        if !known_user || !known_item {
+            test_set.unknown += 1;
+            continue;
+        }
+
+        let user = controller.create_partial_users(&[user_id])?.remove(0);
+        let item = controller.create_partial_items(&[item_id])?.remove(0);
+        test_set.rows.push((user, item, score));
+    }
+
+    Ok(test_set)
+}
+
+/// Warning to show when the database has no users or no ratings, computations over
+/// an empty database only fail deep inside with confusing errors
+pub(crate) fn empty_database_warning<C: Controller>(
+    controller: &C,
+) -> Result<Option<String>, Error> {
+    let users = controller.users_count()?;
+    if users == 0 {
+        return Ok(Some(
+            "The database has no users, was the data loaded?".to_string(),
+        ));
+    }
+
+    let ratings = controller.ratings_count()?;
+    if ratings == 0 {
+        return Ok(Some(format!(
+            "The database has {} users but no ratings, was the data loaded?",
+            users
+        )));
+    }
+
+    Ok(None)
+}
+
+/// Write the user kNN graph to a CSV edge list with a `Source,Target,Weight`
+/// header (the one Gephi imports), edges are written as soon as they're found
+pub(crate) fn export_knn_graph<C, U, I>(
+    engine: &Engine<C, U, I>,
+    k: usize,
+    method: UserMethod,
+    chunk_size: usize,
+    path: &str,
+) -> Result<usize, Error>
+where
+    C: Controller<User = U, Item = I>,
+    U: Entity,
+    I: Entity,
+    eid!(U): Hash + Eq + Ord + Clone + Debug + Default + Display,
+    eid!(I): Hash + Eq + Clone + Debug,
+{
+    let mut csv = csv::Writer::from_path(path)?;
+    csv.write_record(["Source", "Target", "Weight"])?;
+
+    let edges = engine.knn_graph_with(k, method, chunk_size, |user_id, neighbor_id, value| {
+        csv.write_record(&[
+            user_id.to_string(),
+            neighbor_id.to_string(),
+            value.to_string(),
+        ])?;
+
+        Ok(())
+    })?;
+
+    csv.flush()?;
+    Ok(edges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use controller::Entity;
+    use in_memory::InMemoryController;
+
+    #[test]
+    fn import_ratings_summary() -> Result<(), Error> {
+        let controller = InMemoryController::from_ratings(&[(1, 1, 3.0), (2, 2, 4.0)])?;
+        controller.add_item(3);
+
+        let path = std::env::temp_dir().join(format!("import-{}.csv", std::process::id()));
+        let csv = [
+            "user_id,item_id,score",
+            "1,2,5.0",
+            "1,3,2.5",
+            "2,1,1",
+            "not,a,rating",
+            "2,3",
+            "2,3,9.0",
+            // Already rated and unknown user, the batch is retried one by one
+            "1,1,4.0",
+            "7,1,4.0",
+            "2,3,3.0",
+        ];
+        std::fs::write(&path, csv.join("\n"))?;
+
+        let summary = import_ratings(&controller, path.to_str().unwrap(), 3)?;
+        std::fs::remove_file(&path)?;
+
+        let expected = ImportSummary {
+            inserted: 4,
+            skipped: 3,
+            failed: 2,
+        };
+
+        assert_eq!(summary, expected);
+        let user = controller.users_by(&SearchBy::id("2"))?.remove(0);
+        assert_eq!(controller.user_ratings(&user)?.len(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn read_test_set_with_unknown_rows() -> Result<(), Error> {
+        let controller = InMemoryController::from_ratings(&[(1, 1, 3.0), (2, 2, 4.0)])?;
+
+        let path = std::env::temp_dir().join(format!("test-set-{}.csv", std::process::id()));
+        let csv = [
+            "user_id,item_id,score",
+            "1,2,5.0",
+            "2,1,1",
+            "not,a,rating",
+            // Unknown item and unknown user
+            "1,9,4.0",
+            "7,1,4.0",
+        ];
+        std::fs::write(&path, csv.join("\n"))?;
+
+        let test_set = read_test_set(&controller, path.to_str().unwrap());
+        std::fs::remove_file(&path)?;
+        let test_set = test_set?;
+
+        let rows: Vec<_> = test_set
+            .rows
+            .iter()
+            .map(|(user, item, score)| (user.get_id(), item.get_id(), *score))
+            .collect();
+        assert_eq!(rows, vec![(1, 2, 5.0), (2, 1, 1.0)]);
+        assert_eq!((test_set.unknown, test_set.skipped), (2, 1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn warn_about_empty_database() -> Result<(), Error> {
+        let controller = InMemoryController::new();
+        let warning = empty_database_warning(&controller)?;
+        assert!(warning.unwrap().contains("no users"));
+
+        controller.add_user(1);
+        controller.add_item(1);
+        let warning = empty_database_warning(&controller)?;
+        assert!(warning.unwrap().contains("no ratings"));
+
+        let controller = InMemoryController::from_ratings(&[(1, 1, 3.0)])?;
+        assert_eq!(controller.ratings_count()?, 1);
+        assert_eq!(empty_database_warning(&controller)?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn export_knn_graph_csv() -> Result<(), Error> {
+        let controller = InMemoryController::from_ratings(&[
+            (1, 1, 5.0),
+            (1, 2, 3.0),
+            (2, 1, 4.0),
+            (2, 2, 3.0),
+            (3, 1, 1.0),
+            (3, 2, 5.0),
+        ])?;
+        let config = config::Config::default();
+        let engine = Engine::with_controller(&controller, &config);
+
+        let path = std::env::temp_dir().join(format!("knn-graph-{}.csv", std::process::id()));
+        let edges = export_knn_graph(&engine, 1, UserMethod::Manhattan, 2, path.to_str().unwrap());
+        let written = std::fs::read_to_string(&path);
+        std::fs::remove_file(&path)?;
+
+        assert_eq!(edges?, 3);
+        let mut lines: Vec<_> = written?.lines().map(String::from).collect();
+        assert_eq!(lines.remove(0), "Source,Target,Weight");
+
+        lines.sort();
+        assert_eq!(lines, vec!["1,2,1", "2,1,1", "3,2,5"]);
+
+        Ok(())
+    }
+}